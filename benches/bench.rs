@@ -1,5 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use std::fs::File;
+use std::io::{BufReader, Write};
 use std::path::Path;
 
 macro_rules! bench {
@@ -68,7 +69,179 @@ criterion_group! {
     targets = large_xmldoc, large_minidom, large_roxmltree, large_xmltree
 }
 
-criterion_group!(utf_16, utf16_xmldoc);
+// In-memory UTF-16 document, decoded in chunks by the reader versus at once from the slice.
+fn utf16_in_memory(c: &mut Criterion) {
+    let bytes = std::fs::read("medium_utf16.xml").unwrap();
+    let mut group = c.benchmark_group("utf16_in_memory");
+    group.bench_function("parse_reader", |b| {
+        b.iter(|| black_box(xml_doc::Document::parse_reader(&bytes[..]).unwrap()))
+    });
+    group.bench_function("parse_bytes", |b| {
+        b.iter(|| black_box(xml_doc::Document::parse_bytes(&bytes).unwrap()))
+    });
+    group.finish();
+}
+
+criterion_group!(utf_16, utf16_xmldoc, utf16_in_memory);
+
+// Pre-buffered medium.xml, copied into the parser's own buffer versus read from the reader's buffer.
+fn medium_buffered(c: &mut Criterion) {
+    let bytes = std::fs::read("medium.xml").unwrap();
+    let mut group = c.benchmark_group("medium_buffered");
+    group.bench_function("parse_reader", |b| {
+        b.iter(|| {
+            let reader = BufReader::new(&bytes[..]);
+            black_box(xml_doc::Document::parse_reader(reader).unwrap())
+        })
+    });
+    group.bench_function("parse_bufread", |b| {
+        b.iter(|| {
+            let reader = BufReader::new(&bytes[..]);
+            black_box(xml_doc::Document::parse_bufread(reader).unwrap())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(buffered, medium_buffered);
+
+// Parse 10k copies of tiny.xml, reusing `Parser` and the document versus `Document::parse_str`.
+fn tiny_repeated(c: &mut Criterion) {
+    let xml = std::fs::read_to_string("tiny.xml").unwrap();
+    let mut group = c.benchmark_group("tiny_10k");
+    group.sample_size(10);
+    group.bench_function("parse_str", |b| {
+        b.iter(|| {
+            for _ in 0..10_000 {
+                black_box(xml_doc::Document::parse_str(&xml).unwrap());
+            }
+        })
+    });
+    group.bench_function("parser_parse_into", |b| {
+        let mut parser = xml_doc::Parser::new(xml_doc::ReadOptions::default());
+        let mut doc = xml_doc::Document::new();
+        b.iter(|| {
+            for _ in 0..10_000 {
+                parser.parse_into(&mut doc, &xml).unwrap();
+                black_box(&doc);
+            }
+        })
+    });
+    group.finish();
+}
+criterion_group!(reuse, tiny_repeated);
+
+// Parse large.xml with element names in an arena versus in owned strings, and drop the document.
+fn large_name_arena(c: &mut Criterion) {
+    let xml = std::fs::read_to_string("large.xml").unwrap();
+    let mut group = c.benchmark_group("large_name_arena");
+    group.sample_size(20);
+    for (mode, name_arena) in [("owned", false), ("arena", true)] {
+        let opts = xml_doc::ReadOptions {
+            name_arena,
+            ..xml_doc::ReadOptions::default()
+        };
+        group.bench_function(format!("{}/parse", mode), |b| {
+            b.iter_with_large_drop(|| {
+                xml_doc::Document::parse_str_with_opts(&xml, opts.clone()).unwrap()
+            })
+        });
+        group.bench_function(format!("{}/drop", mode), |b| {
+            b.iter_batched(
+                || xml_doc::Document::parse_str_with_opts(&xml, opts.clone()).unwrap(),
+                drop,
+                criterion::BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+criterion_group!(arena, large_name_arena);
+
+// Scan all elements of large.xml, collecting them into a Vec first versus iterating lazily.
+fn large_scan(c: &mut Criterion) {
+    let doc = xml_doc::Document::parse_file("large.xml").unwrap();
+    let root = doc.root_element().unwrap();
+    let mut group = c.benchmark_group("large_scan");
+    group.bench_function("child_elements_recursive", |b| {
+        b.iter(|| {
+            root.child_elements_recursive(&doc)
+                .into_iter()
+                .filter(|e| e.name_is(&doc, "title"))
+                .count()
+        })
+    });
+    group.bench_function("descendants", |b| {
+        b.iter(|| {
+            root.descendants(&doc)
+                .filter(|e| e.name_is(&doc, "title"))
+                .count()
+        })
+    });
+    group.bench_function("descendants_find_first", |b| {
+        b.iter(|| black_box(root.descendants(&doc).find(|e| e.name_is(&doc, "title"))))
+    });
+    group.finish();
+}
+criterion_group!(scan, large_scan);
+
+// Write an element with a 16 MB attribute that needs no escaping,
+// versus writing the same bytes directly.
+fn large_attribute_write(c: &mut Criterion) {
+    let value = "QUJDRA==".repeat(2 << 20);
+    let mut doc = xml_doc::Document::new();
+    let container = doc.container();
+    xml_doc::Element::build("data")
+        .attribute("payload", value.as_str())
+        .push_to(&mut doc, container);
+    let mut group = c.benchmark_group("large_attribute");
+    group.sample_size(20);
+    group.bench_function("write", |b| {
+        b.iter(|| {
+            let mut out = Vec::new();
+            doc.write(&mut out).unwrap();
+            black_box(out)
+        })
+    });
+    group.bench_function("raw_bytes", |b| {
+        b.iter(|| {
+            let mut out = Vec::new();
+            out.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<data payload=\"")
+                .unwrap();
+            out.write_all(value.as_bytes()).unwrap();
+            out.write_all(b"\"/>").unwrap();
+            black_box(out)
+        })
+    });
+    group.finish();
+}
+
+// Write the parsed corpora with `write_str`, and with `write_with_opts` in pretty and compact modes.
+fn xmldoc_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    group.sample_size(20);
+    for file in ["tiny.xml", "medium.xml", "large.xml"] {
+        let doc = xml_doc::Document::parse_file(file).unwrap();
+        group.bench_function(format!("{}/write_str", file), |b| {
+            b.iter(|| black_box(doc.write_str().unwrap()))
+        });
+        for (mode, pretty) in [("pretty", true), ("compact", false)] {
+            group.bench_function(format!("{}/{}", file, mode), |b| {
+                b.iter(|| {
+                    let mut out = Vec::new();
+                    let opts = xml_doc::WriteOptions {
+                        pretty,
+                        ..xml_doc::WriteOptions::default()
+                    };
+                    doc.write_with_opts(&mut out, opts).unwrap();
+                    black_box(out)
+                })
+            });
+        }
+    }
+    group.finish();
+}
+criterion_group!(write, xmldoc_write, large_attribute_write);
 
 criterion_group!(
     xmldoc,
@@ -221,4 +394,7 @@ bench!("medium.xml", medium_xml_rs, xml_rs_parser);
 bench!("large.xml", large_xml_rs, xml_rs_parser);
 criterion_group!(xml_rs, tiny_xml_rs, medium_xml_rs, large_xml_rs);
 
-criterion_main!(tiny, medium, large, utf_16, quick_xml, xml5ever, rustyxml, xml_rs);
+criterion_main!(
+    tiny, medium, large, utf_16, buffered, reuse, arena, scan, write, quick_xml, xml5ever,
+    rustyxml, xml_rs
+);
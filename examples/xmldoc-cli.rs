@@ -0,0 +1,252 @@
+//! A small command line tool built on the public API of `xml_doc`.
+//!
+//! ```text
+//! cargo run --example xmldoc-cli -- format <file> [--indent N | --compact]
+//! cargo run --example xmldoc-cli -- get <file> <query>
+//! cargo run --example xmldoc-cli -- set <file> <query> <value>
+//! cargo run --example xmldoc-cli -- strip-comments <file>
+//! ```
+//!
+//! `set` and `strip-comments` write the modified document to stdout.
+//!
+//! A query is an element path like `/config/server[2]/name`, as returned by `Element::path()`.
+//! The index is 1-based and counts siblings with the same name. (default: 1)
+//! The last step may be `@attr` to select an attribute instead of the element's text.
+use std::io::Write;
+use std::path::Path;
+use xml_doc::{Document, Element, Node, WriteOptions};
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Format {
+        file: String,
+        indent: Option<usize>,
+    },
+    Get {
+        file: String,
+        query: String,
+    },
+    Set {
+        file: String,
+        query: String,
+        value: String,
+    },
+    StripComments {
+        file: String,
+    },
+}
+
+const USAGE: &str = "usage: xmldoc-cli <format|get|set|strip-comments> <file> [args]";
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    let command = match args.as_slice() {
+        ["format", file] => Command::Format {
+            file: file.to_string(),
+            indent: Some(2),
+        },
+        ["format", file, "--compact"] => Command::Format {
+            file: file.to_string(),
+            indent: None,
+        },
+        ["format", file, "--indent", n] => Command::Format {
+            file: file.to_string(),
+            indent: Some(n.parse().map_err(|_| format!("Invalid indent '{}'", n))?),
+        },
+        ["get", file, query] => Command::Get {
+            file: file.to_string(),
+            query: query.to_string(),
+        },
+        ["set", file, query, value] => Command::Set {
+            file: file.to_string(),
+            query: query.to_string(),
+            value: value.to_string(),
+        },
+        ["strip-comments", file] => Command::StripComments {
+            file: file.to_string(),
+        },
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok(command)
+}
+
+fn run(command: &Command, out: &mut impl Write) -> Result<(), String> {
+    match command {
+        Command::Format { file, indent } => {
+            let doc = read(file)?;
+            let opts = match indent {
+                Some(indent) => WriteOptions {
+                    indent_size: *indent,
+                    ..WriteOptions::default()
+                },
+                None => WriteOptions {
+                    pretty: false,
+                    ..WriteOptions::default()
+                },
+            };
+            write(&doc, opts, out)
+        }
+        Command::Get { file, query } => {
+            let doc = read(file)?;
+            let value = match resolve(&doc, query)? {
+                (elem, Some(attr)) => elem
+                    .attribute(&doc, attr)
+                    .ok_or_else(|| format!("No attribute '{}' at '{}'", attr, elem.path(&doc)))?
+                    .to_string(),
+                (elem, None) => elem.text_content(&doc),
+            };
+            writeln!(out, "{}", value).map_err(|err| err.to_string())
+        }
+        Command::Set { file, query, value } => {
+            let mut doc = read(file)?;
+            match resolve(&doc, query)? {
+                (elem, Some(attr)) => elem.set_attribute(&mut doc, attr, value.as_str()),
+                (elem, None) => elem.set_text_content(&mut doc, value.as_str()),
+            }
+            write(&doc, WriteOptions::default(), out)
+        }
+        Command::StripComments { file } => {
+            let mut doc = read(file)?;
+            let container = doc.container();
+            let elements: Vec<Element> = std::iter::once(container)
+                .chain(container.descendants(&doc))
+                .collect();
+            for elem in elements {
+                let comments: Vec<usize> = elem
+                    .children(&doc)
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, node)| matches!(node, Node::Comment(_)))
+                    .map(|(i, _)| i)
+                    .collect();
+                for i in comments.into_iter().rev() {
+                    elem.remove_child(&mut doc, i);
+                }
+            }
+            write(&doc, WriteOptions::default(), out)
+        }
+    }
+}
+
+fn read(file: &str) -> Result<Document, String> {
+    Document::parse_file(Path::new(file)).map_err(|err| format!("Cannot read '{}': {}", file, err))
+}
+
+fn write(doc: &Document, opts: WriteOptions, out: &mut impl Write) -> Result<(), String> {
+    doc.write_with_opts(out, opts)
+        .and_then(|_| Ok(writeln!(out)?))
+        .map_err(|err| err.to_string())
+}
+
+/// Returns the element, and the attribute name if the query ends with `@attr`.
+fn resolve<'q>(doc: &Document, query: &'q str) -> Result<(Element, Option<&'q str>), String> {
+    let bad_query = |reason: &str| format!("Invalid query '{}': {}", query, reason);
+    let steps = query
+        .strip_prefix('/')
+        .ok_or_else(|| bad_query("must start with '/'"))?;
+    let mut steps: Vec<&str> = steps.split('/').collect();
+    let attr = match steps.last().and_then(|last| last.strip_prefix('@')) {
+        Some(attr) => {
+            steps.pop();
+            Some(attr)
+        }
+        None => None,
+    };
+    if steps.is_empty() || steps.iter().any(|step| step.is_empty()) {
+        return Err(bad_query("empty step"));
+    }
+    let mut elem = doc.container();
+    for step in steps {
+        let (name, index) = match step.strip_suffix(']').and_then(|s| s.split_once('[')) {
+            Some((name, index)) => match index.parse::<usize>() {
+                Ok(index) if index > 0 => (name, index),
+                _ => return Err(bad_query(&format!("invalid index in '{}'", step))),
+            },
+            None => (step, 1),
+        };
+        elem = elem
+            .child_elements_iter(doc)
+            .filter(|child| child.full_name(doc) == name)
+            .nth(index - 1)
+            .ok_or_else(|| format!("No element matches '{}'", query))?;
+    }
+    Ok((elem, attr))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = parse_args(&args).and_then(|command| run(&command, &mut std::io::stdout()));
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_args(args: &[&str]) -> Result<String, String> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        let mut out = Vec::new();
+        run(&parse_args(&args)?, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    const ALIGNED: &str = "tests/documents/aligned.xml";
+
+    #[test]
+    fn test_format() {
+        let out = run_args(&["format", ALIGNED, "--compact"]).unwrap();
+        assert!(out.contains("<config><server name=\"prod\" host=\"example.com\" port=\"443\"/>"));
+        let out = run_args(&["format", ALIGNED, "--indent", "4"]).unwrap();
+        assert!(out.contains("\n        <alias>local</alias>\n"));
+        assert!(run_args(&["format", ALIGNED, "--indent", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_get_set() {
+        assert_eq!(
+            run_args(&["get", ALIGNED, "/config/server[2]/alias"]).unwrap(),
+            "local\n"
+        );
+        assert_eq!(
+            run_args(&["get", ALIGNED, "/config/server/@host"]).unwrap(),
+            "example.com\n"
+        );
+        let out = run_args(&["set", ALIGNED, "/config/server[2]/@name", "local"]).unwrap();
+        assert!(out.contains("<server name=\"local\">"));
+        let out = run_args(&["set", ALIGNED, "/config/server[2]/alias", "a<b"]).unwrap();
+        assert!(out.contains("<alias>a&lt;b</alias>"));
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let out = run_args(&["strip-comments", "tests/documents/nodes.xml"]).unwrap();
+        assert!(!out.contains("Comment"));
+        assert!(out.contains("<el/>"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = run_args(&["get", ALIGNED, "config/server"]).unwrap_err();
+        assert!(err.contains("must start with '/'"), "{}", err);
+        let err = run_args(&["get", ALIGNED, "/config/server[0]"]).unwrap_err();
+        assert!(err.contains("invalid index"), "{}", err);
+        let err = run_args(&["get", ALIGNED, "/config/server[3]"]).unwrap_err();
+        assert!(err.contains("No element matches"), "{}", err);
+        let err = run_args(&["get", ALIGNED, "/config/@missing"]).unwrap_err();
+        assert!(
+            err.contains("No attribute 'missing' at '/config'"),
+            "{}",
+            err
+        );
+        let err = run_args(&["get", "tests/documents/missing.xml", "/a"]).unwrap_err();
+        assert!(
+            err.starts_with("Cannot read 'tests/documents/missing.xml'"),
+            "{}",
+            err
+        );
+        assert_eq!(run_args(&["get", ALIGNED]).unwrap_err(), USAGE);
+    }
+}
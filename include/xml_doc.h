@@ -0,0 +1,43 @@
+/* C interface of xml-doc, enabled with the `capi` feature.
+ * See the documentation of the `capi` module for string and ownership conventions. */
+#ifndef XML_DOC_H
+#define XML_DOC_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define XMLDOC_OK 0
+#define XMLDOC_ERR_NULL -1
+#define XMLDOC_ERR_UTF8 -2
+#define XMLDOC_ERR_INVALID_DOCUMENT -3
+#define XMLDOC_ERR_INVALID_ELEMENT -4
+#define XMLDOC_ERR_IO -5
+#define XMLDOC_ERR_DECODE -6
+#define XMLDOC_ERR_MALFORMED -7
+#define XMLDOC_ERR_OTHER -8
+
+typedef struct Document Document;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+int xmldoc_last_error(void);
+
+Document *xmldoc_parse_file(const char *path);
+
+uint64_t xmldoc_root_element(const Document *doc);
+
+size_t xmldoc_elem_name(const Document *doc, uint64_t id, char *out_buf, size_t cap);
+
+int xmldoc_elem_set_attribute(Document *doc, uint64_t id, const char *name, const char *value);
+
+int xmldoc_write_file(const Document *doc, const char *path);
+
+int xmldoc_free(Document *doc);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* XML_DOC_H */
@@ -0,0 +1,264 @@
+//! C-compatible functions, enabled with the `capi` feature.
+//!
+//! Build a C library with `cargo rustc --release --features capi --crate-type cdylib`,
+//! and include `include/xml_doc.h`.
+//!
+//! # Conventions
+//! - Strings passed in are NUL-terminated UTF-8. Strings written out are NUL-terminated UTF-8.
+//! - Elements are referred to by their `u64` id. Id `0` means "no element".
+//! - Functions returning `int` return [`XMLDOC_OK`] or a negative error code.
+//!   Functions returning other values set the error code retrieved by [`xmldoc_last_error`].
+//!
+//! # Ownership
+//! A `Document` returned by [`xmldoc_parse_file`] is owned by the caller,
+//! and must be freed with [`xmldoc_free`] exactly once.
+//! Pointers that are not live documents are rejected with [`XMLDOC_ERR_INVALID_DOCUMENT`],
+//! so freeing a document twice or using it after freeing returns an error instead of crashing.
+//! (Unless the memory was reused for a new document.)
+use crate::document::Document;
+use crate::element::Element;
+use crate::error::Error;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::sync::{Mutex, PoisonError};
+
+pub const XMLDOC_OK: c_int = 0;
+/// A pointer argument was null.
+pub const XMLDOC_ERR_NULL: c_int = -1;
+/// A string argument was not valid UTF-8.
+pub const XMLDOC_ERR_UTF8: c_int = -2;
+/// The document pointer is not a live document. (e.g. already freed)
+pub const XMLDOC_ERR_INVALID_DOCUMENT: c_int = -3;
+/// There is no element with the id in the document.
+pub const XMLDOC_ERR_INVALID_ELEMENT: c_int = -4;
+/// [`Error::Io`] and [`Error::WriteInterrupted`]
+pub const XMLDOC_ERR_IO: c_int = -5;
+/// [`Error::CannotDecode`]
+pub const XMLDOC_ERR_DECODE: c_int = -6;
+/// [`Error::MalformedXML`]
+pub const XMLDOC_ERR_MALFORMED: c_int = -7;
+/// Other [`Error`]s.
+pub const XMLDOC_ERR_OTHER: c_int = -8;
+
+thread_local! {
+    static LAST_ERROR: Cell<c_int> = const { Cell::new(XMLDOC_OK) };
+}
+
+// Addresses of documents returned by `xmldoc_parse_file` and not freed yet.
+static LIVE_DOCUMENTS: Mutex<Option<HashSet<usize>>> = Mutex::new(None);
+
+fn error_code(err: &Error) -> c_int {
+    match err {
+        Error::Io(_) | Error::WriteInterrupted { .. } => XMLDOC_ERR_IO,
+        Error::CannotDecode { .. } => XMLDOC_ERR_DECODE,
+        Error::MalformedXML(_) => XMLDOC_ERR_MALFORMED,
+        _ => XMLDOC_ERR_OTHER,
+    }
+}
+
+fn set_last_error(code: c_int) {
+    LAST_ERROR.with(|last| last.set(code));
+}
+
+fn is_live(doc: *const Document) -> bool {
+    let live = LIVE_DOCUMENTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    live.as_ref()
+        .is_some_and(|set| set.contains(&(doc as usize)))
+}
+
+unsafe fn str_arg<'a>(ptr: *const c_char) -> Result<&'a str, c_int> {
+    if ptr.is_null() {
+        return Err(XMLDOC_ERR_NULL);
+    }
+    CStr::from_ptr(ptr).to_str().map_err(|_| XMLDOC_ERR_UTF8)
+}
+
+fn check_doc(doc: *const Document) -> Result<(), c_int> {
+    if doc.is_null() {
+        return Err(XMLDOC_ERR_NULL);
+    }
+    if !is_live(doc) {
+        return Err(XMLDOC_ERR_INVALID_DOCUMENT);
+    }
+    Ok(())
+}
+
+unsafe fn doc_arg<'a>(doc: *const Document) -> Result<&'a Document, c_int> {
+    check_doc(doc)?;
+    Ok(&*doc)
+}
+
+unsafe fn doc_arg_mut<'a>(doc: *mut Document) -> Result<&'a mut Document, c_int> {
+    check_doc(doc)?;
+    Ok(&mut *doc)
+}
+
+fn elem_arg(doc: &Document, id: u64) -> Result<Element, c_int> {
+    match Element::from_id(doc, id as usize) {
+        Some(elem) if !elem.is_container() => Ok(elem),
+        _ => Err(XMLDOC_ERR_INVALID_ELEMENT),
+    }
+}
+
+/// Error code of the last failed call on this thread, or [`XMLDOC_OK`].
+#[no_mangle]
+pub extern "C" fn xmldoc_last_error() -> c_int {
+    LAST_ERROR.with(|last| last.get())
+}
+
+/// Parse the file at `path`. Returns null on error.
+///
+/// # Safety
+/// `path` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn xmldoc_parse_file(path: *const c_char) -> *mut Document {
+    let path = match str_arg(path) {
+        Ok(path) => path,
+        Err(code) => {
+            set_last_error(code);
+            return std::ptr::null_mut();
+        }
+    };
+    match Document::parse_file(path) {
+        Ok(doc) => {
+            let ptr = Box::into_raw(Box::new(doc));
+            let mut live = LIVE_DOCUMENTS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            live.get_or_insert_with(HashSet::new).insert(ptr as usize);
+            set_last_error(XMLDOC_OK);
+            ptr
+        }
+        Err(err) => {
+            set_last_error(error_code(&err));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Id of the root element, or `0` if there is none or on error.
+///
+/// # Safety
+/// `doc` must be null or a pointer returned by [`xmldoc_parse_file`].
+#[no_mangle]
+pub unsafe extern "C" fn xmldoc_root_element(doc: *const Document) -> u64 {
+    match doc_arg(doc) {
+        Ok(doc) => {
+            set_last_error(XMLDOC_OK);
+            doc.root_element().map_or(0, |elem| elem.id() as u64)
+        }
+        Err(code) => {
+            set_last_error(code);
+            0
+        }
+    }
+}
+
+/// Write the full name of element `id` to `out_buf` with a NUL terminator,
+/// if the name and terminator fit in `cap` bytes.
+///
+/// Returns the length of the name in bytes without the terminator, like `snprintf`.
+/// If it is `>= cap`, nothing is written; call again with a larger buffer.
+/// Returns `0` on error.
+///
+/// # Safety
+/// `doc` must be null or a pointer returned by [`xmldoc_parse_file`].
+/// `out_buf` must be null or valid for writing `cap` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn xmldoc_elem_name(
+    doc: *const Document,
+    id: u64,
+    out_buf: *mut c_char,
+    cap: usize,
+) -> usize {
+    let res = doc_arg(doc).and_then(|doc| Ok((doc, elem_arg(doc, id)?)));
+    let (doc, elem) = match res {
+        Ok(res) => res,
+        Err(code) => {
+            set_last_error(code);
+            return 0;
+        }
+    };
+    let name = elem.full_name(doc).as_bytes();
+    if name.len() < cap {
+        if out_buf.is_null() {
+            set_last_error(XMLDOC_ERR_NULL);
+            return 0;
+        }
+        std::ptr::copy_nonoverlapping(name.as_ptr(), out_buf as *mut u8, name.len());
+        *out_buf.add(name.len()) = 0;
+    }
+    set_last_error(XMLDOC_OK);
+    name.len()
+}
+
+/// Set attribute `name` of element `id` to `value`.
+///
+/// # Safety
+/// `doc` must be null or a pointer returned by [`xmldoc_parse_file`].
+/// `name` and `value` must be null or valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn xmldoc_elem_set_attribute(
+    doc: *mut Document,
+    id: u64,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    let res = (|| {
+        let doc = doc_arg_mut(doc)?;
+        let elem = elem_arg(doc, id)?;
+        elem.set_attribute(doc, str_arg(name)?, str_arg(value)?);
+        Ok(())
+    })();
+    let code = res.err().unwrap_or(XMLDOC_OK);
+    set_last_error(code);
+    code
+}
+
+/// Write the document to the file at `path`, creating or truncating it.
+///
+/// # Safety
+/// `doc` must be null or a pointer returned by [`xmldoc_parse_file`].
+/// `path` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn xmldoc_write_file(doc: *const Document, path: *const c_char) -> c_int {
+    let res = (|| {
+        let doc = doc_arg(doc)?;
+        let path = str_arg(path)?;
+        doc.write_file(path).map_err(|err| error_code(&err))
+    })();
+    let code = res.err().unwrap_or(XMLDOC_OK);
+    set_last_error(code);
+    code
+}
+
+/// Free a document returned by [`xmldoc_parse_file`].
+/// Element ids of the document must not be used afterwards.
+///
+/// Returns [`XMLDOC_ERR_INVALID_DOCUMENT`] without freeing anything
+/// if `doc` is not a live document, e.g. it was already freed.
+///
+/// # Safety
+/// `doc` must be null or a pointer returned by [`xmldoc_parse_file`].
+#[no_mangle]
+pub unsafe extern "C" fn xmldoc_free(doc: *mut Document) -> c_int {
+    let code = if doc.is_null() {
+        XMLDOC_ERR_NULL
+    } else {
+        let mut live = LIVE_DOCUMENTS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if live.as_mut().is_some_and(|set| set.remove(&(doc as usize))) {
+            drop(Box::from_raw(doc));
+            XMLDOC_OK
+        } else {
+            XMLDOC_ERR_INVALID_DOCUMENT
+        }
+    };
+    set_last_error(code);
+    code
+}
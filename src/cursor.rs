@@ -0,0 +1,193 @@
+use crate::document::{Document, MutationEvent, Node};
+use crate::element::Element;
+use crate::error::{Error, Result};
+
+/// Edits to the children of an element, addressed by the indexes children had
+/// when the cursor was created. Created with [`Element::cursor()`].
+///
+/// Edits are recorded in the cursor and applied together by [`ChildCursor::commit()`],
+/// so an index keeps referring to the same child whatever was removed or inserted before it.
+/// If the document is modified in other ways in between, committing fails with [`Error::StaleCursor`].
+///
+/// ```
+/// use xml_doc::{Document, Element};
+///
+/// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/><c/><d/></a>"#).unwrap();
+/// let a = doc.root_element().unwrap();
+/// let mut cursor = a.cursor(&doc);
+/// cursor.remove(0).unwrap();
+/// let e = Element::new(&mut doc, "e");
+/// cursor.insert_after(1, e.as_node()).unwrap(); // after <c/>, which was at index 1
+/// cursor.remove(2).unwrap(); // <d/>
+/// cursor.commit(&mut doc).unwrap();
+/// let names: Vec<&str> = a.child_elements(&doc).iter().map(|e| e.name(&doc)).collect();
+/// assert_eq!(names, ["c", "e"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChildCursor {
+    element: Element,
+    revision: u64,
+    // Children when the cursor was created.
+    children: Vec<Node>,
+    removed: Vec<bool>,
+    // Nodes to insert before each child, and after the last child at `children.len()`.
+    inserted: Vec<Vec<Node>>,
+}
+
+impl Element {
+    /// Create a [`ChildCursor`] to edit the children of this element by their current indexes.
+    pub fn cursor(&self, doc: &Document) -> ChildCursor {
+        self.assert_valid(doc);
+        let children = self.children(doc).clone();
+        let len = children.len();
+        ChildCursor {
+            element: *self,
+            revision: doc.revision(),
+            children,
+            removed: vec![false; len],
+            inserted: vec![Vec::new(); len + 1],
+        }
+    }
+}
+
+impl ChildCursor {
+    /// The element whose children are edited.
+    pub fn element(&self) -> Element {
+        self.element
+    }
+
+    /// Number of children when the cursor was created.
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Returns `true` if the element had no children when the cursor was created.
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// The child at `index` when the cursor was created, even if the cursor removes it.
+    pub fn node(&self, index: usize) -> Option<&Node> {
+        self.children.get(index)
+    }
+
+    /// Index the child at `index` will have after the edits recorded so far are committed,
+    /// or `None` if it is removed.
+    pub fn index(&self, index: usize) -> Option<usize> {
+        if *self.removed.get(index)? {
+            return None;
+        }
+        let kept = self.removed[..index].iter().filter(|r| !**r).count();
+        let inserted: usize = self.inserted[..=index].iter().map(Vec::len).sum();
+        Some(kept + inserted)
+    }
+
+    /// Remove the child at `index`. Does nothing if it is already removed.
+    ///
+    /// # Errors
+    /// - [`Error::IndexOutOfBounds`]: `index >= self.len()`
+    pub fn remove(&mut self, index: usize) -> Result<()> {
+        let len = self.len();
+        match self.removed.get_mut(index) {
+            Some(removed) => {
+                *removed = true;
+                Ok(())
+            }
+            None => Err(Error::IndexOutOfBounds { index, len }),
+        }
+    }
+
+    /// Insert `node` before the child at `index`, after nodes already inserted there.
+    /// `index` may be `self.len()` to insert after the last child.
+    ///
+    /// # Errors
+    /// - [`Error::IndexOutOfBounds`]: `index > self.len()`
+    /// - [`Error::HasAParent`]: `node` is the element of this cursor, or was already inserted.
+    /// - [`Error::ContainerCannotMove`]: `node` is the container element.
+    pub fn insert_before(&mut self, index: usize, node: Node) -> Result<()> {
+        self.insert(index, node)
+    }
+
+    /// Insert `node` after the child at `index`, after nodes already inserted there.
+    ///
+    /// # Errors
+    /// - [`Error::IndexOutOfBounds`]: `index >= self.len()`
+    /// - Errors of [`ChildCursor::insert_before()`].
+    pub fn insert_after(&mut self, index: usize, node: Node) -> Result<()> {
+        let len = self.len();
+        if index >= len {
+            return Err(Error::IndexOutOfBounds { index, len });
+        }
+        self.insert(index + 1, node)
+    }
+
+    fn insert(&mut self, slot: usize, node: Node) -> Result<()> {
+        let len = self.len();
+        if slot > len {
+            return Err(Error::IndexOutOfBounds { index: slot, len });
+        }
+        if let Node::Element(elem) = node {
+            if elem.is_container() {
+                return Err(Error::ContainerCannotMove);
+            }
+            let inserted = self.inserted.iter().flatten().any(|n| *n == node);
+            if inserted || elem == self.element {
+                return Err(Error::HasAParent);
+            }
+        }
+        self.inserted[slot].push(node);
+        Ok(())
+    }
+
+    /// Apply the recorded edits to the element.
+    ///
+    /// Removed elements are left without a parent, unless they are inserted again,
+    /// which moves them. Nothing is modified if an error is returned.
+    ///
+    /// # Errors
+    /// - [`Error::StaleCursor`]: The document was modified since the cursor was created.
+    /// - [`Error::HasAParent`]: An inserted element has a parent, and isn't a child removed by the cursor.
+    /// - [`Error::Rejected`]: The document's mutation validator rejected an inserted node.
+    pub fn commit(self, doc: &mut Document) -> Result<()> {
+        if doc.revision() != self.revision {
+            return Err(Error::StaleCursor);
+        }
+        for node in self.inserted.iter().flatten() {
+            node.check_valid(doc)?;
+            if let Node::Element(elem) = node {
+                let moved = || {
+                    let index = self.children.iter().position(|n| n == node);
+                    index.is_some_and(|index| self.removed[index])
+                };
+                if elem.has_parent(doc) && !moved() {
+                    return Err(Error::HasAParent);
+                }
+            }
+            doc.validate_mutation(&MutationEvent::PushChild {
+                parent: self.element,
+                node,
+            })?;
+        }
+        let inserted_count: usize = self.inserted.iter().map(Vec::len).sum();
+        let mut children = Vec::with_capacity(self.children.len() + inserted_count);
+        for (node, removed) in self.children.iter().zip(&self.removed) {
+            if let (Node::Element(elem), true) = (node, removed) {
+                elem.set_parent(doc, None);
+            }
+        }
+        let mut originals = self.children.into_iter().zip(self.removed);
+        for slot in self.inserted {
+            for node in slot {
+                if let Node::Element(elem) = node {
+                    elem.set_parent(doc, Some(self.element));
+                }
+                children.push(node);
+            }
+            if let Some((node, false)) = originals.next() {
+                children.push(node);
+            }
+        }
+        self.element.set_children(doc, children);
+        Ok(())
+    }
+}
@@ -1,31 +1,73 @@
-use crate::element::{Element, ElementData};
+use crate::element::{rename_keys, Element, ElementArena, ElementShape};
+use crate::encoding::{EncodingWriter, OutputEncoding};
 use crate::error::{Error, Result};
-use crate::parser::{DocumentParser, ReadOptions};
+use crate::parser::{
+    is_whitespace_char, Diagnostic, DocumentParser, ElementAction, ParseMetrics, ReadOptions,
+};
+use crate::spill::ExternalText;
+use crate::value::{Value, ValuePool};
+use indexmap::IndexMap;
 use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::Writer;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "fs")]
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{BufRead, Read, Write};
+use std::panic::RefUnwindSafe;
+#[cfg(feature = "fs")]
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
 
 /// Represents an XML node.
-#[derive(Debug)]
+///
+/// Element nodes are equal if they are the same element, not if their content is equal.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Node {
     /// XML Element
     Element(Element),
     /// XML Character Data ([specification](https://www.w3.org/TR/xml/#syntax))
-    Text(String),
+    Text(Value),
     /// Comments ([specification](https://www.w3.org/TR/xml/#sec-comments))
     Comment(String),
     /// CDATA ([specification](https://www.w3.org/TR/xml/#sec-cdata-sect))
-    CData(String),
+    CData(Value),
     /// Processing Instruction ([specification](https://www.w3.org/TR/xml/#sec-pi))
     PI(String),
     /// Document Type Declaration ([specification](https://www.w3.org/TR/xml/#sec-prolog-dtd))
     DocType(String),
+    /// Character data stored in a file, for text too large to keep in memory.
+    /// See [`ReadOptions::spill_large_text_to`].
+    ExternalText(ExternalText),
+}
+
+/// Kind of a [`Node`], without its content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    Element,
+    Text,
+    Comment,
+    CData,
+    PI,
+    DocType,
+    ExternalText,
 }
 
 impl Node {
+    pub fn kind(&self) -> NodeKind {
+        match self {
+            Node::Element(_) => NodeKind::Element,
+            Node::Text(_) => NodeKind::Text,
+            Node::Comment(_) => NodeKind::Comment,
+            Node::CData(_) => NodeKind::CData,
+            Node::PI(_) => NodeKind::PI,
+            Node::DocType(_) => NodeKind::DocType,
+            Node::ExternalText(_) => NodeKind::ExternalText,
+        }
+    }
+
     /// Useful to use inside `filter_map`.
     ///
     /// ```
@@ -53,27 +95,82 @@ impl Node {
         }
     }
 
-    pub(crate) fn build_text_content<'a>(&self, doc: &'a Document, buf: &'a mut String) {
+    /// Returns content if node is `Text`, `CData`, `ExternalText` or `PI`.
+    /// If node is `Element`, return [Element::text_content()]
+    ///
+    /// Implementation of [Node.textContent](https://developer.mozilla.org/en-US/docs/Web/API/Node/textContent)
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    /// Use [`Node::try_text_content()`] to get an error instead.
+    pub fn text_content(&self, doc: &Document) -> String {
         match self {
-            Node::Element(elem) => elem.build_text_content(doc, buf),
-            Node::Text(text) => buf.push_str(text),
-            Node::CData(text) => buf.push_str(text),
-            Node::PI(text) => buf.push_str(text),
-            _ => {}
+            Node::Element(elem) => elem.text_content(doc),
+            Node::Text(text) | Node::CData(text) => text.to_string(),
+            Node::PI(text) => text.clone(),
+            Node::ExternalText(text) => read_external_text(text),
+            _ => String::new(),
         }
     }
 
-    /// Returns content if node is `Text`, `CData`, or `PI`.
-    /// If node is `Element`, return [Element::text_content()]
+    /// Same as [`Node::text_content()`], but returns an error instead of panicking.
     ///
-    /// Implementation of [Node.textContent](https://developer.mozilla.org/en-US/docs/Web/API/Node/textContent)
-    pub fn text_content(&self, doc: &Document) -> String {
-        let mut buf = String::new();
-        self.build_text_content(doc, &mut buf);
-        buf
+    /// # Errors
+    /// - [`Error::Io`]: The file of a [`Node::ExternalText`] can't be read.
+    pub fn try_text_content(&self, doc: &Document) -> Result<String> {
+        self.check_valid(doc)?;
+        match self {
+            Node::Element(elem) => elem.try_text_content(doc),
+            Node::ExternalText(text) => text.read_to_string(),
+            _ => Ok(self.text_content(doc)),
+        }
+    }
+
+    // Returns `Error::ElementNotFound` if the node is an element that isn't valid in `doc`.
+    pub(crate) fn check_valid(&self, doc: &Document) -> Result<()> {
+        match self {
+            Node::Element(elem) => elem.check_valid(doc),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the node is `Text`, `CData` or `ExternalText` with only XML whitespace
+    /// (space, tab, `\r` and `\n`), as the parser defines it for [`ReadOptions::ignore_whitespace_only`].
+    /// Empty text counts as whitespace only.
+    ///
+    /// An `Element` returns `true` if its text content is whitespace only,
+    /// which is `!elem.has_text_content(doc, true)`. Other nodes return `false`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    pub fn is_whitespace_only(&self, doc: &Document) -> bool {
+        match self {
+            Node::Element(elem) => !elem.has_text_content(doc, true),
+            Node::Text(text) | Node::CData(text) => is_whitespace(text),
+            Node::ExternalText(text) => text.is_whitespace_only().unwrap_or_else(|err| {
+                panic!(
+                    "Cannot read external text at {}: {}",
+                    text.path().display(),
+                    err
+                )
+            }),
+            _ => false,
+        }
     }
 }
 
+pub(crate) fn read_external_text(text: &ExternalText) -> String {
+    text.read_to_string().unwrap_or_else(|err| {
+        panic!(
+            "Cannot read external text at {}: {}",
+            text.path().display(),
+            err
+        )
+    })
+}
+
 /// Represents a XML document or a document fragment.
 ///
 /// To build a document from scratch, use [`Document::new`].
@@ -105,29 +202,124 @@ impl Node {
 /// ```
 ///
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Document {
-    pub(crate) counter: usize, // == self.store.len()
-    pub(crate) store: Vec<ElementData>,
+    pub(crate) store: ElementArena,
     container: Element,
 
     pub(crate) version: String,
-    pub(crate) standalone: bool,
+    pub(crate) standalone: Option<bool>,
+    // XML declaration as it was in the parsed source. See `DeclPolicy::Verbatim`.
+    pub(crate) raw_decl: Option<String>,
+    pub(crate) fragment: bool,
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    // Start tag source of elements whose tag wasn't modified since parsing. See `ReadOptions::high_fidelity`.
+    pub(crate) raw_start_tags: HashMap<Element, String>,
+    // Elements whose namespace declarations came after an attribute in the parsed source.
+    // Only recorded with `ReadOptions::high_fidelity`. See `NsPosition::AsParsed`.
+    pub(crate) namespace_decls_last: HashSet<Element>,
+    // Names of attributes and namespace declarations (`xmlns:p`) quoted with `'` in the parsed source.
+    // Only recorded with `ReadOptions::high_fidelity`. See `QuoteStyle::Preserve`.
+    pub(crate) single_quoted: HashMap<Element, Vec<String>>,
+    // Elements whose empty text child was added by the parser. See `ReadOptions::empty_text_node`.
+    pub(crate) synthesized_empty_text: HashSet<Element>,
+    pub(crate) read_opts: Option<ReadOptions>,
+    pub(crate) parse_metrics: Option<ParseMetrics>,
+    // Incremented when an element is modified. See `Document::revision`.
+    pub(crate) revision: u64,
+    // Values shared by nodes and attributes. See `ReadOptions::dedup_values`.
+    pub(crate) values: ValuePool,
+    // Namespaces declared when needed. See `Document::register_namespace`.
+    registered_namespaces: HashMap<String, String>,
+    // Frozen template roots, indexed by `TemplateId`. See `Document::create_template`.
+    templates: Vec<Element>,
+    prolog_policy: PrologPolicy,
+    validator: ValidatorSlot,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Id of a template created with [`Document::create_template()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TemplateId(usize);
+
+/// How root nodes added with [`Document::push_root_node()`] and [`Document::insert_root_node()`]
+/// are checked. See [`Document::set_prolog_policy()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrologPolicy {
+    /// Keep the DOCTYPE before the root element, and allow only one DOCTYPE.
+    /// Not applied in [fragment mode](Document::set_fragment_mode).
+    Strict,
+    /// Add root nodes where they are asked to be, without checks.
+    #[default]
+    Permissive,
 }
 
 impl Document {
     /// Create a blank new xml document.
     pub fn new() -> Document {
-        let (container, container_data) = Element::container();
+        let (store, container) = ElementArena::new();
         Document {
-            counter: 1, // because container is id 0
-            store: vec![container_data],
+            store,
             container,
             version: String::from("1.0"),
-            standalone: false,
+            standalone: None,
+            raw_decl: None,
+            fragment: false,
+            diagnostics: Vec::new(),
+            raw_start_tags: HashMap::new(),
+            namespace_decls_last: HashSet::new(),
+            single_quoted: HashMap::new(),
+            synthesized_empty_text: HashSet::new(),
+            read_opts: None,
+            parse_metrics: None,
+            revision: 0,
+            values: ValuePool::default(),
+            registered_namespaces: HashMap::new(),
+            templates: Vec::new(),
+            prolog_policy: PrologPolicy::default(),
+            validator: ValidatorSlot(None),
         }
     }
 
+    // Make the document the same as `Document::new()`, keeping the capacity of its storage.
+    // The revision keeps counting up, so cursors of the old content become stale,
+    // and elements of the old content aren't valid anymore.
+    pub(crate) fn clear(&mut self) {
+        self.store.clear();
+        self.container = self.store.container();
+        self.version.clear();
+        self.version.push_str("1.0");
+        self.standalone = None;
+        self.raw_decl = None;
+        self.fragment = false;
+        self.diagnostics.clear();
+        self.raw_start_tags.clear();
+        self.namespace_decls_last.clear();
+        self.single_quoted.clear();
+        self.synthesized_empty_text.clear();
+        self.read_opts = None;
+        self.parse_metrics = None;
+        self.values.clear();
+        self.registered_namespaces.clear();
+        self.templates.clear();
+        self.prolog_policy = PrologPolicy::default();
+        self.validator = ValidatorSlot(None);
+    }
+
+    // Same as `clear`, for a document that will replace `previous`.
+    // Cursors and elements of `previous` aren't valid in this document either.
+    pub(crate) fn clear_after(&mut self, previous: &Document) {
+        self.clear();
+        self.store.follow(&previous.store);
+        self.container = self.store.container();
+        self.revision = self.revision.max(previous.revision) + 1;
+    }
+
     /// Get 'container' element of Document.
     ///
     /// The document uses an invisible 'container' element
@@ -150,200 +342,2229 @@ impl Document {
         self.store.len() == 1
     }
 
+    /// Get `standalone` value of the XML declaration.
+    ///
+    /// `None` if the declaration doesn't have `standalone`.
+    pub fn standalone(&self) -> Option<bool> {
+        self.standalone
+    }
+
+    /// Set `standalone` value of the XML declaration.
+    /// If `None`, `standalone` is not written.
+    ///
+    /// This discards the original declaration kept for [`DeclPolicy::Verbatim`].
+    pub fn set_standalone(&mut self, standalone: Option<bool>) {
+        self.standalone = standalone;
+        self.raw_decl = None;
+        self.revision += 1;
+    }
+
+    /// The XML declaration as it was in the parsed source, such as `<?xml version='1.0'?>`.
+    ///
+    /// `None` if the document wasn't parsed, didn't have a declaration,
+    /// or [`Document::set_standalone()`] was called.
+    pub fn raw_decl(&self) -> Option<&str> {
+        self.raw_decl.as_deref()
+    }
+
+    /// Returns `true` if the document is in fragment mode.
+    ///
+    /// See [`Document::set_fragment_mode()`].
+    pub fn is_fragment(&self) -> bool {
+        self.fragment
+    }
+
+    /// A document in fragment mode is written without the XML declaration,
+    /// even if [`WriteOptions::write_decl`] is `true`.
+    /// Use it for snippets that are included in other documents.
+    ///
+    /// A parsed document is in fragment mode if it didn't have an XML declaration.
+    /// Documents may have multiple root elements in either mode.
+    pub fn set_fragment_mode(&mut self, fragment: bool) {
+        self.fragment = fragment;
+    }
+
+    /// Problems the parser recovered from while parsing this document.
+    ///
+    /// Always empty unless the document was parsed with [`ReadOptions::lenient`]
+    /// or [`ReadOptions::undefined_entities`] other than `Error`.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Options the document was parsed with, or `None` if it wasn't parsed.
+    ///
+    /// ```
+    /// use xml_doc::{Document, ReadOptions};
+    ///
+    /// let doc = Document::parse_str("<?xml version='1.0'?><a> b </a>").unwrap();
+    /// assert_eq!(doc.read_options(), Some(&ReadOptions::default()));
+    /// assert_eq!(Document::new().read_options(), None);
+    /// ```
+    pub fn read_options(&self) -> Option<&ReadOptions> {
+        self.read_opts.as_ref()
+    }
+
+    /// Measurements of the document made while parsing it,
+    /// or `None` if it wasn't parsed with [`ReadOptions::collect_metrics`].
+    ///
+    /// ```
+    /// use xml_doc::{Document, ReadOptions};
+    ///
+    /// let opts = ReadOptions { collect_metrics: true, ..ReadOptions::default() };
+    /// let doc = Document::parse_str_with_opts(r#"<?xml version="1.0"?><a><b c="long"/></a>"#, opts).unwrap();
+    /// let metrics = doc.parse_metrics().unwrap();
+    /// assert_eq!(metrics.max_depth, 2);
+    /// assert_eq!(metrics.max_attribute_value_len, 4);
+    /// ```
+    pub fn parse_metrics(&self) -> Option<&ParseMetrics> {
+        self.parse_metrics.as_ref()
+    }
+
+    /// A number that changes whenever the name, attributes, namespace declarations
+    /// or children of an element are modified, including by parsing,
+    /// or when the XML declaration is changed with [`Document::set_standalone()`].
+    ///
+    /// Getting mutable access, such as with [`Element::mut_attributes()`], counts as a modification.
+    /// Used by [`ChildCursor`](crate::ChildCursor) to detect modifications.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
     /// Get root nodes of document.
     pub fn root_nodes(&self) -> &Vec<Node> {
         self.container.children(self)
     }
 
     /// Get first root node that is an element.
+    ///
+    /// Other root elements are ignored. See [`Document::root_elements()`] and [`Document::assert_single_root()`].
     pub fn root_element(&self) -> Option<Element> {
-        self.container.child_elements(self).get(0).copied()
+        self.container.child_elements(self).first().copied()
     }
 
-    /// Push a node to end of root nodes.
-    /// If doc has no [`Element`], pushing a [`Node::Element`] is
-    /// equivalent to setting it as root element.
-    pub fn push_root_node(&mut self, node: Node) -> Result<()> {
-        let elem = self.container;
-        elem.push_child(self, node)
+    /// Get all root nodes that are elements.
+    ///
+    /// A well-formed document has only one, but this crate allows any number of root elements.
+    pub fn root_elements(&self) -> Vec<Element> {
+        self.container.child_elements(self)
     }
-}
 
-/// &nbsp;
-/// # Parsing
-///
-/// Below are methods for parsing xml.
-/// Parsing from string, file, and reader is supported.
-///
-/// Call `parse_*_with_opts` with custom [`ReadOptions`] to change parser behaviour.
-/// Otherwise, [`ReadOptions::default()`] is used.
-///
-/// # Errors
-/// - [`Error::CannotDecode`]: Could not decode XML. XML declaration may have invalid encoding value.
-/// - [`Error::MalformedXML`]: Could not read XML.
-/// - [`Error::Io`]: IO Error
-impl Document {
-    pub fn parse_str(str: &str) -> Result<Document> {
-        DocumentParser::parse_reader(str.as_bytes(), ReadOptions::default())
+    /// Get the root element, checking that it is the only one.
+    ///
+    /// # Errors
+    /// - [`Error::MultipleRoots`]: The document has more than one root element.
+    /// - [`Error::MissingChild`]: The document has no root element.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Error, ReadOptions};
+    ///
+    /// let opts = ReadOptions { require_decl: false, ..ReadOptions::default() };
+    /// let doc = Document::parse_str_with_opts("<log/><log/>", opts).unwrap();
+    /// assert!(matches!(doc.assert_single_root(), Err(Error::MultipleRoots { count: 2 })));
+    /// ```
+    pub fn assert_single_root(&self) -> Result<Element> {
+        let mut roots = self.container.child_elements_iter(self);
+        match (roots.next(), roots.len()) {
+            (Some(root), 0) => Ok(root),
+            (Some(_), others) => Err(Error::MultipleRoots { count: others + 1 }),
+            (None, _) => Err(Error::MissingChild {
+                parent: String::new(),
+                element_path: self.container.path(self),
+                wanted: None,
+            }),
+        }
     }
-    pub fn parse_str_with_opts(str: &str, opts: ReadOptions) -> Result<Document> {
-        DocumentParser::parse_reader(str.as_bytes(), opts)
+
+    /// Find the first element with name `name` in document order,
+    /// searching every root element and its descendants.
+    ///
+    /// Unlike [`Element::find()`], descendants are searched, not only direct children.
+    pub fn find_descendant(&self, name: &str) -> Option<Element> {
+        self.container
+            .descendants(self)
+            .find(|elem| elem.name_is(self, name))
     }
 
-    pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Document> {
-        let file = File::open(path)?;
-        DocumentParser::parse_reader(file, ReadOptions::default())
+    /// Find all elements with name `name` in document order,
+    /// searching every root element and its descendants.
+    ///
+    /// Unlike [`Element::find_all()`], descendants are searched, not only direct children.
+    pub fn find_all_descendants(&self, name: &str) -> Vec<Element> {
+        self.container
+            .descendants(self)
+            .filter(|elem| elem.name_is(self, name))
+            .collect()
     }
-    pub fn parse_file_with_opts<P: AsRef<Path>>(path: P, opts: ReadOptions) -> Result<Document> {
-        let file = File::open(path)?;
-        DocumentParser::parse_reader(file, opts)
+
+    /// Find an element by a `/`-separated path of element names, starting with the root element.
+    ///
+    /// A leading `/` is allowed. See [`Element::find_path()`].
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><config><db><host>local</host></db></config>"#).unwrap();
+    /// let host = doc.find_path("config/db/host").unwrap();
+    /// assert_eq!(host.text_content(&doc), "local");
+    /// assert_eq!(doc.find_path("/config/db/host"), Some(host));
+    /// assert_eq!(doc.find_path("db/host"), None);
+    /// ```
+    pub fn find_path(&self, path: &str) -> Option<Element> {
+        self.container.find_path(self, path)
     }
 
-    pub fn parse_reader<R: Read>(reader: R) -> Result<Document> {
-        DocumentParser::parse_reader(reader, ReadOptions::default())
+    /// Find all elements matching the last segment of `path`. See [`Element::find_all_path()`].
+    pub fn find_all_path(&self, path: &str) -> Vec<Element> {
+        self.container.find_all_path(self, path)
     }
-    pub fn parse_reader_with_opts<R: Read>(reader: R, opts: ReadOptions) -> Result<Document> {
-        DocumentParser::parse_reader(reader, opts)
+
+    /// Index of [`Document::root_element()`] in [`Document::root_nodes()`].
+    pub fn root_element_index(&self) -> Option<usize> {
+        self.root_nodes()
+            .iter()
+            .position(|node| matches!(node, Node::Element(_)))
     }
-}
 
-/// Options when writing XML.
-pub struct WriteOptions {
-    /// Byte character to indent with. (default: `b' '`)
-    pub indent_char: u8,
-    /// How many indent_char should be used for indent. (default: 2)
-    pub indent_size: usize,
-    /// XML declaration should be written at the top. (default: `true`)
-    pub write_decl: bool,
-}
+    /// Root nodes before the root element, such as the DOCTYPE, comments and processing instructions.
+    /// All root nodes if there is no root element.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Node};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?>
+    /// <?xml-stylesheet href="style.css"?><!-- head --><root/><!-- tail -->"#).unwrap();
+    /// assert_eq!(doc.root_element_index(), Some(2));
+    /// assert_eq!(doc.nodes_before_root().len(), 2);
+    /// assert!(matches!(doc.nodes_after_root(), [Node::Comment(c)] if c == " tail "));
+    /// ```
+    pub fn nodes_before_root(&self) -> &[Node] {
+        let nodes = self.root_nodes();
+        &nodes[..self.root_element_index().unwrap_or(nodes.len())]
+    }
 
-impl WriteOptions {
-    pub fn default() -> WriteOptions {
-        WriteOptions {
-            indent_char: b' ',
-            indent_size: 2,
-            write_decl: true,
+    /// Root nodes after the root element. Empty if there is no root element.
+    pub fn nodes_after_root(&self) -> &[Node] {
+        let nodes = self.root_nodes();
+        match self.root_element_index() {
+            Some(index) => &nodes[index + 1..],
+            None => &[],
         }
     }
-}
 
-/// &nbsp;
-/// # Writing
-///
-/// Below are methods for writing xml.
-/// The XML will be written in UTF-8.
-impl Document {
-    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        self.write_file_with_opts(path, WriteOptions::default())
+    /// Push a node to end of root nodes.
+    /// If doc has no [`Element`], pushing a [`Node::Element`] is
+    /// equivalent to setting it as root element.
+    ///
+    /// A [`Node::Element`] is inserted right after the last root element,
+    /// before trailing nodes such as comments.
+    /// With [`PrologPolicy::Strict`], a [`Node::DocType`] is inserted before the first element.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidProlog`]: With [`PrologPolicy::Strict`], the document already has a DOCTYPE.
+    /// - Errors of [`Element::push_child()`].
+    pub fn push_root_node(&mut self, node: Node) -> Result<()> {
+        let nodes = self.root_nodes();
+        let is_element = |node: &Node| matches!(node, Node::Element(_));
+        let index = match node {
+            Node::Element(_) => nodes
+                .iter()
+                .rposition(is_element)
+                .map_or(nodes.len(), |index| index + 1),
+            Node::DocType(_) if self.checks_prolog() => {
+                nodes.iter().position(is_element).unwrap_or(nodes.len())
+            }
+            _ => nodes.len(),
+        };
+        self.insert_root_node(index, node)
     }
-    pub fn write_file_with_opts<P: AsRef<Path>>(&self, path: P, opts: WriteOptions) -> Result<()> {
-        let mut file = File::open(path)?;
-        self.write_with_opts(&mut file, opts)
+
+    /// Insert a node at `index` of root nodes.
+    ///
+    /// # Errors
+    /// - [`Error::IndexOutOfBounds`]: `index > self.root_nodes().len()`
+    /// - [`Error::InvalidProlog`]: With [`PrologPolicy::Strict`], the document already has a DOCTYPE,
+    ///   or the DOCTYPE would come after an element.
+    /// - Errors of [`Element::insert_child()`].
+    pub fn insert_root_node(&mut self, index: usize, node: Node) -> Result<()> {
+        let len = self.root_nodes().len();
+        if index > len {
+            return Err(Error::IndexOutOfBounds { index, len });
+        }
+        if self.checks_prolog() {
+            let nodes = self.root_nodes();
+            let is_doctype = |node: &Node| matches!(node, Node::DocType(_));
+            let is_element = |node: &Node| matches!(node, Node::Element(_));
+            match node {
+                Node::DocType(_) if nodes.iter().any(is_doctype) => {
+                    return Err(Error::InvalidProlog(
+                        "Document already has a DOCTYPE".to_string(),
+                    ));
+                }
+                Node::DocType(_) if nodes[..index].iter().any(is_element) => {
+                    return Err(Error::InvalidProlog(
+                        "DOCTYPE must come before the root element".to_string(),
+                    ));
+                }
+                Node::Element(_) if nodes[index..].iter().any(is_doctype) => {
+                    return Err(Error::InvalidProlog(
+                        "Element must come after the DOCTYPE".to_string(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        let elem = self.container;
+        elem.insert_child(self, index, node)
     }
 
-    pub fn write_str(&self) -> Result<String> {
-        self.write_str_with_opts(WriteOptions::default())
+    pub fn prolog_policy(&self) -> PrologPolicy {
+        self.prolog_policy
     }
-    pub fn write_str_with_opts(&self, opts: WriteOptions) -> Result<String> {
-        let mut buf: Vec<u8> = Vec::with_capacity(200);
-        self.write_with_opts(&mut buf, opts)?;
-        Ok(String::from_utf8(buf)?)
+
+    /// Set how [`Document::push_root_node()`] and [`Document::insert_root_node()`] check the prolog.
+    /// Nodes already in the document are not checked. (default: [`PrologPolicy::Permissive`])
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element, Node, PrologPolicy};
+    ///
+    /// let mut doc = Document::new();
+    /// doc.set_prolog_policy(PrologPolicy::Strict);
+    /// let root = Element::new(&mut doc, "html");
+    /// doc.push_root_node(root.as_node()).unwrap();
+    /// doc.push_root_node(Node::DocType("html".to_string())).unwrap();
+    /// assert_eq!(doc.write_str().unwrap(), r#"<?xml version="1.0" encoding="UTF-8"?>
+    /// <!DOCTYPE html>
+    /// <html/>"#);
+    /// assert!(doc.push_root_node(Node::DocType("html".to_string())).is_err());
+    /// ```
+    pub fn set_prolog_policy(&mut self, policy: PrologPolicy) {
+        self.prolog_policy = policy;
     }
 
-    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
-        self.write_with_opts(writer, WriteOptions::default())
+    fn checks_prolog(&self) -> bool {
+        self.prolog_policy == PrologPolicy::Strict && !self.fragment
     }
-    pub fn write_with_opts(&self, writer: &mut impl Write, opts: WriteOptions) -> Result<()> {
-        let container = self.container();
-        let mut writer = Writer::new_with_indent(writer, opts.indent_char, opts.indent_size);
-        if opts.write_decl {
-            self.write_decl(&mut writer)?;
+
+    /// Rename namespace prefix `old` to `new` across the document.
+    ///
+    /// `xmlns:old` declarations, element names and attribute names are all rewritten,
+    /// so every name keeps resolving to the same namespace.
+    /// Returns the number of rewritten declarations, element names and attribute names.
+    ///
+    /// # Errors
+    /// - [`Error::PrefixCollision`]: `new` is already bound to a different namespace
+    ///   where a renamed name would now resolve it, an element would end up with
+    ///   two attributes of the same name, or either prefix is empty or reserved (`xml`, `xmlns`).
+    ///   The document is not modified.
+    pub fn rename_prefix(&mut self, old: &str, new: &str) -> Result<usize> {
+        if old == new {
+            return Ok(0);
         }
-        self.write_nodes(&mut writer, container.children(self))?;
-        writer.write_event(Event::Eof)?;
-        Ok(())
-    }
+        let reserved = |p: &str| p.is_empty() || p == "xml" || p == "xmlns";
+        if reserved(old) || reserved(new) {
+            return Err(Error::PrefixCollision(new.to_string()));
+        }
+        let elements = self.container.child_elements_recursive(self);
 
-    fn write_decl(&self, writer: &mut Writer<impl Write>) -> Result<()> {
-        let standalone = match self.standalone {
-            true => Some("yes".as_bytes()),
-            false => None,
-        };
-        writer.write_event(Event::Decl(BytesDecl::new(
-            self.version.as_bytes(),
-            Some("UTF-8".as_bytes()),
-            standalone,
-        )))?;
-        Ok(())
-    }
+        // Check that every name using `old` or `new` resolves to the same namespace after renaming.
+        for elem in &elements {
+            let decls = elem.namespace_decls(self);
+            if let (Some(old_ns), Some(new_ns)) = (decls.get(old), decls.get(new)) {
+                if old_ns != new_ns {
+                    return Err(Error::PrefixCollision(new.to_string()));
+                }
+            }
+            let mut prefixes = vec![elem.prefix(self)];
+            for key in elem.attributes(self).keys() {
+                let (prefix, name) = Element::separate_prefix_name(key);
+                if prefix == old && elem.attribute(self, &format!("{}:{}", new, name)).is_some() {
+                    return Err(Error::PrefixCollision(new.to_string()));
+                }
+                prefixes.push(prefix);
+            }
+            for prefix in prefixes {
+                if prefix != old && prefix != new {
+                    continue;
+                }
+                if elem.namespace_for_prefix(self, prefix)
+                    != self.renamed_namespace(*elem, old, new)
+                {
+                    return Err(Error::PrefixCollision(new.to_string()));
+                }
+            }
+        }
 
-    fn write_nodes(&self, writer: &mut Writer<impl Write>, nodes: &[Node]) -> Result<()> {
-        for node in nodes {
-            match node {
-                Node::Element(eid) => self.write_element(writer, *eid)?,
-                Node::Text(text) => {
-                    writer.write_event(Event::Text(BytesText::from_plain_str(text)))?
+        let mut count = 0;
+        for elem in elements {
+            let decls = elem.mut_namespace_decls(self);
+            if let Some((index, _, ns)) = decls.shift_remove_full(old) {
+                if !decls.contains_key(new) {
+                    decls.shift_insert(index, new.to_string(), ns);
                 }
-                Node::DocType(text) => writer.write_event(Event::DocType(
-                    BytesText::from_plain_str(&format!(" {}", text)), // add a whitespace before text
-                ))?,
-                // Comment, CData, and PI content is not escaped.
-                Node::Comment(text) => {
-                    writer.write_event(Event::Comment(BytesText::from_escaped_str(text)))?
+                count += 1;
+            }
+            if elem.prefix_is(self, old) {
+                elem.set_prefix(self, new);
+                count += 1;
+            }
+            if elem.uses_prefix(self, old) {
+                count += rename_keys(elem.mut_attributes(self), |key| {
+                    let (prefix, name) = Element::separate_prefix_name(key);
+                    (prefix == old).then(|| format!("{}:{}", new, name))
+                });
+            }
+        }
+        Ok(count)
+    }
+
+    /// Rewrite namespace prefixes so that each namespace uses a single prefix across the document.
+    ///
+    /// `preferred` maps a namespace URI to the prefix it should use.
+    /// Other namespaces keep one of their current prefixes if it isn't taken,
+    /// or get a generated prefix such as `ns0`.
+    /// Prefixed declarations, element names and attribute names are rewritten,
+    /// so every name keeps its [`Element::expanded_name()`].
+    /// Declarations already in scope from an ancestor are removed.
+    /// The default namespace, unprefixed names and undeclared prefixes are left unchanged.
+    /// Template instances are materialized.
+    ///
+    /// Returns the number of rewritten element names and attribute names.
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xml_doc::Document;
+    ///
+    /// let xml = r#"<?xml version="1.0"?><a xmlns:ns0="urn:s"><ns1:b xmlns:ns1="urn:s" ns0:c="1"/></a>"#;
+    /// let mut doc = Document::parse_str(xml).unwrap();
+    /// let preferred = HashMap::from([("urn:s".to_string(), "s".to_string())]);
+    /// assert_eq!(doc.unify_namespace_prefixes(&preferred).unwrap(), 2);
+    /// let b = doc.root_element().unwrap().child_elements(&doc)[0];
+    /// assert_eq!(b.full_name(&doc), "s:b");
+    /// assert_eq!(b.attribute(&doc, "s:c"), Some("1"));
+    /// assert!(b.namespace_decls(&doc).is_empty());
+    /// ```
+    ///
+    /// # Errors
+    /// - [`Error::PrefixCollision`]: A preferred prefix is empty, reserved (`xml`, `xmlns`),
+    ///   preferred for two namespaces or used undeclared in the document,
+    ///   or an element would end up with two attributes of the same name.
+    ///   The document is not modified.
+    pub fn unify_namespace_prefixes(
+        &mut self,
+        preferred: &HashMap<String, String>,
+    ) -> Result<usize> {
+        let reserved = |p: &str| p.is_empty() || p == "xml" || p == "xmlns" || p.contains(':');
+        let elements = self.container.child_elements_recursive(self);
+
+        // Namespaces in order of first declaration, with the prefixes bound to them.
+        let mut namespaces: Vec<(&str, Vec<&str>)> = Vec::new();
+        // Prefixes that don't resolve to a namespace, so can't be bound.
+        let mut taken: HashSet<String> = HashSet::new();
+        // Namespace of the element name and of each attribute name, if prefixed.
+        let mut resolved = Vec::new();
+        for elem in &elements {
+            let mut decls: Vec<(&String, &String)> = elem
+                .namespace_decls(self)
+                .iter()
+                .filter(|(prefix, _)| !reserved(prefix))
+                .collect();
+            decls.sort_unstable();
+            for (prefix, ns) in decls {
+                match namespaces.iter_mut().find(|(uri, _)| uri == ns) {
+                    Some((_, prefixes)) => prefixes.push(prefix),
+                    None => namespaces.push((ns, vec![prefix])),
                 }
-                Node::CData(text) => {
-                    writer.write_event(Event::CData(BytesText::from_escaped_str(text)))?
+            }
+            let mut resolve = |prefix| {
+                if reserved(prefix) {
+                    return None;
                 }
-                Node::PI(text) => {
-                    writer.write_event(Event::PI(BytesText::from_escaped_str(text)))?
+                let ns = elem.namespace_for_prefix(self, prefix);
+                if ns.is_none() {
+                    taken.insert(prefix.to_string());
                 }
+                ns
             };
+            let elem_ns = resolve(elem.prefix(self));
+            let attrs: Vec<(&str, Option<&str>)> = elem
+                .attributes(self)
+                .keys()
+                .map(|key| (key.as_str(), resolve(Element::separate_prefix_name(key).0)))
+                .collect();
+            resolved.push((elem_ns, attrs));
         }
-        Ok(())
-    }
 
-    fn write_element(&self, writer: &mut Writer<impl Write>, element: Element) -> Result<()> {
-        let name_bytes = element.full_name(self).as_bytes();
-        let mut start = BytesStart::borrowed_name(name_bytes);
-        for (key, val) in element.attributes(self) {
-            let val = quick_xml::escape::escape(val.as_bytes());
-            start.push_attribute((key.as_bytes(), &val[..]));
+        // Choose a prefix for each namespace.
+        let mut chosen: HashMap<&str, String> = HashMap::new();
+        for (ns, prefix) in preferred {
+            if reserved(prefix) || !taken.insert(prefix.to_string()) {
+                return Err(Error::PrefixCollision(prefix.to_string()));
+            }
+            chosen.insert(ns, prefix.to_string());
         }
-        for (prefix, val) in element.namespace_decls(self) {
-            let attr_name = if prefix.is_empty() {
-                "xmlns".to_string()
-            } else {
-                format!("xmlns:{}", prefix)
+        let mut generated = 0;
+        for (ns, prefixes) in &namespaces {
+            if chosen.contains_key(ns) {
+                continue;
+            }
+            let prefix = match prefixes.iter().find(|prefix| !taken.contains(**prefix)) {
+                Some(prefix) => prefix.to_string(),
+                None => loop {
+                    let prefix = format!("ns{}", generated);
+                    generated += 1;
+                    if !taken.contains(&prefix) {
+                        break prefix;
+                    }
+                },
             };
-            let val = quick_xml::escape::escape(val.as_bytes());
-            start.push_attribute((attr_name.as_bytes(), &val[..]));
+            taken.insert(prefix.clone());
+            chosen.insert(ns, prefix);
         }
-        if element.has_children(self) {
-            writer.write_event(Event::Start(start))?;
-            self.write_nodes(writer, element.children(self))?;
-            writer.write_event(Event::End(BytesEnd::borrowed(name_bytes)))?;
-        } else {
-            writer.write_event(Event::Empty(start))?;
+
+        // Check for duplicate attributes before modifying anything.
+        let mut renames = Vec::new();
+        for (elem, (elem_ns, attrs)) in elements.iter().zip(resolved) {
+            let (prefix, _) = elem.prefix_name(self);
+            let new_prefix = elem_ns
+                .map(|ns| chosen[ns].clone())
+                .filter(|new| new != prefix);
+            let mut keys = HashSet::new();
+            let mut new_attrs = HashMap::new();
+            for (key, ns) in attrs {
+                let (prefix, name) = Element::separate_prefix_name(key);
+                let new_key = match ns {
+                    Some(ns) if chosen[ns] != prefix => format!("{}:{}", chosen[ns], name),
+                    _ => key.to_string(),
+                };
+                if new_key != key {
+                    new_attrs.insert(key.to_string(), new_key.clone());
+                }
+                if !keys.insert(new_key) {
+                    return Err(Error::PrefixCollision(prefix.to_string()));
+                }
+            }
+            renames.push((*elem, new_prefix, new_attrs));
         }
-        Ok(())
-    }
-}
+        let chosen: HashMap<String, String> = chosen
+            .into_iter()
+            .map(|(ns, prefix)| (ns.to_string(), prefix))
+            .collect();
 
-impl FromStr for Document {
-    type Err = Error;
+        let mut count = 0;
+        for (elem, new_prefix, new_attrs) in renames {
+            if let Some(prefix) = new_prefix {
+                elem.set_prefix(self, prefix);
+                count += 1;
+            }
+            if !new_attrs.is_empty() {
+                count += rename_keys(elem.mut_attributes(self), |key| new_attrs.get(key).cloned());
+            }
 
-    fn from_str(s: &str) -> Result<Document> {
-        Document::parse_str(s)
+            // Ancestors are rewritten first, so a declaration already in scope can be dropped.
+            let parent = elem.parent(self).unwrap_or(self.container);
+            let mut decls = IndexMap::new();
+            for (prefix, ns) in elem.namespace_decls(self) {
+                if reserved(prefix) {
+                    decls.insert(prefix.clone(), ns.clone());
+                    continue;
+                }
+                let prefix = &chosen[ns];
+                if parent.namespace_for_prefix(self, prefix) != Some(ns) {
+                    decls.insert(prefix.clone(), ns.clone());
+                }
+            }
+            if &decls != elem.namespace_decls(self) {
+                *elem.mut_namespace_decls(self) = decls;
+            }
+        }
+        Ok(count)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn test_add_element() {
-        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+    /// Concatenate all text of the document, with `separator` between each text node.
+    /// Useful for search indexing.
+    ///
+    /// See [`Element::text_content_sep()`] for what is included.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str("<?xml version='1.0'?><doc><p>one</p><!--note--><p>two</p></doc>").unwrap();
+    /// assert_eq!(doc.extract_text(" "), "one two");
+    /// ```
+    pub fn extract_text(&self, separator: &str) -> String {
+        self.container.text_content_sep(self, separator)
+    }
+
+    /// Merge adjacent text nodes and remove empty ones in the whole document.
+    /// See [`Element::normalize()`].
+    pub fn normalize(&mut self) {
+        let container = self.container;
+        container.normalize(self);
+    }
+
+    /// Returns `true` if both documents have the same tree content.
+    ///
+    /// The XML declaration is not compared. [`Node::ExternalText`] equals a [`Node::Text`] with the same text,
+    /// or a [`Node::CData`] if it was a CDATA section.
+    pub fn content_eq(&self, other: &Document) -> bool {
+        nodes_eq(self, self.root_nodes(), other, other.root_nodes())
+    }
+
+    /// Approximate number of bytes used by the document, including heap allocations.
+    ///
+    /// This is an estimate for comparing documents or options,
+    /// and doesn't include allocator overhead.
+    pub fn estimate_memory_usage(&self) -> usize {
+        let mut size = std::mem::size_of::<Document>();
+        size += self.store.heap_size();
+        size += self.version.capacity();
+        size += self.diagnostics.capacity() * std::mem::size_of::<Diagnostic>();
+        for diagnostic in &self.diagnostics {
+            size += diagnostic.message.capacity();
+        }
+        size += self.raw_start_tags.capacity() * std::mem::size_of::<(Element, String)>();
+        for tag in self.raw_start_tags.values() {
+            size += tag.capacity();
+        }
+        size += self.namespace_decls_last.capacity() * std::mem::size_of::<Element>();
+        size += self.single_quoted.capacity() * std::mem::size_of::<(Element, Vec<String>)>();
+        for names in self.single_quoted.values() {
+            size += names.capacity() * std::mem::size_of::<String>();
+            size += names.iter().map(String::capacity).sum::<usize>();
+        }
+        size += self.synthesized_empty_text.capacity() * std::mem::size_of::<Element>();
+        size += self.templates.capacity() * std::mem::size_of::<Element>();
+        size += self.values.heap_size();
+        size
+    }
+
+    /// Count nodes in the document tree.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><a id="1"><b>text</b><!--c--></a>"#).unwrap();
+    /// let stats = doc.stats();
+    /// assert_eq!((stats.elements, stats.attributes, stats.texts, stats.comments), (2, 1, 1, 1));
+    /// ```
+    pub fn stats(&self) -> DocumentStats {
+        let mut stats = DocumentStats::default();
+        for node in self.container.descendant_nodes(self) {
+            match node {
+                Node::Element(elem) => {
+                    stats.elements += 1;
+                    stats.attributes += elem.attributes(self).len();
+                    stats.namespace_decls += elem.namespace_decls(self).len();
+                }
+                Node::Text(_) | Node::CData(_) | Node::ExternalText(_) => stats.texts += 1,
+                Node::Comment(_) => stats.comments += 1,
+                Node::PI(_) | Node::DocType(_) => stats.others += 1,
+            }
+        }
+        stats
+    }
+
+    /// Freeze a copy of `element` and its descendants as a template,
+    /// to be stamped with [`Element::push_template_instance()`].
+    ///
+    /// `element` itself is left unchanged. The copy has no parent and isn't part of the document tree.
+    /// Details kept by [`ReadOptions::high_fidelity`] are not copied, so instances are written like new elements.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><root><row><cell/></row></root>"#).unwrap();
+    /// let root = doc.root_element().unwrap();
+    /// let row = root.find(&doc, "row").unwrap();
+    /// let template = doc.create_template(row);
+    /// for _ in 0..2 {
+    ///     root.push_template_instance(&mut doc, template);
+    /// }
+    /// assert_eq!(root.child_elements(&doc).len(), 3);
+    /// assert_eq!(root.descendants(&doc).filter(|e| e.name_is(&doc, "cell")).count(), 3);
+    /// ```
+    pub fn create_template(&mut self, element: Element) -> TemplateId {
+        let root = element.deep_copy(self);
+        root.share_values(self);
+        for elem in std::iter::once(root).chain(root.child_elements_recursive(self)) {
+            self.raw_start_tags.remove(&elem);
+            self.namespace_decls_last.remove(&elem);
+            self.single_quoted.remove(&elem);
+        }
+        self.templates.push(root);
+        TemplateId(self.templates.len() - 1)
+    }
+
+    /// Copy `elem` and its descendants from `source` into this document, returning the copy.
+    ///
+    /// The copy has no parent, and can be pushed to an element of this document.
+    /// Namespace declarations of `elem` are copied, but not those of its ancestors.
+    /// Use [`Document::import_with_namespaces()`] to also copy declarations in scope.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let source = Document::parse_str(r#"<?xml version="1.0"?><a><item id="1">text</item></a>"#).unwrap();
+    /// let item = source.root_element().unwrap().find(&source, "item").unwrap();
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><b/>"#).unwrap();
+    /// let root = doc.root_element().unwrap();
+    /// let copy = doc.import_element(&source, item);
+    /// root.push_child(&mut doc, copy.as_node()).unwrap();
+    /// assert_eq!(root.find(&doc, "item").unwrap().text_content(&doc), "text");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `elem` is the container of `source`. Use [`Document::clone()`] to copy the whole document.
+    pub fn import_element(&mut self, source: &Document, elem: Element) -> Element {
+        elem.assert_valid(source);
+        assert!(
+            !elem.is_container(),
+            "Container element can't be imported, clone the document instead"
+        );
+        elem.import_copy(source, self)
+    }
+
+    /// Same as [`Document::import_element()`], but namespace declarations in scope of `elem`
+    /// are also declared on the copy, so its prefixes keep their namespaces.
+    ///
+    /// Declarations of `elem` are kept, followed by those of its ancestors that `elem` doesn't shadow.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `elem` is the container of `source`.
+    pub fn import_with_namespaces(&mut self, source: &Document, elem: Element) -> Element {
+        let copy = self.import_element(source, elem);
+        let mut ancestor = elem.parent(source);
+        while let Some(parent) = ancestor {
+            for (prefix, namespace) in parent.namespace_decls(source) {
+                if !copy.namespace_decls(self).contains_key(prefix) {
+                    copy.set_namespace_decl(self, prefix.as_str(), namespace.as_str());
+                }
+            }
+            ancestor = parent.parent(source);
+        }
+        copy
+    }
+
+    // Panics if `template` is from another document.
+    pub(crate) fn template_root(&self, template: TemplateId) -> Element {
+        self.templates[template.0]
+    }
+
+    /// Register the namespace of `prefix`, to be declared when an undeclared prefix is written.
+    ///
+    /// Registered namespaces aren't declared in the document itself.
+    /// They are only used by [`WriteOptions::declare_missing_prefixes`].
+    pub fn register_namespace<S, T>(&mut self, prefix: S, namespace: T)
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        self.registered_namespaces
+            .insert(prefix.into(), namespace.into());
+    }
+
+    /// Get the namespace registered for `prefix` with [`Document::register_namespace()`].
+    pub fn registered_namespace(&self, prefix: &str) -> Option<&str> {
+        self.registered_namespaces.get(prefix).map(|ns| ns.as_str())
+    }
+
+    /// Check that every prefix of element and attribute names is declared in scope.
+    ///
+    /// Registered namespaces don't count as declared.
+    ///
+    /// # Errors
+    /// - [`Error::UndeclaredPrefix`]: The first element in document order that has
+    ///   an undeclared prefix. See [`Element::undeclared_prefixes()`].
+    ///
+    /// ```
+    /// use xml_doc::{Document, Error};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/></a>"#).unwrap();
+    /// assert!(doc.check_prefixes().is_ok());
+    /// let b = doc.root_element().unwrap().find(&doc, "b").unwrap();
+    /// b.set_attribute(&mut doc, "foo:bar", "1");
+    /// assert!(matches!(doc.check_prefixes(), Err(Error::UndeclaredPrefix { .. })));
+    /// ```
+    pub fn check_prefixes(&self) -> Result<()> {
+        for elem in self.container.descendants(self) {
+            if let Some(prefix) = elem.undeclared_prefixes(self).first() {
+                return Err(Error::UndeclaredPrefix {
+                    prefix: prefix.to_string(),
+                    element_path: elem.path(self),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Declarations to add to `element` for `WriteOptions::declare_missing_prefixes`.
+    // An undeclared prefix is declared on the outermost element using it,
+    // since it is also undeclared on every ancestor.
+    fn missing_prefix_decls(&self, element: Element) -> Result<Vec<(&str, &str)>> {
+        let mut decls = Vec::new();
+        for prefix in element.undeclared_prefixes(self) {
+            if element
+                .ancestors(self)
+                .any(|ancestor| ancestor.uses_prefix(self, prefix))
+            {
+                continue;
+            }
+            match self.registered_namespace(prefix) {
+                Some(namespace) => decls.push((prefix, namespace)),
+                None => {
+                    return Err(Error::UndeclaredPrefix {
+                        prefix: prefix.to_string(),
+                        element_path: element.path(self),
+                    })
+                }
+            }
+        }
+        Ok(decls)
+    }
+
+    // Declarations for `WriteOptions::hoist_namespace_decls`, root elements' first, then in document order.
+    // An unprefixed element without a default namespace binds the default prefix to no namespace,
+    // which isn't declared.
+    fn hoisted_namespace_decls(&self) -> Result<Vec<(&str, &str)>> {
+        let mut decls: IndexMap<&str, &str> = IndexMap::new();
+        let mut no_default = false;
+        let roots = self.container.child_elements_iter(self);
+        let elements = roots.chain(self.container.descendants(self));
+        for elem in elements {
+            let mut bindings: Vec<(&str, &str)> = elem
+                .namespace_decls(self)
+                .iter()
+                .map(|(prefix, ns)| (prefix.as_str(), ns.as_str()))
+                .collect();
+            if elem.prefix(self).is_empty() && elem.namespace(self).unwrap_or_default().is_empty() {
+                no_default = true;
+                bindings.push(("", ""));
+            }
+            for (prefix, ns) in bindings {
+                let first = match decls.get(prefix) {
+                    Some(first) => *first,
+                    None if prefix.is_empty() && no_default => "",
+                    None => {
+                        decls.insert(prefix, ns);
+                        continue;
+                    }
+                };
+                if first != ns {
+                    return Err(Error::PrefixConflict {
+                        prefix: prefix.to_string(),
+                        first: first.to_string(),
+                        second: ns.to_string(),
+                        element_path: elem.path(self),
+                    });
+                }
+            }
+        }
+        Ok(decls.into_iter().collect())
+    }
+
+    // Namespace that prefix `new` would resolve to at `elem` after renaming `old` to `new`.
+    fn renamed_namespace(&self, elem: Element, old: &str, new: &str) -> Option<&str> {
+        let mut elem = elem;
+        loop {
+            let decls = elem.namespace_decls(self);
+            if let Some(ns) = decls.get(old).or_else(|| decls.get(new)) {
+                return Some(ns);
+            }
+            elem = elem.parent(self)?;
+        }
+    }
+}
+
+/// A modification checked by the validator set with [`Document::set_mutation_validator()`].
+#[derive(Debug, Clone, Copy)]
+pub enum MutationEvent<'a> {
+    /// [`Element::try_set_attribute()`]
+    SetAttribute {
+        element: Element,
+        name: &'a str,
+        value: &'a str,
+    },
+    /// [`Element::try_set_text_content()`]
+    SetText { element: Element, text: &'a str },
+    /// [`Element::try_set_full_name()`]
+    SetName {
+        element: Element,
+        full_name: &'a str,
+    },
+    /// [`Element::push_child()`], [`Element::insert_child()`], [`Element::replace_child()`],
+    /// [`Element::replace_with()`], each node pushed by [`Element::push_parsed_fragment()`]
+    /// and each node inserted by [`ChildCursor::commit()`](crate::ChildCursor::commit)
+    PushChild { parent: Element, node: &'a Node },
+}
+
+/// See [`Document::set_mutation_validator()`].
+pub type MutationValidator =
+    dyn Fn(&MutationEvent) -> std::result::Result<(), String> + Send + Sync + RefUnwindSafe;
+
+#[derive(Clone)]
+struct ValidatorSlot(Option<Arc<MutationValidator>>);
+
+impl std::fmt::Debug for ValidatorSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "Some(<validator>)"),
+            None => write!(f, "None"),
+        }
+    }
+}
+
+/// &nbsp;
+/// # Mutation validation
+///
+/// A validator can reject modifications to the document, for applications
+/// that need every edit to follow rules such as allowed attribute values.
+///
+/// Only fallible methods consult the validator:
+/// [`Element::try_set_attribute()`], [`Element::try_set_text_content()`],
+/// [`Element::try_set_full_name()`], [`Element::push_child()`], [`Element::insert_child()`],
+/// [`Element::replace_child()`], [`Element::replace_with()`]
+/// and [`ChildCursor::commit()`](crate::ChildCursor::commit).
+/// Infallible methods like [`Element::set_attribute()`] always apply the change.
+impl Document {
+    /// Set a validator that is called before each checked modification.
+    /// If it returns `Err(reason)`, the modification isn't applied
+    /// and the method returns [`Error::Rejected`] with `reason`.
+    ///
+    /// Replaces the previous validator. The validator is shared by clones of the document.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element, Error, MutationEvent};
+    ///
+    /// let mut doc = Document::new();
+    /// let container = doc.container();
+    /// let root = Element::build("root").push_to(&mut doc, container);
+    /// doc.set_mutation_validator(Box::new(|event| match event {
+    ///     MutationEvent::SetAttribute { value: "", .. } => {
+    ///         Err("empty attribute value".to_string())
+    ///     }
+    ///     _ => Ok(()),
+    /// }));
+    /// assert!(matches!(root.try_set_attribute(&mut doc, "id", ""), Err(Error::Rejected(_))));
+    /// assert!(root.try_set_attribute(&mut doc, "id", "main").is_ok());
+    /// ```
+    pub fn set_mutation_validator(&mut self, validator: Box<MutationValidator>) {
+        self.validator = ValidatorSlot(Some(Arc::from(validator)));
+    }
+
+    /// Remove the validator set with [`Document::set_mutation_validator()`].
+    pub fn clear_mutation_validator(&mut self) {
+        self.validator = ValidatorSlot(None);
+    }
+
+    pub(crate) fn validate_mutation(&self, event: &MutationEvent) -> Result<()> {
+        match event {
+            MutationEvent::SetAttribute { element, .. }
+            | MutationEvent::SetText { element, .. }
+            | MutationEvent::SetName { element, .. } => element.check_valid(self)?,
+            MutationEvent::PushChild { parent, node } => {
+                parent.check_valid(self)?;
+                node.check_valid(self)?;
+            }
+        }
+        match &self.validator.0 {
+            Some(validator) => validator(event).map_err(Error::Rejected),
+            None => Ok(()),
+        }
+    }
+}
+
+/// &nbsp;
+/// # Parsing
+///
+/// Below are methods for parsing xml.
+/// Parsing from string, bytes, file, and reader is supported.
+///
+/// Call `parse_*_with_opts` with custom [`ReadOptions`] to change parser behaviour.
+/// Otherwise, [`ReadOptions::default()`] is used.
+///
+/// # Errors
+/// - [`Error::CannotDecode`]: Could not decode XML. XML declaration may have invalid encoding value.
+/// - [`Error::MalformedXML`]: Could not read XML.
+/// - [`Error::Io`]: IO Error
+impl Document {
+    pub fn parse_str(str: &str) -> Result<Document> {
+        DocumentParser::parse_bytes(str.as_bytes(), ReadOptions::default())
+    }
+    pub fn parse_str_with_opts(str: &str, opts: ReadOptions) -> Result<Document> {
+        DocumentParser::parse_bytes(str.as_bytes(), opts)
+    }
+
+    /// Parse an in-memory document in any supported encoding.
+    ///
+    /// Unlike `parse_reader`, UTF-8 input is read directly from the slice,
+    /// and other encodings are decoded at once instead of in chunks.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode(r#"<?xml version="1.0" encoding="Shift_JIS"?><a>日本語</a>"#);
+    /// let doc = Document::parse_bytes(&bytes).unwrap();
+    /// assert_eq!(doc.root_element().unwrap().text_content(&doc), "日本語");
+    /// ```
+    pub fn parse_bytes(bytes: &[u8]) -> Result<Document> {
+        DocumentParser::parse_bytes(bytes, ReadOptions::default())
+    }
+    pub fn parse_bytes_with_opts(bytes: &[u8], opts: ReadOptions) -> Result<Document> {
+        DocumentParser::parse_bytes(bytes, opts)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Document> {
+        let file = File::open(path)?;
+        DocumentParser::parse_reader(file, ReadOptions::default())
+    }
+    #[cfg(feature = "fs")]
+    pub fn parse_file_with_opts<P: AsRef<Path>>(path: P, opts: ReadOptions) -> Result<Document> {
+        let file = File::open(path)?;
+        DocumentParser::parse_reader(file, opts)
+    }
+
+    pub fn parse_reader<R: Read>(reader: R) -> Result<Document> {
+        DocumentParser::parse_reader(reader, ReadOptions::default())
+    }
+    pub fn parse_reader_with_opts<R: Read>(reader: R, opts: ReadOptions) -> Result<Document> {
+        DocumentParser::parse_reader(reader, opts)
+    }
+
+    /// Parse from a reader that is already buffered, such as a [`BufReader`](std::io::BufReader).
+    ///
+    /// Unlike `parse_reader`, UTF-8 input is read from the buffer of `reader`
+    /// instead of being copied into another one. Other encodings are decoded
+    /// from the buffer of `reader`.
+    ///
+    /// ```
+    /// use std::io::BufReader;
+    /// use xml_doc::Document;
+    ///
+    /// let xml = r#"<?xml version="1.0"?><a>text</a>"#;
+    /// let doc = Document::parse_bufread(BufReader::new(xml.as_bytes())).unwrap();
+    /// assert_eq!(doc.root_element().unwrap().text_content(&doc), "text");
+    /// ```
+    pub fn parse_bufread<B: BufRead>(reader: B) -> Result<Document> {
+        DocumentParser::parse_bufread(reader, ReadOptions::default())
+    }
+    pub fn parse_bufread_with_opts<B: BufRead>(reader: B, opts: ReadOptions) -> Result<Document> {
+        DocumentParser::parse_bufread(reader, opts)
+    }
+
+    /// Parse with `filter` deciding what to do with each element,
+    /// given its full name and attributes. (Namespace declarations are not included.)
+    ///
+    /// Children of skipped subtrees are not parsed into the document.
+    ///
+    /// ```
+    /// use xml_doc::{Document, ElementAction, ReadOptions};
+    ///
+    /// let xml = r#"<?xml version="1.0"?>
+    /// <file><name>a.png</name><binaryBlob>iVBORw0KGgo...</binaryBlob></file>"#;
+    /// let doc = Document::parse_reader_with_filter(xml.as_bytes(), ReadOptions::default(), |name, _| {
+    ///     match name {
+    ///         "binaryBlob" => ElementAction::SkipSubtree,
+    ///         _ => ElementAction::Keep,
+    ///     }
+    /// }).unwrap();
+    /// assert_eq!(doc.root_element().unwrap().child_element_count(&doc), 1);
+    /// ```
+    pub fn parse_reader_with_filter<R, F>(
+        reader: R,
+        opts: ReadOptions,
+        mut filter: F,
+    ) -> Result<Document>
+    where
+        R: Read,
+        F: FnMut(&str, &IndexMap<String, Value>) -> ElementAction,
+    {
+        DocumentParser::parse_reader_with_filter(reader, opts, Some(&mut filter))
+    }
+}
+
+/// Options when writing XML.
+pub struct WriteOptions {
+    /// Byte character to indent with. (default: `b' '`)
+    pub indent_char: u8,
+    /// How many indent_char should be used for indent. (default: 2)
+    pub indent_size: usize,
+    /// XML declaration should be written at the top,
+    /// unless the document is in [fragment mode](Document::set_fragment_mode). (default: `true`)
+    pub write_decl: bool,
+    /// How the XML declaration is written. (default: [`DeclPolicy::Normalized`])
+    pub decl: DeclPolicy,
+    /// Indent and add line breaks between nodes. If `false`, nodes are written as is. (default: `true`)
+    pub pretty: bool,
+    /// Called for each element to customize how it is written. (default: `None`)
+    ///
+    /// ```
+    /// use xml_doc::{Document, ElementWriteStyle, WriteOptions};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?>
+    /// <html><script>if (a &lt; b) {}</script></html>"#).unwrap();
+    /// let opts = WriteOptions {
+    ///     element_hook: Some(Box::new(|doc, elem| ElementWriteStyle {
+    ///         cdata_text: elem.name(doc) == "script",
+    ///         ..ElementWriteStyle::default()
+    ///     })),
+    ///     ..WriteOptions::default()
+    /// };
+    /// let xml = doc.write_str_with_opts(opts).unwrap();
+    /// assert!(xml.contains("<script><![CDATA[if (a < b) {}]]></script>"));
+    /// ```
+    pub element_hook: Option<ElementHook>,
+    /// Which elements without content are written as `<tag/>`.
+    /// [`ElementWriteStyle::expand_empty`] still takes precedence. (default: [`SelfCloseEmpty::Childless`])
+    pub self_close_empty: SelfCloseEmpty,
+    /// Where namespace declarations are written in start tags, relative to attributes.
+    /// Within each group, names keep the order they were parsed or added in. (default: [`NsPosition::First`])
+    ///
+    /// Doesn't apply to start tags kept with [`ReadOptions::high_fidelity`], which are written as is.
+    pub namespace_position: NsPosition,
+    /// Declare prefixes of element and attribute names that aren't declared in scope,
+    /// using namespaces registered with [`Document::register_namespace()`].
+    /// The declaration is added to the outermost element using the prefix.
+    ///
+    /// Writing fails with [`Error::UndeclaredPrefix`] if such a prefix isn't registered.
+    /// If `false`, names are written as is, even if their prefix isn't declared. (default: `false`)
+    ///
+    /// ```
+    /// use xml_doc::{Document, WriteOptions};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/></a>"#).unwrap();
+    /// let b = doc.root_element().unwrap().find(&doc, "b").unwrap();
+    /// b.set_attribute(&mut doc, "xlink:href", "#top");
+    /// doc.register_namespace("xlink", "http://www.w3.org/1999/xlink");
+    /// let opts = WriteOptions {
+    ///     declare_missing_prefixes: true,
+    ///     ..WriteOptions::default()
+    /// };
+    /// let xml = doc.write_str_with_opts(opts).unwrap();
+    /// assert!(xml.contains(r##"<b xmlns:xlink="http://www.w3.org/1999/xlink" xlink:href="#top"/>"##));
+    /// ```
+    pub declare_missing_prefixes: bool,
+    /// Write every namespace declaration of the document on the root element,
+    /// and none on other elements. The document isn't modified. (default: `false`)
+    ///
+    /// The root element declares its own namespaces first, then the others in document order.
+    /// Writing fails with [`Error::PrefixConflict`] if a prefix is bound to different namespaces
+    /// in different places, including a default namespace that doesn't apply to the whole document.
+    /// Nothing is written in that case. Elements whose declarations change
+    /// aren't written with the start tag kept by [`ReadOptions::high_fidelity`].
+    ///
+    /// ```
+    /// use xml_doc::{Document, WriteOptions};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?>
+    /// <a xmlns="urn:a"><x:b xmlns:x="urn:x"/><x:c xmlns:x="urn:x"/></a>"#).unwrap();
+    /// let opts = WriteOptions { hoist_namespace_decls: true, pretty: false, ..WriteOptions::default() };
+    /// let xml = doc.write_str_with_opts(opts).unwrap();
+    /// assert!(xml.ends_with(r#"<a xmlns="urn:a" xmlns:x="urn:x"><x:b/><x:c/></a>"#));
+    /// ```
+    pub hoist_namespace_decls: bool,
+    /// Quote character around attribute values and namespace declarations. (default: [`QuoteStyle::Double`])
+    ///
+    /// Values are escaped so that they are parsed back as they are: the quote character,
+    /// `&`, `<`, tabs, line breaks and spaces that the parser would normalize are written as references.
+    /// Doesn't apply to start tags kept with [`ReadOptions::high_fidelity`], which are written as is.
+    pub attribute_quotes: QuoteStyle,
+    /// Write a value that contains the quote character chosen by `attribute_quotes`,
+    /// but not the other one, between the other quote characters instead of escaping it.
+    /// If `false`, the quote character is never switched. (default: `false`)
+    ///
+    /// ```
+    /// use xml_doc::{Document, QuoteStyle, WriteOptions};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><a b='say "hi"' c="it's"/>"#).unwrap();
+    /// let opts = WriteOptions { attribute_quotes: QuoteStyle::Single, ..WriteOptions::default() };
+    /// let xml = doc.write_str_with_opts(opts).unwrap();
+    /// assert!(xml.ends_with(r#"<a b='say "hi"' c='it&apos;s'/>"#));
+    ///
+    /// let opts = WriteOptions {
+    ///     attribute_quotes: QuoteStyle::Single,
+    ///     allow_quote_fallback: true,
+    ///     ..WriteOptions::default()
+    /// };
+    /// let xml = doc.write_str_with_opts(opts).unwrap();
+    /// assert!(xml.ends_with(r#"<a b='say "hi"' c="it's"/>"#));
+    /// ```
+    pub allow_quote_fallback: bool,
+    /// End the output with a line break (`\n`) after the last root node,
+    /// unless nothing else is written. Nothing else is written after it. (default: `false`)
+    pub trailing_newline: bool,
+    /// Encoding to write in, as a label such as `"ISO-8859-1"` or `"Shift_JIS"`.
+    /// `None` writes UTF-8. The XML declaration declares this encoding. (default: `None`)
+    ///
+    /// See [`encoding_rs::Encoding::for_label`] for valid labels, except that ASCII and ISO-8859-1
+    /// labels encode only their own characters, instead of those of windows-1252.
+    /// UTF-16 isn't supported. Characters the encoding lacks are handled by `unmappable`.
+    ///
+    /// [`Document::write_str_with_opts()`] and [`Document::write_fmt_with_opts()`] fail
+    /// if the output isn't valid UTF-8.
+    pub encoding: Option<String>,
+    /// What to do with characters that can't be written in `encoding`.
+    /// (default: [`UnmappablePolicy::CharRef`])
+    pub unmappable: UnmappablePolicy,
+    /// In `pretty` mode, write comments that precede an element on their own lines
+    /// directly above it, at its indentation. (default: `false`)
+    ///
+    /// Whitespace-only text between the comments and the element is not written,
+    /// and whitespace-only text before the comments is written as the blank lines it contains.
+    /// Elements with non-whitespace text, CDATA or external text children are written as is,
+    /// so that their text content doesn't change.
+    ///
+    /// ```
+    /// use xml_doc::{Document, ReadOptions, WriteOptions};
+    ///
+    /// let xml = "<?xml version=\"1.0\"?>\n<config><a/>  <!-- b -->\n\n<b/></config>";
+    /// let read_opts = ReadOptions { trim_text: false, ..ReadOptions::default() };
+    /// let doc = Document::parse_str_with_opts(xml, read_opts).unwrap();
+    /// let opts = WriteOptions { align_comments: true, ..WriteOptions::default() };
+    /// assert_eq!(doc.write_str_with_opts(opts).unwrap(), r#"<?xml version="1.0" encoding="UTF-8"?>
+    /// <config>
+    ///   <a/>
+    ///   <!-- b -->
+    ///   <b/>
+    /// </config>"#);
+    /// ```
+    pub align_comments: bool,
+    /// Flush the writer each time about this many bytes were written since the last flush,
+    /// and once more at the end. (default: `None`)
+    ///
+    /// The written bytes are checked between nodes, and between chunks of [`Node::ExternalText`],
+    /// so a large node is written before the flush. The output is the same with or without it.
+    /// If writing fails, [`Error::WriteInterrupted`] tells how many bytes were flushed.
+    pub flush_every_bytes: Option<usize>,
+}
+
+/// How characters missing from the output encoding are written. See [`WriteOptions::unmappable`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnmappablePolicy {
+    /// Write a character reference such as `&#8364;` in text and attribute values.
+    /// References aren't recognized in names, comments, processing instructions, CDATA sections
+    /// and DOCTYPE, so the character is an error there as with `Error`.
+    #[default]
+    CharRef,
+    /// Fail with [`Error::Unmappable`].
+    Error,
+}
+
+/// Which elements without content are written as `<tag/>`. See [`WriteOptions::self_close_empty`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SelfCloseEmpty {
+    /// Elements without child nodes.
+    #[default]
+    Childless,
+    /// Also write `<tag></tag>` of the parsed source as `<tag/>`.
+    ///
+    /// Only affects elements whose empty text node was added by the parser
+    /// because of [`ReadOptions::empty_text_node`], not empty text set by the user.
+    ParsedEmpty,
+    /// Elements without child nodes, unless they were written with start and end tags
+    /// in the parsed source. See [`Element::authored_shape()`].
+    Preserve,
+}
+
+/// Where namespace declarations are written in start tags. See [`WriteOptions::namespace_position`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NsPosition {
+    /// Before attributes. e.g. `<a xmlns:x="urn:x" x:b="1"/>`
+    #[default]
+    First,
+    /// After attributes. e.g. `<a x:b="1" xmlns:x="urn:x"/>`
+    Last,
+    /// After attributes if they came after an attribute in the parsed source, otherwise first.
+    /// The source order is only recorded when parsed with [`ReadOptions::high_fidelity`].
+    AsParsed,
+}
+
+/// Quote character around attribute values. See [`WriteOptions::attribute_quotes`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuoteStyle {
+    /// e.g. `<a b="1"/>`
+    #[default]
+    Double,
+    /// e.g. `<a b='1'/>`
+    Single,
+    /// The quote character of each attribute in the parsed source, and `"` for other attributes.
+    /// The quote characters are only recorded when parsed with [`ReadOptions::high_fidelity`].
+    Preserve,
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions {
+            indent_char: b' ',
+            indent_size: 2,
+            write_decl: true,
+            decl: DeclPolicy::default(),
+            pretty: true,
+            self_close_empty: SelfCloseEmpty::default(),
+            namespace_position: NsPosition::default(),
+            declare_missing_prefixes: false,
+            hoist_namespace_decls: false,
+            attribute_quotes: QuoteStyle::default(),
+            allow_quote_fallback: false,
+            trailing_newline: false,
+            encoding: None,
+            unmappable: UnmappablePolicy::default(),
+            align_comments: false,
+            flush_every_bytes: None,
+            element_hook: None,
+        }
+    }
+}
+
+/// Counts of what was written. See [`Document::write_with_stats()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteStats {
+    /// Elements written, not including those skipped by [`ElementWriteStyle::skip`].
+    pub elements: usize,
+    /// Attributes of written elements, not including namespace declarations.
+    pub attributes: usize,
+    /// Namespace declarations of written elements, including those added by
+    /// [`WriteOptions::declare_missing_prefixes`] and [`WriteOptions::hoist_namespace_decls`].
+    pub namespace_decls: usize,
+    /// Bytes written, after encoding.
+    pub bytes: usize,
+    /// Times text, an attribute value or a namespace was escaped.
+    /// Text of [`Node::ExternalText`] is escaped once per chunk.
+    pub escape_calls: usize,
+    /// Times the writer was flushed because of [`WriteOptions::flush_every_bytes`].
+    pub flushes: usize,
+}
+
+/// Counts of nodes in a document. See [`Document::stats()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DocumentStats {
+    pub elements: usize,
+    /// Attributes of all elements, not including namespace declarations.
+    pub attributes: usize,
+    pub namespace_decls: usize,
+    /// [`Node::Text`], [`Node::CData`] and [`Node::ExternalText`] nodes.
+    pub texts: usize,
+    pub comments: usize,
+    /// Processing instructions and DOCTYPE nodes.
+    pub others: usize,
+}
+
+/// How the XML declaration is written. See [`WriteOptions::decl`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeclPolicy {
+    /// Write `<?xml version="..." encoding="UTF-8"?>`, with `standalone` if it is set.
+    /// The encoding is that of [`WriteOptions::encoding`].
+    #[default]
+    Normalized,
+    /// Write the declaration exactly as it was in the parsed source. (See [`Document::raw_decl()`])
+    ///
+    /// Falls back to `Normalized` if there is no original declaration,
+    /// or it declares an encoding other than [`WriteOptions::encoding`].
+    Verbatim,
+    /// Don't write the declaration. Same as setting [`WriteOptions::write_decl`] to `false`.
+    Omit,
+}
+
+/// See [`WriteOptions::element_hook`].
+pub type ElementHook = Box<dyn Fn(&Document, Element) -> ElementWriteStyle + Send + Sync>;
+
+/// How an element is written. Returned by [`WriteOptions::element_hook`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElementWriteStyle {
+    /// Don't indent or add line breaks inside the element. (default: `false`)
+    pub inline: bool,
+    /// Write its child [`Node::Text`]s as CDATA sections. (default: `false`)
+    pub cdata_text: bool,
+    /// Write `<tag></tag>` instead of `<tag/>` if it has no children. (default: `false`)
+    pub expand_empty: bool,
+    /// Don't write the element and its children. (default: `false`)
+    pub skip: bool,
+}
+
+/// &nbsp;
+/// # Writing
+///
+/// Below are methods for writing xml.
+/// The XML will be written in UTF-8, unless [`WriteOptions::encoding`] is set.
+///
+/// Output is deterministic: namespace declarations and then attributes
+/// are written in the order they were parsed or added, unless the original start tag is kept.
+/// Declarations added by [`WriteOptions::declare_missing_prefixes`] come last, sorted by prefix,
+/// and declarations moved by [`WriteOptions::hoist_namespace_decls`] are in document order.
+/// (See [`WriteOptions::namespace_position`])
+/// (See [`ReadOptions::high_fidelity`])
+impl Document {
+    #[cfg(feature = "fs")]
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_file_with_opts(path, WriteOptions::default())
+    }
+    #[cfg(feature = "fs")]
+    pub fn write_file_with_opts<P: AsRef<Path>>(&self, path: P, opts: WriteOptions) -> Result<()> {
+        let mut file = File::create(path)?;
+        self.write_with_opts(&mut file, opts)
+    }
+
+    pub fn write_str(&self) -> Result<String> {
+        self.write_str_with_opts(WriteOptions::default())
+    }
+    pub fn write_str_with_opts(&self, opts: WriteOptions) -> Result<String> {
+        let mut buf: Vec<u8> = Vec::with_capacity(200);
+        self.write_with_opts(&mut buf, opts)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        self.write_with_opts(writer, WriteOptions::default())
+    }
+
+    /// Write the document to `writer`.
+    ///
+    /// # Errors
+    /// - [`Error::WriteInterrupted`]: `writer` returned an error.
+    ///   Its fields tell how much was written, and which element was being written.
+    /// - [`Error::MalformedXML`]: The document can't be written as well-formed XML.
+    /// - [`Error::UndeclaredPrefix`]: With [`WriteOptions::declare_missing_prefixes`],
+    ///   a prefix is neither declared nor registered.
+    /// - [`Error::PrefixConflict`]: With [`WriteOptions::hoist_namespace_decls`],
+    ///   a prefix is bound to different namespaces. Nothing is written,
+    ///   unless the conflict is with a prefix declared by [`WriteOptions::declare_missing_prefixes`].
+    /// - [`Error::UnsupportedEncoding`]: [`WriteOptions::encoding`] isn't supported. Nothing is written.
+    /// - [`Error::Unmappable`]: A character can't be written in [`WriteOptions::encoding`].
+    pub fn write_with_opts(&self, writer: &mut impl Write, opts: WriteOptions) -> Result<()> {
+        self.write_with_stats(writer, opts)?;
+        Ok(())
+    }
+
+    /// Same as [`Document::write_with_opts()`], but also returns counts of what was written.
+    ///
+    /// ```
+    /// use xml_doc::{Document, WriteOptions};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><a id="1"><b>text</b></a>"#).unwrap();
+    /// let mut out = Vec::new();
+    /// let stats = doc.write_with_stats(&mut out, WriteOptions::default()).unwrap();
+    /// assert_eq!(stats.elements, 2);
+    /// assert_eq!(stats.attributes, 1);
+    /// assert_eq!(stats.bytes, out.len());
+    /// ```
+    pub fn write_with_stats(
+        &self,
+        writer: &mut impl Write,
+        opts: WriteOptions,
+    ) -> Result<WriteStats> {
+        let encoding = OutputEncoding::for_label(opts.encoding.as_deref())?;
+        let hoisted = match opts.hoist_namespace_decls {
+            true => Some(self.hoisted_namespace_decls()?),
+            false => None,
+        };
+        #[cfg(feature = "tracing")]
+        let span = crate::trace::write_span(encoding.name());
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        let written = Cell::new(0);
+        let mut counter = CountingWriter {
+            inner: writer,
+            written: &written,
+        };
+        let mut encoder = EncodingWriter::new(&mut counter, encoding);
+        let mut writer = if opts.pretty {
+            Writer::new_with_indent(&mut encoder, opts.indent_char, opts.indent_size)
+        } else {
+            Writer::new(&mut encoder)
+        };
+        let mut state = WriteState {
+            opts: &opts,
+            encoding,
+            stats: WriteStats::default(),
+            written: &written,
+            flushed: 0,
+            hoisted,
+        };
+        let result = self
+            .write_document(&mut writer, &mut state)
+            .and_then(|_| Ok(encoder.finish()?))
+            .and_then(|_| {
+                if opts.flush_every_bytes.is_some() && state.flushed < written.get() {
+                    encoder.flush()?;
+                    state.flushed = written.get();
+                    state.stats.flushes += 1;
+                }
+                Ok(())
+            });
+        let bytes_written = written.get();
+        let bytes_flushed = state.flushed;
+        let stats = WriteStats {
+            bytes: bytes_written,
+            ..state.stats
+        };
+        let result = match result {
+            Err(Error::Io(source)) => Err(Error::WriteInterrupted {
+                bytes_written,
+                bytes_flushed,
+                at: self.container.path(self),
+                source,
+            }),
+            Err(Error::WriteInterrupted { at, source, .. }) => Err(Error::WriteInterrupted {
+                bytes_written,
+                bytes_flushed,
+                at,
+                source,
+            }),
+            res => res.map(|_| stats),
+        };
+        #[cfg(feature = "tracing")]
+        crate::trace::write_finished(&span, &stats, &result);
+        result
+    }
+
+    fn write_document<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        state: &mut WriteState,
+    ) -> Result<()> {
+        let (opts, encoding) = (state.opts, state.encoding);
+        let write_decl = opts.write_decl && opts.decl != DeclPolicy::Omit && !self.fragment;
+        if write_decl {
+            self.write_decl(writer, opts.decl, encoding)?;
+        }
+        let style = ElementWriteStyle::default();
+        let nodes = self.container.children(self);
+        self.write_nodes(writer, nodes, &style, write_decl, state)
+            .map_err(|err| self.unmappable_at(err, self.container))?;
+        if opts.trailing_newline && (write_decl || !nodes.is_empty()) {
+            writer.write(b"\n")?;
+        }
+        // quick-xml doesn't write anything for Eof.
+        writer.write_event(Event::Eof)?;
+        Ok(())
+    }
+
+    /// Write to a [`std::fmt::Write`], such as `String`.
+    ///
+    /// # Errors
+    /// - [`Error::Fmt`]: `out` returned an error.
+    pub fn write_fmt_with_opts(
+        &self,
+        out: &mut impl std::fmt::Write,
+        opts: WriteOptions,
+    ) -> Result<()> {
+        let mut adapter = FmtAdapter {
+            out,
+            pending: Vec::new(),
+            error: None,
+        };
+        match self.write_with_opts(&mut adapter, opts) {
+            Err(Error::WriteInterrupted { .. }) if adapter.error.is_some() => {
+                Err(Error::Fmt(std::fmt::Error))
+            }
+            res => res,
+        }
+    }
+
+    fn write_decl(
+        &self,
+        writer: &mut Writer<impl Write>,
+        policy: DeclPolicy,
+        encoding: OutputEncoding,
+    ) -> Result<()> {
+        if policy == DeclPolicy::Verbatim {
+            if let Some(raw) = self.verbatim_decl(encoding) {
+                // Strip `<?` and `?>`, which quick-xml writes back.
+                let content = &raw.as_bytes()[2..raw.len() - 2];
+                let decl = BytesDecl::from_start(BytesStart::borrowed(content, 3));
+                writer.write_event(Event::Decl(decl))?;
+                return Ok(());
+            }
+        }
+        let standalone = self.standalone.map(|standalone| match standalone {
+            true => "yes".as_bytes(),
+            false => "no".as_bytes(),
+        });
+        writer.write_event(Event::Decl(BytesDecl::new(
+            self.version.as_bytes(),
+            Some(encoding.name().as_bytes()),
+            standalone,
+        )))?;
+        Ok(())
+    }
+
+    // Original declaration, if it can be written as is in `encoding`.
+    fn verbatim_decl(&self, encoding: OutputEncoding) -> Option<&str> {
+        let raw = self.raw_decl.as_deref()?;
+        let content = raw.strip_prefix("<?")?.strip_suffix("?>")?;
+        let decl = BytesDecl::from_start(BytesStart::borrowed(content.as_bytes(), 3));
+        let declared = match decl.encoding() {
+            None => OutputEncoding::Utf8,
+            Some(Ok(label)) => OutputEncoding::for_label(std::str::from_utf8(&label).ok()).ok()?,
+            Some(Err(_)) => return None,
+        };
+        (declared == encoding && encoding.find_unmappable(raw).is_none()).then_some(raw)
+    }
+
+    // `style` is the style of the parent element.
+    // `line_break` is whether writer would start a new line before the next element,
+    // which quick-xml doesn't expose.
+    fn write_nodes<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        nodes: &[Node],
+        style: &ElementWriteStyle,
+        mut line_break: bool,
+        state: &mut WriteState,
+    ) -> Result<()> {
+        let (opts, encoding) = (state.opts, state.encoding);
+        let align_comments = opts.pretty && opts.align_comments && !has_text(nodes);
+        for (i, node) in nodes.iter().enumerate() {
+            if align_comments {
+                match node {
+                    Node::Text(text) if is_whitespace(text) => {
+                        if is_aligned_comment(nodes, i + 1) {
+                            // Keep blank lines. The comment starts on a new line.
+                            for _ in 1..text.matches('\n').count() {
+                                writer.write(b"\n")?;
+                            }
+                            continue;
+                        }
+                        if i > 0 && is_aligned_comment(nodes, i - 1) {
+                            continue;
+                        }
+                    }
+                    Node::Comment(_) if !line_break && is_aligned_comment(nodes, i) => {
+                        writer.write_indent()?;
+                    }
+                    _ => (),
+                }
+            }
+            match node {
+                Node::Element(eid) => {
+                    let style = match &opts.element_hook {
+                        Some(hook) => hook(self, *eid),
+                        None => ElementWriteStyle::default(),
+                    };
+                    if style.skip {
+                        continue;
+                    }
+                    self.write_element(writer, *eid, &style, line_break, state)?
+                }
+                Node::Text(text) if style.cdata_text => {
+                    encoding.check(text, "CDATA section")?;
+                    // "]]>" can't be inside a CDATA section, so split it into two sections.
+                    let text = text.replace("]]>", "]]]]><![CDATA[>");
+                    writer.write_event(Event::CData(BytesText::from_escaped_str(&text)))?
+                }
+                Node::Text(text) if encoding == OutputEncoding::Utf8 => {
+                    state.stats.escape_calls += 1;
+                    writer.write_event(Event::Text(BytesText::from_plain_str(text)))?
+                }
+                Node::Text(text) => self.write_encoded_text(writer, text, state)?,
+                Node::DocType(text) => {
+                    encoding.check(text, "DOCTYPE")?;
+                    writer.write_event(Event::DocType(
+                        BytesText::from_plain_str(&format!(" {}", text)), // add a whitespace before text
+                    ))?
+                }
+                // Comment, CData, and PI content is not escaped.
+                Node::Comment(text) => {
+                    encoding.check(text, "comment")?;
+                    writer.write_event(Event::Comment(BytesText::from_escaped_str(text)))?
+                }
+                Node::CData(text) => {
+                    encoding.check(text, "CDATA section")?;
+                    writer.write_event(Event::CData(BytesText::from_escaped_str(text.as_str())))?
+                }
+                Node::PI(text) => {
+                    encoding.check(text, "processing instruction")?;
+                    writer.write_event(Event::PI(BytesText::from_escaped_str(text)))?
+                }
+                Node::ExternalText(text)
+                    if (text.is_cdata() || style.cdata_text)
+                        && encoding != OutputEncoding::Utf8 =>
+                {
+                    let text = text.read_to_string()?;
+                    encoding.check(&text, "CDATA section")?;
+                    let text = text.replace("]]>", "]]]]><![CDATA[>");
+                    writer.write_event(Event::CData(BytesText::from_escaped_str(&text)))?
+                }
+                // Characters may be split between chunks, so replacing them needs the whole text.
+                Node::ExternalText(text) if encoding != OutputEncoding::Utf8 => {
+                    self.write_encoded_text(writer, &text.read_to_string()?, state)?
+                }
+                // Streamed from the file.
+                Node::ExternalText(text) if text.is_cdata() || style.cdata_text => {
+                    // Empty text keeps writer from adding a line break after it.
+                    writer.write_event(Event::Text(BytesText::from_escaped_str("")))?;
+                    writer.write(b"<![CDATA[")?;
+                    text.for_each_cdata_chunk(|chunk| {
+                        writer.write(chunk)?;
+                        flush_checkpoint(writer, state)
+                    })?;
+                    writer.write(b"]]>")?
+                }
+                Node::ExternalText(text) => {
+                    // Empty text keeps writer from adding a line break after it.
+                    writer.write_event(Event::Text(BytesText::from_escaped_str("")))?;
+                    text.for_each_chunk(|chunk| {
+                        state.stats.escape_calls += 1;
+                        writer.write(&quick_xml::escape::escape(chunk))?;
+                        flush_checkpoint(writer, state)
+                    })?
+                }
+            };
+            line_break = !matches!(node, Node::Text(_) | Node::CData(_) | Node::ExternalText(_));
+            flush_checkpoint(writer, state)?;
+        }
+        Ok(())
+    }
+
+    // Write text escaped, replacing characters that `encoding` lacks according to `opts.unmappable`.
+    fn write_encoded_text<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        text: &str,
+        state: &mut WriteState,
+    ) -> Result<()> {
+        let (opts, encoding) = (state.opts, state.encoding);
+        state.stats.escape_calls += 1;
+        let encoded = escape_encoded(text, encoding, opts.unmappable, "text")?;
+        writer.write_event(Event::Text(BytesText::from_escaped_str(encoded)))?;
+        Ok(())
+    }
+
+    // Add the path of `element` to an `Error::Unmappable` raised while writing its content.
+    fn unmappable_at(&self, err: Error, element: Element) -> Error {
+        match err {
+            Error::Unmappable {
+                character,
+                encoding,
+                context,
+                element_path,
+            } if element_path.is_empty() => Error::Unmappable {
+                character,
+                encoding,
+                context,
+                element_path: element.path(self),
+            },
+            err => err,
+        }
+    }
+
+    fn write_element<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        element: Element,
+        style: &ElementWriteStyle,
+        line_break: bool,
+        state: &mut WriteState,
+    ) -> Result<()> {
+        // The innermost element records where writing failed.
+        // `bytes_written` and `bytes_flushed` are filled in by `write_with_opts`.
+        self.write_element_inner(writer, element, style, line_break, state)
+            .map_err(|err| match err {
+                Error::Io(source) => Error::WriteInterrupted {
+                    bytes_written: 0,
+                    bytes_flushed: 0,
+                    at: element.path(self),
+                    source,
+                },
+                err => self.unmappable_at(err, element),
+            })
+    }
+
+    fn write_element_inner<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        element: Element,
+        style: &ElementWriteStyle,
+        line_break: bool,
+        state: &mut WriteState,
+    ) -> Result<()> {
+        let (opts, encoding) = (state.opts, state.encoding);
+        if style.inline {
+            // Write the element without indentation, then insert it at the current indentation.
+            let mut buf: Vec<u8> = Vec::new();
+            let inline_style = ElementWriteStyle {
+                inline: false,
+                ..style.clone()
+            };
+            self.write_element(
+                &mut Writer::new(&mut buf),
+                element,
+                &inline_style,
+                false,
+                state,
+            )?;
+            if line_break {
+                writer.write_indent()?;
+            }
+            writer.write(&buf)?;
+            return Ok(());
+        }
+        let name_bytes = element.full_name(self).as_bytes();
+        let decls = self.start_tag_decls(element, state)?;
+        let own_decls = element.namespace_decls(self);
+        let decls_as_parsed = decls.len() == own_decls.len()
+            && decls
+                .iter()
+                .zip(own_decls)
+                .all(|((prefix, ns), (own_prefix, own_ns))| prefix == own_prefix && ns == own_ns);
+        state.stats.elements += 1;
+        state.stats.attributes += element.attributes(self).len();
+        state.stats.namespace_decls += decls.len();
+        let start = match self.raw_start_tags.get(&element) {
+            Some(raw) if decls_as_parsed && encoding.find_unmappable(raw).is_none() => {
+                BytesStart::borrowed(raw.as_bytes(), name_bytes.len())
+            }
+            _ => {
+                // Each attribute value and namespace declaration is escaped.
+                state.stats.escape_calls += element.attributes(self).len() + decls.len();
+                self.build_start_tag(element, opts, encoding, &decls)?
+            }
+        };
+        let expand_empty = style.expand_empty
+            || (opts.self_close_empty == SelfCloseEmpty::Preserve
+                && matches!(
+                    element.authored_shape(self),
+                    Some(ElementShape::EmptyPair | ElementShape::WithContent)
+                ));
+        if element.has_children(self)
+            && !(opts.self_close_empty == SelfCloseEmpty::ParsedEmpty
+                && self.is_synthesized_empty(element))
+        {
+            writer.write_event(Event::Start(start))?;
+            let children = element.children(self);
+            self.write_nodes(writer, children, style, true, state)?;
+            writer.write_event(Event::End(BytesEnd::borrowed(name_bytes)))?;
+        } else if expand_empty {
+            writer.write_event(Event::Start(start))?;
+            // Empty text keeps writer from adding a line break before the end tag.
+            writer.write_event(Event::Text(BytesText::from_escaped_str("")))?;
+            writer.write_event(Event::End(BytesEnd::borrowed(name_bytes)))?;
+        } else {
+            writer.write_event(Event::Empty(start))?;
+        }
+        Ok(())
+    }
+
+    // Only child is the empty text node added by the parser.
+    fn is_synthesized_empty(&self, element: Element) -> bool {
+        self.synthesized_empty_text.contains(&element)
+            && matches!(element.children(self).as_slice(), [Node::Text(text)] if text.is_empty())
+    }
+
+    // Namespace declarations to write on `element`: its own, or the hoisted ones on root elements,
+    // followed by those added by `WriteOptions::declare_missing_prefixes`.
+    fn start_tag_decls<'a>(
+        &'a self,
+        element: Element,
+        state: &WriteState<'a>,
+    ) -> Result<Vec<(&'a str, &'a str)>> {
+        let mut decls: Vec<(&str, &str)> = match &state.hoisted {
+            Some(hoisted) if element.is_root(self) => hoisted.clone(),
+            Some(_) => Vec::new(),
+            None => element
+                .namespace_decls(self)
+                .iter()
+                .map(|(prefix, ns)| (prefix.as_str(), ns.as_str()))
+                .collect(),
+        };
+        if state.opts.declare_missing_prefixes {
+            let hoisted = state.hoisted.as_deref().unwrap_or_default();
+            for (prefix, ns) in self.missing_prefix_decls(element)? {
+                match hoisted
+                    .iter()
+                    .find(|(hoisted_prefix, _)| *hoisted_prefix == prefix)
+                {
+                    Some((_, hoisted_ns)) if *hoisted_ns == ns => {}
+                    Some((_, hoisted_ns)) => {
+                        return Err(Error::PrefixConflict {
+                            prefix: prefix.to_string(),
+                            first: hoisted_ns.to_string(),
+                            second: ns.to_string(),
+                            element_path: element.path(self),
+                        })
+                    }
+                    None => decls.push((prefix, ns)),
+                }
+            }
+        }
+        Ok(decls)
+    }
+
+    // `decls` are the namespace declarations to write. See `start_tag_decls`.
+    fn build_start_tag(
+        &self,
+        element: Element,
+        opts: &WriteOptions,
+        encoding: OutputEncoding,
+        decls: &[(&str, &str)],
+    ) -> Result<BytesStart<'_>> {
+        let name = element.full_name(self);
+        encoding.check(name, "element name")?;
+        let decls_last = match opts.namespace_position {
+            NsPosition::First => false,
+            NsPosition::Last => true,
+            NsPosition::AsParsed => self.namespace_decls_last.contains(&element),
+        };
+        let mut attributes: Vec<(Cow<str>, Cow<str>, u8)> = Vec::new();
+        if !decls_last {
+            self.push_namespace_decls(element, &mut attributes, opts, encoding, decls)?;
+        }
+        for (key, val) in element.attributes(self) {
+            // Namespace declaration set with `set_attribute` may duplicate one in `namespace_decls`.
+            let decl_prefix = match key.as_str() {
+                "xmlns" => Some(""),
+                key => key.strip_prefix("xmlns:"),
+            };
+            if let Some(prefix) = decl_prefix {
+                if element.namespace_decls(self).contains_key(prefix) {
+                    return Err(Error::MalformedXML(format!(
+                        "Namespace prefix '{}' is declared twice on element '{}'",
+                        prefix,
+                        element.full_name(self)
+                    )));
+                }
+            }
+            encoding.check(key, "attribute name")?;
+            let quote = self.attribute_quote(element, key, val, opts);
+            let val = escape_attribute_value(val, quote);
+            let val = encode_escaped(val, encoding, opts.unmappable, "attribute value")?;
+            attributes.push((Cow::Borrowed(key), val, quote));
+        }
+        if decls_last {
+            self.push_namespace_decls(element, &mut attributes, opts, encoding, decls)?;
+        }
+        if attributes.is_empty() {
+            return Ok(BytesStart::borrowed_name(name.as_bytes()));
+        }
+        // Allocate the whole tag at once, so long values aren't copied again as it grows.
+        let len = attributes
+            .iter()
+            .map(|(key, val, _)| key.len() + val.len() + 4)
+            .sum::<usize>();
+        let mut buf = Vec::with_capacity(name.len() + len);
+        buf.extend_from_slice(name.as_bytes());
+        for (key, val, quote) in &attributes {
+            buf.push(b' ');
+            buf.extend_from_slice(key.as_bytes());
+            buf.push(b'=');
+            buf.push(*quote);
+            buf.extend_from_slice(val.as_bytes());
+            buf.push(*quote);
+        }
+        Ok(BytesStart::owned(buf, name.len()))
+    }
+
+    fn push_namespace_decls<'a>(
+        &self,
+        element: Element,
+        attributes: &mut Vec<(Cow<'a, str>, Cow<'a, str>, u8)>,
+        opts: &WriteOptions,
+        encoding: OutputEncoding,
+        decls: &[(&'a str, &'a str)],
+    ) -> Result<()> {
+        for &(prefix, val) in decls {
+            let attr_name = if prefix.is_empty() {
+                "xmlns".to_string()
+            } else {
+                format!("xmlns:{}", prefix)
+            };
+            encoding.check(prefix, "namespace prefix")?;
+            let quote = self.attribute_quote(element, &attr_name, val, opts);
+            let val = escape_attribute_value(val, quote);
+            let val = encode_escaped(val, encoding, opts.unmappable, "attribute value")?;
+            attributes.push((Cow::Owned(attr_name), val, quote));
+        }
+        Ok(())
+    }
+
+    // Quote character to write `value` of the attribute or namespace declaration `key` with.
+    fn attribute_quote(&self, element: Element, key: &str, value: &str, opts: &WriteOptions) -> u8 {
+        let quote = match opts.attribute_quotes {
+            QuoteStyle::Double => b'"',
+            QuoteStyle::Single => b'\'',
+            QuoteStyle::Preserve => match self.single_quoted.get(&element) {
+                Some(names) if names.iter().any(|name| name == key) => b'\'',
+                _ => b'"',
+            },
+        };
+        let other = if quote == b'"' { b'\'' } else { b'"' };
+        let bytes = value.as_bytes();
+        if opts.allow_quote_fallback && bytes.contains(&quote) && !bytes.contains(&other) {
+            other
+        } else {
+            quote
+        }
+    }
+}
+
+// Passed through the writer methods.
+struct WriteState<'a> {
+    opts: &'a WriteOptions,
+    encoding: OutputEncoding,
+    stats: WriteStats,
+    written: &'a Cell<usize>, // updated by `CountingWriter`
+    flushed: usize,           // bytes written before the last flush
+    // Declarations written on root elements, if `WriteOptions::hoist_namespace_decls`.
+    hoisted: Option<Vec<(&'a str, &'a str)>>,
+}
+
+// Flush if `WriteOptions::flush_every_bytes` were written since the last flush.
+fn flush_checkpoint<W: Write>(writer: &mut Writer<W>, state: &mut WriteState) -> Result<()> {
+    if let Some(every) = state.opts.flush_every_bytes {
+        let written = state.written.get();
+        if written - state.flushed >= every {
+            writer.inner().flush()?;
+            state.flushed = written;
+            state.stats.flushes += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Counts bytes accepted by the inner writer.
+struct CountingWriter<'a, W: Write> {
+    inner: W,
+    written: &'a Cell<usize>,
+}
+
+impl<'a, W: Write> Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.written.set(self.written.get() + len);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bridges [`std::io::Write`] used by quick-xml to a [`std::fmt::Write`].
+struct FmtAdapter<'a, W: std::fmt::Write> {
+    out: &'a mut W,
+    pending: Vec<u8>, // incomplete UTF-8 sequence at the end of last write
+    error: Option<std::fmt::Error>,
+}
+
+impl<'a, W: std::fmt::Write> Write for FmtAdapter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid_len = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(err) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+        };
+        // Safe to unwrap since bytes up to valid_len are checked above.
+        #[allow(clippy::unwrap_used)]
+        let valid = std::str::from_utf8(&self.pending[..valid_len]).unwrap();
+        if let Err(err) = self.out.write_str(valid) {
+            self.error = Some(err);
+            return Err(std::io::Error::other(err));
+        }
+        self.pending.drain(..valid_len);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Same as `quick_xml::escape::escape()`, without checking that the result is UTF-8 again.
+// Text that doesn't need escaping is borrowed.
+fn escape(text: &str) -> Cow<'_, str> {
+    let special = |b: u8| matches!(b, b'<' | b'>' | b'\'' | b'&' | b'"');
+    let mut index = match find_special(text.as_bytes(), special) {
+        Some(index) => index,
+        None => return Cow::Borrowed(text),
+    };
+    let mut escaped = String::with_capacity(text.len() + 16);
+    let mut rest = text;
+    loop {
+        escaped.push_str(&rest[..index]);
+        escaped.push_str(match rest.as_bytes()[index] {
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            b'\'' => "&apos;",
+            b'&' => "&amp;",
+            _ => "&quot;",
+        });
+        rest = &rest[index + 1..];
+        match find_special(rest.as_bytes(), special) {
+            Some(next) => index = next,
+            None => break,
+        }
+    }
+    escaped.push_str(rest);
+    Cow::Owned(escaped)
+}
+
+/// Escape an attribute value to write between `quote` characters (`"` or `'`),
+/// so that parsing it gives back `value` exactly.
+///
+/// `&`, `<` and `quote` are escaped, and so are tabs, line breaks, and the spaces that
+/// attribute value normalization would remove: at the start, at the end, and after another space.
+/// Values that don't need escaping are borrowed.
+pub(crate) fn escape_attribute_value(value: &str, quote: u8) -> Cow<'_, str> {
+    let bytes = value.as_bytes();
+    let special = |b: u8| matches!(b, b'&' | b'<' | b'\t' | b'\n' | b'\r' | b' ') || b == quote;
+    let mut escaped = String::new();
+    let mut copied = 0; // bytes of `value` already in `escaped`
+    let mut start = 0;
+    while let Some(found) = find_special(&bytes[start..], special) {
+        let index = start + found;
+        start = index + 1;
+        let reference = match bytes[index] {
+            b'&' => "&amp;",
+            b'<' => "&lt;",
+            b'"' => "&quot;",
+            b'\'' => "&apos;",
+            b'\t' => "&#9;",
+            b'\n' => "&#10;",
+            b'\r' => "&#13;",
+            _ if index == 0 || index == bytes.len() - 1 || bytes[index - 1] == b' ' => "&#32;",
+            _ => continue,
+        };
+        if copied == 0 {
+            escaped.reserve(value.len() + 16);
+        }
+        escaped.push_str(&value[copied..index]);
+        escaped.push_str(reference);
+        copied = start;
+    }
+    if copied == 0 {
+        return Cow::Borrowed(value);
+    }
+    escaped.push_str(&value[copied..]);
+    Cow::Owned(escaped)
+}
+
+// Index of the first byte for which `special` is true.
+// Bytes are checked 64 at a time without branching, which the compiler vectorizes,
+// so long values are scanned about as fast as they are copied.
+fn find_special(bytes: &[u8], special: impl Fn(u8) -> bool) -> Option<usize> {
+    let mut start = 0;
+    for chunk in bytes.chunks(64) {
+        if chunk.iter().fold(false, |found, &b| found | special(b)) {
+            return chunk.iter().position(|&b| special(b)).map(|i| start + i);
+        }
+        start += chunk.len();
+    }
+    None
+}
+
+// Escape `text`, then handle characters that `encoding` lacks following `policy`.
+fn escape_encoded<'a>(
+    text: &'a str,
+    encoding: OutputEncoding,
+    policy: UnmappablePolicy,
+    context: &'static str,
+) -> Result<Cow<'a, str>> {
+    encode_escaped(escape(text), encoding, policy, context)
+}
+
+// Handle characters of already escaped text that `encoding` lacks following `policy`.
+fn encode_escaped<'a>(
+    escaped: Cow<'a, str>,
+    encoding: OutputEncoding,
+    policy: UnmappablePolicy,
+    context: &'static str,
+) -> Result<Cow<'a, str>> {
+    match escaped {
+        Cow::Borrowed(text) => encoding.encode_value(text, policy, context),
+        Cow::Owned(escaped) => {
+            let replaced = match encoding.encode_value(&escaped, policy, context)? {
+                Cow::Borrowed(_) => None,
+                Cow::Owned(replaced) => Some(replaced),
+            };
+            Ok(Cow::Owned(replaced.unwrap_or(escaped)))
+        }
+    }
+}
+
+fn is_whitespace(text: &str) -> bool {
+    text.chars().all(is_whitespace_char)
+}
+
+// Whether `nodes` has text that would change if whitespace was added or removed.
+fn has_text(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Text(text) => !is_whitespace(text),
+        Node::CData(_) | Node::ExternalText(_) => true,
+        _ => false,
+    })
+}
+
+// Whether `nodes[index]` is a comment followed by an element, with only whitespace and comments between.
+// See `WriteOptions::align_comments`.
+fn is_aligned_comment(nodes: &[Node], index: usize) -> bool {
+    if !matches!(nodes.get(index), Some(Node::Comment(_))) {
+        return false;
+    }
+    let next = nodes[index + 1..].iter().find(|node| match node {
+        Node::Text(text) => !is_whitespace(text),
+        Node::Comment(_) => false,
+        _ => true,
+    });
+    matches!(next, Some(Node::Element(_)))
+}
+
+fn nodes_eq(doc: &Document, nodes: &[Node], other_doc: &Document, other: &[Node]) -> bool {
+    nodes.len() == other.len()
+        && nodes.iter().zip(other).all(|pair| match pair {
+            (Node::Element(a), Node::Element(b)) => {
+                a.full_name(doc) == b.full_name(other_doc)
+                    && a.attributes(doc) == b.attributes(other_doc)
+                    && a.namespace_decls(doc) == b.namespace_decls(other_doc)
+                    && nodes_eq(doc, a.children(doc), other_doc, b.children(other_doc))
+            }
+            (Node::Text(a), Node::Text(b)) | (Node::CData(a), Node::CData(b)) => a == b,
+            (Node::Comment(a), Node::Comment(b))
+            | (Node::PI(a), Node::PI(b))
+            | (Node::DocType(a), Node::DocType(b)) => a == b,
+            (Node::ExternalText(a), Node::ExternalText(b)) => {
+                a.is_cdata() == b.is_cdata()
+                    && a.len() == b.len()
+                    && a.read_to_string().ok() == b.read_to_string().ok()
+            }
+            // Same text, whether or not it was spilled to a file.
+            (Node::Text(a), Node::ExternalText(b)) | (Node::ExternalText(b), Node::Text(a)) => {
+                !b.is_cdata() && external_text_eq(a, b)
+            }
+            (Node::CData(a), Node::ExternalText(b)) | (Node::ExternalText(b), Node::CData(a)) => {
+                b.is_cdata() && external_text_eq(a, b)
+            }
+            _ => false,
+        })
+}
+
+fn external_text_eq(text: &str, external: &ExternalText) -> bool {
+    text.len() == external.len() && external.read_to_string().ok().as_deref() == Some(text)
+}
+
+impl FromStr for Document {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Document> {
+        Document::parse_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_element() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
         <basic>
             Text
             <c />
@@ -359,4 +2580,142 @@ mod tests {
             basic.children(&doc).last().unwrap().as_element().unwrap()
         )
     }
+
+    #[test]
+    fn test_nodes_around_root() {
+        let xml = r#"<?xml version="1.0"?>
+<?xml-stylesheet href="style.css"?>
+<!-- head -->
+<root/>
+<!-- tail -->"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        assert_eq!(doc.root_element_index(), Some(2));
+        assert!(matches!(
+            doc.nodes_before_root(),
+            [Node::PI(_), Node::Comment(_)]
+        ));
+        assert!(matches!(doc.nodes_after_root(), [Node::Comment(c)] if c == " tail "));
+
+        // Elements are pushed before trailing nodes, other nodes at the end.
+        let second = Element::new(&mut doc, "second");
+        doc.push_root_node(second.as_node()).unwrap();
+        let third = Element::new(&mut doc, "third");
+        doc.push_root_node(third.as_node()).unwrap();
+        doc.push_root_node(Node::Comment(" end ".to_string()))
+            .unwrap();
+        assert_eq!(doc.root_element(), Some(root));
+        assert_eq!(doc.root_element_index(), Some(2));
+        assert_eq!(doc.root_nodes()[3].as_element(), Some(second));
+        assert_eq!(doc.root_nodes()[4].as_element(), Some(third));
+        assert!(matches!(
+            doc.nodes_after_root(),
+            [
+                Node::Element(_),
+                Node::Element(_),
+                Node::Comment(_),
+                Node::Comment(_)
+            ]
+        ));
+
+        let mut doc = Document::new();
+        doc.push_root_node(Node::Comment(" only ".to_string()))
+            .unwrap();
+        assert_eq!(doc.root_element_index(), None);
+        assert_eq!(doc.nodes_before_root().len(), 1);
+        assert!(doc.nodes_after_root().is_empty());
+        let root = Element::new(&mut doc, "root");
+        doc.push_root_node(root.as_node()).unwrap();
+        assert_eq!(doc.root_element_index(), Some(1));
+    }
+
+    #[test]
+    fn test_rename_prefix() {
+        let xml = include_str!("../tests/documents/namespace.xml");
+        let mut doc = Document::from_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let foo = root.child_elements(&doc)[0];
+        let bar = root.child_elements(&doc)[1];
+        let c = bar.child_elements(&doc)[0];
+        let elements = doc.container.child_elements_recursive(&doc);
+        let namespaces: Vec<_> = elements
+            .iter()
+            .map(|elem| (elem.namespace(&doc), elem.namespace_for_prefix(&doc, "p")))
+            .map(|(ns, p_ns)| (ns.map(String::from), p_ns.map(String::from)))
+            .collect();
+
+        // 2 declarations, 2 element names, 1 attribute
+        assert_eq!(doc.rename_prefix("p", "q").unwrap(), 5);
+        for (elem, (ns, p_ns)) in elements.iter().zip(&namespaces) {
+            assert_eq!(elem.namespace(&doc), ns.as_deref());
+            assert_eq!(elem.namespace_for_prefix(&doc, "q"), p_ns.as_deref());
+        }
+        assert_eq!(foo.full_name(&doc), "q:foo");
+        assert_eq!(foo.attribute(&doc, "q:attr"), Some("val"));
+        assert_eq!(foo.namespace(&doc), Some("pns"));
+        assert_eq!(bar.namespace(&doc), Some("in2"));
+        assert_eq!(c.namespace_for_prefix(&doc, "q"), Some("in2"));
+        assert_eq!(c.namespace_for_prefix(&doc, "p"), None);
+        let written = doc.write_str().unwrap();
+        assert!(!written.contains("p:"));
+        assert!(!written.contains("xmlns:p"));
+
+        // q is bound to a different namespace where p:x would resolve it.
+        let xml = r#"<root xmlns:p="a" xmlns:q="b"><p:x q:y="1"/></root>"#;
+        let opts = ReadOptions {
+            require_decl: false,
+            ..ReadOptions::default()
+        };
+        let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+        let x = doc.root_element().unwrap().child_elements(&doc)[0];
+        assert!(matches!(
+            doc.rename_prefix("p", "q"),
+            Err(Error::PrefixCollision(_))
+        ));
+        assert_eq!(x.full_name(&doc), "p:x");
+        assert_eq!(x.namespace(&doc), Some("a"));
+    }
+
+    #[test]
+    fn test_escape() {
+        for text in [
+            "",
+            "plain",
+            "<a href=\"x\">'&amp;'</a>",
+            "日本<語>",
+            "&",
+            "tail>",
+        ] {
+            let expected = quick_xml::escape::escape(text.as_bytes());
+            assert_eq!(escape(text).as_bytes(), expected.as_ref());
+        }
+        assert!(matches!(escape("clean 日本語"), Cow::Borrowed(_)));
+        let latin1 = OutputEncoding::Latin1;
+        let policy = UnmappablePolicy::CharRef;
+        assert_eq!(
+            escape_encoded("€ & é", latin1, policy, "text").unwrap(),
+            "&#8364; &amp; é"
+        );
+        assert!(matches!(
+            escape_encoded("é", latin1, policy, "text").unwrap(),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_estimate_memory_usage() {
+        let mut doc = Document::new();
+        let empty = doc.estimate_memory_usage();
+        assert!(empty >= std::mem::size_of::<Document>());
+
+        let container = doc.container();
+        let root = Element::build("root").push_to(&mut doc, container);
+        let small = doc.estimate_memory_usage();
+        assert!(small > empty);
+
+        let long = "x".repeat(10_000);
+        root.set_attribute(&mut doc, "attr", long.clone());
+        root.push_child(&mut doc, Node::Text(long.into())).unwrap();
+        assert!(doc.estimate_memory_usage() >= small + 20_000);
+    }
 }
@@ -0,0 +1,177 @@
+use crate::error::{Error, Result};
+use crate::parser::is_name;
+use quick_xml::escape::unescape;
+use std::collections::HashMap;
+
+/// Extract general internal entity declarations (`<!ENTITY name "value">`) from a DTD,
+/// to use as [`ReadOptions::entity_catalog`](crate::ReadOptions::entity_catalog).
+///
+/// The returned values are replacement texts: character references and predefined entities
+/// in them are expanded, as are references to entities declared before them in `dtd_text`.
+/// Parameter entities, external entities, comments and other declarations are skipped.
+/// If an entity is declared more than once, the first declaration is used.
+///
+/// This only reads `dtd_text`. Loading DTD files is up to the caller.
+///
+/// # Errors
+/// - [`Error::MalformedXML`]: A declaration is not terminated, an entity name is not a valid
+///   XML [Name](https://www.w3.org/TR/xml/#NT-Name), or a value references an undeclared entity.
+///
+/// ```
+/// use xml_doc::parse_dtd_entities;
+///
+/// let dtd = r#"<!ENTITY company "Acme &amp; Co.">
+/// <!ENTITY legalnotice "&#169; &company;">
+/// <!ENTITY % common SYSTEM "common.ent">"#;
+/// let entities = parse_dtd_entities(dtd).unwrap();
+/// assert_eq!(entities["legalnotice"], "© Acme & Co.");
+/// assert_eq!(entities.len(), 2);
+/// ```
+pub fn parse_dtd_entities(dtd_text: &str) -> Result<HashMap<String, String>> {
+    let mut entities = HashMap::new();
+    let mut rest = dtd_text;
+    while let Some(lt) = rest.find('<') {
+        rest = &rest[lt..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = skip_past(comment, "-->", "comment")?;
+        } else if let Some(pi) = rest.strip_prefix("<?") {
+            rest = skip_past(pi, "?>", "processing instruction")?;
+        } else if let Some(decl) = rest.strip_prefix("<!ENTITY") {
+            rest = parse_entity_decl(decl, &mut entities)?;
+        } else {
+            rest = skip_decl(&rest[1..])?;
+        }
+    }
+    Ok(entities)
+}
+
+fn skip_past<'a>(text: &'a str, end: &str, context: &str) -> Result<&'a str> {
+    match text.find(end) {
+        Some(pos) => Ok(&text[pos + end.len()..]),
+        None => Err(unterminated(context)),
+    }
+}
+
+fn unterminated(context: &str) -> Error {
+    Error::MalformedXML(format!("Unterminated {} in DTD", context))
+}
+
+// Skip to after the `>` ending the declaration, ignoring `>` in quoted strings.
+fn skip_decl(text: &str) -> Result<&str> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Ok(&text[i + 1..]),
+            (Some(q), c) if q == c => quote = None,
+            _ => (),
+        }
+    }
+    Err(unterminated("declaration"))
+}
+
+// Parse the declaration after `<!ENTITY`, and return the text after it.
+fn parse_entity_decl<'a>(decl: &'a str, entities: &mut HashMap<String, String>) -> Result<&'a str> {
+    let text = decl.trim_start_matches(is_whitespace);
+    if text.len() == decl.len() || text.starts_with('%') {
+        // `<!ENTITYfoo` is not an entity declaration, and parameter entities are ignored.
+        return skip_decl(decl);
+    }
+    let name_end = text
+        .find(|c: char| is_whitespace(c) || c == '>')
+        .unwrap_or(text.len());
+    let name = &text[..name_end];
+    if !is_name(name) {
+        return Err(Error::MalformedXML(format!(
+            "Invalid entity name '{}' in DTD",
+            name
+        )));
+    }
+    let text = text[name_end..].trim_start_matches(is_whitespace);
+    let quote = match text.chars().next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        // External entity
+        _ => return skip_decl(text),
+    };
+    let value_end = text[1..]
+        .find(quote)
+        .ok_or_else(|| unterminated("entity value"))?;
+    let value = &text[1..value_end + 1];
+    let rest = skip_decl(&text[value_end + 2..])?;
+    if !entities.contains_key(name) {
+        let value = expand_value(value, entities)?;
+        entities.insert(name.to_string(), value);
+    }
+    Ok(rest)
+}
+
+// Expand references in an entity value, using entities declared so far.
+fn expand_value(value: &str, entities: &HashMap<String, String>) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(amp) = rest.find('&') {
+        expanded.push_str(&rest[..amp]);
+        let end = rest[amp..]
+            .find(';')
+            .ok_or_else(|| unterminated("reference"))?
+            + amp;
+        let reference = &rest[amp..=end];
+        match entities.get(&rest[amp + 1..end]) {
+            Some(text) => expanded.push_str(text),
+            None => {
+                let unescaped = unescape(reference.as_bytes()).map_err(|_| {
+                    Error::MalformedXML(format!("Undefined entity '{}' in DTD", reference))
+                })?;
+                expanded.push_str(&String::from_utf8_lossy(&unescaped));
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\r' | '\n' | '\t' | ' ')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dtd_entities() {
+        let dtd = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- <!ENTITY commented "no"> -->
+<!ELEMENT doc (#PCDATA)>
+<!ATTLIST doc note CDATA "<!ENTITY quoted 'no'>">
+<!ENTITY % param "<!ENTITY inparam 'no'>">
+<!ENTITY ext SYSTEM "ext.xml">
+<!ENTITY logo SYSTEM "logo.png" NDATA png>
+<!ENTITY single 'it&apos;s "quoted" &lt;b&gt;'>
+<!ENTITY twice "first">
+<!ENTITY twice "second">
+%param;"#;
+        let entities = parse_dtd_entities(dtd).unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities["single"], r#"it's "quoted" <b>"#);
+        assert_eq!(entities["twice"], "first");
+    }
+
+    #[test]
+    fn test_parse_dtd_entities_errors() {
+        for dtd in [
+            r#"<!ENTITY name "value"#,
+            r#"<!ENTITY name "value""#,
+            r#"<!ENTITY 1name "value">"#,
+            r#"<!ENTITY name "&later;"><!ENTITY later "x">"#,
+            "<!-- comment",
+        ] {
+            assert!(
+                matches!(parse_dtd_entities(dtd), Err(Error::MalformedXML(_))),
+                "{}",
+                dtd
+            );
+        }
+    }
+}
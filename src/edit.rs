@@ -0,0 +1,105 @@
+use crate::document::{Document, WriteOptions};
+use crate::error::Result;
+use crate::parser::ReadOptions;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Options of [`edit_file()`].
+#[derive(Default)]
+pub struct EditOptions {
+    /// Copy the original file to `<file name>.bak` before it is overwritten. (default: `false`)
+    ///
+    /// An existing backup is overwritten. No backup is made if the file isn't written.
+    pub backup: bool,
+    /// Options to parse the file with.
+    pub read_opts: ReadOptions,
+    /// Options to write the edited document with.
+    pub write_opts: WriteOptions,
+}
+
+/// What [`edit_file()`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditOutcome {
+    /// Whether the file was written.
+    pub written: bool,
+    /// Size of the original file.
+    pub bytes_read: usize,
+    /// Size of the written file, or `0` if it wasn't written.
+    pub bytes_written: usize,
+    /// Path of the backup, if one was made.
+    pub backup: Option<PathBuf>,
+}
+
+/// Parse the file at `path`, edit the document with `f`, and write it back if `f` changed it.
+///
+/// The document is changed if its [`Document::revision()`] differs from before `f`,
+/// so any modification counts, even one that sets a value to what it already was.
+///
+/// The file is written to a temporary file next to it, which then replaces it
+/// with the permissions of the original.
+/// If `f` or anything else fails, the original file is left untouched.
+///
+/// ```no_run
+/// use xml_doc::{edit_file, EditOptions};
+///
+/// let opts = EditOptions { backup: true, ..EditOptions::default() };
+/// let outcome = edit_file("config.xml", opts, |doc| {
+///     let root = doc.root_element().unwrap();
+///     root.set_attribute(doc, "version", "2");
+///     Ok(())
+/// }).unwrap();
+/// assert!(outcome.written);
+/// ```
+///
+/// # Errors
+/// - Errors from reading, parsing or writing the file.
+/// - Errors returned by `f`.
+pub fn edit_file<P, F>(path: P, opts: EditOptions, f: F) -> Result<EditOutcome>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut Document) -> Result<()>,
+{
+    let path = path.as_ref();
+    let original = fs::read(path)?;
+    let mut doc = Document::parse_bytes_with_opts(&original, opts.read_opts)?;
+    let revision = doc.revision();
+    f(&mut doc)?;
+
+    let mut outcome = EditOutcome {
+        written: false,
+        bytes_read: original.len(),
+        bytes_written: 0,
+        backup: None,
+    };
+    if doc.revision() == revision {
+        return Ok(outcome);
+    }
+
+    let mut output = Vec::with_capacity(original.len());
+    doc.write_with_opts(&mut output, opts.write_opts)?;
+    if opts.backup {
+        let backup = with_suffix(path, ".bak");
+        fs::write(&backup, &original)?;
+        outcome.backup = Some(backup);
+    }
+    let temp = with_suffix(path, &format!(".{}.tmp", std::process::id()));
+    let permissions = fs::metadata(path)?.permissions();
+    let replaced = fs::write(&temp, &output)
+        .and_then(|_| fs::set_permissions(&temp, permissions))
+        .and_then(|_| fs::rename(&temp, path));
+    if let Err(err) = replaced {
+        let _ = fs::remove_file(&temp);
+        return Err(err.into());
+    }
+    outcome.written = true;
+    outcome.bytes_written = output.len();
+    Ok(outcome)
+}
+
+// `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(suffix);
+    path.with_file_name(name)
+}
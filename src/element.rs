@@ -1,14 +1,276 @@
-use crate::document::{Document, Node};
+use crate::document::{read_external_text, Document, MutationEvent, Node, NodeKind, TemplateId};
 use crate::error::{Error, Result};
-use std::collections::HashMap;
+use crate::highlight::{HighlightWriter, Theme};
+use crate::names::{Name, NameArena};
+use crate::parser::{is_name, is_whitespace_char, DocumentParser, ReadOptions};
+use crate::value::Value;
+use indexmap::IndexMap;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::str::FromStr;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct ElementData {
-    full_name: String,
-    attributes: HashMap<String, String>, // q:attr="val" => {"q:attr": "val"}
-    namespace_decls: HashMap<String, String>, // local namespace newly defined in attributes
+    full_name: Name,
+    attributes: IndexMap<String, Value>, // q:attr="val" => {"q:attr": "val"}
+    namespace_decls: IndexMap<String, String>, // local namespace newly defined in attributes
     parent: Option<Element>,
     children: Vec<Node>,
+    // Template whose root this element shares its content with. See `Element::push_template_instance`.
+    instance_of: Option<TemplateId>,
+    // How the element was written in the parsed source. See `Element::authored_shape`.
+    authored_shape: Option<ElementShape>,
+}
+
+/// Storage of [`ElementData`], where an [`Element`]'s id is its index.
+/// The container element is always at index 0. Elements are only removed
+/// when the document is cleared, or when a new element couldn't be pushed.
+#[derive(Debug, Clone)]
+pub(crate) struct ElementArena {
+    store: Vec<ElementData>,
+    names: NameArena,
+    // Incremented when the arena is cleared, so elements created before are no longer valid.
+    generation: u32,
+    // Emptied storage of cleared elements, reused by elements pushed afterwards.
+    spare_children: Vec<Vec<Node>>,
+    spare_attributes: Vec<IndexMap<String, Value>>,
+}
+
+impl ElementArena {
+    /// Create an arena with only the container element.
+    pub(crate) fn new() -> (ElementArena, Element) {
+        let container_data = ElementData {
+            full_name: Name::Owned(String::new()),
+            attributes: IndexMap::new(),
+            namespace_decls: IndexMap::new(),
+            parent: None,
+            children: Vec::new(),
+            instance_of: None,
+            authored_shape: None,
+        };
+        let arena = ElementArena {
+            store: vec![container_data],
+            names: NameArena::default(),
+            generation: 0,
+            spare_children: Vec::new(),
+            spare_attributes: Vec::new(),
+        };
+        (
+            arena,
+            Element {
+                id: 0,
+                generation: 0,
+            },
+        )
+    }
+
+    pub(crate) fn push(&mut self, mut data: ElementData) -> Element {
+        debug_assert!(!self.store.is_empty(), "container must be at index 0");
+        if data.children.capacity() == 0 {
+            if let Some(children) = self.spare_children.pop() {
+                data.children = children;
+            }
+        }
+        let elem = Element {
+            id: self.store.len(),
+            generation: self.generation,
+        };
+        self.store.push(data);
+        elem
+    }
+
+    /// Remove elements pushed after the arena had `len` elements.
+    /// Older elements they had as children are left without a parent.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        debug_assert!(len > 0, "container must be at index 0");
+        for data in self.store.split_off(len) {
+            for node in data.children {
+                if let Node::Element(child) = node {
+                    if child.id < len {
+                        self.store[child.id].parent = None;
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, elem: Element) -> bool {
+        elem.id < self.store.len() && elem.generation == self.generation
+    }
+
+    // Panics if `elem` isn't valid, rather than returning data of another element.
+    pub(crate) fn get(&self, elem: Element) -> &ElementData {
+        assert!(self.contains(elem), "Element isn't valid in the document");
+        &self.store[elem.id]
+    }
+
+    pub(crate) fn get_mut(&mut self, elem: Element) -> &mut ElementData {
+        assert!(self.contains(elem), "Element isn't valid in the document");
+        &mut self.store[elem.id]
+    }
+
+    /// The container element of the current generation.
+    pub(crate) fn container(&self) -> Element {
+        Element {
+            id: 0,
+            generation: self.generation,
+        }
+    }
+
+    /// Store `name` in the name arena. See [`ReadOptions::name_arena`](crate::ReadOptions::name_arena).
+    pub(crate) fn alloc_name(&mut self, name: &str) -> Name {
+        self.names.alloc(name)
+    }
+
+    pub(crate) fn name<'a>(&'a self, name: &'a Name) -> &'a str {
+        self.names.get(name)
+    }
+
+    /// Empty attributes map, reusing the storage of a cleared element if there is one.
+    pub(crate) fn take_attributes(&mut self) -> IndexMap<String, Value> {
+        self.spare_attributes.pop().unwrap_or_default()
+    }
+
+    /// Remove all elements except the container, and clear the container. Keeps capacity,
+    /// including that of the removed elements' children and attributes.
+    pub(crate) fn clear(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        for data in self.store.drain(1..) {
+            let ElementData {
+                mut children,
+                mut attributes,
+                ..
+            } = data;
+            if children.capacity() > 0 {
+                children.clear();
+                self.spare_children.push(children);
+            }
+            if attributes.capacity() > 0 {
+                attributes.clear();
+                self.spare_attributes.push(attributes);
+            }
+        }
+        let container = &mut self.store[0];
+        container.attributes.clear();
+        container.namespace_decls.clear();
+        container.children.clear();
+        self.names.clear();
+    }
+
+    /// Start a generation after the one of `previous`, so its elements aren't valid in this arena.
+    pub(crate) fn follow(&mut self, previous: &ElementArena) {
+        self.generation = previous.generation.wrapping_add(1);
+    }
+
+    /// Number of elements, including the container.
+    pub(crate) fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    /// Approximate heap bytes used by the arena. See [`Document::estimate_memory_usage()`].
+    pub(crate) fn heap_size(&self) -> usize {
+        let mut size = self.store.capacity() * std::mem::size_of::<ElementData>();
+        size += self.names.heap_size();
+        size += self.spare_children.capacity() * std::mem::size_of::<Vec<Node>>();
+        for children in &self.spare_children {
+            size += children.capacity() * std::mem::size_of::<Node>();
+        }
+        size += self.spare_attributes.capacity() * std::mem::size_of::<IndexMap<String, Value>>();
+        size += self
+            .spare_attributes
+            .iter()
+            .map(|attributes| map_heap_size(attributes, |_| 0))
+            .sum::<usize>();
+        // Shared values are counted once.
+        let mut seen = HashSet::new();
+        for data in &self.store {
+            size += data.full_name.heap_size();
+            size += map_heap_size(&data.attributes, |val| val.heap_size(&mut seen));
+            size += map_heap_size(&data.namespace_decls, String::capacity);
+            size += data.children.capacity() * std::mem::size_of::<Node>();
+            for node in &data.children {
+                size += match node {
+                    Node::Element(_) | Node::ExternalText(_) => 0,
+                    Node::Text(text) | Node::CData(text) => text.heap_size(&mut seen),
+                    Node::Comment(text) | Node::PI(text) | Node::DocType(text) => text.capacity(),
+                };
+            }
+        }
+        size
+    }
+}
+
+// Entries with their hash, and the index table. Ignores the index table's control bytes.
+fn map_heap_size<V, F>(map: &IndexMap<String, V>, mut value_size: F) -> usize
+where
+    F: FnMut(&V) -> usize,
+{
+    let entry_size = std::mem::size_of::<(usize, String, V)>() + std::mem::size_of::<usize>();
+    let mut size = map.capacity() * entry_size;
+    for (key, val) in map {
+        size += key.capacity() + value_size(val);
+    }
+    size
+}
+
+// Rename keys of `map` for which `rename` returns a new key, keeping their position.
+// Returns the number of renamed keys.
+pub(crate) fn rename_keys<V, F>(map: &mut IndexMap<String, V>, rename: F) -> usize
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut count = 0;
+    *map = std::mem::take(map)
+        .into_iter()
+        .map(|(key, value)| match rename(&key) {
+            Some(new_key) => {
+                count += 1;
+                (new_key, value)
+            }
+            None => (key, value),
+        })
+        .collect();
+    count
+}
+
+// The prefix declared by attribute `key`, if it is `xmlns` or `xmlns:prefix`.
+pub(crate) fn namespace_decl_prefix(key: &str) -> Option<&str> {
+    match key {
+        "xmlns" => Some(""),
+        key => key.strip_prefix("xmlns:"),
+    }
+}
+
+// Insert attribute `name`, or a namespace declaration if `name` declares one.
+fn insert_attribute(
+    attributes: &mut IndexMap<String, Value>,
+    namespace_decls: &mut IndexMap<String, String>,
+    name: String,
+    value: Value,
+) {
+    match namespace_decl_prefix(&name) {
+        Some(prefix) => {
+            namespace_decls.insert(prefix.to_string(), value.into_string());
+        }
+        None => {
+            attributes.insert(name, value);
+        }
+    };
+}
+
+// No empty text node, and no text node right after another. See `Element::normalize`.
+fn is_normalized(children: &[Node]) -> bool {
+    let mut prev_text = false;
+    for node in children {
+        match node {
+            Node::Text(text) if text.is_empty() || prev_text => return false,
+            Node::Text(_) => prev_text = true,
+            _ => prev_text = false,
+        }
+    }
+    true
 }
 
 /// An easy way to build a new element
@@ -45,18 +307,24 @@ pub(crate) struct ElementData {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ElementBuilder {
     full_name: String,
-    attributes: HashMap<String, String>,
-    namespace_decls: HashMap<String, String>,
-    text_content: Option<String>,
+    attributes: IndexMap<String, Value>,
+    namespace_decls: IndexMap<String, String>,
+    children: Vec<BuilderChild>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BuilderChild {
+    Node(Node),
+    Element(ElementBuilder),
 }
 
 impl ElementBuilder {
     fn new(full_name: String) -> ElementBuilder {
         ElementBuilder {
-            full_name: full_name,
-            attributes: HashMap::new(),
-            namespace_decls: HashMap::new(),
-            text_content: None,
+            full_name,
+            attributes: IndexMap::new(),
+            namespace_decls: IndexMap::new(),
+            children: Vec::new(),
         }
     }
 
@@ -76,7 +344,28 @@ impl ElementBuilder {
         S: Into<String>,
         T: Into<String>,
     {
-        self.attributes.insert(name.into(), value.into());
+        self.attributes
+            .insert(name.into(), Value::from(value.into()));
+        self
+    }
+
+    /// Add every attribute in `attrs`, in order.
+    ///
+    /// As when parsing, `xmlns` and `xmlns:prefix` keys add namespace declarations instead.
+    pub fn attributes<I, K, V>(mut self, attrs: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        for (name, value) in attrs {
+            insert_attribute(
+                &mut self.attributes,
+                &mut self.namespace_decls,
+                name.into(),
+                Value::from(value.into()),
+            );
+        }
         self
     }
 
@@ -89,25 +378,228 @@ impl ElementBuilder {
         self
     }
 
-    pub fn text_content<S: Into<String>>(mut self, text: S) -> Self {
-        self.text_content = Some(text.into());
+    /// Add a text node.
+    ///
+    /// Child nodes appear in the order they were added, so calling this more than once
+    /// adds more text nodes.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element, WriteOptions};
+    ///
+    /// let mut doc = Document::new();
+    /// let container = doc.container();
+    /// Element::build("script")
+    ///     .comment(" generated ")
+    ///     .text_content("before")
+    ///     .cdata("if (a < b) {}")
+    ///     .pi("pi data")
+    ///     .push_to(&mut doc, container);
+    /// let opts = WriteOptions { write_decl: false, pretty: false, ..WriteOptions::default() };
+    /// assert_eq!(
+    ///     doc.write_str_with_opts(opts).unwrap(),
+    ///     "<script><!-- generated -->before<![CDATA[if (a < b) {}]]><?pi data?></script>"
+    /// );
+    /// ```
+    pub fn text_content<S: Into<String>>(self, text: S) -> Self {
+        self.node(Node::Text(Value::from(text.into())))
+    }
+
+    /// Add text after the children added so far. If the last one is text,
+    /// `text` is appended to it instead of adding a node. See [`Element::append_text()`].
+    pub fn append_text<S: Into<String>>(mut self, text: S) -> Self {
+        match self.children.last_mut() {
+            Some(BuilderChild::Node(Node::Text(last))) => last.to_mut().push_str(&text.into()),
+            _ => self
+                .children
+                .push(BuilderChild::Node(Node::Text(Value::from(text.into())))),
+        }
+        self
+    }
+
+    /// Add a comment node.
+    pub fn comment<S: Into<String>>(self, text: S) -> Self {
+        self.node(Node::Comment(text.into()))
+    }
+
+    /// Add a CDATA node.
+    pub fn cdata<S: Into<String>>(self, text: S) -> Self {
+        self.node(Node::CData(Value::from(text.into())))
+    }
+
+    /// Add a processing instruction node. `content` is the target followed by its data,
+    /// e.g. `"xml-stylesheet href='a.xsl'"`.
+    pub fn pi<S: Into<String>>(self, content: S) -> Self {
+        self.node(Node::PI(content.into()))
+    }
+
+    /// Add a child node. A [`Node::Element`] must not have a parent,
+    /// and is attached when this element is built.
+    pub fn node(mut self, node: Node) -> Self {
+        self.children.push(BuilderChild::Node(node));
+        self
+    }
+
+    /// Add a child element, built when this element is built.
+    ///
+    /// Child nodes appear in the order they were added.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element};
+    ///
+    /// let mut doc = Document::new();
+    /// let host = Element::build("host").text_content("localhost");
+    /// let server = Element::build("server")
+    ///     .child(host)
+    ///     .child(Element::build("port").text_content("80"))
+    ///     .finish(&mut doc);
+    /// assert_eq!(server.child_text(&doc, "host").as_deref(), Some("localhost"));
+    /// ```
+    pub fn child(mut self, child: ElementBuilder) -> Self {
+        self.children.push(BuilderChild::Element(child));
         self
     }
 
+    /// Add a child element named `name`, built by `build` from a new builder.
+    /// Same as `.child(build(Element::build(name)))`.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element};
+    ///
+    /// let mut doc = Document::new();
+    /// let container = doc.container();
+    /// Element::build("config")
+    ///     .child_element("server", |server| {
+    ///         server
+    ///             .attribute("id", "main")
+    ///             .child_element("host", |host| host.text_content("localhost"))
+    ///             .child_element("port", |port| port.text_content("80"))
+    ///     })
+    ///     .child_element("debug", |debug| debug)
+    ///     .push_to(&mut doc, container);
+    ///
+    /// assert_eq!(doc.write_str().unwrap(), r#"<?xml version="1.0" encoding="UTF-8"?>
+    /// <config>
+    ///   <server id="main">
+    ///     <host>localhost</host>
+    ///     <port>80</port>
+    ///   </server>
+    ///   <debug/>
+    /// </config>"#);
+    /// ```
+    pub fn child_element<S, F>(self, name: S, build: F) -> Self
+    where
+        S: Into<String>,
+        F: FnOnce(ElementBuilder) -> ElementBuilder,
+    {
+        self.child(build(Element::build(name)))
+    }
+
+    /// Create the element in `doc`.
+    ///
+    /// Nothing is allocated in `doc` until the builder is finished,
+    /// so dropping an unfinished builder doesn't leave unused element data behind.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an element added with [`ElementBuilder::node()`] has a parent or is the container.
+    /// Use [`ElementBuilder::try_finish()`] to get an error instead.
     pub fn finish(self, doc: &mut Document) -> Element {
-        let elem = Element::with_data(doc, self.full_name, self.attributes, self.namespace_decls);
-        if let Some(text) = self.text_content {
-            elem.push_child(doc, Node::Text(text)).unwrap();
+        let elem = Element::with_data(
+            doc,
+            self.full_name.into(),
+            self.attributes,
+            self.namespace_decls,
+        );
+        for child in self.children {
+            match child {
+                BuilderChild::Element(builder) => {
+                    builder.push_to(doc, elem);
+                }
+                BuilderChild::Node(node) => {
+                    if let Err(err) = elem.attach_child(doc, None, node) {
+                        panic!("{}", err);
+                    }
+                }
+            }
         }
         elem
     }
 
+    /// Same as [`ElementBuilder::finish()`], but checks names and added elements first.
+    ///
+    /// # Errors
+    /// Nothing is created in `doc` on error.
+    /// - [`Error::InvalidName`]: The element name, an attribute name or a namespace prefix
+    ///   of the element or its child elements is not a valid XML [Name](https://www.w3.org/TR/xml/#NT-Name).
+    /// - [`Error::HasAParent`]: An element added with [`ElementBuilder::node()`] has a parent.
+    /// - [`Error::ContainerCannotMove`]: The container was added with [`ElementBuilder::node()`].
+    pub fn try_finish(self, doc: &mut Document) -> Result<Element> {
+        self.validate(doc)?;
+        Ok(self.finish(doc))
+    }
+
     /// Push this element to the parent's children.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parent` is not an element of `doc`,
+    /// or if an element added with [`ElementBuilder::node()`] has a parent or is the container.
+    /// Use [`ElementBuilder::try_push_to()`] to get an error instead.
     pub fn push_to(self, doc: &mut Document, parent: Element) -> Element {
         let elem = self.finish(doc);
-        elem.push_to(doc, parent).unwrap();
+        // A new element has no parent and isn't the container, so pushing it can't fail.
+        #[allow(clippy::unwrap_used)]
+        parent.attach_child(doc, None, elem.as_node()).unwrap();
         elem
     }
+
+    /// Same as [`ElementBuilder::push_to()`], but checks names and added elements first.
+    ///
+    /// # Errors
+    /// Nothing is created in `doc` on error,
+    /// and elements added with [`ElementBuilder::node()`] are left without a parent.
+    /// - Errors of [`ElementBuilder::try_finish()`].
+    /// - Errors of [`Element::push_child()`], such as [`Error::Rejected`] by the mutation validator.
+    pub fn try_push_to(self, doc: &mut Document, parent: Element) -> Result<Element> {
+        parent.check_valid(doc)?;
+        let len = doc.store.len();
+        let elem = self.try_finish(doc)?;
+        if let Err(err) = parent.push_child(doc, elem.as_node()) {
+            doc.store.truncate(len);
+            return Err(err);
+        }
+        Ok(elem)
+    }
+
+    fn validate(&self, doc: &Document) -> Result<()> {
+        let names = std::iter::once(&self.full_name)
+            .chain(self.attributes.keys())
+            .chain(self.namespace_decls.keys().filter(|p| !p.is_empty()));
+        for name in names {
+            if !is_name(name) {
+                return Err(Error::InvalidName {
+                    name: name.clone(),
+                    position: None,
+                });
+            }
+        }
+        for child in &self.children {
+            match child {
+                BuilderChild::Element(builder) => builder.validate(doc)?,
+                BuilderChild::Node(Node::Element(elem)) if !elem.is_valid(doc) => {
+                    return Err(Error::ElementNotFound)
+                }
+                BuilderChild::Node(Node::Element(elem)) if elem.is_container() => {
+                    return Err(Error::ContainerCannotMove)
+                }
+                BuilderChild::Node(Node::Element(elem)) if elem.parent(doc).is_some() => {
+                    return Err(Error::HasAParent)
+                }
+                BuilderChild::Node(_) => {}
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Represents an XML element. It acts as a pointer to actual element data stored in Document.
@@ -121,6 +613,7 @@ impl ElementBuilder {
 /// Note that an element may only interact with elements of the same document,
 /// but the crate doesn't know which document an element is from.
 /// Trying to push an element from a different Document may result in unexpected errors.
+/// Use [`Document::import_element()`] to copy an element into another document.
 ///
 /// # Examples
 ///
@@ -144,9 +637,11 @@ impl ElementBuilder {
 ///     .collect();
 /// ```
 ///
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Element {
     id: usize,
+    // Generation of the arena when the element was created. See `ElementArena::clear`.
+    generation: u32,
 }
 
 impl Element {
@@ -155,7 +650,23 @@ impl Element {
     /// If full_name contains `:`,
     /// everything before that will be interpreted as a namespace prefix.
     pub fn new<S: Into<String>>(doc: &mut Document, full_name: S) -> Self {
-        Self::with_data(doc, full_name.into(), HashMap::new(), HashMap::new())
+        let full_name: String = full_name.into();
+        Self::with_data(doc, full_name.into(), IndexMap::new(), IndexMap::new())
+    }
+
+    /// Create a new empty element with `full_name`, checking that it is a valid XML name.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidName`]: `full_name` is not a valid XML [Name](https://www.w3.org/TR/xml/#NT-Name).
+    pub fn try_new<S: Into<String>>(doc: &mut Document, full_name: S) -> Result<Self> {
+        let full_name = full_name.into();
+        if !is_name(&full_name) {
+            return Err(Error::InvalidName {
+                name: full_name,
+                position: None,
+            });
+        }
+        Ok(Self::new(doc, full_name))
     }
 
     /// Chain methods to build an element easily.
@@ -180,34 +691,20 @@ impl Element {
 
     pub(crate) fn with_data(
         doc: &mut Document,
-        full_name: String,
-        attributes: HashMap<String, String>,
-        namespace_decls: HashMap<String, String>,
+        full_name: Name,
+        attributes: IndexMap<String, Value>,
+        namespace_decls: IndexMap<String, String>,
     ) -> Element {
-        let elem = Element { id: doc.counter };
         let elem_data = ElementData {
             full_name,
             attributes,
             namespace_decls,
             parent: None,
             children: vec![],
+            instance_of: None,
+            authored_shape: None,
         };
-        doc.store.push(elem_data);
-        doc.counter += 1;
-        elem
-    }
-
-    /// Create a container Element
-    pub(crate) fn container() -> (Element, ElementData) {
-        let elem_data = ElementData {
-            full_name: String::new(),
-            attributes: HashMap::new(),
-            namespace_decls: HashMap::new(),
-            parent: None,
-            children: Vec::new(),
-        };
-        let elem = Element { id: 0 };
-        (elem, elem_data)
+        doc.store.push(elem_data)
     }
 
     /// Returns `true` if element is a container.
@@ -217,6 +714,18 @@ impl Element {
         self.id == 0
     }
 
+    #[cfg(any(feature = "capi", feature = "js"))]
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns `None` if there is no element with `id` in `doc`.
+    #[cfg(any(feature = "capi", feature = "js"))]
+    pub(crate) fn from_id(doc: &Document, id: usize) -> Option<Element> {
+        let generation = doc.store.generation;
+        Some(Element { id, generation }).filter(|elem| elem.is_valid(doc))
+    }
+
     /// Equivalent to `Node::Element(self)`
     pub fn as_node(&self) -> Node {
         Node::Element(*self)
@@ -234,30 +743,172 @@ impl Element {
 }
 
 /// Below are methods that take `&Document` as its first argument.
+///
+/// Methods that don't return a [`Result`] panic if the element isn't
+/// [valid](Element::is_valid) in the document. Those that do return [`Error::ElementNotFound`].
 impl Element {
+    /// Returns `true` if the element exists in `doc`.
+    ///
+    /// Elements are never removed from a document, even when detached,
+    /// so an element created in `doc` stays valid until its content is replaced by
+    /// [`Parser::parse_into()`](crate::Parser::parse_into). After that, old elements aren't valid,
+    /// even if an element with the same id was parsed.
+    /// An element of another document may be valid, but refer to another element.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Error, Parser, ReadOptions};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/></a>"#).unwrap();
+    /// let b = doc.root_element().unwrap().child_elements(&doc)[0];
+    /// Parser::new(ReadOptions::default()).parse_into(&mut doc, r#"<?xml version="1.0"?><a/>"#).unwrap();
+    /// assert!(!b.is_valid(&doc));
+    /// assert!(matches!(b.try_name(&doc), Err(Error::ElementNotFound)));
+    /// ```
+    pub fn is_valid(&self, doc: &Document) -> bool {
+        doc.store.contains(*self)
+    }
+
+    // Returns `Error::ElementNotFound` if the element isn't valid in `doc`.
+    pub(crate) fn check_valid(&self, doc: &Document) -> Result<()> {
+        match self.is_valid(doc) {
+            true => Ok(()),
+            false => Err(Error::ElementNotFound),
+        }
+    }
+
+    // Same as `check_valid`, for methods that panic instead.
+    pub(crate) fn assert_valid(&self, doc: &Document) {
+        if let Err(err) = self.check_valid(doc) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Same as [`Element::full_name()`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    /// - [`Error::ElementNotFound`]: The element isn't [valid](Element::is_valid) in `doc`.
+    pub fn try_full_name<'a>(&self, doc: &'a Document) -> Result<&'a str> {
+        self.check_valid(doc)?;
+        Ok(self.full_name(doc))
+    }
+
+    /// Same as [`Element::name()`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    /// - [`Error::ElementNotFound`]: The element isn't [valid](Element::is_valid) in `doc`.
+    pub fn try_name<'a>(&self, doc: &'a Document) -> Result<&'a str> {
+        self.check_valid(doc)?;
+        Ok(self.name(doc))
+    }
+
+    /// Same as [`Element::attribute()`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    /// - [`Error::ElementNotFound`]: The element isn't [valid](Element::is_valid) in `doc`.
+    pub fn try_attribute<'a>(&self, doc: &'a Document, name: &str) -> Result<Option<&'a str>> {
+        self.check_valid(doc)?;
+        Ok(self.attribute(doc, name))
+    }
+
+    /// Same as [`Element::parent()`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    /// - [`Error::ElementNotFound`]: The element isn't [valid](Element::is_valid) in `doc`.
+    pub fn try_parent(&self, doc: &Document) -> Result<Option<Element>> {
+        self.check_valid(doc)?;
+        Ok(self.parent(doc))
+    }
+
+    /// Same as [`Element::children()`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    /// - [`Error::ElementNotFound`]: The element isn't [valid](Element::is_valid) in `doc`.
+    pub fn try_children<'a>(&self, doc: &'a Document) -> Result<&'a Vec<Node>> {
+        self.check_valid(doc)?;
+        Ok(self.children(doc))
+    }
+
     fn data<'a>(&self, doc: &'a Document) -> &'a ElementData {
-        doc.store.get(self.id).unwrap()
+        doc.store.get(*self)
+    }
+
+    // Name, attributes and namespace declarations.
+    // A template instance reads them from the template root. Children are always its own.
+    fn content<'a>(&self, doc: &'a Document) -> &'a ElementData {
+        let data = self.data(doc);
+        match data.instance_of {
+            Some(template) => doc.store.get(doc.template_root(template)),
+            None => data,
+        }
     }
 
+    // A template instance is materialized first, so its data can be modified.
     fn mut_data<'a>(&self, doc: &'a mut Document) -> &'a mut ElementData {
-        doc.store.get_mut(self.id).unwrap()
+        self.materialize(doc);
+        doc.revision += 1;
+        doc.store.get_mut(*self)
+    }
+
+    // Replace all children, returning the old ones. Parents of elements aren't changed.
+    pub(crate) fn set_children(&self, doc: &mut Document, children: Vec<Node>) -> Vec<Node> {
+        std::mem::replace(&mut self.mut_data(doc).children, children)
+    }
+
+    // Keep only the children for which `keep` returns true. Removed elements aren't detached.
+    pub(crate) fn retain_children<F>(&self, doc: &mut Document, keep: F)
+    where
+        F: FnMut(&Node) -> bool,
+    {
+        self.mut_data(doc).children.retain(keep);
+    }
+
+    // Replace the child at `index` with `node`. Neither may be an element.
+    pub(crate) fn set_text_child(&self, doc: &mut Document, index: usize, node: Node) {
+        debug_assert!(!matches!(node, Node::Element(_)));
+        self.mut_data(doc).children[index] = node;
+    }
+
+    // Changing the parent doesn't materialize a template instance.
+    pub(crate) fn set_parent(&self, doc: &mut Document, parent: Option<Element>) {
+        doc.store.get_mut(*self).parent = parent;
+    }
+
+    // Use when modifying name, attributes or namespace declarations,
+    // so the start tag kept in high fidelity mode isn't written anymore.
+    fn mut_tag_data<'a>(&self, doc: &'a mut Document) -> &'a mut ElementData {
+        doc.raw_start_tags.remove(self);
+        self.mut_data(doc)
     }
 
     /// Returns true if this element is the root node of document.
     ///
     /// Note that this crate allows Document to have multiple elements, even though it's not valid xml.
     pub fn is_root(&self, doc: &Document) -> bool {
-        self.parent(doc).map_or(false, |p| p.is_container())
+        self.parent(doc).is_some_and(|p| p.is_container())
     }
 
     /// Get full name of element, including its namespace prefix.
     /// Use [`Element::name()`] to get its name without the prefix.
     pub fn full_name<'a>(&self, doc: &'a Document) -> &'a str {
-        &self.data(doc).full_name
+        doc.store.name(&self.content(doc).full_name)
     }
 
     pub fn set_full_name<S: Into<String>>(&self, doc: &mut Document, name: S) {
-        self.mut_data(doc).full_name = name.into();
+        self.mut_tag_data(doc).full_name = Name::Owned(name.into());
+    }
+
+    /// Same as [`Element::set_full_name()`], but checked by the document's mutation validator.
+    ///
+    /// # Errors
+    /// - [`Error::Rejected`]: The validator rejected the change. The element isn't modified.
+    pub fn try_set_full_name<S: Into<String>>(&self, doc: &mut Document, name: S) -> Result<()> {
+        let name = name.into();
+        doc.validate_mutation(&MutationEvent::SetName {
+            element: *self,
+            full_name: &name,
+        })?;
+        self.set_full_name(doc, name);
+        Ok(())
     }
 
     /// Get prefix and name of element. If it doesn't have prefix, will return an empty string.
@@ -281,14 +932,14 @@ impl Element {
     ///
     /// If prefix is an empty string, removes prefix.
     pub fn set_prefix<S: Into<String>>(&self, doc: &mut Document, prefix: S) {
-        let data = self.mut_data(doc);
-        let (_, name) = Self::separate_prefix_name(&data.full_name);
+        let name = self.name(doc);
         let prefix: String = prefix.into();
-        if prefix.is_empty() {
-            data.full_name = name.to_string();
+        let full_name = if prefix.is_empty() {
+            name.to_string()
         } else {
-            data.full_name = format!("{}:{}", prefix, name);
-        }
+            format!("{}:{}", prefix, name)
+        };
+        self.set_full_name(doc, full_name);
     }
 
     /// Get name of element, without its namespace prefix.
@@ -299,21 +950,78 @@ impl Element {
         self.prefix_name(doc).1
     }
 
+    /// Same as [`Element::name()`], but returns an owned `String` that doesn't borrow `doc`.
+    ///
+    /// ```compile_fail
+    /// # use xml_doc::Document;
+    /// # let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a/>"#).unwrap();
+    /// # let elem = doc.root_element().unwrap();
+    /// let name = elem.name(&doc);
+    /// elem.set_name(&mut doc, "b"); // `doc` is still borrowed by `name`
+    /// elem.set_attribute(&mut doc, "was", name);
+    /// ```
+    /// ```
+    /// # use xml_doc::Document;
+    /// # let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a/>"#).unwrap();
+    /// # let elem = doc.root_element().unwrap();
+    /// let name = elem.name_owned(&doc);
+    /// elem.set_name(&mut doc, "b");
+    /// elem.set_attribute(&mut doc, "was", name);
+    /// assert_eq!(elem.attribute(&doc, "was"), Some("a"));
+    /// ```
+    pub fn name_owned(&self, doc: &Document) -> String {
+        self.name(doc).to_string()
+    }
+
+    /// Returns `true` if [`Element::name()`] is `expected`.
+    ///
+    /// The result doesn't borrow `doc`, so it can be kept while modifying the document.
+    ///
+    /// ```
+    /// # use xml_doc::Document;
+    /// # let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/><c/></a>"#).unwrap();
+    /// # let root = doc.root_element().unwrap();
+    /// for elem in root.child_elements(&doc) {
+    ///     let was_b = elem.name_is(&doc, "b");
+    ///     elem.set_name(&mut doc, "item");
+    ///     if was_b {
+    ///         elem.set_attribute(&mut doc, "kind", "b");
+    ///     }
+    /// }
+    /// assert_eq!(root.find(&doc, "item").unwrap().attribute(&doc, "kind"), Some("b"));
+    /// ```
+    pub fn name_is(&self, doc: &Document, expected: &str) -> bool {
+        self.name(doc) == expected
+    }
+
+    /// Returns `true` if [`Element::full_name()`] is `expected`. See [`Element::name_is()`].
+    pub fn full_name_is(&self, doc: &Document, expected: &str) -> bool {
+        self.full_name(doc) == expected
+    }
+
+    /// Returns `true` if [`Element::prefix()`] is `expected`. See [`Element::name_is()`].
+    pub fn prefix_is(&self, doc: &Document, expected: &str) -> bool {
+        self.prefix(doc) == expected
+    }
+
     /// Set name of element, preserving its prefix.
     ///
     /// `name` should not have a `:`,
     /// or everything before `:` may be interpreted as namespace prefix.
     pub fn set_name<S: Into<String>>(&self, doc: &mut Document, name: S) {
-        let data = self.mut_data(doc);
-        let (prefix, _) = Self::separate_prefix_name(&data.full_name);
-        if prefix.is_empty() {
-            data.full_name = name.into();
+        let prefix = self.prefix(doc);
+        let full_name = if prefix.is_empty() {
+            name.into()
         } else {
-            data.full_name = format!("{}:{}", prefix, name.into());
-        }
+            format!("{}:{}", prefix, name.into())
+        };
+        self.set_full_name(doc, full_name);
     }
 
-    /// Get attributes of element.
+    /// Get attributes of element, in the order they were parsed or added.
+    ///
+    /// A new attribute is added at the end, and a changed one keeps its position.
+    /// Use [`IndexMap::shift_remove`] to remove one without changing the order of the rest.
     ///
     /// The attribute names may have namespace prefix. To strip the prefix and only its name, call [`Element::separate_prefix_name`].
     /// ```
@@ -331,8 +1039,8 @@ impl Element {
     ///     // ("", "id"), ("pre", "name")
     /// }
     /// ```
-    pub fn attributes<'a>(&self, doc: &'a Document) -> &'a HashMap<String, String> {
-        &self.data(doc).attributes
+    pub fn attributes<'a>(&self, doc: &'a Document) -> &'a IndexMap<String, Value> {
+        &self.content(doc).attributes
     }
 
     /// Get attribute value of an element by its full name. (Namespace prefix isn't stripped)
@@ -340,20 +1048,312 @@ impl Element {
         self.attributes(doc).get(name).map(|v| v.as_str())
     }
 
-    /// Add or set attribute.
+    /// Get an attribute whose local name is `local`, ignoring its namespace prefix.
+    /// Returns `(full_name, value)`.
     ///
-    /// If `name` contains a `:`,
-    /// everything before `:` will be interpreted as namespace prefix.
-    pub fn set_attribute<S, T>(&self, doc: &mut Document, name: S, value: T)
-    where
-        S: Into<String>,
-        T: Into<String>,
-    {
-        self.mut_attributes(doc).insert(name.into(), value.into());
+    /// If there are multiple matches, the attribute without prefix is returned if it exists,
+    /// otherwise the first one in [`Element::attributes_by_local_name()`].
+    pub fn attribute_by_local_name<'a>(
+        &self,
+        doc: &'a Document,
+        local: &str,
+    ) -> Option<(&'a str, &'a str)> {
+        if let Some((key, value)) = self.attributes(doc).get_key_value(local) {
+            return Some((key.as_str(), value.as_str()));
+        }
+        self.attributes_by_local_name(doc, local).into_iter().next()
     }
 
-    pub fn mut_attributes<'a>(&self, doc: &'a mut Document) -> &'a mut HashMap<String, String> {
-        &mut self.mut_data(doc).attributes
+    /// Get all attributes whose local name is `local`, ignoring their namespace prefix.
+    /// Returns `(full_name, value)`s in the order of [`Element::attributes()`].
+    pub fn attributes_by_local_name<'a>(
+        &self,
+        doc: &'a Document,
+        local: &str,
+    ) -> Vec<(&'a str, &'a str)> {
+        self.attributes(doc)
+            .iter()
+            .filter(|(key, _)| Element::separate_prefix_name(key).1 == local)
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+
+    /// Get attribute value by its namespace and local name, whatever prefix it uses.
+    ///
+    /// Attributes without prefix have no namespace, so `namespace` of `""` only matches them.
+    /// The default namespace doesn't apply to attributes.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let xml = r#"<?xml version="1.0"?><a xmlns:l="http://www.w3.org/1999/xlink" l:href="x" href="y"/>"#;
+    /// let doc = Document::parse_str(xml).unwrap();
+    /// let a = doc.root_element().unwrap();
+    /// assert_eq!(a.attribute_ns(&doc, "http://www.w3.org/1999/xlink", "href"), Some("x"));
+    /// assert_eq!(a.attribute_ns(&doc, "", "href"), Some("y"));
+    /// ```
+    pub fn attribute_ns<'a>(
+        &self,
+        doc: &'a Document,
+        namespace: &str,
+        local_name: &str,
+    ) -> Option<&'a str> {
+        self.attribute_key_ns(doc, namespace, local_name)
+            .and_then(|key| self.attribute(doc, key))
+    }
+
+    // Full name of the attribute with the expanded name. If more than one prefix
+    // is bound to `namespace`, the first one in attribute order is returned.
+    fn attribute_key_ns<'a>(
+        &self,
+        doc: &'a Document,
+        namespace: &str,
+        local_name: &str,
+    ) -> Option<&'a str> {
+        self.attributes(doc)
+            .keys()
+            .filter(|key| {
+                let (prefix, name) = Element::separate_prefix_name(key);
+                name == local_name
+                    && match prefix {
+                        "" => namespace.is_empty(),
+                        prefix => self.namespace_for_prefix(doc, prefix) == Some(namespace),
+                    }
+            })
+            .map(|key| key.as_str())
+            .next()
+    }
+
+    /// Add or set attribute.
+    ///
+    /// If `name` contains a `:`,
+    /// everything before `:` will be interpreted as namespace prefix.
+    pub fn set_attribute<S, T>(&self, doc: &mut Document, name: S, value: T)
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        self.mut_attributes(doc)
+            .insert(name.into(), Value::from(value.into()));
+    }
+
+    /// Add or set every attribute in `attrs`, in order.
+    ///
+    /// As when parsing, `xmlns` and `xmlns:prefix` keys set namespace declarations instead.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a id="0"/>"#).unwrap();
+    /// let a = doc.root_element().unwrap();
+    /// a.set_attributes(&mut doc, [("id", "1"), ("class", "x"), ("xmlns:x", "urn:x")]);
+    /// assert_eq!(a.attribute(&doc, "id"), Some("1"));
+    /// assert_eq!(a.attribute(&doc, "class"), Some("x"));
+    /// assert_eq!(a.namespace_for_prefix(&doc, "x"), Some("urn:x"));
+    /// ```
+    pub fn set_attributes<I, K, V>(&self, doc: &mut Document, attrs: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let data = self.mut_tag_data(doc);
+        for (name, value) in attrs {
+            insert_attribute(
+                &mut data.attributes,
+                &mut data.namespace_decls,
+                name.into(),
+                Value::from(value.into()),
+            );
+        }
+    }
+
+    /// Same as [`Element::set_attribute()`], but checked by the document's mutation validator.
+    ///
+    /// # Errors
+    /// - [`Error::Rejected`]: The validator rejected the change. The element isn't modified.
+    pub fn try_set_attribute<S, T>(&self, doc: &mut Document, name: S, value: T) -> Result<()>
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        let name = name.into();
+        let value = value.into();
+        doc.validate_mutation(&MutationEvent::SetAttribute {
+            element: *self,
+            name: &name,
+            value: &value,
+        })?;
+        self.set_attribute(doc, name, value);
+        Ok(())
+    }
+
+    /// Add or set attribute by its namespace and local name.
+    ///
+    /// An existing attribute with the same namespace and local name keeps its prefix.
+    /// Otherwise a prefix bound to `namespace` in scope is used, or `ns0`, `ns1`, ...
+    /// is declared on this element if there is none. `namespace` of `""` sets an attribute without prefix.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a xmlns:x="urn:x"/>"#).unwrap();
+    /// let a = doc.root_element().unwrap();
+    /// a.set_attribute_ns(&mut doc, "urn:x", "id", "1");
+    /// a.set_attribute_ns(&mut doc, "urn:y", "id", "2");
+    /// assert_eq!(a.attribute(&doc, "x:id"), Some("1"));
+    /// assert_eq!(a.attribute(&doc, "ns0:id"), Some("2"));
+    /// assert_eq!(a.namespace_for_prefix(&doc, "ns0"), Some("urn:y"));
+    /// ```
+    pub fn set_attribute_ns<S: Into<String>>(
+        &self,
+        doc: &mut Document,
+        namespace: &str,
+        local_name: &str,
+        value: S,
+    ) {
+        if let Some(key) = self.attribute_key_ns(doc, namespace, local_name) {
+            let key = key.to_string();
+            self.set_attribute(doc, key, value);
+            return;
+        }
+        if namespace.is_empty() {
+            self.set_attribute(doc, local_name, value);
+            return;
+        }
+        let prefix = match self.attribute_prefix_for_namespace(doc, namespace) {
+            Some(prefix) => prefix.to_string(),
+            None => {
+                let prefix = (0..)
+                    .map(|n| format!("ns{}", n))
+                    .find(|prefix| {
+                        self.namespace_for_prefix(doc, prefix).is_none()
+                            && !self.uses_prefix(doc, prefix)
+                    })
+                    .unwrap_or_default();
+                self.set_namespace_decl(doc, prefix.as_str(), namespace);
+                prefix
+            }
+        };
+        self.set_attribute(doc, format!("{}:{}", prefix, local_name), value);
+    }
+
+    // Like `prefix_for_namespace`, but never the default namespace.
+    fn attribute_prefix_for_namespace<'a>(&self, doc: &'a Document, uri: &str) -> Option<&'a str> {
+        match self.prefix_for_namespace(doc, uri) {
+            Some("") => self
+                .in_scope_namespaces(doc)
+                .into_iter()
+                .filter(|(prefix, ns)| !prefix.is_empty() && *ns == uri)
+                .map(|(prefix, _)| prefix)
+                .min(),
+            prefix => prefix,
+        }
+    }
+
+    /// Get attribute value by its full name, setting it to `default` first if it doesn't exist.
+    ///
+    /// The element isn't modified if the attribute already exists.
+    pub fn ensure_attribute<'a, S>(&self, doc: &'a mut Document, name: &str, default: S) -> &'a str
+    where
+        S: Into<String>,
+    {
+        if self.attribute(doc, name).is_none() {
+            self.set_attribute(doc, name, default);
+        }
+        self.attribute(doc, name).unwrap_or_default()
+    }
+
+    /// Remove attribute by its full name, returning its value.
+    ///
+    /// Returns `None` and doesn't modify the element if the attribute doesn't exist.
+    pub fn remove_attribute(&self, doc: &mut Document, name: &str) -> Option<String> {
+        self.attribute(doc, name)?;
+        self.mut_attributes(doc)
+            .shift_remove(name)
+            .map(Value::into_string)
+    }
+
+    /// Remove all attributes. Namespace declarations are kept.
+    pub fn clear_attributes(&self, doc: &mut Document) {
+        if !self.attributes(doc).is_empty() {
+            self.mut_attributes(doc).clear();
+        }
+    }
+
+    /// Change attributes with `f`, applying all changes at once if it returns `Ok`.
+    ///
+    /// Changes are made to an [`AttrBatch`], which shows the values as changed so far.
+    /// Added or changed attributes are checked before anything is applied.
+    /// Attributes keep their position, and new attributes are added at the end.
+    /// As with [`Element::set_attributes()`], `xmlns` and `xmlns:prefix` keys
+    /// set namespace declarations instead when the changes are applied.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><input min="1" max="9"/>"#).unwrap();
+    /// let input = doc.root_element().unwrap();
+    /// let result = input.update_attributes(&mut doc, |batch| {
+    ///     batch.set("min", "5");
+    ///     batch.set("max", "3");
+    ///     let min: i32 = batch.get("min").unwrap().parse().unwrap();
+    ///     let max: i32 = batch.get("max").unwrap().parse().unwrap();
+    ///     if min > max {
+    ///         return Err("min is larger than max".to_string());
+    ///     }
+    ///     Ok(())
+    /// });
+    /// assert!(result.is_err());
+    /// assert_eq!(input.attribute(&doc, "min"), Some("1"));
+    /// ```
+    ///
+    /// # Errors
+    /// Nothing is changed on error.
+    /// - [`Error::Rejected`]: `f` returned an error, with its message,
+    ///   or the document's mutation validator rejected an added or changed attribute.
+    /// - [`Error::InvalidName`]: The name of an added attribute isn't a valid XML
+    ///   [Name](https://www.w3.org/TR/xml/#NT-Name).
+    pub fn update_attributes<F>(&self, doc: &mut Document, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut AttrBatch) -> std::result::Result<(), String>,
+    {
+        self.check_valid(doc)?;
+        let original = self.attributes(doc);
+        let mut batch = AttrBatch {
+            element: *self,
+            attributes: original.clone(),
+        };
+        f(&mut batch).map_err(Error::Rejected)?;
+        if &batch.attributes == original {
+            return Ok(());
+        }
+        for (name, value) in &batch.attributes {
+            if original.get(name) == Some(value) {
+                continue;
+            }
+            if !original.contains_key(name) && !is_name(name) {
+                return Err(Error::InvalidName {
+                    name: name.clone(),
+                    position: None,
+                });
+            }
+            doc.validate_mutation(&MutationEvent::SetAttribute {
+                element: *self,
+                name,
+                value,
+            })?;
+        }
+        let data = self.mut_tag_data(doc);
+        data.attributes.clear();
+        for (name, value) in batch.attributes {
+            insert_attribute(&mut data.attributes, &mut data.namespace_decls, name, value);
+        }
+        Ok(())
+    }
+
+    pub fn mut_attributes<'a>(&self, doc: &'a mut Document) -> &'a mut IndexMap<String, Value> {
+        &mut self.mut_tag_data(doc).attributes
     }
 
     /// Gets the namespace of this element.
@@ -363,18 +1363,28 @@ impl Element {
         self.namespace_for_prefix(doc, self.prefix(doc))
     }
 
-    /// Gets HashMap of `xmlns:prefix=namespace` declared in this element's attributes.
+    /// Gets the namespace and the name without prefix of this element.
+    ///
+    /// Elements with the same expanded name have the same meaning, whatever prefix they use.
+    ///
+    /// `<x:name xmlns:x="urn:x">` -> `(Some("urn:x"), "name")`
+    pub fn expanded_name<'a>(&self, doc: &'a Document) -> (Option<&'a str>, &'a str) {
+        (self.namespace(doc), self.name(doc))
+    }
+
+    /// Gets map of `xmlns:prefix=namespace` declared in this element's attributes,
+    /// in the order they were parsed or added.
     ///
     /// Default namespace has empty string as key.
-    pub fn namespace_decls<'a>(&self, doc: &'a Document) -> &'a HashMap<String, String> {
-        &self.data(doc).namespace_decls
+    pub fn namespace_decls<'a>(&self, doc: &'a Document) -> &'a IndexMap<String, String> {
+        &self.content(doc).namespace_decls
     }
 
     pub fn mut_namespace_decls<'a>(
         &self,
         doc: &'a mut Document,
-    ) -> &'a mut HashMap<String, String> {
-        &mut self.mut_data(doc).namespace_decls
+    ) -> &'a mut IndexMap<String, String> {
+        &mut self.mut_tag_data(doc).namespace_decls
     }
 
     pub fn set_namespace_decl<S, T>(&self, doc: &mut Document, prefix: S, namespace: T)
@@ -386,6 +1396,15 @@ impl Element {
             .insert(prefix.into(), namespace.into());
     }
 
+    /// Remove namespace declaration of `prefix`, returning its namespace.
+    /// Use empty string for the default namespace.
+    ///
+    /// Returns `None` and doesn't modify the element if `prefix` isn't declared on this element.
+    pub fn remove_namespace_decl(&self, doc: &mut Document, prefix: &str) -> Option<String> {
+        self.namespace_decls(doc).get(prefix)?;
+        self.mut_namespace_decls(doc).shift_remove(prefix)
+    }
+
     /// Get namespace value given prefix, for this element.
     /// "xml" and "xmlns" returns its default namespace.
     pub fn namespace_for_prefix<'a>(&self, doc: &'a Document, prefix: &str) -> Option<&'a str> {
@@ -396,34 +1415,337 @@ impl Element {
         };
         let mut elem = *self;
         loop {
-            let data = elem.data(doc);
-            if let Some(value) = data.namespace_decls.get(prefix) {
+            if let Some(value) = elem.namespace_decls(doc).get(prefix) {
                 return Some(value);
             }
             elem = elem.parent(doc)?;
         }
     }
 
-    pub(crate) fn build_text_content<'a>(&self, doc: &'a Document, buf: &'a mut String) {
-        for child in self.children(doc) {
-            child.build_text_content(doc, buf);
+    /// Get a prefix bound to namespace `uri` that can be used in this element.
+    ///
+    /// Prefixes declared closer to this element are preferred.
+    /// A prefix bound to `uri` by an ancestor is skipped if a closer element rebinds it to another namespace.
+    /// Returns `Some("")` if `uri` is the default namespace, which doesn't apply to attributes.
+    /// "xml" and "xmlns" namespaces return their reserved prefix.
+    pub fn prefix_for_namespace<'a>(&self, doc: &'a Document, uri: &str) -> Option<&'a str> {
+        match uri {
+            "http://www.w3.org/XML/1998/namespace" => return Some("xml"),
+            "http://www.w3.org/2000/xmlns/" => return Some("xmlns"),
+            _ => (),
+        };
+        let mut elem = *self;
+        loop {
+            let mut prefixes: Vec<&str> = elem
+                .namespace_decls(doc)
+                .iter()
+                .filter(|(_, ns)| *ns == uri)
+                .map(|(prefix, _)| prefix.as_str())
+                .collect();
+            prefixes.sort_unstable();
+            for prefix in prefixes {
+                if self.namespace_for_prefix(doc, prefix) == Some(uri) {
+                    return Some(prefix);
+                }
+            }
+            elem = elem.parent(doc)?;
+        }
+    }
+
+    /// Prefixes of the element name and attribute names that aren't declared in scope.
+    ///
+    /// Writing an element with an undeclared prefix gives namespace-ill-formed XML.
+    /// See [`Document::check_prefixes()`] and [`WriteOptions::declare_missing_prefixes`](crate::WriteOptions::declare_missing_prefixes).
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a xmlns:x="urn:x"/>"#).unwrap();
+    /// let a = doc.root_element().unwrap();
+    /// a.set_attribute(&mut doc, "x:id", "1");
+    /// a.set_attribute(&mut doc, "y:id", "2");
+    /// assert_eq!(a.undeclared_prefixes(&doc), vec!["y"]);
+    /// ```
+    pub fn undeclared_prefixes<'a>(&self, doc: &'a Document) -> Vec<&'a str> {
+        let attr_prefixes = self
+            .attributes(doc)
+            .keys()
+            .map(|key| Self::separate_prefix_name(key).0);
+        let mut prefixes: Vec<&str> = std::iter::once(self.prefix(doc))
+            .chain(attr_prefixes)
+            .filter(|prefix| !prefix.is_empty() && self.namespace_for_prefix(doc, prefix).is_none())
+            .collect();
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        prefixes
+    }
+
+    // Whether the element name or an attribute name has `prefix`.
+    pub(crate) fn uses_prefix(&self, doc: &Document, prefix: &str) -> bool {
+        self.prefix_is(doc, prefix)
+            || self
+                .attributes(doc)
+                .keys()
+                .any(|key| Self::separate_prefix_name(key).0 == prefix)
+    }
+
+    /// Get all namespace declarations in scope for this element, as `prefix -> namespace`.
+    ///
+    /// Declarations closer to this element shadow ones declared in its ancestors.
+    /// Default namespace has empty string as key.
+    /// The reserved "xml" and "xmlns" prefixes are not included.
+    pub fn in_scope_namespaces<'a>(&self, doc: &'a Document) -> HashMap<&'a str, &'a str> {
+        let mut namespaces = HashMap::new();
+        let mut elem = *self;
+        loop {
+            for (prefix, ns) in elem.namespace_decls(doc) {
+                namespaces.entry(prefix.as_str()).or_insert(ns.as_str());
+            }
+            match elem.parent(doc) {
+                Some(parent) => elem = parent,
+                None => return namespaces,
+            }
         }
     }
 
     /// Concatenate all text content of this element, including its child elements `text_content()`.
+    /// Comments and processing instructions are not included.
     ///
     /// Implementation of [Node.textContent](https://developer.mozilla.org/en-US/docs/Web/API/Node/textContent)
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    /// Use [`Element::try_text_content()`] to get an error instead.
     pub fn text_content(&self, doc: &Document) -> String {
+        self.text_content_sep(doc, "")
+    }
+
+    /// Same as [`Element::text_content()`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    /// - [`Error::Io`]: The file of a [`Node::ExternalText`] can't be read.
+    pub fn try_text_content(&self, doc: &Document) -> Result<String> {
+        self.check_valid(doc)?;
+        let mut buf = String::new();
+        let mut chunks = self.text_chunks(doc);
+        while let Some(node) = chunks.next_node() {
+            match node {
+                Node::Text(text) | Node::CData(text) => buf.push_str(text),
+                Node::ExternalText(text) => buf.push_str(&text.read_to_string()?),
+                _ => {}
+            }
+        }
+        Ok(buf)
+    }
+
+    /// Like [`Element::text_content()`], but inserts `separator` between text nodes.
+    ///
+    /// Every non-empty [`Node::Text`] and [`Node::CData`] descendant is a separate chunk,
+    /// and `separator` is inserted between each consecutive chunk, in document order.
+    /// So `<p>one<b>two</b></p>` gives `"one"`, `separator`, `"two"`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    pub fn text_content_sep(&self, doc: &Document, separator: &str) -> String {
         let mut buf = String::new();
-        self.build_text_content(doc, &mut buf);
+        for chunk in self.text_chunks(doc) {
+            if !buf.is_empty() {
+                buf.push_str(separator);
+            }
+            buf.push_str(&chunk);
+        }
         buf
     }
 
+    /// Iterate over the text of [`Node::Text`], [`Node::CData`] and [`Node::ExternalText`] descendants
+    /// in document order, without concatenating them. Empty text nodes are skipped.
+    ///
+    /// Concatenating the chunks gives [`Element::text_content()`].
+    /// Chunks are borrowed, except for `ExternalText` which is read from its file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str("<?xml version='1.0'?><p>one<b>two</b><![CDATA[three]]></p>").unwrap();
+    /// let p = doc.root_element().unwrap();
+    /// let chunks: Vec<_> = p.text_chunks(&doc).collect();
+    /// assert_eq!(chunks, vec!["one", "two", "three"]);
+    /// ```
+    pub fn text_chunks<'a>(&self, doc: &'a Document) -> TextChunks<'a> {
+        TextChunks {
+            doc,
+            stack: vec![self.children(doc).iter()],
+        }
+    }
+
+    /// Length in bytes of [`Element::text_content()`], without concatenating the text.
+    ///
+    /// The length of [`Node::ExternalText`] is known without reading its file.
+    pub fn text_content_len(&self, doc: &Document) -> usize {
+        let mut len = 0;
+        let mut chunks = self.text_chunks(doc);
+        while let Some(node) = chunks.next_node() {
+            len += match node {
+                Node::Text(text) | Node::CData(text) => text.len(),
+                Node::ExternalText(text) => text.len(),
+                _ => 0,
+            };
+        }
+        len
+    }
+
+    /// Returns `true` if [`Element::text_content()`] isn't empty, without concatenating the text.
+    /// Stops at the first text that counts.
+    ///
+    /// If `ignore_whitespace` is `true`, text made only of XML whitespace (space, tab, `\r` and `\n`)
+    /// doesn't count. See [`Node::is_whitespace_only()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ignore_whitespace` is `true` and the file of a [`Node::ExternalText`] can't be read.
+    ///
+    /// ```
+    /// use xml_doc::{Document, ReadOptions};
+    ///
+    /// let opts = ReadOptions { trim_text: false, ..ReadOptions::default() };
+    /// let doc = Document::parse_str_with_opts("<?xml version='1.0'?><a> <b>\n</b> </a>", opts).unwrap();
+    /// let a = doc.root_element().unwrap();
+    /// assert!(a.has_text_content(&doc, false));
+    /// assert!(!a.has_text_content(&doc, true));
+    /// ```
+    pub fn has_text_content(&self, doc: &Document, ignore_whitespace: bool) -> bool {
+        let mut chunks = self.text_chunks(doc);
+        while let Some(node) = chunks.next_node() {
+            if !ignore_whitespace || !node.is_whitespace_only(doc) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns `true` if [`Element::text_content()`] equals `text`,
+    /// comparing chunk by chunk without allocating.
+    ///
+    /// [`Node::ExternalText`] is streamed from its file, and doesn't match if it can't be read.
+    pub fn text_eq(&self, doc: &Document, text: &str) -> bool {
+        let mut rest = text.as_bytes();
+        let mut chunks = self.text_chunks(doc);
+        while let Some(node) = chunks.next_node() {
+            let matched = match node {
+                Node::Text(chunk) | Node::CData(chunk) => match rest.strip_prefix(chunk.as_bytes())
+                {
+                    Some(remaining) => {
+                        rest = remaining;
+                        true
+                    }
+                    None => false,
+                },
+                Node::ExternalText(external) => external
+                    .for_each_chunk(|chunk| match rest.strip_prefix(chunk) {
+                        Some(remaining) => {
+                            rest = remaining;
+                            Ok(())
+                        }
+                        None => Err(Error::MalformedXML(String::new())), // stops reading
+                    })
+                    .is_ok(),
+                _ => unreachable!(),
+            };
+            if !matched {
+                return false;
+            }
+        }
+        rest.is_empty()
+    }
+
     /// Clears all its children and inserts a [`Node::Text`] with given text.
     pub fn set_text_content<S: Into<String>>(&self, doc: &mut Document, text: S) {
         self.clear_children(doc);
-        let node = Node::Text(text.into());
-        self.push_child(doc, node).unwrap();
+        self.mut_data(doc)
+            .children
+            .push(Node::Text(Value::from(text.into())));
+    }
+
+    /// Same as [`Element::set_text_content()`], but checked by the document's mutation validator.
+    ///
+    /// # Errors
+    /// - [`Error::Rejected`]: The validator rejected the change. The element isn't modified.
+    pub fn try_set_text_content<S: Into<String>>(&self, doc: &mut Document, text: S) -> Result<()> {
+        let text = text.into();
+        doc.validate_mutation(&MutationEvent::SetText {
+            element: *self,
+            text: &text,
+        })?;
+        self.set_text_content(doc, text);
+        Ok(())
+    }
+
+    /// Add `text` at the end of its children, keeping the other children.
+    ///
+    /// If the last child is a [`Node::Text`], `text` is appended to it instead of adding a node.
+    /// `text` is added as is, so an empty `text` adds an empty text node
+    /// if the last child isn't text.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><p><b>Hello</b></p>"#).unwrap();
+    /// let p = doc.root_element().unwrap();
+    /// p.append_text(&mut doc, ", world");
+    /// p.append_text(&mut doc, "!");
+    /// assert_eq!(p.children(&doc).len(), 2);
+    /// assert_eq!(p.text_content(&doc), "Hello, world!");
+    /// ```
+    pub fn append_text<S: Into<String>>(&self, doc: &mut Document, text: S) {
+        let children = &mut self.mut_data(doc).children;
+        match children.last_mut() {
+            Some(Node::Text(last)) => last.to_mut().push_str(&text.into()),
+            _ => children.push(Node::Text(Value::from(text.into()))),
+        }
+    }
+
+    /// Add `text` at the start of its children, keeping the other children.
+    ///
+    /// If the first child is a [`Node::Text`], `text` is prepended to it instead of adding a node.
+    /// See [`Element::append_text()`].
+    pub fn prepend_text<S: Into<String>>(&self, doc: &mut Document, text: S) {
+        let children = &mut self.mut_data(doc).children;
+        match children.first_mut() {
+            Some(Node::Text(first)) => first.to_mut().insert_str(0, &text.into()),
+            _ => children.insert(0, Node::Text(Value::from(text.into()))),
+        }
+    }
+}
+
+/// Below are methods for writing an element.
+impl Element {
+    /// Write the element and its children, with tokens surrounded by strings from `theme`.
+    ///
+    /// With [`NullTheme`](crate::NullTheme), the output is the same as the element written
+    /// with [`Document::write()`] using default options. Attributes are written in stored order,
+    /// and the original start tag kept by [`ReadOptions::high_fidelity`](crate::ReadOptions::high_fidelity) isn't used.
+    ///
+    /// ```
+    /// use xml_doc::{AnsiTheme, Document};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><a href="/">Home</a>"#).unwrap();
+    /// let mut out = Vec::new();
+    /// doc.root_element().unwrap().write_highlighted(&doc, &mut out, &AnsiTheme).unwrap();
+    /// assert!(String::from_utf8(out).unwrap().starts_with("<\x1b[34ma\x1b[0m"));
+    /// ```
+    pub fn write_highlighted(
+        &self,
+        doc: &Document,
+        out: &mut impl std::io::Write,
+        theme: &dyn Theme,
+    ) -> Result<()> {
+        HighlightWriter::new(doc, out, theme).write_element(*self)
     }
 }
 
@@ -438,69 +1760,567 @@ impl Element {
         self.parent(doc).is_some()
     }
 
-    /// Get child [`Node`]s of this element.
-    pub fn children<'a>(&self, doc: &'a Document) -> &'a Vec<Node> {
-        &self.data(doc).children
+    /// Iterate over the parent, grandparent and so on, up to the root element.
+    ///
+    /// The container is not included, so the container and detached elements yield nothing.
+    /// In a detached subtree, iteration ends at the topmost detached element.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><list><deleted><item/></deleted></list>"#).unwrap();
+    /// let item = doc.root_element().unwrap().descendants(&doc).last().unwrap();
+    /// assert!(item.ancestors(&doc).any(|e| e.name_is(&doc, "deleted")));
+    /// ```
+    pub fn ancestors<'a>(&self, doc: &'a Document) -> Ancestors<'a> {
+        Ancestors {
+            doc,
+            next: self.parent(doc).filter(|p| !p.is_container()),
+        }
     }
 
-    fn _children_recursive<'a>(&self, doc: &'a Document, nodes: &mut Vec<&'a Node>) {
-        for node in self.children(doc) {
-            nodes.push(node);
-            if let Node::Element(elem) = &node {
-                elem._children_recursive(doc, nodes);
+    /// Returns `true` if `other` is the parent, grandparent and so on, of this element.
+    ///
+    /// Unlike [`Element::ancestors()`], the container counts as an ancestor,
+    /// so `elem.has_ancestor(&doc, doc.container())` checks that `elem` is attached to the document.
+    pub fn has_ancestor(&self, doc: &Document, other: Element) -> bool {
+        let mut elem = *self;
+        while let Some(parent) = elem.parent(doc) {
+            if parent == other {
+                return true;
             }
+            elem = parent;
         }
+        false
     }
 
-    /// Get all child nodes recursively. (i.e. includes its children's children.)
-    pub fn children_recursive<'a>(&self, doc: &'a Document) -> Vec<&'a Node> {
-        let mut nodes = Vec::new();
-        self._children_recursive(doc, &mut nodes);
-        nodes
+    // Parent's children and the index of this element in them.
+    // `None` for root elements and detached elements.
+    fn siblings<'a>(&self, doc: &'a Document) -> Option<(&'a [Node], usize)> {
+        let parent = self.parent(doc).filter(|p| !p.is_container())?;
+        let siblings = parent.children(doc);
+        let index = siblings
+            .iter()
+            .position(|n| n.as_element() == Some(*self))?;
+        Some((siblings, index))
     }
 
-    /// `!self.children(doc).is_empty()`
-    pub fn has_children(&self, doc: &Document) -> bool {
-        !self.children(doc).is_empty()
+    /// Get the node right after this element in its parent's children.
+    ///
+    /// Returns `None` for the last child, root elements and detached elements.
+    ///
+    /// Elements don't store their index, so this scans the parent's children to find it:
+    /// it takes `O(n)` time for an element with `n` siblings.
+    /// To visit every sibling, iterate over [`Element::children()`] of the parent instead.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Node};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/>text<c/></a>"#).unwrap();
+    /// let b = doc.root_element().unwrap().find(&doc, "b").unwrap();
+    /// assert!(matches!(b.next_sibling(&doc), Some(Node::Text(text)) if text == "text"));
+    /// assert_eq!(b.next_sibling_element(&doc).unwrap().name(&doc), "c");
+    /// assert!(b.prev_sibling(&doc).is_none());
+    /// ```
+    pub fn next_sibling<'a>(&self, doc: &'a Document) -> Option<&'a Node> {
+        let (siblings, index) = self.siblings(doc)?;
+        siblings.get(index + 1)
     }
 
-    /// Get only child [`Element`]s of this element.
+    /// Get the node right before this element in its parent's children.
     ///
-    /// This calls `.children().iter().filter_map().collect()`.
-    /// Use [`Element::children()`] if performance is important.
-    pub fn child_elements(&self, doc: &Document) -> Vec<Element> {
-        self.children(doc)
-            .iter()
-            .filter_map(|node| {
-                if let Node::Element(elemid) = node {
-                    Some(*elemid)
-                } else {
-                    None
-                }
-            })
-            .collect()
+    /// Returns `None` for the first child, root elements and detached elements.
+    /// Takes `O(n)` time like [`Element::next_sibling()`].
+    pub fn prev_sibling<'a>(&self, doc: &'a Document) -> Option<&'a Node> {
+        let (siblings, index) = self.siblings(doc)?;
+        siblings[..index].last()
     }
 
-    /// Get child [`Element`]s recursively. (i.e. includes its child element's child elements)
-    pub fn child_elements_recursive(&self, doc: &Document) -> Vec<Element> {
-        self.children_recursive(doc)
-            .iter()
-            .filter_map(|node| {
-                if let Node::Element(elemid) = node {
-                    Some(*elemid)
-                } else {
-                    None
-                }
-            })
-            .collect()
+    /// Get the first element after this element in its parent's children, skipping other nodes.
+    ///
+    /// Returns `None` for the last child element, root elements and detached elements.
+    /// Takes `O(n)` time like [`Element::next_sibling()`].
+    pub fn next_sibling_element(&self, doc: &Document) -> Option<Element> {
+        let (siblings, index) = self.siblings(doc)?;
+        siblings[index + 1..].iter().find_map(|n| n.as_element())
     }
 
-    /// Find first direct child element with name `name`.
+    /// Get the last element before this element in its parent's children, skipping other nodes.
+    ///
+    /// Returns `None` for the first child element, root elements and detached elements.
+    /// Takes `O(n)` time like [`Element::next_sibling()`].
+    pub fn prev_sibling_element(&self, doc: &Document) -> Option<Element> {
+        let (siblings, index) = self.siblings(doc)?;
+        siblings[..index].iter().rev().find_map(|n| n.as_element())
+    }
+
+    /// Get child [`Node`]s of this element.
+    pub fn children<'a>(&self, doc: &'a Document) -> &'a Vec<Node> {
+        &self.data(doc).children
+    }
+
+    /// Iterate over child [`Node`]s of `kind`.
+    pub fn children_of_kind<'a>(
+        &self,
+        doc: &'a Document,
+        kind: NodeKind,
+    ) -> impl DoubleEndedIterator<Item = &'a Node> + 'a {
+        self.children(doc)
+            .iter()
+            .filter(move |node| node.kind() == kind)
+    }
+
+    /// Get content of child [`Node::Comment`]s.
+    pub fn child_comments<'a>(&self, doc: &'a Document) -> Vec<&'a str> {
+        self.child_strings(doc, NodeKind::Comment).collect()
+    }
+
+    /// Get content of child [`Node::CData`]s.
+    pub fn child_cdata_sections<'a>(&self, doc: &'a Document) -> Vec<&'a str> {
+        self.child_strings(doc, NodeKind::CData).collect()
+    }
+
+    /// Get content of child [`Node::Text`]s. CDATA sections are not included.
+    pub fn child_text_nodes<'a>(&self, doc: &'a Document) -> Vec<&'a str> {
+        self.child_strings(doc, NodeKind::Text).collect()
+    }
+
+    /// Get content of the first child [`Node::Comment`].
+    pub fn first_comment<'a>(&self, doc: &'a Document) -> Option<&'a str> {
+        self.child_strings(doc, NodeKind::Comment).next()
+    }
+
+    fn child_strings<'a>(
+        &self,
+        doc: &'a Document,
+        kind: NodeKind,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.children_of_kind(doc, kind)
+            .filter_map(|node| match node {
+                Node::Text(text) | Node::CData(text) => Some(text.as_str()),
+                Node::Comment(text) => Some(text.as_str()),
+                _ => None,
+            })
+    }
+
+    /// Get all child nodes recursively. (i.e. includes its children's children.)
+    ///
+    /// Same as collecting [`Element::descendant_nodes()`].
+    pub fn children_recursive<'a>(&self, doc: &'a Document) -> Vec<&'a Node> {
+        self.descendant_nodes(doc).collect()
+    }
+
+    /// `!self.children(doc).is_empty()`
+    pub fn has_children(&self, doc: &Document) -> bool {
+        !self.children(doc).is_empty()
+    }
+
+    /// How the element was written in the parsed source.
+    /// `None` if the element wasn't parsed.
+    ///
+    /// The shape isn't updated when the element is modified.
+    ///
+    /// ```
+    /// use xml_doc::{Document, ElementShape};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/><c></c><d>x</d></a>"#).unwrap();
+    /// let a = doc.root_element().unwrap();
+    /// let shapes: Vec<_> = a.child_elements(&doc).iter().map(|e| e.authored_shape(&doc)).collect();
+    /// assert_eq!(shapes, [
+    ///     Some(ElementShape::SelfClosing),
+    ///     Some(ElementShape::EmptyPair),
+    ///     Some(ElementShape::WithContent),
+    /// ]);
+    /// ```
+    pub fn authored_shape(&self, doc: &Document) -> Option<ElementShape> {
+        self.data(doc).authored_shape
+    }
+
+    pub(crate) fn set_authored_shape(&self, doc: &mut Document, shape: ElementShape) {
+        doc.store.get_mut(*self).authored_shape = Some(shape);
+    }
+
+    /// Get only child [`Element`]s of this element.
+    ///
+    /// This calls `.children().iter().filter_map().collect()`.
+    /// Use [`Element::children()`] if performance is important.
+    pub fn child_elements(&self, doc: &Document) -> Vec<Element> {
+        self.children(doc)
+            .iter()
+            .filter_map(|node| {
+                if let Node::Element(elemid) = node {
+                    Some(*elemid)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Iterate over child [`Element`]s of this element without allocating a `Vec`.
+    ///
+    /// The iterator can be reversed with `.rev()`.
+    pub fn child_elements_iter<'a>(&self, doc: &'a Document) -> ChildElements<'a> {
+        ChildElements {
+            nodes: self.children(doc).iter(),
+            remaining: self.child_element_count(doc),
+        }
+    }
+
+    /// Borrowed list of child [`Element`]s of this element, which can be used in a `for` loop.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><list><a/>text<b/></list>"#).unwrap();
+    /// let list = doc.root_element().unwrap();
+    /// let mut names = Vec::new();
+    /// for child in list.child_element_list(&doc) {
+    ///     names.push(child.name(&doc));
+    /// }
+    /// assert_eq!(names, ["a", "b"]);
+    /// ```
+    pub fn child_element_list<'a>(&self, doc: &'a Document) -> ChildElementList<'a> {
+        ChildElementList {
+            nodes: self.children(doc),
+        }
+    }
+
+    /// Get child [`Element`]s whose [`Element::namespace()`] is `uri`, in document order.
+    ///
+    /// Use [`Element::children_grouped_by_namespace()`] to get elements without a namespace.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?>
+    /// <feed xmlns="urn:atom" xmlns:itunes="urn:itunes"><title/><itunes:author/><id/></feed>
+    /// "#).unwrap();
+    /// let feed = doc.root_element().unwrap();
+    /// let atom = feed.children_in_namespace(&doc, "urn:atom");
+    /// assert_eq!(atom.iter().map(|e| e.name(&doc)).collect::<Vec<_>>(), ["title", "id"]);
+    /// ```
+    pub fn children_in_namespace(&self, doc: &Document, uri: &str) -> Vec<Element> {
+        self.child_namespaces(doc)
+            .filter(|(_, namespace)| *namespace == Some(uri))
+            .map(|(elem, _)| elem)
+            .collect()
+    }
+
+    /// Group child [`Element`]s by their namespace, in document order within each group.
+    ///
+    /// Elements without a namespace, including those in scope of `xmlns=""`, are grouped under `None`.
+    /// Elements whose prefix isn't declared are left out.
+    pub fn children_grouped_by_namespace(
+        &self,
+        doc: &Document,
+    ) -> HashMap<Option<String>, Vec<Element>> {
+        let mut groups: HashMap<Option<String>, Vec<Element>> = HashMap::new();
+        for (elem, namespace) in self.child_namespaces(doc) {
+            groups
+                .entry(namespace.map(str::to_string))
+                .or_default()
+                .push(elem);
+        }
+        groups
+    }
+
+    // Child elements with their namespace, where `None` is no namespace.
+    // Elements with an undeclared prefix are skipped.
+    // Each prefix is resolved once for children that don't declare namespaces themselves.
+    fn child_namespaces<'a>(
+        &self,
+        doc: &'a Document,
+    ) -> impl Iterator<Item = (Element, Option<&'a str>)> + 'a {
+        let parent = *self;
+        let mut resolved: HashMap<&'a str, Option<&'a str>> = HashMap::new();
+        self.child_elements_iter(doc).filter_map(move |elem| {
+            let prefix = elem.prefix(doc);
+            let namespace = if elem.namespace_decls(doc).is_empty() {
+                *resolved
+                    .entry(prefix)
+                    .or_insert_with(|| parent.namespace_for_prefix(doc, prefix))
+            } else {
+                elem.namespace_for_prefix(doc, prefix)
+            };
+            match namespace {
+                Some("") => Some((elem, None)),
+                None if !prefix.is_empty() => None,
+                namespace => Some((elem, namespace)),
+            }
+        })
+    }
+
+    /// Iterate over descendant [`Element`]s in document order.
+    /// (i.e. includes its child element's child elements)
+    ///
+    /// Elements are visited as the iterator advances, so stopping early doesn't visit the rest of the tree.
+    pub fn descendants<'a>(&self, doc: &'a Document) -> Descendants<'a> {
+        Descendants {
+            nodes: self.descendant_nodes(doc),
+        }
+    }
+
+    /// Iterate over descendant [`Node`]s in document order.
+    /// (i.e. includes its children's children)
+    ///
+    /// Use [`DescendantNodes::with_depth()`] to get the depth of each node.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Node};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><a><b>text</b><c/></a>"#).unwrap();
+    /// let a = doc.root_element().unwrap();
+    /// let depths: Vec<usize> = a.descendant_nodes(&doc).with_depth().map(|(depth, _)| depth).collect();
+    /// assert_eq!(depths, [1, 2, 1]);
+    /// ```
+    pub fn descendant_nodes<'a>(&self, doc: &'a Document) -> DescendantNodes<'a> {
+        DescendantNodes {
+            doc,
+            stack: vec![self.children(doc).iter()],
+            depth: 0,
+        }
+    }
+
+    /// Number of child [`Element`]s, without allocating.
+    pub fn child_element_count(&self, doc: &Document) -> usize {
+        self.children(doc)
+            .iter()
+            .filter(|n| matches!(n, Node::Element(_)))
+            .count()
+    }
+
+    /// Get the first child [`Element`].
+    pub fn first_child_element(&self, doc: &Document) -> Option<Element> {
+        self.children(doc).iter().find_map(|n| n.as_element())
+    }
+
+    /// Get the last child [`Element`].
+    pub fn last_child_element(&self, doc: &Document) -> Option<Element> {
+        self.children(doc).iter().rev().find_map(|n| n.as_element())
+    }
+
+    /// Get the child [`Element`], if it is the only child element. Other nodes are ignored.
+    ///
+    /// # Errors
+    /// - [`Error::MissingChild`]: The element has no child elements.
+    /// - [`Error::ExtraChildren`]: The element has more than one child element.
+    pub fn only_child_element(&self, doc: &Document) -> Result<Element> {
+        self.check_valid(doc)?;
+        let mut elems = self.children(doc).iter().filter_map(|n| n.as_element());
+        let first = elems.next().ok_or_else(|| Error::MissingChild {
+            parent: self.full_name(doc).to_string(),
+            element_path: self.path(doc),
+            wanted: None,
+        })?;
+        if elems.next().is_some() {
+            return Err(Error::ExtraChildren {
+                parent: self.full_name(doc).to_string(),
+                element_path: self.path(doc),
+                wanted: None,
+                found_count: self.child_element_count(doc),
+            });
+        }
+        Ok(first)
+    }
+
+    /// Get the only direct child element with name `name`. Names are compared like [`Element::find()`].
+    ///
+    /// # Errors
+    /// - [`Error::MissingChild`]: There is no child element with the name.
+    /// - [`Error::ExtraChildren`]: There are more than one child element with the name.
+    pub fn expect_child(&self, doc: &Document, name: &str) -> Result<Element> {
+        self.check_valid(doc)?;
+        let mut elems = self
+            .child_elements_iter(doc)
+            .filter(|e| e.name_is(doc, name));
+        let first = elems.next().ok_or_else(|| Error::MissingChild {
+            parent: self.full_name(doc).to_string(),
+            element_path: self.path(doc),
+            wanted: Some(name.to_string()),
+        })?;
+        let extra = elems.count();
+        if extra > 0 {
+            return Err(Error::ExtraChildren {
+                parent: self.full_name(doc).to_string(),
+                element_path: self.path(doc),
+                wanted: Some(name.to_string()),
+                found_count: extra + 1,
+            });
+        }
+        Ok(first)
+    }
+
+    /// Get the text of an element that only has text, with leading and trailing whitespace trimmed.
+    /// Returns `""` if the element has no children.
+    ///
+    /// # Errors
+    /// - [`Error::NotTextOnly`]: The element has children other than a single
+    ///   [`Node::Text`] or [`Node::CData`].
+    /// - [`Error::ElementNotFound`]: The element isn't [valid](Element::is_valid) in `doc`.
+    pub fn expect_text<'a>(&self, doc: &'a Document) -> Result<&'a str> {
+        self.check_valid(doc)?;
+        match self.children(doc).as_slice() {
+            [] => Ok(""),
+            [Node::Text(text)] | [Node::CData(text)] => Ok(text.trim()),
+            _ => Err(Error::NotTextOnly {
+                element_path: self.path(doc),
+            }),
+        }
+    }
+
+    /// Get attribute value by its full name.
+    ///
+    /// # Errors
+    /// - [`Error::MissingAttribute`]: The element doesn't have the attribute.
+    /// - [`Error::ElementNotFound`]: The element isn't [valid](Element::is_valid) in `doc`.
+    pub fn expect_attribute<'a>(&self, doc: &'a Document, name: &str) -> Result<&'a str> {
+        self.check_valid(doc)?;
+        self.attribute(doc, name)
+            .ok_or_else(|| Error::MissingAttribute {
+                element_path: self.path(doc),
+                wanted: name.to_string(),
+            })
+    }
+
+    /// Parse the value of attribute `name` (its full name) with [`str::parse()`].
+    /// Returns `None` if the element doesn't have the attribute.
+    ///
+    /// The value is parsed as it is, without trimming whitespace.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><server port="8080" host="a"/>"#).unwrap();
+    /// let server = doc.root_element().unwrap();
+    /// assert_eq!(server.attribute_parse::<u16>(&doc, "port").unwrap(), Some(8080));
+    /// assert_eq!(server.attribute_parse::<u16>(&doc, "timeout").unwrap(), None);
+    /// assert!(server.attribute_parse::<u16>(&doc, "host").is_err());
+    /// ```
+    ///
+    /// # Errors
+    /// - [`Error::InvalidValue`]: The value can't be parsed into `T`.
+    pub fn attribute_parse<T>(&self, doc: &Document, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.check_valid(doc)?;
+        match self.attribute(doc, name) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|err| self.invalid_value(doc, Some(name), value, err)),
+            None => Ok(None),
+        }
+    }
+
+    /// Same as [`Element::attribute_parse()`], but returns `default` if the element
+    /// doesn't have the attribute.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidValue`]: The value can't be parsed into `T`.
+    pub fn attribute_or<T>(&self, doc: &Document, name: &str, default: T) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        Ok(self.attribute_parse(doc, name)?.unwrap_or(default))
+    }
+
+    /// Parse [`Element::text_content()`] with [`str::parse()`],
+    /// with leading and trailing whitespace trimmed.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><ratio> 0.5 </ratio>"#).unwrap();
+    /// let ratio = doc.root_element().unwrap();
+    /// assert_eq!(ratio.text_content_parse::<f64>(&doc).unwrap(), 0.5);
+    /// ```
+    ///
+    /// # Errors
+    /// - [`Error::InvalidValue`]: The text can't be parsed into `T`.
+    /// - [`Error::Io`]: The file of a [`Node::ExternalText`] can't be read.
+    pub fn text_content_parse<T>(&self, doc: &Document) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let text = self.try_text_content(doc)?;
+        let value = text.trim_matches(is_whitespace_char);
+        value
+            .parse()
+            .map_err(|err| self.invalid_value(doc, None, value, err))
+    }
+
+    fn invalid_value(
+        &self,
+        doc: &Document,
+        attribute: Option<&str>,
+        value: &str,
+        err: impl Display,
+    ) -> Error {
+        Error::InvalidValue {
+            element_path: self.path(doc),
+            attribute: attribute.map(str::to_string),
+            value: value.to_string(),
+            reason: err.to_string(),
+        }
+    }
+
+    /// Path of the element from the root, made of full names of its ancestors and itself.
+    /// When there are sibling elements with the same full name, 1-based position is added.
+    /// e.g. `/config/server[2]/name`
+    ///
+    /// The container's path is `/`. If the element isn't attatched to the document,
+    /// the path starts from its topmost ancestor.
+    pub fn path(&self, doc: &Document) -> String {
+        self.assert_valid(doc);
+        let mut segments = Vec::new();
+        let mut elem = *self;
+        while let Some(parent) = elem.parent(doc) {
+            let name = elem.full_name(doc);
+            let mut same_name = parent
+                .child_elements_iter(doc)
+                .filter(|e| e.full_name(doc) == name);
+            // `elem` is a child of `parent`.
+            #[allow(clippy::unwrap_used)]
+            let index = same_name.position(|e| e == elem).unwrap();
+            if index > 0 || same_name.next().is_some() {
+                segments.push(format!("{}[{}]", name, index + 1));
+            } else {
+                segments.push(name.to_string());
+            }
+            elem = parent;
+        }
+        if !elem.is_container() {
+            segments.push(elem.full_name(doc).to_string());
+        }
+        segments.reverse();
+        format!("/{}", segments.join("/"))
+    }
+
+    /// Get child [`Element`]s recursively. (i.e. includes its child element's child elements)
+    ///
+    /// Same as collecting [`Element::descendants()`].
+    pub fn child_elements_recursive(&self, doc: &Document) -> Vec<Element> {
+        self.descendants(doc).collect()
+    }
+
+    /// Find first direct child element with name `name`.
     pub fn find(&self, doc: &Document, name: &str) -> Option<Element> {
         self.children(doc)
             .iter()
             .filter_map(|n| n.as_element())
-            .find(|e| e.name(doc) == name)
+            .find(|e| e.name_is(doc, name))
+    }
+
+    /// Find last direct child element with name `name`.
+    pub fn rfind(&self, doc: &Document, name: &str) -> Option<Element> {
+        self.child_elements_iter(doc)
+            .rev()
+            .find(|e| e.name_is(doc, name))
     }
 
     /// Find all direct child element with name `name`.
@@ -508,229 +2328,2824 @@ impl Element {
         self.children(doc)
             .iter()
             .filter_map(|n| n.as_element())
-            .filter(|e| e.name(doc) == name)
+            .filter(|e| e.name_is(doc, name))
             .collect()
     }
-}
 
-/// Below are functions that modify its tree-structure.
-///
-/// Because an element has reference to both its parent and its children,
-/// an element's parent and children is not directly exposed for modification.
-/// But in return, it is not possible for a document to be in an inconsistant state,
-/// where an element's parent doesn't have the element as its children.
-impl Element {
-    /// Equivalent to `vec.push()`.
-    /// # Errors
-    /// - [`Error::HasAParent`]: When you want to replace an element's parent with another,
-    /// call `element.detatch()` to make it parentless first.
-    /// This is to make it explicit that you are changing an element's parent, not adding another.
-    /// - [`Error::ContainerCannotMove`]: The container element's parent must always be None.
-    pub fn push_child(&self, doc: &mut Document, node: Node) -> Result<()> {
-        if let Node::Element(elem) = node {
-            if elem.is_container() {
-                return Err(Error::ContainerCannotMove);
-            }
-            let data = elem.mut_data(doc);
-            if data.parent.is_some() {
-                return Err(Error::HasAParent);
-            }
-            data.parent = Some(*self);
+    /// Find an element by a `/`-separated path of element names, descending from this element.
+    ///
+    /// At each level, the first child element with the segment as its name is taken,
+    /// as with [`Element::find()`]. A segment with a prefix such as `ns:tag` is compared
+    /// with the full name instead. Returns `None` if any segment isn't found.
+    ///
+    /// A leading `/` starts from the document, so the first segment names the root element.
+    /// A trailing `/` is ignored. An empty path or an empty segment doesn't match anything.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let xml = r#"<?xml version="1.0"?><book><metadata><author>Lewis Carroll</author></metadata></book>"#;
+    /// let doc = Document::parse_str(xml).unwrap();
+    /// let book = doc.root_element().unwrap();
+    /// let author = book.find_path(&doc, "metadata/author").unwrap();
+    /// assert_eq!(author.text_content(&doc), "Lewis Carroll");
+    /// assert_eq!(book.find_path(&doc, "/book/metadata/author"), Some(author));
+    /// assert_eq!(book.find_path(&doc, "metadata/title"), None);
+    /// ```
+    pub fn find_path(&self, doc: &Document, path: &str) -> Option<Element> {
+        let (start, segments) = self.path_segments(doc, path)?;
+        segments.into_iter().try_fold(start, |elem, segment| {
+            elem.child_elements_iter(doc)
+                .find(|e| e.path_segment_is(doc, segment))
+        })
+    }
+
+    /// Find all direct child elements matching the last segment of `path`,
+    /// in the element found by [`Element::find_path()`] for the other segments.
+    ///
+    /// The result is empty if the path isn't found. Its first element is the one returned by `find_path`.
+    pub fn find_all_path(&self, doc: &Document, path: &str) -> Vec<Element> {
+        let (start, mut segments) = match self.path_segments(doc, path) {
+            Some(found) => found,
+            None => return Vec::new(),
+        };
+        // `path_segments` returns at least one segment.
+        #[allow(clippy::unwrap_used)]
+        let last = segments.pop().unwrap();
+        let parent = segments.into_iter().try_fold(start, |elem, segment| {
+            elem.child_elements_iter(doc)
+                .find(|e| e.path_segment_is(doc, segment))
+        });
+        match parent {
+            Some(parent) => parent
+                .child_elements_iter(doc)
+                .filter(|e| e.path_segment_is(doc, last))
+                .collect(),
+            None => Vec::new(),
         }
-        self.mut_data(doc).children.push(node);
-        Ok(())
     }
 
-    /// Equivalent to `parent.push_child()`.
+    // Element to start from and non-empty segments of `path`, or `None` if a segment is empty.
+    fn path_segments<'p>(&self, doc: &Document, path: &'p str) -> Option<(Element, Vec<&'p str>)> {
+        let (start, path) = match path.strip_prefix('/') {
+            Some(path) => (doc.container(), path),
+            None => (*self, path),
+        };
+        let path = path.strip_suffix('/').unwrap_or(path);
+        let segments: Vec<&str> = path.split('/').collect();
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return None;
+        }
+        Some((start, segments))
+    }
+
+    // A path segment with a prefix matches the full name, otherwise the name without prefix.
+    fn path_segment_is(&self, doc: &Document, segment: &str) -> bool {
+        if segment.contains(':') {
+            self.full_name_is(doc, segment)
+        } else {
+            self.name_is(doc, segment)
+        }
+    }
+
+    /// Find direct child elements that have a child element `child_name`
+    /// whose trimmed text content is `expected`.
     ///
-    /// # Errors
-    /// - [`Error::HasAParent`]: When you want to replace an element's parent with another,
-    /// call `element.detatch()` to make it parentless first.
-    /// This is to make it explicit that you are changing an element's parent, not adding another.
-    /// - [`Error::ContainerCannotMove`]: The container element's parent must always be None.
-    pub fn push_to(&self, doc: &mut Document, parent: Element) -> Result<()> {
-        parent.push_child(doc, self.as_node())
+    /// `child_name` is compared as a segment of [`Element::find_path()`].
+    /// Same as the query `*[child_name='expected']`. See [`Element::find_where_child_text_with()`].
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let xml = r#"<?xml version="1.0"?><jobs><job><status> failed </status></job><job><status>done</status></job></jobs>"#;
+    /// let doc = Document::parse_str(xml).unwrap();
+    /// let jobs = doc.root_element().unwrap();
+    /// let failed = jobs.find_where_child_text(&doc, "status", "failed");
+    /// assert_eq!(failed, &jobs.child_elements(&doc)[..1]);
+    /// ```
+    pub fn find_where_child_text(
+        &self,
+        doc: &Document,
+        child_name: &str,
+        expected: &str,
+    ) -> Vec<Element> {
+        self.find_where_child_text_with(doc, child_name, expected, TextMatch::Trimmed)
     }
 
-    /// Equivalent to `vec.insert()`.
+    /// Same as [`Element::find_where_child_text()`], comparing text as `text_match` says.
+    pub fn find_where_child_text_with(
+        &self,
+        doc: &Document,
+        child_name: &str,
+        expected: &str,
+        text_match: TextMatch,
+    ) -> Vec<Element> {
+        self.find_all_where(doc, |doc, e| {
+            e.has_child_text(doc, child_name, expected, text_match)
+        })
+    }
+
+    // Whether a child element matching `child_name` has text `expected`.
+    pub(crate) fn has_child_text(
+        &self,
+        doc: &Document,
+        child_name: &str,
+        expected: &str,
+        text_match: TextMatch,
+    ) -> bool {
+        self.child_elements_iter(doc).any(|child| {
+            child.path_segment_is(doc, child_name) && child.text_is(doc, expected, text_match)
+        })
+    }
+
+    // Text of `ExternalText` that can't be read doesn't match, as with `text_eq`.
+    pub(crate) fn text_is(&self, doc: &Document, expected: &str, text_match: TextMatch) -> bool {
+        match text_match {
+            TextMatch::Exact => self.text_eq(doc, expected),
+            TextMatch::Trimmed => self
+                .try_text_content(doc)
+                .is_ok_and(|text| text.trim() == expected),
+        }
+    }
+
+    /// Find first direct child element with local name `local_name` in namespace `namespace`,
+    /// whatever prefix it uses.
     ///
-    /// # Panics
+    /// The namespace of each child is resolved with [`Element::namespace()`],
+    /// so an unprefixed child is in the default namespace declared with `xmlns="..."` in scope.
+    /// An empty `namespace` matches children with no namespace.
     ///
-    /// Panics if `index > self.children().len()`
+    /// ```
+    /// use xml_doc::Document;
     ///
-    /// # Errors
-    /// - [`Error::HasAParent`]: When you want to replace an element's parent with another,
-    /// call `element.detatch()` to make it parentless first.
-    /// This is to make it explicit that you are changing an element's parent, not adding another.
-    /// - [`Error::ContainerCannotMove`]: The container element's parent must always be None.
-    pub fn insert_child(&self, doc: &mut Document, index: usize, node: Node) -> Result<()> {
-        if let Node::Element(elem) = node {
-            if elem.is_container() {
-                return Err(Error::ContainerCannotMove);
-            }
-            let data = elem.mut_data(doc);
-            if data.parent.is_some() {
-                return Err(Error::HasAParent);
+    /// let xml = r#"<?xml version="1.0"?><root xmlns:a="urn:x"><a:item/><item xmlns="urn:x"/><item/></root>"#;
+    /// let doc = Document::parse_str(xml).unwrap();
+    /// let root = doc.root_element().unwrap();
+    /// let children = root.child_elements(&doc);
+    /// assert_eq!(root.find_ns(&doc, "urn:x", "item"), Some(children[0]));
+    /// assert_eq!(root.find_all_ns(&doc, "urn:x", "item"), &children[..2]);
+    /// assert_eq!(root.find_ns(&doc, "", "item"), Some(children[2]));
+    /// ```
+    pub fn find_ns(&self, doc: &Document, namespace: &str, local_name: &str) -> Option<Element> {
+        self.child_elements_iter(doc)
+            .find(|e| e.name_is(doc, local_name) && e.namespace_is(doc, namespace))
+    }
+
+    /// Find all direct child elements with local name `local_name` in namespace `namespace`.
+    /// See [`Element::find_ns()`].
+    pub fn find_all_ns(&self, doc: &Document, namespace: &str, local_name: &str) -> Vec<Element> {
+        self.child_elements_iter(doc)
+            .filter(|e| e.name_is(doc, local_name) && e.namespace_is(doc, namespace))
+            .collect()
+    }
+
+    // An element with an undeclared prefix isn't in any namespace, not even the empty one.
+    fn namespace_is(&self, doc: &Document, namespace: &str) -> bool {
+        match self.namespace(doc) {
+            Some(ns) => ns == namespace,
+            None => namespace.is_empty() && self.prefix(doc).is_empty(),
+        }
+    }
+
+    /// Find first direct child element with name `name` and attribute `attr` equal to `value`.
+    ///
+    /// `attr` is matched against the full attribute name, so a query for `name`
+    /// doesn't match a prefixed attribute `ns:name`.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let xml = r#"<?xml version="1.0"?><servers><server name="dev"/><server name="prod"/></servers>"#;
+    /// let doc = Document::parse_str(xml).unwrap();
+    /// let servers = doc.root_element().unwrap();
+    /// let prod = servers.find_by_attribute(&doc, "server", "name", "prod").unwrap();
+    /// assert_eq!(prod, servers.child_elements(&doc)[1]);
+    /// ```
+    pub fn find_by_attribute(
+        &self,
+        doc: &Document,
+        name: &str,
+        attr: &str,
+        value: &str,
+    ) -> Option<Element> {
+        self.find_where(doc, |doc, e| {
+            e.name_is(doc, name) && e.attribute(doc, attr) == Some(value)
+        })
+    }
+
+    /// Find first direct child element for which `predicate` returns `true`.
+    pub fn find_where<F>(&self, doc: &Document, predicate: F) -> Option<Element>
+    where
+        F: Fn(&Document, Element) -> bool,
+    {
+        self.child_elements_iter(doc).find(|e| predicate(doc, *e))
+    }
+
+    /// Find all direct child elements for which `predicate` returns `true`.
+    pub fn find_all_where<F>(&self, doc: &Document, predicate: F) -> Vec<Element>
+    where
+        F: Fn(&Document, Element) -> bool,
+    {
+        self.child_elements_iter(doc)
+            .filter(|e| predicate(doc, *e))
+            .collect()
+    }
+
+    /// Text content of the first direct child element with name `name`,
+    /// or `None` if there isn't any. Same as `self.find(doc, name).map(|e| e.text_content(doc))`.
+    ///
+    /// If multiple children have the name, the first one wins.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str("<?xml version='1.0'?><book><title>Alice in Wonderland</title></book>").unwrap();
+    /// let book = doc.root_element().unwrap();
+    /// assert_eq!(book.child_text(&doc, "title").unwrap(), "Alice in Wonderland");
+    /// assert_eq!(book.child_text(&doc, "author"), None);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    /// Use [`Element::find()`] and [`Element::try_text_content()`] to get an error instead.
+    pub fn child_text(&self, doc: &Document, name: &str) -> Option<String> {
+        self.find(doc, name).map(|elem| elem.text_content(doc))
+    }
+
+    /// Same as [`Element::child_text()`], with leading and trailing whitespace removed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    pub fn child_text_trimmed(&self, doc: &Document, name: &str) -> Option<String> {
+        let elem = self.find(doc, name)?;
+        let text = elem.text_content(doc);
+        match text.trim() {
+            trimmed if trimmed.len() == text.len() => Some(text),
+            trimmed => Some(trimmed.to_string()),
+        }
+    }
+
+    /// Text content of the first direct child element with local name `name` in `namespace`,
+    /// or `None` if there isn't any. The prefix of the child doesn't matter.
+    /// See [`Element::find_ns()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    pub fn child_text_ns(&self, doc: &Document, namespace: &str, name: &str) -> Option<String> {
+        self.find_ns(doc, namespace, name)
+            .map(|elem| elem.text_content(doc))
+    }
+
+    /// Replace the text of the first direct child element with full name `full_name`,
+    /// creating it at the end of its children if there isn't any.
+    /// See [`Element::ensure_child()`] and [`Element::set_text_content()`].
+    ///
+    /// Returns the child element.
+    pub fn set_child_text<S: Into<String>>(
+        &self,
+        doc: &mut Document,
+        full_name: &str,
+        value: S,
+    ) -> Element {
+        let child = self.ensure_child(doc, full_name);
+        child.set_text_content(doc, value);
+        child
+    }
+}
+
+/// How text is compared by [`Element::find_where_child_text_with()`] and [`Element::evaluate_with()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextMatch {
+    /// Leading and trailing whitespace of the text is ignored.
+    #[default]
+    Trimmed,
+    /// The text must be equal.
+    Exact,
+}
+
+/// How an element was written in the parsed source. See [`Element::authored_shape()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ElementShape {
+    /// `<a/>`
+    SelfClosing,
+    /// `<a></a>`, without child nodes between the tags.
+    EmptyPair,
+    /// Start and end tags with child nodes between them.
+    /// Whitespace removed by [`ReadOptions::trim_text`] and
+    /// [`ReadOptions::ignore_whitespace_only`] doesn't count as child nodes.
+    WithContent,
+}
+
+/// Attribute changes that are applied together. See [`Element::update_attributes()`].
+#[derive(Debug)]
+pub struct AttrBatch {
+    element: Element,
+    attributes: IndexMap<String, Value>,
+}
+
+impl AttrBatch {
+    /// The element whose attributes are changed.
+    pub fn element(&self) -> Element {
+        self.element
+    }
+
+    /// Get attribute value by its full name, with the changes made so far.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(|v| v.as_str())
+    }
+
+    /// Attributes with the changes made so far.
+    pub fn attributes(&self) -> &IndexMap<String, Value> {
+        &self.attributes
+    }
+
+    /// Add or set attribute.
+    ///
+    /// `xmlns` and `xmlns:prefix` keys set namespace declarations when the changes are applied.
+    pub fn set<S, T>(&mut self, name: S, value: T)
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        self.attributes
+            .insert(name.into(), Value::from(value.into()));
+    }
+
+    /// Remove attribute by its full name, returning its value.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.attributes.shift_remove(name).map(Value::into_string)
+    }
+
+    /// Rename attribute `from` to `to`, keeping its value and position.
+    /// An attribute named `to` is replaced. Returns `false` if there is no attribute `from`.
+    pub fn rename<S: Into<String>>(&mut self, from: &str, to: S) -> bool {
+        let to = to.into();
+        if from == to {
+            return self.attributes.contains_key(from);
+        }
+        let (mut index, _, value) = match self.attributes.shift_remove_full(from) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if let Some((replaced, _, _)) = self.attributes.shift_remove_full(&to) {
+            if replaced < index {
+                index -= 1;
+            }
+        }
+        self.attributes.shift_insert(index, to, value);
+        true
+    }
+}
+
+/// Iterator over child elements. See [`Element::child_elements_iter()`].
+#[derive(Debug, Clone)]
+pub struct ChildElements<'a> {
+    nodes: std::slice::Iter<'a, Node>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for ChildElements<'a> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Element> {
+        let elem = self.nodes.find_map(|n| n.as_element())?;
+        self.remaining -= 1;
+        Some(elem)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> DoubleEndedIterator for ChildElements<'a> {
+    fn next_back(&mut self) -> Option<Element> {
+        let elem = self
+            .nodes
+            .rfind(|n| matches!(n, Node::Element(_)))?
+            .as_element();
+        self.remaining -= 1;
+        elem
+    }
+}
+
+impl<'a> ExactSizeIterator for ChildElements<'a> {}
+
+/// Child elements of an element. See [`Element::child_element_list()`].
+///
+/// Iterating over it, by value or by reference, yields a [`ChildElements`] iterator.
+#[derive(Debug, Clone, Copy)]
+pub struct ChildElementList<'a> {
+    nodes: &'a [Node],
+}
+
+impl<'a> ChildElementList<'a> {
+    /// Iterate over the child elements. Same as [`Element::child_elements_iter()`].
+    pub fn iter(&self) -> ChildElements<'a> {
+        ChildElements {
+            nodes: self.nodes.iter(),
+            remaining: self.len(),
+        }
+    }
+
+    /// Number of child elements.
+    pub fn len(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|n| matches!(n, Node::Element(_)))
+            .count()
+    }
+
+    /// Returns `true` if there is no child element.
+    pub fn is_empty(&self) -> bool {
+        self.first().is_none()
+    }
+
+    /// First child element.
+    pub fn first(&self) -> Option<Element> {
+        self.nodes.iter().find_map(|n| n.as_element())
+    }
+
+    /// Last child element.
+    pub fn last(&self) -> Option<Element> {
+        self.nodes.iter().rev().find_map(|n| n.as_element())
+    }
+}
+
+impl<'a> IntoIterator for ChildElementList<'a> {
+    type Item = Element;
+    type IntoIter = ChildElements<'a>;
+
+    fn into_iter(self) -> ChildElements<'a> {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &ChildElementList<'a> {
+    type Item = Element;
+    type IntoIter = ChildElements<'a>;
+
+    fn into_iter(self) -> ChildElements<'a> {
+        self.iter()
+    }
+}
+
+/// Iterator over ancestor elements, from the parent upwards. See [`Element::ancestors()`].
+#[derive(Debug, Clone)]
+pub struct Ancestors<'a> {
+    doc: &'a Document,
+    next: Option<Element>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Element> {
+        let elem = self.next?;
+        self.next = elem.parent(self.doc).filter(|p| !p.is_container());
+        Some(elem)
+    }
+}
+
+/// Iterator over descendant nodes in document order. See [`Element::descendant_nodes()`].
+#[derive(Debug, Clone)]
+pub struct DescendantNodes<'a> {
+    doc: &'a Document,
+    stack: Vec<std::slice::Iter<'a, Node>>,
+    depth: usize,
+}
+
+impl<'a> DescendantNodes<'a> {
+    /// Depth of the node last returned by `next()`, where children are at depth 1.
+    /// `0` before the first node.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Iterate over `(depth, node)` pairs. See [`DescendantNodes::depth()`].
+    pub fn with_depth(mut self) -> impl Iterator<Item = (usize, &'a Node)> {
+        std::iter::from_fn(move || {
+            let node = self.next()?;
+            Some((self.depth, node))
+        })
+    }
+}
+
+impl<'a> Iterator for DescendantNodes<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<&'a Node> {
+        loop {
+            let nodes = self.stack.last_mut()?;
+            match nodes.next() {
+                Some(node) => {
+                    self.depth = self.stack.len();
+                    if let Node::Element(elem) = node {
+                        self.stack.push(elem.children(self.doc).iter());
+                    }
+                    return Some(node);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over descendant elements in document order. See [`Element::descendants()`].
+#[derive(Debug, Clone)]
+pub struct Descendants<'a> {
+    nodes: DescendantNodes<'a>,
+}
+
+impl<'a> Descendants<'a> {
+    /// Depth of the element last returned by `next()`, where child elements are at depth 1.
+    /// `0` before the first element.
+    pub fn depth(&self) -> usize {
+        self.nodes.depth
+    }
+
+    /// Iterate over `(depth, element)` pairs. See [`Descendants::depth()`].
+    pub fn with_depth(mut self) -> impl Iterator<Item = (usize, Element)> + 'a {
+        std::iter::from_fn(move || {
+            let elem = self.next()?;
+            Some((self.depth(), elem))
+        })
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Element> {
+        self.nodes.find_map(|node| node.as_element())
+    }
+}
+
+/// Iterator over text chunks of an element. See [`Element::text_chunks()`].
+#[derive(Debug, Clone)]
+pub struct TextChunks<'a> {
+    doc: &'a Document,
+    stack: Vec<std::slice::Iter<'a, Node>>,
+}
+
+impl<'a> TextChunks<'a> {
+    // Next non-empty `Text`, `CData` or `ExternalText` node.
+    fn next_node(&mut self) -> Option<&'a Node> {
+        loop {
+            let nodes = self.stack.last_mut()?;
+            match nodes.next() {
+                Some(Node::Element(elem)) => self.stack.push(elem.children(self.doc).iter()),
+                Some(node @ Node::Text(text)) | Some(node @ Node::CData(text))
+                    if !text.is_empty() =>
+                {
+                    return Some(node);
+                }
+                Some(node @ Node::ExternalText(text)) if !text.is_empty() => return Some(node),
+                Some(_) => {}
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for TextChunks<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Cow<'a, str>> {
+        match self.next_node()? {
+            Node::Text(text) | Node::CData(text) => Some(Cow::Borrowed(text)),
+            Node::ExternalText(text) => Some(Cow::Owned(read_external_text(text))),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Below are functions that modify its tree-structure.
+///
+/// Because an element has reference to both its parent and its children,
+/// an element's parent and children is not directly exposed for modification.
+/// But in return, it is not possible for a document to be in an inconsistant state,
+/// where an element's parent doesn't have the element as its children.
+impl Element {
+    /// Equivalent to `vec.push()`.
+    /// # Errors
+    /// - [`Error::HasAParent`]: When you want to replace an element's parent with another,
+    ///   call `element.detatch()` to make it parentless first.
+    ///   This is to make it explicit that you are changing an element's parent, not adding another.
+    /// - [`Error::ContainerCannotMove`]: The container element's parent must always be None.
+    /// - [`Error::Rejected`]: The document's mutation validator rejected the change.
+    pub fn push_child(&self, doc: &mut Document, node: Node) -> Result<()> {
+        doc.validate_mutation(&MutationEvent::PushChild {
+            parent: *self,
+            node: &node,
+        })?;
+        self.attach_child(doc, None, node)
+    }
+
+    /// Equivalent to `parent.push_child()`.
+    ///
+    /// # Errors
+    /// - [`Error::HasAParent`]: When you want to replace an element's parent with another,
+    ///   call `element.detatch()` to make it parentless first.
+    ///   This is to make it explicit that you are changing an element's parent, not adding another.
+    /// - [`Error::ContainerCannotMove`]: The container element's parent must always be None.
+    pub fn push_to(&self, doc: &mut Document, parent: Element) -> Result<()> {
+        parent.push_child(doc, self.as_node())
+    }
+
+    /// Equivalent to `vec.insert()`.
+    ///
+    /// # Errors
+    /// - [`Error::IndexOutOfBounds`]: `index > self.children().len()`
+    /// - [`Error::HasAParent`]: When you want to replace an element's parent with another,
+    ///   call `element.detatch()` to make it parentless first.
+    ///   This is to make it explicit that you are changing an element's parent, not adding another.
+    /// - [`Error::ContainerCannotMove`]: The container element's parent must always be None.
+    /// - [`Error::Rejected`]: The document's mutation validator rejected the change.
+    pub fn insert_child(&self, doc: &mut Document, index: usize, node: Node) -> Result<()> {
+        doc.validate_mutation(&MutationEvent::PushChild {
+            parent: *self,
+            node: &node,
+        })?;
+        self.attach_child(doc, Some(index), node)
+    }
+
+    /// Parse `xml` as a fragment and push its nodes to this element. Returns the pushed nodes.
+    ///
+    /// The document's [`ReadOptions`] are used if it was parsed, otherwise the default options.
+    /// Prefixes in `xml` resolve to namespaces in scope of this element.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><list xmlns:x="urn:x"/>"#).unwrap();
+    /// let list = doc.root_element().unwrap();
+    /// let nodes = list.push_parsed_fragment(&mut doc, "<x:item>a</x:item><!--b--><x:item/>").unwrap();
+    /// assert_eq!(nodes.len(), 3);
+    /// let item = list.first_child_element(&doc).unwrap();
+    /// assert_eq!(item.namespace(&doc), Some("urn:x"));
+    /// ```
+    ///
+    /// # Errors
+    /// Nothing is pushed to this element on error.
+    /// - [`Error::MalformedXML`]: `xml` isn't a well-formed fragment,
+    ///   or it has an XML declaration or a DOCTYPE.
+    /// - [`Error::Rejected`]: The document's mutation validator rejected a node.
+    /// - Other errors from parsing, as [`Document::parse_str()`].
+    pub fn push_parsed_fragment(&self, doc: &mut Document, xml: &str) -> Result<Vec<Node>> {
+        let opts = doc.read_opts.clone().unwrap_or_default();
+        self.push_parsed_fragment_with(doc, xml, opts)
+    }
+
+    /// Same as [`Element::push_parsed_fragment()`], but parses with `opts`.
+    /// [`ReadOptions::require_decl`] and [`ReadOptions::encoding`] are ignored.
+    pub fn push_parsed_fragment_with(
+        &self,
+        doc: &mut Document,
+        xml: &str,
+        opts: ReadOptions,
+    ) -> Result<Vec<Node>> {
+        self.check_valid(doc)?;
+        let start = self.children(doc).len();
+        let diagnostics = doc.diagnostics.len();
+        let result = DocumentParser::parse_fragment(doc, *self, xml, opts).and_then(|_| {
+            let pushed = &self.children(doc)[start..];
+            for node in pushed {
+                doc.validate_mutation(&MutationEvent::PushChild {
+                    parent: *self,
+                    node,
+                })?;
+            }
+            Ok(pushed.to_vec())
+        });
+        match result {
+            Ok(pushed) => Ok(pushed),
+            Err(err) => {
+                // Nodes pushed before the error are detached.
+                if self.children(doc).len() > start {
+                    let pushed: Vec<Node> = self.mut_data(doc).children.drain(start..).collect();
+                    for node in pushed {
+                        if let Node::Element(elem) = node {
+                            elem.set_parent(doc, None);
+                        }
+                    }
+                }
+                doc.diagnostics.truncate(diagnostics);
+                Err(err)
+            }
+        }
+    }
+
+    // Push or insert `node` without consulting the mutation validator.
+    fn attach_child(&self, doc: &mut Document, index: Option<usize>, node: Node) -> Result<()> {
+        if let Some(index) = index {
+            let len = self.children(doc).len();
+            if index > len {
+                return Err(Error::IndexOutOfBounds { index, len });
+            }
+        }
+        if let Node::Element(elem) = node {
+            if elem.is_container() {
+                return Err(Error::ContainerCannotMove);
+            }
+            if elem.has_parent(doc) {
+                return Err(Error::HasAParent);
+            }
+            elem.set_parent(doc, Some(*self));
+        }
+        let children = &mut self.mut_data(doc).children;
+        match index {
+            Some(index) => children.insert(index, node),
+            None => children.push(node),
+        }
+        Ok(())
+    }
+
+    /// Get the first direct child element with full name `full_name`,
+    /// or create one and push it to the end of its children if there isn't any.
+    ///
+    /// Unlike [`Element::find()`], the namespace prefix is compared too.
+    pub fn ensure_child(&self, doc: &mut Document, full_name: &str) -> Element {
+        let index = self.children(doc).len();
+        self.ensure_child_at(doc, full_name, index)
+    }
+
+    /// Same as [`Element::ensure_child()`], but a new child is inserted at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a child needs to be created and `index > self.children().len()`.
+    /// Use [`Element::try_ensure_child_at()`] to get an error instead.
+    pub fn ensure_child_at(&self, doc: &mut Document, full_name: &str, index: usize) -> Element {
+        match self.try_ensure_child_at(doc, full_name, index) {
+            Ok(elem) => elem,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Same as [`Element::ensure_child_at()`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    /// - [`Error::IndexOutOfBounds`]: A child needs to be created and `index > self.children().len()`.
+    ///   Nothing is created in `doc`.
+    pub fn try_ensure_child_at(
+        &self,
+        doc: &mut Document,
+        full_name: &str,
+        index: usize,
+    ) -> Result<Element> {
+        self.check_valid(doc)?;
+        let existing = self
+            .child_elements_iter(doc)
+            .find(|e| e.full_name_is(doc, full_name));
+        if let Some(elem) = existing {
+            return Ok(elem);
+        }
+        let len = self.children(doc).len();
+        if index > len {
+            return Err(Error::IndexOutOfBounds { index, len });
+        }
+        let elem = Element::new(doc, full_name);
+        self.attach_child(doc, Some(index), elem.as_node())?;
+        Ok(elem)
+    }
+
+    /// Same as [`Element::remove_child()`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    /// - [`Error::IndexOutOfBounds`]: `index >= self.children().len()`
+    pub fn try_remove_child(&self, doc: &mut Document, index: usize) -> Result<Node> {
+        self.check_valid(doc)?;
+        let len = self.children(doc).len();
+        if index >= len {
+            return Err(Error::IndexOutOfBounds { index, len });
+        }
+        Ok(self.remove_child(doc, index))
+    }
+
+    /// Equivalent to `vec.remove()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= self.children().len()`.
+    /// Use [`Element::try_remove_child()`] to get an error instead.
+    pub fn remove_child(&self, doc: &mut Document, index: usize) -> Node {
+        let node = self.mut_data(doc).children.remove(index);
+        if let Node::Element(elem) = node {
+            elem.set_parent(doc, None);
+        }
+        node
+    }
+
+    /// Equivalent to `vec.pop()`.
+    pub fn pop_child(&self, doc: &mut Document) -> Option<Node> {
+        let child = self.mut_data(doc).children.pop();
+        if let Some(Node::Element(elem)) = &child {
+            elem.set_parent(doc, None);
+        }
+        child
+    }
+
+    /// Remove all children and return them.
+    pub fn clear_children(&self, doc: &mut Document) -> Vec<Node> {
+        let count = self.children(doc).len();
+        let mut removed = Vec::with_capacity(count);
+        for _ in 0..count {
+            let child = self.remove_child(doc, 0);
+            removed.push(child);
+        }
+        doc.synthesized_empty_text.remove(self);
+        removed
+    }
+
+    /// Sort all children with `compare`. The sort is stable.
+    ///
+    /// Text, comments and other nodes are sorted along with elements,
+    /// so they are placed wherever `compare` orders them.
+    /// Use [`Element::sort_child_elements_by_key()`] to keep them in place.
+    pub fn sort_children_by<F>(&self, doc: &mut Document, mut compare: F)
+    where
+        F: FnMut(&Document, &Node, &Node) -> Ordering,
+    {
+        self.materialize(doc);
+        let mut children = self.children(doc).clone();
+        children.sort_by(|a, b| compare(doc, a, b));
+        self.set_children(doc, children);
+    }
+
+    /// Sort child elements by the key `key` returns for each of them. The sort is stable.
+    ///
+    /// Other nodes keep their indexes, and the sorted elements fill the indexes elements had.
+    /// `key` is called once for each child element.
+    ///
+    /// ```
+    /// use xml_doc::{Document, NodeKind};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?>
+    /// <deps><dep name="b"/><!-- pinned --><dep name="c"/><dep name="a"/></deps>
+    /// "#).unwrap();
+    /// let deps = doc.root_element().unwrap();
+    /// deps.sort_child_elements_by_key(&mut doc, |doc, dep| dep.attribute(doc, "name").map(str::to_string));
+    /// let names: Vec<_> = deps.child_elements(&doc).iter().map(|dep| dep.attribute(&doc, "name")).collect();
+    /// assert_eq!(names, [Some("a"), Some("b"), Some("c")]);
+    /// assert_eq!(deps.children(&doc)[1].kind(), NodeKind::Comment);
+    /// ```
+    pub fn sort_child_elements_by_key<K, F>(&self, doc: &mut Document, mut key: F)
+    where
+        K: Ord,
+        F: FnMut(&Document, Element) -> K,
+    {
+        self.materialize(doc);
+        let mut keyed: Vec<(K, Element)> = self
+            .child_elements_iter(doc)
+            .map(|elem| (key(doc, elem), elem))
+            .collect();
+        keyed.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut sorted = keyed.into_iter().map(|(_, elem)| elem.as_node());
+        let children = self
+            .children(doc)
+            .iter()
+            .map(|node| match node {
+                Node::Element(_) => sorted.next().unwrap_or_else(|| node.clone()),
+                node => node.clone(),
+            })
+            .collect();
+        self.set_children(doc, children);
+    }
+
+    /// Removes itself from its parent. Note that you can't attach this element to other documents.
+    /// Copy it with [`Document::import_element()`] instead.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::ContainerCannotMove`]: You can't detatch container element
+    pub fn detatch(&self, doc: &mut Document) -> Result<()> {
+        self.check_valid(doc)?;
+        if self.is_container() {
+            return Err(Error::ContainerCannotMove);
+        }
+        if let Some(parent) = self.parent(doc) {
+            let pos = parent
+                .children(doc)
+                .iter()
+                .position(|n| n.as_element() == Some(*self));
+            if let Some(pos) = pos {
+                parent.remove_child(doc, pos);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the child at `index` with `node`, returning the replaced child.
+    /// A replaced element is left without a parent.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Node};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/><c/></a>"#).unwrap();
+    /// let a = doc.root_element().unwrap();
+    /// let old = a.replace_child(&mut doc, 0, Node::Comment("b".to_string())).unwrap();
+    /// assert_eq!(old.as_element().unwrap().parent(&doc), None);
+    /// assert_eq!(a.children(&doc)[0], Node::Comment("b".to_string()));
+    /// ```
+    ///
+    /// # Errors
+    /// - [`Error::IndexOutOfBounds`]: `index >= self.children().len()`
+    /// - [`Error::HasAParent`]: `node` is an element that already has a parent.
+    /// - [`Error::ContainerCannotMove`]: `node` is the container element.
+    /// - [`Error::Rejected`]: The document's mutation validator rejected the change.
+    pub fn replace_child(&self, doc: &mut Document, index: usize, node: Node) -> Result<Node> {
+        self.check_valid(doc)?;
+        let len = self.children(doc).len();
+        if index >= len {
+            return Err(Error::IndexOutOfBounds { index, len });
+        }
+        doc.validate_mutation(&MutationEvent::PushChild {
+            parent: *self,
+            node: &node,
+        })?;
+        if let Node::Element(elem) = node {
+            if elem.is_container() {
+                return Err(Error::ContainerCannotMove);
+            }
+            if elem.has_parent(doc) {
+                return Err(Error::HasAParent);
+            }
+            elem.set_parent(doc, Some(*self));
+        }
+        let old = std::mem::replace(&mut self.mut_data(doc).children[index], node);
+        if let Node::Element(elem) = old {
+            elem.set_parent(doc, None);
+        }
+        doc.synthesized_empty_text.remove(self);
+        Ok(old)
+    }
+
+    /// Replace itself in its parent with `node`, at the same position.
+    /// This element is left without a parent.
+    ///
+    /// # Errors
+    /// - [`Error::ContainerCannotMove`]: This element or `node` is the container element.
+    /// - [`Error::NoParent`]: This element has no parent.
+    /// - Errors of [`Element::replace_child()`].
+    pub fn replace_with(&self, doc: &mut Document, node: Node) -> Result<()> {
+        self.check_valid(doc)?;
+        if self.is_container() {
+            return Err(Error::ContainerCannotMove);
+        }
+        let parent = self.parent(doc).ok_or(Error::NoParent)?;
+        let index = parent
+            .children(doc)
+            .iter()
+            .position(|n| n.as_element() == Some(*self))
+            .ok_or(Error::NoParent)?;
+        parent.replace_child(doc, index, node)?;
+        Ok(())
+    }
+
+    /// Remove this element from its parent, and put its children in its place. Returns the moved children.
+    ///
+    /// This element is left without a parent and children.
+    /// Namespace declarations of this element aren't moved, so prefixes it declared
+    /// may not resolve in the moved children anymore.
+    ///
+    /// ```
+    /// use xml_doc::{Document, ReadOptions};
+    ///
+    /// let opts = ReadOptions { trim_text: false, ..ReadOptions::default() };
+    /// let mut doc = Document::parse_str_with_opts(r#"<?xml version="1.0"?><p>a <b>bold</b> c</p>"#, opts).unwrap();
+    /// let p = doc.root_element().unwrap();
+    /// let b = p.find(&doc, "b").unwrap();
+    /// b.dissolve(&mut doc).unwrap();
+    /// assert_eq!(p.children(&doc).len(), 3);
+    /// assert_eq!(p.text_content(&doc), "a bold c");
+    /// ```
+    ///
+    /// # Errors
+    /// - [`Error::ContainerCannotMove`]: This element is the container.
+    /// - [`Error::NoParent`]: This element has no parent.
+    pub fn dissolve(&self, doc: &mut Document) -> Result<Vec<Node>> {
+        self.check_valid(doc)?;
+        if self.is_container() {
+            return Err(Error::ContainerCannotMove);
+        }
+        let parent = self.parent(doc).ok_or(Error::NoParent)?;
+        let index = parent
+            .children(doc)
+            .iter()
+            .position(|n| n.as_element() == Some(*self))
+            .ok_or(Error::NoParent)?;
+        let children = std::mem::take(&mut self.mut_data(doc).children);
+        doc.synthesized_empty_text.remove(self);
+        for node in &children {
+            if let Node::Element(elem) = node {
+                elem.set_parent(doc, Some(parent));
+            }
+        }
+        parent
+            .mut_data(doc)
+            .children
+            .splice(index..=index, children.iter().cloned());
+        self.set_parent(doc, None);
+        Ok(children)
+    }
+
+    /// Create an element named `wrapper_name`, put it in place of this element in its parent,
+    /// and move this element into it. Returns the new element.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><list><item/><item/></list>"#).unwrap();
+    /// let list = doc.root_element().unwrap();
+    /// let item = list.child_elements(&doc)[1];
+    /// let deprecated = item.wrap(&mut doc, "deprecated").unwrap();
+    /// assert_eq!(list.child_elements(&doc)[1], deprecated);
+    /// assert_eq!(item.parent(&doc), Some(deprecated));
+    /// ```
+    ///
+    /// # Errors
+    /// - [`Error::ContainerCannotMove`]: This element is the container.
+    /// - [`Error::NoParent`]: This element has no parent. Nothing is created.
+    pub fn wrap(&self, doc: &mut Document, wrapper_name: &str) -> Result<Element> {
+        self.check_valid(doc)?;
+        if self.is_container() {
+            return Err(Error::ContainerCannotMove);
+        }
+        if !self.has_parent(doc) {
+            return Err(Error::NoParent);
+        }
+        let wrapper = Element::new(doc, wrapper_name);
+        self.wrap_with(doc, wrapper)?;
+        Ok(wrapper)
+    }
+
+    /// Same as [`Element::wrap()`], but wraps this element in `wrapper`,
+    /// after the children it already has.
+    ///
+    /// # Errors
+    /// - [`Error::ContainerCannotMove`]: This element or `wrapper` is the container.
+    /// - [`Error::NoParent`]: This element has no parent.
+    /// - [`Error::HasAParent`]: `wrapper` already has a parent.
+    pub fn wrap_with(&self, doc: &mut Document, wrapper: Element) -> Result<()> {
+        self.check_valid(doc)?;
+        wrapper.check_valid(doc)?;
+        if self.is_container() {
+            return Err(Error::ContainerCannotMove);
+        }
+        let parent = self.parent(doc).ok_or(Error::NoParent)?;
+        let index = parent
+            .children(doc)
+            .iter()
+            .position(|n| n.as_element() == Some(*self))
+            .ok_or(Error::NoParent)?;
+        parent.wrap_children(doc, index..index + 1, wrapper)
+    }
+
+    /// Move the children in `range` into `wrapper`, after the children it already has,
+    /// and put `wrapper` where they were.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><h/><p/><p/><h/></a>"#).unwrap();
+    /// let a = doc.root_element().unwrap();
+    /// let section = Element::new(&mut doc, "section");
+    /// a.wrap_children(&mut doc, 0..3, section).unwrap();
+    /// assert_eq!(a.children(&doc).len(), 2);
+    /// assert_eq!(section.children(&doc).len(), 3);
+    /// ```
+    ///
+    /// # Errors
+    /// - [`Error::IndexOutOfBounds`]: `range` isn't in `0..=self.children().len()`.
+    /// - [`Error::ContainerCannotMove`]: `wrapper` is the container.
+    /// - [`Error::HasAParent`]: `wrapper` already has a parent.
+    /// - [`Error::ElementNotFound`]: This element or `wrapper` isn't [valid](Element::is_valid) in `doc`.
+    pub fn wrap_children(
+        &self,
+        doc: &mut Document,
+        range: std::ops::Range<usize>,
+        wrapper: Element,
+    ) -> Result<()> {
+        self.check_valid(doc)?;
+        wrapper.check_valid(doc)?;
+        let len = self.children(doc).len();
+        if range.end > len {
+            return Err(Error::IndexOutOfBounds {
+                index: range.end,
+                len,
+            });
+        }
+        if range.start > range.end {
+            return Err(Error::IndexOutOfBounds {
+                index: range.start,
+                len: range.end,
+            });
+        }
+        if wrapper.is_container() {
+            return Err(Error::ContainerCannotMove);
+        }
+        if wrapper.has_parent(doc) {
+            return Err(Error::HasAParent);
+        }
+        let nodes: Vec<Node> = self
+            .mut_data(doc)
+            .children
+            .splice(range, std::iter::once(wrapper.as_node()))
+            .collect();
+        for node in &nodes {
+            if let Node::Element(elem) = node {
+                elem.set_parent(doc, Some(wrapper));
+            }
+        }
+        wrapper.set_parent(doc, Some(*self));
+        wrapper.mut_data(doc).children.extend(nodes);
+        Ok(())
+    }
+
+    /// Copy this element and its descendants into new elements, returning the copy of this element.
+    ///
+    /// The copy has no parent, and is written the same as this element.
+    /// Namespace declarations of ancestors aren't copied, so push the copy
+    /// where its prefixes are declared. Template instances are copied as materialized elements.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><table><row><cell/></row></table>"#).unwrap();
+    /// let table = doc.root_element().unwrap();
+    /// let row = table.find(&doc, "row").unwrap();
+    /// let copy = row.clone_subtree(&mut doc);
+    /// copy.set_attribute(&mut doc, "id", "2");
+    /// table.push_child(&mut doc, copy.as_node()).unwrap();
+    /// assert_eq!(table.child_elements(&doc).len(), 2);
+    /// assert_eq!(row.attribute(&doc, "id"), None);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if this element is the container. Use [`Document::clone()`] to copy the whole document.
+    pub fn clone_subtree(&self, doc: &mut Document) -> Element {
+        assert!(
+            !self.is_container(),
+            "Container element can't be cloned, clone the document instead"
+        );
+        self.deep_copy(doc)
+    }
+
+    /// Push a copy of this element and its descendants to `parent`, returning the copy.
+    /// See [`Element::clone_subtree()`].
+    ///
+    /// # Errors
+    /// - [`Error::ContainerCannotMove`]: This element is the container.
+    /// - Errors of [`Element::push_child()`]. The copy is left without a parent.
+    pub fn clone_to(&self, doc: &mut Document, parent: Element) -> Result<Element> {
+        self.check_valid(doc)?;
+        parent.check_valid(doc)?;
+        if self.is_container() {
+            return Err(Error::ContainerCannotMove);
+        }
+        let copy = self.deep_copy(doc);
+        parent.push_child(doc, copy.as_node())?;
+        Ok(copy)
+    }
+
+    /// Copy the name, attributes and namespace declarations of this element into a new element
+    /// without parent and children. Unlike [`Element::clone_subtree()`], descendants aren't visited.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><list class="a"><item/></list>"#).unwrap();
+    /// let list = doc.root_element().unwrap();
+    /// let shell = list.clone_shell(&mut doc);
+    /// assert_eq!(shell.attribute(&doc, "class"), Some("a"));
+    /// assert!(!shell.has_children(&doc));
+    /// ```
+    pub fn clone_shell(&self, doc: &mut Document) -> Element {
+        let content = self.content(doc);
+        let full_name = content.full_name.clone();
+        let attributes = content.attributes.clone();
+        let namespace_decls = content.namespace_decls.clone();
+        Element::with_data(doc, full_name, attributes, namespace_decls)
+    }
+
+    /// Same as [`Element::clone_shell()`], but creates the copy in `target`.
+    /// This element is from `doc`.
+    ///
+    /// Namespace declarations of ancestors aren't copied, so prefixes of the copy
+    /// only resolve with its own declarations until it is attached.
+    pub fn clone_shell_into(&self, doc: &Document, target: &mut Document) -> Element {
+        let content = self.content(doc);
+        Element::with_data(
+            target,
+            self.full_name(doc).to_string().into(),
+            content.attributes.clone(),
+            content.namespace_decls.clone(),
+        )
+    }
+
+    /// Replaces itself in its parent with a [`Node::Text`] of its [`Element::text_content()`],
+    /// at the same position. The element and its subtree are detatched.
+    ///
+    /// If a sibling just before or after is a [`Node::Text`], the text is merged into it,
+    /// so there are no consecutive text nodes. [`Node::CData`] siblings are not merged.
+    /// If the element has no text content and there is nothing to merge, no node is inserted.
+    ///
+    /// Does nothing if the element has no parent.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::ContainerCannotMove`]: You can't flatten container element
+    /// - [`Error::Io`]: The file of a [`Node::ExternalText`] in the element can't be read.
+    ///   Nothing is changed.
+    pub fn flatten_to_text(&self, doc: &mut Document) -> Result<()> {
+        self.check_valid(doc)?;
+        if self.is_container() {
+            return Err(Error::ContainerCannotMove);
+        }
+        let parent = match self.parent(doc) {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+        let mut text = self.try_text_content(doc)?;
+        let mut pos = match parent
+            .children(doc)
+            .iter()
+            .position(|n| n.as_element() == Some(*self))
+        {
+            Some(pos) => pos,
+            None => return Ok(()),
+        };
+        parent.remove_child(doc, pos);
+
+        let children = &mut parent.mut_data(doc).children;
+        if let Some(Node::Text(next)) = children.get(pos) {
+            text.push_str(next);
+            children.remove(pos);
+        }
+        if pos > 0 {
+            if let Node::Text(prev) = &children[pos - 1] {
+                text.insert_str(0, prev);
+                children.remove(pos - 1);
+                pos -= 1;
+            }
+        }
+        if !text.is_empty() {
+            children.insert(pos, Node::Text(text.into()));
+        }
+        Ok(())
+    }
+
+    /// Merge each run of adjacent [`Node::Text`] children into one node and remove empty ones,
+    /// in this element and its descendants, like DOM's `Node.normalize()`.
+    ///
+    /// [`Node::CData`] and [`Node::ExternalText`] are not merged with text.
+    /// The empty text node [`ReadOptions::empty_text_node`] adds to `<tag></tag>` is removed as well.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element, Node};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><p>a<b/></p>"#).unwrap();
+    /// let p = doc.root_element().unwrap();
+    /// p.remove_child(&mut doc, 1);
+    /// p.push_child(&mut doc, Node::Text("b".into())).unwrap();
+    /// p.normalize(&mut doc);
+    /// assert_eq!(p.children(&doc), &[Node::Text("ab".into())]);
+    /// ```
+    pub fn normalize(&self, doc: &mut Document) {
+        let mut stack = vec![*self];
+        while let Some(elem) = stack.pop() {
+            if !is_normalized(elem.children(doc)) {
+                let children = std::mem::take(&mut elem.mut_data(doc).children);
+                let mut merged: Vec<Node> = Vec::with_capacity(children.len());
+                for node in children {
+                    if let Node::Text(text) = &node {
+                        if text.is_empty() {
+                            continue;
+                        }
+                        if let Some(Node::Text(prev)) = merged.last_mut() {
+                            prev.to_mut().push_str(text);
+                            continue;
+                        }
+                    }
+                    merged.push(node);
+                }
+                elem.set_children(doc, merged);
+            }
+            stack.extend(elem.child_elements_iter(doc).rev());
+        }
+    }
+
+    /// Calls [`Element::flatten_to_text()`] on all descendant elements whose name is in `tag_names`,
+    /// innermost elements first.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc_str = r#"<?xml version="1.0"?><p>Some <b>bold</b> text</p>"#;
+    /// let mut doc = Document::parse_str(doc_str).unwrap();
+    /// let p = doc.root_element().unwrap();
+    /// p.strip_markup(&mut doc, &["b"]);
+    /// assert_eq!(p.children(&doc).len(), 1);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] in a matching element can't be read.
+    pub fn strip_markup(&self, doc: &mut Document, tag_names: &[&str]) {
+        let matching: Vec<Element> = self
+            .child_elements_recursive(doc)
+            .into_iter()
+            .filter(|e| tag_names.contains(&e.name(doc)))
+            .collect();
+        for elem in matching.into_iter().rev() {
+            // Descendants are never the container, so only reading external text can fail.
+            if let Err(err) = elem.flatten_to_text(doc) {
+                panic!("{}", err);
+            }
+        }
+    }
+}
+
+/// Below are methods related to templates. See [`Document::create_template()`].
+impl Element {
+    /// Push an instance of `template` to the end of this element's children,
+    /// and return it.
+    ///
+    /// The instance shares its name, attributes and namespace declarations with the template.
+    /// Its descendants are new elements copied from the template's, so each instance still
+    /// adds one element per element of the template. Only text and attribute values are shared
+    /// with the template, so the cost of an instance doesn't depend on the length of its text.
+    /// It is written exactly like a copy of the template would be.
+    ///
+    /// Modifying the instance's own name, attributes, namespace declarations or children
+    /// first materializes it: the shared data is copied into it, and other instances are left unchanged.
+    /// Descendants belong to the instance, so they can be modified like any other element.
+    ///
+    /// Like [`ElementBuilder::push_to()`], this doesn't call the document's mutation validator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `template` was not created in `doc`.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Element};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><list/>"#).unwrap();
+    /// let list = doc.root_element().unwrap();
+    /// let item = Element::build("item").text_content("empty").finish(&mut doc);
+    /// let template = doc.create_template(item);
+    /// let first = list.push_template_instance(&mut doc, template);
+    /// let second = list.push_template_instance(&mut doc, template);
+    /// second.set_attribute(&mut doc, "n", "2");
+    /// assert!(first.is_template_instance(&doc));
+    /// assert!(!second.is_template_instance(&doc));
+    /// assert_eq!(first.attribute(&doc, "n"), None);
+    /// assert_eq!(second.text_content(&doc), "empty");
+    /// ```
+    pub fn push_template_instance(&self, doc: &mut Document, template: TemplateId) -> Element {
+        // Check `template` before creating the instance.
+        doc.template_root(template);
+        let instance =
+            Element::with_data(doc, String::new().into(), IndexMap::new(), IndexMap::new());
+        doc.store.get_mut(instance).instance_of = Some(template);
+        doc.template_root(template).copy_children_to(doc, instance);
+        // A new element has no parent and isn't the container, so pushing it can't fail.
+        #[allow(clippy::unwrap_used)]
+        self.attach_child(doc, None, instance.as_node()).unwrap();
+        instance
+    }
+
+    /// Returns `true` if this element is an instance of a template that wasn't materialized yet.
+    /// See [`Element::push_template_instance()`].
+    pub fn is_template_instance(&self, doc: &Document) -> bool {
+        self.data(doc).instance_of.is_some()
+    }
+
+    /// Copy the name, attributes and namespace declarations of the template into this element,
+    /// so it no longer shares them with the template.
+    /// Does nothing if this element is not a template instance.
+    ///
+    /// Methods that modify the element call this automatically.
+    pub fn materialize(&self, doc: &mut Document) {
+        let template = match self.data(doc).instance_of {
+            Some(template) => template,
+            None => return,
+        };
+        let root = doc.template_root(template);
+        let content = root.data(doc);
+        let full_name = content.full_name.clone();
+        let attributes = content.attributes.clone();
+        let namespace_decls = content.namespace_decls.clone();
+        let data = doc.store.get_mut(*self);
+        data.full_name = full_name;
+        data.attributes = attributes;
+        data.namespace_decls = namespace_decls;
+        data.instance_of = None;
+    }
+
+    // Share text and attribute values of this element and its descendants,
+    // so copies of them don't copy the values.
+    pub(crate) fn share_values(&self, doc: &mut Document) {
+        let mut stack = vec![*self];
+        while let Some(elem) = stack.pop() {
+            let data = doc.store.get_mut(elem);
+            data.attributes.values_mut().for_each(Value::share);
+            for node in &mut data.children {
+                match node {
+                    Node::Element(child) => stack.push(*child),
+                    Node::Text(text) | Node::CData(text) => text.share(),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Copy this element and its descendants into a new element without a parent.
+    /// Template instances are copied as materialized elements.
+    pub(crate) fn deep_copy(&self, doc: &mut Document) -> Element {
+        let content = self.content(doc);
+        let full_name = content.full_name.clone();
+        let attributes = content.attributes.clone();
+        let namespace_decls = content.namespace_decls.clone();
+        let copy = Element::with_data(doc, full_name, attributes, namespace_decls);
+        if let Some(shape) = self.authored_shape(doc) {
+            copy.set_authored_shape(doc, shape);
+        }
+        if let Some(raw) = doc.raw_start_tags.get(self).cloned() {
+            doc.raw_start_tags.insert(copy, raw);
+        }
+        if doc.namespace_decls_last.contains(self) {
+            doc.namespace_decls_last.insert(copy);
+        }
+        if let Some(names) = doc.single_quoted.get(self).cloned() {
+            doc.single_quoted.insert(copy, names);
+        }
+        if doc.synthesized_empty_text.contains(self) {
+            doc.synthesized_empty_text.insert(copy);
+        }
+        self.copy_children_to(doc, copy);
+        copy
+    }
+
+    // Push copies of this element's children to the children of `dest`.
+    fn copy_children_to(&self, doc: &mut Document, dest: Element) {
+        let children = self.children(doc).clone();
+        let mut copies = Vec::with_capacity(children.len());
+        for node in children {
+            copies.push(match node {
+                Node::Element(elem) => {
+                    let copy = elem.deep_copy(doc);
+                    copy.set_parent(doc, Some(dest));
+                    copy.as_node()
+                }
+                node => node,
+            });
+        }
+        doc.store.get_mut(dest).children.extend(copies);
+    }
+
+    // Same as `deep_copy`, but this element is from `source` and the copy is created in `target`.
+    pub(crate) fn import_copy(&self, source: &Document, target: &mut Document) -> Element {
+        let content = self.content(source);
+        let copy = Element::with_data(
+            target,
+            self.full_name(source).to_string().into(),
+            content.attributes.clone(),
+            content.namespace_decls.clone(),
+        );
+        if let Some(shape) = self.authored_shape(source) {
+            copy.set_authored_shape(target, shape);
+        }
+        if let Some(raw) = source.raw_start_tags.get(self) {
+            target.raw_start_tags.insert(copy, raw.clone());
+        }
+        if source.namespace_decls_last.contains(self) {
+            target.namespace_decls_last.insert(copy);
+        }
+        if let Some(names) = source.single_quoted.get(self) {
+            target.single_quoted.insert(copy, names.clone());
+        }
+        if source.synthesized_empty_text.contains(self) {
+            target.synthesized_empty_text.insert(copy);
+        }
+        let mut copies = Vec::with_capacity(self.children(source).len());
+        for node in self.children(source) {
+            copies.push(match node {
+                Node::Element(elem) => {
+                    let child = elem.import_copy(source, target);
+                    child.set_parent(target, Some(copy));
+                    child.as_node()
+                }
+                node => node.clone(),
+            });
+        }
+        target.store.get_mut(copy).children = copies;
+        copy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Document, Element, Node, NodeKind};
+    use crate::document::{MutationEvent, WriteOptions};
+    use crate::error::Error;
+    use crate::parser::ReadOptions;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_children() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <outer>
+            inside outer
+            <middle>
+                <inner>
+                    inside
+                </inner>
+                after inside
+            </middle>
+            <after>
+                inside after
+            </after>
+        </outer>
+        "#;
+        let doc = Document::parse_str(xml).unwrap();
+        let outer = doc.container().child_elements(&doc)[0];
+        let middle = outer.child_elements(&doc)[0];
+        let inner = middle.child_elements(&doc)[0];
+        let after = outer.child_elements(&doc)[1];
+        assert_eq!(doc.container().child_elements(&doc).len(), 1);
+        assert_eq!(outer.name(&doc), "outer");
+        assert_eq!(middle.name(&doc), "middle");
+        assert_eq!(inner.name(&doc), "inner");
+        assert_eq!(after.name(&doc), "after");
+        assert_eq!(outer.children(&doc).len(), 3);
+        assert_eq!(outer.child_elements(&doc).len(), 2);
+        assert_eq!(doc.container().children_recursive(&doc).len(), 8);
+        assert_eq!(
+            doc.container().child_elements_recursive(&doc),
+            vec![outer, middle, inner, after]
+        );
+    }
+
+    #[test]
+    fn test_namespace() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <root xmlns="ns", xmlns:p="pns">
+            <p:foo xmlns="inner">
+                Hello
+            </p:foo>
+            <p:bar xmlns:p="in2">
+                <c />
+                World!
+            </p:bar>
+        </root>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let container = doc.container().children(&doc)[0].as_element().unwrap();
+        let child_elements = container.child_elements(&doc);
+        let foo = *child_elements.first().unwrap();
+        let bar = *child_elements.get(1).unwrap();
+        let c = bar.child_elements(&doc)[0];
+        assert_eq!(c.prefix_name(&doc), ("", "c"));
+        assert_eq!(bar.full_name(&doc), "p:bar");
+        assert_eq!(bar.prefix(&doc), "p");
+        assert_eq!(bar.name(&doc), "bar");
+        assert_eq!(c.namespace(&doc).unwrap(), "ns");
+        assert_eq!(c.namespace_for_prefix(&doc, "p").unwrap(), "in2");
+        assert!(c.namespace_for_prefix(&doc, "random").is_none());
+        assert_eq!(bar.namespace(&doc).unwrap(), "in2");
+        assert_eq!(bar.namespace_for_prefix(&doc, "").unwrap(), "ns");
+        assert_eq!(foo.namespace(&doc).unwrap(), "pns");
+        assert_eq!(foo.namespace_for_prefix(&doc, "").unwrap(), "inner");
+        assert_eq!(foo.namespace_for_prefix(&doc, "p").unwrap(), "pns");
+        assert_eq!(container.namespace(&doc).unwrap(), "ns");
+    }
+
+    #[test]
+    fn test_find_text_content() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <core>
+            <p>Text</p>
+            <b>Text2</b>
+        </core>
+        "#;
+        let doc = Document::parse_str(xml).unwrap();
+        assert_eq!(
+            doc.root_element()
+                .unwrap()
+                .find(&doc, "p")
+                .unwrap()
+                .text_content(&doc),
+            "Text"
+        );
+        assert_eq!(
+            doc.root_element()
+                .unwrap()
+                .find(&doc, "b")
+                .unwrap()
+                .text_content(&doc),
+            "Text2"
+        );
+        assert_eq!(doc.root_element().unwrap().text_content(&doc), "TextText2")
+    }
+
+    #[test]
+    fn test_find_where() {
+        let xml = r#"<?xml version="1.0"?>
+        <servers xmlns:ns="urn:ns">
+            <server ns:name="prod"/>
+            <server name="dev" port="80"><server name="prod"/></server>
+            <backup name="prod"/>
+            <server name="prod" port="8080"/>
+            <server name="prod"/>
+        </servers>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let children = root.child_elements(&doc);
+        assert_eq!(
+            root.find_by_attribute(&doc, "server", "name", "prod"),
+            Some(children[3])
+        );
+        assert_eq!(root.find_by_attribute(&doc, "server", "name", "test"), None);
+        assert_eq!(
+            root.find_by_attribute(&doc, "server", "ns:name", "prod"),
+            Some(children[0])
+        );
+        let with_port = |doc: &Document, e: Element| e.attribute(doc, "port").is_some();
+        assert_eq!(root.find_where(&doc, with_port), Some(children[1]));
+        assert_eq!(
+            root.find_all_where(&doc, with_port),
+            [children[1], children[3]]
+        );
+        assert!(root.find_all_where(&doc, |_, _| false).is_empty());
+    }
+
+    #[test]
+    fn test_find_path() {
+        let xml = r#"<?xml version="1.0"?>
+        <config xmlns:ns="urn:ns">
+            <server name="dev"><port>80</port></server>
+            <server name="prod"><port>443</port><port>8443</port><ns:tag>a</ns:tag></server>
+            <ns:tag><tag>b</tag></ns:tag>
+        </config>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let config = doc.root_element().unwrap();
+        let dev = config.find(&doc, "server").unwrap();
+        let port = dev.find(&doc, "port").unwrap();
+        assert_eq!(config.find_path(&doc, "server"), Some(dev));
+        assert_eq!(config.find_path(&doc, "server/port"), Some(port));
+        assert_eq!(config.find_path(&doc, "server/port/"), Some(port));
+        assert_eq!(port.find_path(&doc, "/config/server/port"), Some(port));
+        assert_eq!(doc.find_path("config/server/port"), Some(port));
+        assert_eq!(doc.find_path("/config/server/port/"), Some(port));
+        // Only the first match is descended into.
+        assert_eq!(config.find_path(&doc, "server/tag"), None);
+        assert_eq!(config.find_path(&doc, "server/missing"), None);
+        assert_eq!(config.find_path(&doc, "/server"), None);
+        for path in [
+            "",
+            "/",
+            "//",
+            "server//port",
+            "/config//server",
+            "server/port//",
+        ] {
+            assert_eq!(config.find_path(&doc, path), None, "{}", path);
+            assert!(config.find_all_path(&doc, path).is_empty(), "{}", path);
+        }
+
+        // Prefixed segments match the full name, others the name without prefix.
+        let tags = config.find_all_path(&doc, "tag");
+        assert_eq!(tags.len(), 1);
+        assert_eq!(config.find_path(&doc, "ns:tag"), Some(tags[0]));
+        assert_eq!(
+            config
+                .find_path(&doc, "ns:tag/tag")
+                .unwrap()
+                .text_content(&doc),
+            "b"
+        );
+        assert_eq!(config.find_path(&doc, "tag/ns:tag"), None);
+        assert_eq!(config.find_path(&doc, "other:tag"), None);
+
+        let ports = doc.find_all_path("config/server/port");
+        assert_eq!(ports, [port]);
+        assert_eq!(config.find_all_path(&doc, "server").len(), 2);
+        assert!(config.find_all_path(&doc, "missing/port").is_empty());
+    }
+
+    #[test]
+    fn test_find_ns() {
+        let xml = r#"<?xml version="1.0"?>
+        <root xmlns:a="urn:x" xmlns:b="urn:y">
+            <b:item n="1"/>
+            <a:item n="2"/>
+            <item n="3"/>
+            <group xmlns="urn:x" xmlns:b="urn:x">
+                <item n="4"/>
+                <b:item n="5"/>
+                <item xmlns="" n="6"/>
+                <c:item n="7"/>
+            </group>
+        </root>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let n = |elems: Vec<Element>| -> Vec<String> {
+            elems
+                .iter()
+                .map(|e| e.attribute(&doc, "n").unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(n(root.find_all_ns(&doc, "urn:x", "item")), ["2"]);
+        assert_eq!(n(root.find_all_ns(&doc, "urn:y", "item")), ["1"]);
+        assert_eq!(n(root.find_all_ns(&doc, "", "item")), ["3"]);
+        assert!(root.find_ns(&doc, "urn:z", "item").is_none());
+
+        // Default namespace, a rebound prefix, an undeclared default and an undeclared prefix.
+        // The declaration on the element itself applies.
+        let group = root.find_ns(&doc, "urn:x", "group").unwrap();
+        assert_eq!(group, root.child_elements(&doc)[3]);
+        assert!(root.find_ns(&doc, "", "group").is_none());
+        assert_eq!(n(group.find_all_ns(&doc, "urn:x", "item")), ["4", "5"]);
+        assert_eq!(n(group.find_all_ns(&doc, "", "item")), ["6"]);
+        assert_eq!(
+            group.find_ns(&doc, "urn:x", "item"),
+            Some(group.child_elements(&doc)[0])
+        );
+    }
+
+    #[test]
+    fn test_attribute_ns() {
+        let xml = r#"<?xml version="1.0"?>
+        <root xmlns="urn:d" xmlns:a="urn:x" xmlns:l="http://www.w3.org/1999/xlink">
+            <item a:type="1" href="bare" l:href="link" xml:lang="en" c:id="undeclared"/>
+            <item xmlns:b="urn:x" b:type="2"/>
+        </root>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let items = root.child_elements(&doc);
+        let xlink = "http://www.w3.org/1999/xlink";
+        let xml_ns = "http://www.w3.org/XML/1998/namespace";
+        assert_eq!(items[0].attribute_ns(&doc, "urn:x", "type"), Some("1"));
+        assert_eq!(items[1].attribute_ns(&doc, "urn:x", "type"), Some("2"));
+        assert_eq!(items[0].attribute_ns(&doc, xlink, "href"), Some("link"));
+        assert_eq!(items[0].attribute_ns(&doc, "", "href"), Some("bare"));
+        assert_eq!(items[0].attribute_ns(&doc, xml_ns, "lang"), Some("en"));
+        // The default namespace and undeclared prefixes don't apply.
+        assert_eq!(items[0].attribute_ns(&doc, "urn:d", "href"), None);
+        assert_eq!(items[0].attribute_ns(&doc, "", "id"), None);
+        assert_eq!(items[0].attribute_ns(&doc, "", "type"), None);
+
+        // Existing attribute keeps its prefix, even if another prefix is closer.
+        items[1].set_attribute_ns(&mut doc, "urn:x", "type", "3");
+        assert_eq!(items[1].attribute(&doc, "b:type"), Some("3"));
+        items[1].set_attribute_ns(&mut doc, "urn:x", "id", "4");
+        assert_eq!(items[1].attribute(&doc, "b:id"), Some("4"));
+        items[1].set_attribute_ns(&mut doc, "", "id", "5");
+        assert_eq!(items[1].attribute(&doc, "id"), Some("5"));
+        items[1].set_attribute_ns(&mut doc, xml_ns, "lang", "fr");
+        assert_eq!(items[1].attribute(&doc, "xml:lang"), Some("fr"));
+        assert!(items[1].namespace_decls(&doc).get("xml").is_none());
+
+        // The default namespace needs a prefix, and `c` is used without declaration.
+        items[0].set_attribute_ns(&mut doc, "urn:d", "id", "6");
+        items[0].set_attribute_ns(&mut doc, "urn:c", "id", "7");
+        assert_eq!(items[0].attribute(&doc, "ns0:id"), Some("6"));
+        assert_eq!(items[0].attribute(&doc, "ns1:id"), Some("7"));
+        assert_eq!(items[0].namespace_for_prefix(&doc, "ns0"), Some("urn:d"));
+        assert_eq!(items[0].attribute_ns(&doc, "urn:c", "id"), Some("7"));
+        assert_eq!(items[0].attribute(&doc, "c:id"), Some("undeclared"));
+    }
+
+    #[test]
+    fn test_remove_attribute() {
+        let xml = r#"<?xml version="1.0"?><a xmlns="urn:d" xmlns:x="urn:x" id="1" x:n="2"><b c="3"/></a>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let a = doc.root_element().unwrap();
+        let b = a.first_child_element(&doc).unwrap();
+        assert_eq!(a.remove_attribute(&mut doc, "id"), Some("1".to_string()));
+        assert_eq!(a.remove_attribute(&mut doc, "id"), None);
+        assert_eq!(a.remove_attribute(&mut doc, "n"), None);
+        assert_eq!(a.remove_namespace_decl(&mut doc, "y"), None);
+        assert_eq!(
+            doc.write_str().unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<a xmlns="urn:d" xmlns:x="urn:x" x:n="2">
+  <b c="3"/>
+</a>"#
+        );
+
+        assert_eq!(
+            a.remove_namespace_decl(&mut doc, ""),
+            Some("urn:d".to_string())
+        );
+        a.remove_attribute(&mut doc, "x:n");
+        assert_eq!(
+            a.remove_namespace_decl(&mut doc, "x"),
+            Some("urn:x".to_string())
+        );
+        b.clear_attributes(&mut doc);
+        assert!(b.attributes(&doc).is_empty());
+        assert_eq!(
+            doc.write_str().unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<a>
+  <b/>
+</a>"#
+        );
+    }
+
+    #[test]
+    fn test_set_attributes() {
+        let xml = r#"<?xml version="1.0"?><a id="0" b="1"/>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let a = doc.root_element().unwrap();
+        a.set_attributes(&mut doc, vec![("c", "2"), ("id", "3")]);
+        a.set_attributes(&mut doc, [("xmlns", "urn:d"), ("xmlns:x", "urn:x")]);
+        a.set_attributes(
+            &mut doc,
+            HashMap::from([("x:d".to_string(), "4".to_string())]),
+        );
+        a.set_attributes(&mut doc, Vec::<(&str, String)>::new());
+        let attrs: Vec<(&str, &str)> = a
+            .attributes(&doc)
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(attrs, [("id", "3"), ("b", "1"), ("c", "2"), ("x:d", "4")]);
+        assert_eq!(a.namespace(&doc), Some("urn:d"));
+        assert_eq!(a.namespace_for_prefix(&doc, "x"), Some("urn:x"));
+
+        let b = Element::build("b")
+            .attribute("id", "0")
+            .attributes([("z", "1"), ("y", "2"), ("xmlns:y", "urn:y"), ("id", "3")])
+            .finish(&mut doc);
+        let keys: Vec<&String> = b.attributes(&doc).keys().collect();
+        assert_eq!(keys, ["id", "z", "y"]);
+        assert_eq!(b.attribute(&doc, "id"), Some("3"));
+        assert_eq!(b.namespace_decls(&doc).get("y").unwrap(), "urn:y");
+
+        // Same as parsing.
+        let xml = r#"<?xml version="1.0"?><b id="3" z="1" y="2" xmlns:y="urn:y"/>"#;
+        let parsed_doc = Document::parse_str(xml).unwrap();
+        let parsed = parsed_doc.root_element().unwrap();
+        assert_eq!(parsed.attributes(&parsed_doc), b.attributes(&doc));
+        assert_eq!(parsed.namespace_decls(&parsed_doc), b.namespace_decls(&doc));
+    }
+
+    #[test]
+    fn test_update_attributes() {
+        let xml = r#"<?xml version="1.0"?><a id="1" class="x" title="t"/>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let a = doc.root_element().unwrap();
+        let attrs = |doc: &Document| -> Vec<(String, String)> {
+            a.attributes(doc)
+                .iter()
+                .map(|(k, v)| (k.clone(), v.to_string()))
+                .collect()
+        };
+        let original = attrs(&doc);
+
+        // Rollback
+        let err = a
+            .update_attributes(&mut doc, |batch| {
+                batch.set("id", "2");
+                batch.remove("class");
+                assert_eq!(batch.get("id"), Some("2"));
+                assert_eq!(batch.get("class"), None);
+                Err("invalid input".to_string())
+            })
+            .unwrap_err();
+        assert!(matches!(err, Error::Rejected(ref msg) if msg == "invalid input"));
+        assert_eq!(attrs(&doc), original);
+        let err = a
+            .update_attributes(&mut doc, |batch| {
+                batch.set("id", "2");
+                batch.set("1st", "x");
+                Ok(())
+            })
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidName { name, .. } if name == "1st"));
+        assert_eq!(attrs(&doc), original);
+
+        // Rename then set
+        a.update_attributes(&mut doc, |batch| {
+            assert!(batch.rename("class", "kind"));
+            batch.set("class", "y");
+            batch.set("kind", format!("{}!", batch.get("kind").unwrap()));
+            assert!(batch.rename("id", "title"));
+            assert!(!batch.rename("missing", "other"));
+            assert_eq!(batch.element(), a);
+            Ok(())
+        })
+        .unwrap();
+        let expected = [("title", "1"), ("kind", "x!"), ("class", "y")];
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(attrs(&doc), expected);
+
+        // Added and changed attributes are checked by the validator.
+        doc.set_mutation_validator(Box::new(|event| match event {
+            MutationEvent::SetAttribute { name: "id", .. } => Err("id is fixed".to_string()),
+            _ => Ok(()),
+        }));
+        let err = a
+            .update_attributes(&mut doc, |batch| {
+                batch.set("class", "z");
+                batch.rename("title", "id");
+                Ok(())
+            })
+            .unwrap_err();
+        assert!(matches!(err, Error::Rejected(ref msg) if msg == "id is fixed"));
+        assert_eq!(attrs(&doc), expected);
+        a.update_attributes(&mut doc, |batch| {
+            batch.remove("title");
+            batch.set("class", "z");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(a.attribute(&doc, "title"), None);
+        assert_eq!(a.attribute(&doc, "class"), Some("z"));
+
+        // Namespace declarations are set like with `set_attributes`.
+        a.update_attributes(&mut doc, |batch| {
+            batch.set("xmlns:x", "urn:x");
+            batch.set("xmlns", "urn:default");
+            batch.set("x:ref", "1");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(a.namespace_for_prefix(&doc, "x"), Some("urn:x"));
+        assert_eq!(a.namespace_for_prefix(&doc, ""), Some("urn:default"));
+        assert_eq!(a.attribute(&doc, "xmlns:x"), None);
+        assert_eq!(a.attribute(&doc, "xmlns"), None);
+        assert_eq!(a.attribute(&doc, "x:ref"), Some("1"));
+        let names: Vec<&str> = a.attributes(&doc).keys().map(String::as_str).collect();
+        assert_eq!(names, ["kind", "class", "x:ref"]);
+    }
+
+    #[test]
+    fn test_name_is() {
+        let xml = r#"<?xml version="1.0"?><root xmlns:p="urn:p"><p:a/><b/><p:b/></root>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        for elem in root.descendants(&doc).chain(std::iter::once(root)) {
+            for candidate in ["", "p", "a", "b", "p:a", "p:b", "root"] {
+                assert_eq!(elem.name_is(&doc, candidate), elem.name(&doc) == candidate);
+                assert_eq!(
+                    elem.full_name_is(&doc, candidate),
+                    elem.full_name(&doc) == candidate
+                );
+                assert_eq!(
+                    elem.prefix_is(&doc, candidate),
+                    elem.prefix(&doc) == candidate
+                );
+            }
+            assert_eq!(elem.name_owned(&doc), elem.name(&doc));
+        }
+        // Renaming while holding the result of the comparison.
+        for elem in root.child_elements(&doc) {
+            let is_b = elem.name_is(&doc, "b");
+            if elem.prefix_is(&doc, "p") {
+                elem.set_prefix(&mut doc, "");
+            }
+            if is_b {
+                elem.set_name(&mut doc, "c");
+            }
+        }
+        let names: Vec<_> = root
+            .child_elements_iter(&doc)
+            .map(|e| e.full_name(&doc))
+            .collect();
+        assert_eq!(names, ["a", "c", "c"]);
+    }
+
+    #[test]
+    fn test_child_text() {
+        let xml = r#"<?xml version="1.0"?>
+        <item xmlns:dc="urn:dc">
+            <title>  First <b>bold</b> title </title>
+            <title>Second</title>
+            <dc:creator>Alice</dc:creator>
+            <creator>Bob</creator>
+            <empty/>
+        </item>"#;
+        let opts = ReadOptions {
+            trim_text: false,
+            ..ReadOptions::default()
+        };
+        let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+        let item = doc.root_element().unwrap();
+        assert_eq!(
+            item.child_text(&doc, "title").unwrap(),
+            "  First bold title "
+        );
+        assert_eq!(
+            item.child_text_trimmed(&doc, "title").unwrap(),
+            "First bold title"
+        );
+        assert_eq!(item.child_text(&doc, "empty").unwrap(), "");
+        assert_eq!(item.child_text(&doc, "missing"), None);
+        assert_eq!(item.child_text_trimmed(&doc, "missing"), None);
+        // `find()` compares local names.
+        assert_eq!(item.child_text(&doc, "creator").unwrap(), "Alice");
+        assert_eq!(
+            item.child_text_ns(&doc, "urn:dc", "creator").unwrap(),
+            "Alice"
+        );
+        assert_eq!(item.child_text_ns(&doc, "urn:x", "creator"), None);
+
+        let title = item.find(&doc, "title").unwrap();
+
+        assert_eq!(item.set_child_text(&mut doc, "title", "New"), title);
+        assert_eq!(title.children(&doc).len(), 1);
+        assert_eq!(item.child_text(&doc, "title").unwrap(), "New");
+        let author = item.set_child_text(&mut doc, "dc:author", "Carol");
+        assert_eq!(item.last_child_element(&doc), Some(author));
+        assert_eq!(
+            item.child_text_ns(&doc, "urn:dc", "author").unwrap(),
+            "Carol"
+        );
+    }
+
+    #[test]
+    fn test_child_element_accessors() {
+        let xml = r#"<?xml version="1.0"?>
+        <root><none>text</none><one>a<x/>b</one><many>a<x/><y/>b<z/>c</many></root>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let none = root.find(&doc, "none").unwrap();
+        let one = root.find(&doc, "one").unwrap();
+        let many = root.find(&doc, "many").unwrap();
+
+        assert_eq!(none.child_element_count(&doc), 0);
+        assert_eq!(none.first_child_element(&doc), None);
+        assert_eq!(none.last_child_element(&doc), None);
+        match none.only_child_element(&doc) {
+            Err(Error::MissingChild { parent, .. }) => assert_eq!(parent, "none"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let x = one.find(&doc, "x").unwrap();
+        assert_eq!(one.child_element_count(&doc), 1);
+        assert_eq!(one.first_child_element(&doc), Some(x));
+        assert_eq!(one.last_child_element(&doc), Some(x));
+        assert_eq!(one.only_child_element(&doc).unwrap(), x);
+
+        assert_eq!(many.child_element_count(&doc), 3);
+        assert_eq!(many.first_child_element(&doc), many.find(&doc, "x"));
+        assert_eq!(many.last_child_element(&doc), many.find(&doc, "z"));
+        match many.only_child_element(&doc) {
+            Err(Error::ExtraChildren {
+                parent,
+                found_count,
+                ..
+            }) => {
+                assert_eq!(parent, "many");
+                assert_eq!(found_count, 3);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_child_element_iterators() {
+        let xml = r#"<?xml version="1.0"?>
+        <list>
+            <item id="1"><sub/></item>
+            text
+            <header/>
+            <item id="2"/>
+            <item id="3"><sub><sub/></sub></item>
+        </list>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let list = doc.root_element().unwrap();
+
+        let iter = list.child_elements_iter(&doc);
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.collect::<Vec<_>>(), list.child_elements(&doc));
+        let mut reversed = list.child_elements(&doc);
+        reversed.reverse();
+        let rev: Vec<Element> = list.child_elements_iter(&doc).rev().collect();
+        assert_eq!(rev, reversed);
+
+        let mut iter = list.child_elements_iter(&doc);
+        iter.next();
+        iter.next_back();
+        assert_eq!(iter.len(), 2);
+        assert_eq!(
+            iter.map(|e| e.name(&doc)).collect::<Vec<_>>(),
+            ["header", "item"]
+        );
+
+        let first = list.find(&doc, "item").unwrap();
+        let last = list.rfind(&doc, "item").unwrap();
+        assert_eq!(first.attribute(&doc, "id"), Some("1"));
+        assert_eq!(last.attribute(&doc, "id"), Some("3"));
+        assert_eq!(list.rfind(&doc, "footer"), None);
+
+        let descendants: Vec<Element> = list.descendants(&doc).collect();
+        assert_eq!(descendants, list.child_elements_recursive(&doc));
+        assert_eq!(descendants.len(), 7);
+
+        let children = list.child_element_list(&doc);
+        assert_eq!(children.len(), 4);
+        assert_eq!(children.first(), Some(first));
+        assert_eq!(children.last(), Some(last));
+        let mut ids = Vec::new();
+        for child in &children {
+            ids.push(child.attribute(&doc, "id"));
+        }
+        assert_eq!(ids, [Some("1"), None, Some("2"), Some("3")]);
+        let mut visited = Vec::new();
+        for child in children {
+            visited.push(child);
+        }
+        assert_eq!(visited, list.child_elements(&doc));
+        let sub = first.child_element_list(&doc).first().unwrap();
+        assert!(sub.child_element_list(&doc).is_empty());
+        assert_eq!(sub.child_element_list(&doc).into_iter().next(), None);
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let xml = r#"<?xml version="1.0"?><a><b><c><d><e/></d></c></b><f/></a>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let a = doc.root_element().unwrap();
+        let e = a.descendants(&doc).find(|e| e.name_is(&doc, "e")).unwrap();
+        let names: Vec<&str> = e.ancestors(&doc).map(|e| e.name(&doc)).collect();
+        assert_eq!(names, ["d", "c", "b", "a"]);
+        assert_eq!(a.ancestors(&doc).count(), 0);
+        assert_eq!(doc.container().ancestors(&doc).count(), 0);
+
+        let b = a.find(&doc, "b").unwrap();
+        let f = a.find(&doc, "f").unwrap();
+        assert!(e.has_ancestor(&doc, b));
+        assert!(e.has_ancestor(&doc, doc.container()));
+        assert!(!e.has_ancestor(&doc, f));
+        assert!(!e.has_ancestor(&doc, e));
+        assert!(!a.has_ancestor(&doc, e));
+
+        // Detached subtree
+        let c = b.find(&doc, "c").unwrap();
+        c.detatch(&mut doc).unwrap();
+        let names: Vec<&str> = e.ancestors(&doc).map(|e| e.name(&doc)).collect();
+        assert_eq!(names, ["d", "c"]);
+        assert_eq!(c.ancestors(&doc).count(), 0);
+        assert!(e.has_ancestor(&doc, c));
+        assert!(!e.has_ancestor(&doc, a));
+        assert!(!e.has_ancestor(&doc, doc.container()));
+    }
+
+    #[test]
+    fn test_siblings() {
+        let xml = r#"<?xml version="1.0"?><!--c--><a><b/><!--x--><c/>text<d/></a>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let a = doc.root_element().unwrap();
+        let [b, c, d] =
+            [a.find(&doc, "b"), a.find(&doc, "c"), a.find(&doc, "d")].map(Option::unwrap);
+
+        assert!(matches!(b.next_sibling(&doc), Some(Node::Comment(_))));
+        assert_eq!(b.prev_sibling(&doc).map(Node::kind), None);
+        assert!(matches!(c.prev_sibling(&doc), Some(Node::Comment(_))));
+        assert!(matches!(c.next_sibling(&doc), Some(Node::Text(_))));
+        assert_eq!(d.next_sibling(&doc).map(Node::kind), None);
+
+        assert_eq!(b.next_sibling_element(&doc), Some(c));
+        assert_eq!(c.next_sibling_element(&doc), Some(d));
+        assert_eq!(d.next_sibling_element(&doc), None);
+        assert_eq!(d.prev_sibling_element(&doc), Some(c));
+        assert_eq!(b.prev_sibling_element(&doc), None);
+
+        // Root elements and detached elements have no siblings.
+        assert_eq!(a.prev_sibling(&doc).map(Node::kind), None);
+        assert_eq!(doc.container().next_sibling_element(&doc), None);
+        c.detatch(&mut doc).unwrap();
+        assert_eq!(c.next_sibling(&doc).map(Node::kind), None);
+        assert_eq!(c.prev_sibling_element(&doc), None);
+        assert_eq!(b.next_sibling_element(&doc), Some(d));
+    }
+
+    #[test]
+    fn test_descendant_nodes() {
+        let xml = r#"<?xml version="1.0"?><a><b>one<c/></b><!--x--><d><e><f/></e></d>two</a>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let a = doc.root_element().unwrap();
+        let nodes: Vec<(usize, NodeKind)> = a
+            .descendant_nodes(&doc)
+            .with_depth()
+            .map(|(depth, node)| (depth, node.kind()))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                (1, NodeKind::Element),
+                (2, NodeKind::Text),
+                (2, NodeKind::Element),
+                (1, NodeKind::Comment),
+                (1, NodeKind::Element),
+                (2, NodeKind::Element),
+                (3, NodeKind::Element),
+                (1, NodeKind::Text),
+            ]
+        );
+        assert_eq!(a.children_recursive(&doc).len(), 8);
+
+        let names: Vec<(usize, &str)> = a
+            .descendants(&doc)
+            .with_depth()
+            .map(|(depth, elem)| (depth, elem.name(&doc)))
+            .collect();
+        assert_eq!(names, [(1, "b"), (2, "c"), (1, "d"), (2, "e"), (3, "f")]);
+
+        // Stopping early leaves the rest unvisited.
+        let mut iter = a.descendants(&doc);
+        assert_eq!(iter.depth(), 0);
+        let e = iter.find(|e| e.name_is(&doc, "e")).unwrap();
+        assert_eq!(iter.depth(), 2);
+        assert_eq!(iter.next(), e.find(&doc, "f"));
+        assert_eq!(iter.next(), None);
+
+        let leaf = a.descendants(&doc).last().unwrap();
+        assert_eq!(leaf.descendant_nodes(&doc).count(), 0);
+    }
+
+    #[test]
+    fn test_ensure() {
+        let xml = r#"<?xml version="1.0"?>
+        <config xmlns:p="urn:p"><item/><other/></config>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let config = doc.root_element().unwrap();
+        let item = config.find(&doc, "item").unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(config.ensure_child(&mut doc, "item"), item);
+            let p_item = config.ensure_child(&mut doc, "p:item");
+            let first = config.ensure_child_at(&mut doc, "first", 0);
+            assert_eq!(config.children(&doc)[0].as_element(), Some(first));
+            assert_eq!(config.last_child_element(&doc), Some(p_item));
+            assert_eq!(config.child_element_count(&doc), 4);
+
+            assert_eq!(item.ensure_attribute(&mut doc, "version", "1"), "1");
+            assert_eq!(item.ensure_attribute(&mut doc, "p:version", "2"), "2");
+            assert_eq!(item.attributes(&doc).len(), 2);
+        }
+        item.set_attribute(&mut doc, "version", "3");
+        assert_eq!(item.ensure_attribute(&mut doc, "version", "1"), "3");
+    }
+
+    #[test]
+    fn test_expect() {
+        let xml = r#"<?xml version="1.0"?>
+        <config>
+            <server port="80"><name> prod </name><alias>a</alias><alias>b</alias></server>
+            <server><name>dev<b/></name></server>
+        </config>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let config = doc.root_element().unwrap();
+        let servers = config.find_all(&doc, "server");
+        assert_eq!(config.path(&doc), "/config");
+        assert_eq!(servers[1].path(&doc), "/config/server[2]");
+        assert_eq!(doc.container().path(&doc), "/");
+
+        let name = servers[0].expect_child(&doc, "name").unwrap();
+        assert_eq!(name.path(&doc), "/config/server[1]/name");
+        assert_eq!(name.expect_text(&doc).unwrap(), "prod");
+        assert_eq!(servers[0].expect_attribute(&doc, "port").unwrap(), "80");
+
+        match config.expect_child(&doc, "client").unwrap_err() {
+            Error::MissingChild {
+                parent,
+                element_path,
+                wanted,
+            } => {
+                assert_eq!(parent, "config");
+                assert_eq!(element_path, "/config");
+                assert_eq!(wanted.as_deref(), Some("client"));
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+        let err = servers[0].expect_child(&doc, "alias").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Element '/config/server[1]' has 2 child elements 'alias', expected one"
+        );
+        match err {
+            Error::ExtraChildren {
+                element_path,
+                found_count,
+                ..
+            } => {
+                assert_eq!(element_path, "/config/server[1]");
+                assert_eq!(found_count, 2);
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+        let name = servers[1].expect_child(&doc, "name").unwrap();
+        match name.expect_text(&doc).unwrap_err() {
+            Error::NotTextOnly { element_path } => {
+                assert_eq!(element_path, "/config/server[2]/name")
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+        match servers[1].expect_attribute(&doc, "port").unwrap_err() {
+            Error::MissingAttribute {
+                element_path,
+                wanted,
+            } => {
+                assert_eq!(element_path, "/config/server[2]");
+                assert_eq!(wanted, "port");
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_parse_values() {
+        #[derive(Debug, PartialEq)]
+        enum Mode {
+            Fast,
+            Safe,
+        }
+        impl std::str::FromStr for Mode {
+            type Err = String;
+            fn from_str(s: &str) -> Result<Mode, String> {
+                match s {
+                    "fast" => Ok(Mode::Fast),
+                    "safe" => Ok(Mode::Safe),
+                    _ => Err("expected 'fast' or 'safe'".to_string()),
+                }
+            }
+        }
+
+        let xml = r#"<?xml version="1.0"?>
+        <config timeout="30" ratio="-1.5e2" debug="true" mode="safe" bad="abc">
+            <retries> 3 </retries>
+            <mode>fast</mode>
+            <limit>-1</limit>
+        </config>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let config = doc.root_element().unwrap();
+        assert_eq!(
+            config.attribute_parse(&doc, "timeout").unwrap(),
+            Some(30u32)
+        );
+        assert_eq!(
+            config.attribute_parse(&doc, "ratio").unwrap(),
+            Some(-150.0f64)
+        );
+        assert_eq!(config.attribute_parse(&doc, "debug").unwrap(), Some(true));
+        assert_eq!(
+            config.attribute_parse(&doc, "mode").unwrap(),
+            Some(Mode::Safe)
+        );
+        assert_eq!(
+            config.attribute_parse::<u32>(&doc, "missing").unwrap(),
+            None
+        );
+        assert_eq!(config.attribute_or(&doc, "timeout", 10u32).unwrap(), 30);
+        assert_eq!(config.attribute_or(&doc, "missing", 10u32).unwrap(), 10);
+        assert!(!config.attribute_or(&doc, "missing", false).unwrap());
+        assert!(config.attribute_parse::<bool>(&doc, "timeout").is_err());
+        assert!(config.attribute_or(&doc, "bad", 1.0f32).is_err());
+        // Attribute values aren't trimmed.
+        config.set_attribute(&mut doc, "space", " 1");
+        assert!(config.attribute_parse::<u8>(&doc, "space").is_err());
+
+        let err = config.attribute_parse::<u32>(&doc, "bad").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Attribute 'bad' of '/config' has invalid value 'abc': invalid digit found in string"
+        );
+        match err {
+            Error::InvalidValue {
+                element_path,
+                attribute,
+                value,
+                ..
+            } => {
+                assert_eq!(element_path, "/config");
+                assert_eq!(attribute.as_deref(), Some("bad"));
+                assert_eq!(value, "abc");
             }
-            data.parent = Some(*self);
+            err => panic!("unexpected error: {:?}", err),
         }
-        self.mut_data(doc).children.insert(index, node);
-        Ok(())
-    }
 
-    /// Equivalent to `vec.remove()`.
-    ///
-    /// # Panics
-    ///
-    /// Panics if `index >= self.children().len()`.
-    pub fn remove_child(&self, doc: &mut Document, index: usize) -> Node {
-        let node = self.mut_data(doc).children.remove(index);
-        if let Node::Element(elem) = node {
-            elem.mut_data(doc).parent = None;
+        let retries = config.find(&doc, "retries").unwrap();
+        let mode = config.find(&doc, "mode").unwrap();
+        let limit = config.find(&doc, "limit").unwrap();
+        assert_eq!(retries.text_content_parse::<u8>(&doc).unwrap(), 3);
+        assert_eq!(retries.text_content_parse::<f32>(&doc).unwrap(), 3.0);
+        assert_eq!(mode.text_content_parse::<Mode>(&doc).unwrap(), Mode::Fast);
+        assert_eq!(limit.text_content_parse::<i64>(&doc).unwrap(), -1);
+        let err = limit.text_content_parse::<usize>(&doc).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Text of '/config/limit' has invalid value '-1': invalid digit found in string"
+        );
+        let err = config.text_content_parse::<Mode>(&doc).unwrap_err();
+        match err {
+            Error::InvalidValue {
+                attribute, reason, ..
+            } => {
+                assert_eq!(attribute, None);
+                assert_eq!(reason, "expected 'fast' or 'safe'");
+            }
+            err => panic!("unexpected error: {:?}", err),
         }
-        node
     }
 
-    /// Equivalent to `vec.pop()`.
-    pub fn pop_child(&self, doc: &mut Document) -> Option<Node> {
-        let child = self.mut_data(doc).children.pop();
-        if let Some(Node::Element(elem)) = &child {
-            elem.mut_data(doc).parent = None;
-        }
-        child
-    }
+    #[test]
+    fn test_clone_subtree() {
+        let xml = r#"<?xml version="1.0"?>
+<table xmlns:x="urn:x">
+  <row x:id="1" class="a" xmlns:y="urn:y">
+    <cell>text<!--c--><![CDATA[<cdata>]]><?pi data?></cell>
+    <y:cell></y:cell>
+  </row>
+</table>"#;
+        let opts = ReadOptions {
+            high_fidelity: true,
+            ..ReadOptions::default()
+        };
+        let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+        let table = doc.root_element().unwrap();
+        let row = table.find(&doc, "row").unwrap();
+        let before = doc.write_str().unwrap();
+        let allocated = doc.store.len();
 
-    /// Remove all children and return them.
-    pub fn clear_children(&self, doc: &mut Document) -> Vec<Node> {
-        let count = self.children(doc).len();
-        let mut removed = Vec::with_capacity(count);
-        for _ in 0..count {
-            let child = self.remove_child(doc, 0);
-            removed.push(child);
+        let copy = row.clone_subtree(&mut doc);
+        assert_eq!(doc.store.len(), allocated + 3);
+        assert_ne!(copy, row);
+        assert_eq!(copy.parent(&doc), None);
+        assert_eq!(copy.attributes(&doc), row.attributes(&doc));
+        assert_eq!(copy.namespace_decls(&doc), row.namespace_decls(&doc));
+        let cells = copy.child_elements(&doc);
+        let original_cells = row.child_elements(&doc);
+        for (cell, original) in cells.iter().zip(&original_cells) {
+            assert_ne!(cell, original);
+            assert_eq!(cell.parent(&doc), Some(copy));
         }
-        removed
+        let kinds: Vec<NodeKind> = cells[0].children(&doc).iter().map(|n| n.kind()).collect();
+        assert_eq!(
+            kinds,
+            [
+                NodeKind::Text,
+                NodeKind::Comment,
+                NodeKind::CData,
+                NodeKind::PI
+            ]
+        );
+
+        // Modifying the copy doesn't affect the source.
+        copy.set_attribute(&mut doc, "class", "b");
+        cells[0].clear_children(&mut doc);
+        cells[1].set_text_content(&mut doc, "new");
+        assert_eq!(doc.write_str().unwrap(), before);
+
+        // The copy is written the same.
+        let copy = table
+            .find(&doc, "row")
+            .unwrap()
+            .clone_to(&mut doc, table)
+            .unwrap();
+        assert_eq!(table.child_elements(&doc), [row, copy]);
+        let written = doc.write_str().unwrap();
+        let row_xml = r#"<row x:id="1" class="a" xmlns:y="urn:y">
+    <cell>text<!--c--><![CDATA[<cdata>]]><?pi data?>
+    </cell>
+    <y:cell></y:cell>
+  </row>"#;
+        assert_eq!(written.matches(row_xml).count(), 2, "{}", written);
+        assert!(matches!(
+            doc.container().clone_to(&mut doc, table),
+            Err(Error::ContainerCannotMove)
+        ));
     }
 
-    /// Removes itself from its parent. Note that you can't attach this element to other documents.
-    ///
-    /// # Errors
-    ///
-    /// - [`Error::ContainerCannotMove`]: You can't detatch container element
-    pub fn detatch(&self, doc: &mut Document) -> Result<()> {
-        if self.is_container() {
-            return Err(Error::ContainerCannotMove);
-        }
-        let data = self.mut_data(doc);
-        if let Some(parent) = data.parent {
-            let pos = parent
-                .children(doc)
-                .iter()
-                .position(|n| n.as_element() == Some(*self))
-                .unwrap();
-            parent.remove_child(doc, pos);
-        }
-        Ok(())
+    #[test]
+    fn test_clone_shell() {
+        let xml = r#"<?xml version="1.0"?>
+<root xmlns:x="urn:x">
+  <x:list x:id="1" class="a" xmlns="urn:d"><item/><item/></x:list>
+</root>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let list = doc.root_element().unwrap().child_elements(&doc)[0];
+        let allocated = doc.store.len();
+
+        let shell = list.clone_shell(&mut doc);
+        assert_eq!(doc.store.len(), allocated + 1);
+        assert_ne!(shell, list);
+        assert_eq!(shell.parent(&doc), None);
+        assert!(!shell.has_children(&doc));
+        assert_eq!(shell.full_name(&doc), "x:list");
+        assert_eq!(shell.attributes(&doc), list.attributes(&doc));
+        assert_eq!(shell.namespace_decls(&doc), list.namespace_decls(&doc));
+        shell.set_attribute(&mut doc, "class", "b");
+        shell.set_namespace_decl(&mut doc, "", "urn:other");
+        shell.set_full_name(&mut doc, "list");
+        assert_eq!(list.attribute(&doc, "class"), Some("a"));
+        assert_eq!(list.namespace_decls(&doc).get("").unwrap(), "urn:d");
+        assert_eq!(list.full_name(&doc), "x:list");
+        assert_eq!(list.child_elements(&doc).len(), 2);
+
+        // Into another document
+        let mut other = Document::new();
+        let shell = list.clone_shell_into(&doc, &mut other);
+        assert_eq!(shell.parent(&other), None);
+        assert!(!shell.has_children(&other));
+        assert_eq!(shell.attribute(&other, "x:id"), Some("1"));
+        assert_eq!(shell.namespace_for_prefix(&other, ""), Some("urn:d"));
+        assert_eq!(shell.namespace(&other), None);
+        let container = other.container();
+        let root = Element::build("root")
+            .namespace_decl("x", "urn:y")
+            .push_to(&mut other, container);
+        root.push_child(&mut other, shell.as_node()).unwrap();
+        assert_eq!(shell.namespace(&other), Some("urn:y"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{Document, Element, Node};
+    #[test]
+    fn test_builder_try_push_to() {
+        let mut doc = Document::new();
+        let container = doc.container();
+        let root = Element::build("root")
+            .try_push_to(&mut doc, container)
+            .unwrap();
+        let allocated = doc.store.len();
+
+        // Unfinished or invalid builders don't allocate
+        let builder = Element::build("item").attribute("id", "1");
+        drop(builder);
+        let err = Element::build("item")
+            .attribute("1id", "1")
+            .try_push_to(&mut doc, root)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidName { name, .. } if name == "1id"));
+        let err = Element::build("item")
+            .namespace_decl("", "ns")
+            .namespace_decl("a b", "ns")
+            .try_finish(&mut doc)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidName { name, .. } if name == "a b"));
+        let err = Element::build("item")
+            .child_element("sub", |sub| sub.child_element("2", |b| b))
+            .try_push_to(&mut doc, root)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidName { name, .. } if name == "2"));
+        assert_eq!(doc.store.len(), allocated);
+        assert!(!root.has_children(&doc));
+
+        let item = Element::build("p:item")
+            .namespace_decl("p", "ns")
+            .try_push_to(&mut doc, root)
+            .unwrap();
+        assert_eq!(root.first_child_element(&doc), Some(item));
+        assert_eq!(doc.store.len(), allocated + 1);
+    }
 
     #[test]
-    fn test_children() {
-        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
-        <outer>
-            inside outer
-            <middle>
-                <inner>
-                    inside
-                </inner>
-                after inside
-            </middle>
-            <after>
-                inside after
-            </after>
-        </outer>
-        "#;
-        let doc = Document::parse_str(xml).unwrap();
-        let outer = doc.container().child_elements(&doc)[0];
-        let middle = outer.child_elements(&doc)[0];
-        let inner = middle.child_elements(&doc)[0];
-        let after = outer.child_elements(&doc)[1];
-        assert_eq!(doc.container().child_elements(&doc).len(), 1);
-        assert_eq!(outer.name(&doc), "outer");
-        assert_eq!(middle.name(&doc), "middle");
-        assert_eq!(inner.name(&doc), "inner");
-        assert_eq!(after.name(&doc), "after");
-        assert_eq!(outer.children(&doc).len(), 3);
-        assert_eq!(outer.child_elements(&doc).len(), 2);
-        assert_eq!(doc.container().children_recursive(&doc).len(), 8);
+    fn test_builder_children() {
+        let mut doc = Document::new();
+        let list = Element::build("list")
+            .text_content("items:")
+            .child_element("item", |item| item.attribute("n", "1"))
+            .child(Element::build("item").attribute("n", "2"))
+            .child_element("group", |group| {
+                group
+                    .child_element("item", |item| item.attribute("n", "3"))
+                    .child_element("item", |item| item.text_content("4"))
+            })
+            .finish(&mut doc);
+        assert_eq!(list.parent(&doc), None);
+        let kinds: Vec<NodeKind> = list.children(&doc).iter().map(|n| n.kind()).collect();
         assert_eq!(
-            doc.container().child_elements_recursive(&doc),
-            vec![outer, middle, inner, after]
+            kinds,
+            [
+                NodeKind::Text,
+                NodeKind::Element,
+                NodeKind::Element,
+                NodeKind::Element
+            ]
         );
+        let children = list.child_elements(&doc);
+        assert_eq!(children[0].attribute(&doc, "n"), Some("1"));
+        assert_eq!(children[1].attribute(&doc, "n"), Some("2"));
+        let group = children[2];
+        assert_eq!(group.name(&doc), "group");
+        let items = group.child_elements(&doc);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].parent(&doc), Some(group));
+        assert_eq!(items[0].attribute(&doc, "n"), Some("3"));
+        assert_eq!(items[1].text_content(&doc), "4");
     }
 
     #[test]
-    fn test_namespace() {
-        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
-        <root xmlns="ns", xmlns:p="pns">
-            <p:foo xmlns="inner">
-                Hello
-            </p:foo>
-            <p:bar xmlns:p="in2">
-                <c />
-                World!
-            </p:bar>
-        </root>"#;
-        let doc = Document::parse_str(xml).unwrap();
-        let container = doc.container().children(&doc)[0].as_element().unwrap();
-        let child_elements = container.child_elements(&doc);
-        let foo = *child_elements.get(0).unwrap();
-        let bar = *child_elements.get(1).unwrap();
-        let c = bar.child_elements(&doc)[0];
-        assert_eq!(c.prefix_name(&doc), ("", "c"));
-        assert_eq!(bar.full_name(&doc), "p:bar");
-        assert_eq!(bar.prefix(&doc), "p");
-        assert_eq!(bar.name(&doc), "bar");
-        assert_eq!(c.namespace(&doc).unwrap(), "ns");
-        assert_eq!(c.namespace_for_prefix(&doc, "p").unwrap(), "in2");
-        assert!(c.namespace_for_prefix(&doc, "random").is_none());
-        assert_eq!(bar.namespace(&doc).unwrap(), "in2");
-        assert_eq!(bar.namespace_for_prefix(&doc, "").unwrap(), "ns");
-        assert_eq!(foo.namespace(&doc).unwrap(), "pns");
-        assert_eq!(foo.namespace_for_prefix(&doc, "").unwrap(), "inner");
-        assert_eq!(foo.namespace_for_prefix(&doc, "p").unwrap(), "pns");
-        assert_eq!(container.namespace(&doc).unwrap(), "ns");
+    fn test_builder_nodes() {
+        let mut doc = Document::new();
+        let container = doc.container();
+        let existing = Element::new(&mut doc, "existing");
+        let root = Element::build("root")
+            .comment(" generated by test ")
+            .pi("xml-stylesheet href='a.xsl'")
+            .text_content("one")
+            .child_element("a", |a| a.cdata("x < y").text_content("two"))
+            .text_content("three")
+            .node(existing.as_node())
+            .node(Node::Text("four".into()))
+            .push_to(&mut doc, container);
+        assert_eq!(existing.parent(&doc), Some(root));
+        let opts = WriteOptions {
+            write_decl: false,
+            pretty: false,
+            ..WriteOptions::default()
+        };
+        assert_eq!(
+            doc.write_str_with_opts(opts).unwrap(),
+            "<root><!-- generated by test --><?xml-stylesheet href='a.xsl'?>one\
+             <a><![CDATA[x < y]]>two</a>three<existing/>four</root>"
+        );
+
+        // Elements that can't be attached
+        let allocated = doc.store.len();
+        let err = Element::build("b")
+            .child_element("c", |c| c.node(existing.as_node()))
+            .try_push_to(&mut doc, root)
+            .unwrap_err();
+        assert!(matches!(err, Error::HasAParent));
+        let err = Element::build("b")
+            .node(container.as_node())
+            .try_finish(&mut doc)
+            .unwrap_err();
+        assert!(matches!(err, Error::ContainerCannotMove));
+        assert_eq!(doc.store.len(), allocated);
     }
 
     #[test]
-    fn test_find_text_content() {
-        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
-        <core>
-            <p>Text</p>
-            <b>Text2</b>
-        </core>
-        "#;
+    fn test_attribute_by_local_name() {
+        let xml = r#"<?xml version="1.0"?>
+        <root xmlns:a="urn:a" xmlns:b="urn:b">
+            <all b:type="2" type="0" a:type="1" a:types="x"/>
+            <prefixed b:type="2" a:type="1"/>
+            <one b:type="2" other="x"/>
+        </root>"#;
         let doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let all = root.find(&doc, "all").unwrap();
+        let prefixed = root.find(&doc, "prefixed").unwrap();
+        let one = root.find(&doc, "one").unwrap();
+
         assert_eq!(
-            doc.root_element()
-                .unwrap()
-                .find(&doc, "p")
-                .unwrap()
-                .text_content(&doc),
-            "Text"
+            all.attributes_by_local_name(&doc, "type"),
+            [("b:type", "2"), ("type", "0"), ("a:type", "1")]
         );
         assert_eq!(
-            doc.root_element()
-                .unwrap()
-                .find(&doc, "b")
-                .unwrap()
-                .text_content(&doc),
-            "Text2"
+            all.attribute_by_local_name(&doc, "type"),
+            Some(("type", "0"))
         );
-        assert_eq!(doc.root_element().unwrap().text_content(&doc), "TextText2")
+        assert_eq!(
+            prefixed.attribute_by_local_name(&doc, "type"),
+            Some(("b:type", "2"))
+        );
+        assert_eq!(
+            one.attribute_by_local_name(&doc, "type"),
+            Some(("b:type", "2"))
+        );
+        assert_eq!(one.attributes_by_local_name(&doc, "type").len(), 1);
+        assert_eq!(one.attribute_by_local_name(&doc, "missing"), None);
+        assert!(one.attributes_by_local_name(&doc, "missing").is_empty());
+    }
+
+    #[test]
+    fn test_try_push_to_rejected() {
+        let xml = r#"<?xml version="1.0"?><root><a/></root>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let loose = Element::new(&mut doc, "loose");
+        let len = doc.store.len();
+        doc.set_mutation_validator(Box::new(|event| match event {
+            MutationEvent::PushChild { .. } => Err("no new children".to_string()),
+            _ => Ok(()),
+        }));
+
+        let result = Element::build("item")
+            .child_element("inner", |inner| inner.node(loose.as_node()))
+            .try_push_to(&mut doc, root);
+        assert!(matches!(result, Err(Error::Rejected(_))));
+        assert_eq!(doc.store.len(), len);
+        assert_eq!(loose.parent(&doc), None);
+        assert_eq!(root.child_elements(&doc).len(), 1);
+
+        doc.clear_mutation_validator();
+        let item = Element::build("item")
+            .child_element("inner", |inner| inner.node(loose.as_node()))
+            .try_push_to(&mut doc, root)
+            .unwrap();
+        let inner = item.child_elements(&doc)[0];
+        assert_eq!(loose.parent(&doc), Some(inner));
+        assert_eq!(root.child_elements(&doc)[1], item);
+    }
+
+    #[test]
+    fn test_element_ids() {
+        let xml = r#"<?xml version="1.0"?><a><b/><c><d/></c></a>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let container = doc.container();
+        assert!(container.is_container());
+        assert_eq!(doc.store.len(), 5);
+        let elems = container.child_elements_recursive(&doc);
+        assert_eq!(elems.iter().map(|e| e.id).collect::<Vec<_>>(), [1, 2, 3, 4]);
+
+        // Elements are allocated after parsed elements, even when detatched.
+        let b = elems[1];
+        b.detatch(&mut doc).unwrap();
+        let e = Element::new(&mut doc, "e");
+        let f = Element::build("f").push_to(&mut doc, e);
+        assert_eq!((e.id, f.id), (5, 6));
+        assert_eq!(doc.store.len(), 7);
+        assert_eq!(b.name(&doc), "b");
+
+        // Ids stay valid in cloned documents.
+        let mut cloned = doc.clone();
+        for elem in elems.iter().chain(&[e, f]) {
+            assert_eq!(elem.full_name(&doc), elem.full_name(&cloned));
+        }
+        f.set_name(&mut cloned, "g");
+        assert_eq!(f.name(&doc), "f");
+        assert_eq!(f.parent(&cloned), Some(e));
+    }
+
+    #[test]
+    fn test_strip_markup() {
+        let xml = r#"<?xml version="1.0"?>
+        <p>This is <b>bold and <i>italic</i></b>, <![CDATA[cdata]]><i>it</i> and <u>under</u><b></b>.</p>"#;
+        let opts = ReadOptions {
+            trim_text: false,
+            ..ReadOptions::default()
+        };
+        let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+        let p = doc.root_element().unwrap();
+        let u = p.find(&doc, "u").unwrap();
+        p.strip_markup(&mut doc, &["b", "i"]);
+        let children = p.children(&doc);
+        assert_eq!(children.len(), 5);
+        assert!(matches!(&children[0], Node::Text(t) if t == "This is bold and italic, "));
+        assert!(matches!(&children[1], Node::CData(t) if t == "cdata"));
+        assert!(matches!(&children[2], Node::Text(t) if t == "it and "));
+        assert_eq!(children[3].as_element(), Some(u));
+        assert!(matches!(&children[4], Node::Text(t) if t == "."));
+
+        u.flatten_to_text(&mut doc).unwrap();
+        let children = p.children(&doc);
+        assert_eq!(children.len(), 3);
+        assert!(matches!(&children[2], Node::Text(t) if t == "it and under."));
+        assert!(!u.has_parent(&doc));
+        assert!(doc.container().flatten_to_text(&mut doc).is_err());
+    }
+
+    #[test]
+    fn test_text_content_sep() {
+        let xml = r#"<?xml version="1.0"?>
+        <doc><p>one<b>two</b></p><?pi x?><!--cmt--><p><![CDATA[three]]></p><p></p></doc>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        assert_eq!(root.text_content(&doc), "onetwothree");
+        assert_eq!(root.text_content_sep(&doc, ""), root.text_content(&doc));
+        assert_eq!(root.text_content_sep(&doc, "|"), "one|two|three");
+        assert_eq!(doc.extract_text("\n"), "one\ntwo\nthree");
+        let pi = &root.children(&doc)[1];
+        assert_eq!(pi.text_content(&doc), "pi x");
+    }
+
+    #[test]
+    fn test_text_chunks() {
+        let xml = r#"<?xml version="1.0"?>
+        <doc>a<b>bc<![CDATA[<d>]]></b><!--e-->f<c/><![CDATA[]]>gh</doc>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let chunks: Vec<_> = root.text_chunks(&doc).collect();
+        assert_eq!(chunks, vec!["a", "bc", "<d>", "f", "gh"]);
+        assert_eq!(chunks.concat(), root.text_content(&doc));
+
+        assert!(root.text_eq(&doc, "abc<d>fgh"));
+        assert!(root.text_eq(&doc, &root.text_content(&doc)));
+        assert!(!root.text_eq(&doc, "abc<d>fg"));
+        assert!(!root.text_eq(&doc, "abc<d>fghi"));
+        assert!(!root.text_eq(&doc, "ab"));
+        assert!(!root.text_eq(&doc, "abd<d>fgh"));
+        let c = root.child_elements(&doc)[1];
+        assert_eq!(c.text_chunks(&doc).count(), 0);
+        assert!(c.text_eq(&doc, ""));
     }
 
     #[test]
@@ -783,4 +5198,407 @@ mod tests {
         assert_eq!(root.children(&doc).len(), 0);
         assert_eq!(a.parent(&doc), None);
     }
+
+    #[test]
+    fn test_replace_child() {
+        let mut doc = Document::new();
+        let container = doc.container();
+        let root = Element::build("root").push_to(&mut doc, container);
+        let a = Element::build("a").push_to(&mut doc, root);
+        root.push_child(&mut doc, Node::Text("text".into()))
+            .unwrap();
+
+        // Element.replace_child with an element
+        let b = Element::new(&mut doc, "b");
+        let old = root.replace_child(&mut doc, 0, b.as_node()).unwrap();
+        assert_eq!(old.as_element(), Some(a));
+        assert_eq!(a.parent(&doc), None);
+        assert_eq!(b.parent(&doc), Some(root));
+        assert_eq!(root.children(&doc)[0].as_element(), Some(b));
+        assert_eq!(root.children(&doc).len(), 2);
+
+        // Element.replace_child with text
+        let old = root
+            .replace_child(&mut doc, 1, Node::Comment("c".to_string()))
+            .unwrap();
+        assert_eq!(old, Node::Text("text".into()));
+        assert_eq!(root.children(&doc)[1], Node::Comment("c".to_string()));
+
+        // Element.replace_with
+        b.replace_with(&mut doc, a.as_node()).unwrap();
+        assert_eq!(b.parent(&doc), None);
+        assert_eq!(a.parent(&doc), Some(root));
+        assert_eq!(root.children(&doc)[0].as_element(), Some(a));
+        a.replace_with(&mut doc, Node::Text("a".into())).unwrap();
+        assert_eq!(a.parent(&doc), None);
+        assert_eq!(root.child_elements(&doc).len(), 0);
+        assert_eq!(root.children(&doc).len(), 2);
+
+        // Errors leave the tree unchanged
+        let child = Element::build("child").push_to(&mut doc, root);
+        assert!(matches!(
+            root.replace_child(&mut doc, 0, child.as_node()),
+            Err(Error::HasAParent)
+        ));
+        assert!(matches!(
+            root.replace_child(&mut doc, 0, container.as_node()),
+            Err(Error::ContainerCannotMove)
+        ));
+        assert!(matches!(
+            root.replace_child(&mut doc, 3, b.as_node()),
+            Err(Error::IndexOutOfBounds { index: 3, len: 3 })
+        ));
+        assert!(matches!(
+            container.replace_with(&mut doc, b.as_node()),
+            Err(Error::ContainerCannotMove)
+        ));
+        assert!(matches!(
+            a.replace_with(&mut doc, b.as_node()),
+            Err(Error::NoParent)
+        ));
+        assert!(matches!(
+            child.replace_with(&mut doc, root.as_node()),
+            Err(Error::HasAParent)
+        ));
+        assert_eq!(root.children(&doc)[2].as_element(), Some(child));
+        assert_eq!(child.parent(&doc), Some(root));
+        assert_eq!(b.parent(&doc), None);
+        assert_eq!(a.parent(&doc), None);
+
+        // Rejected by the mutation validator
+        doc.set_mutation_validator(Box::new(|event| match event {
+            MutationEvent::PushChild {
+                node: Node::Comment(_),
+                ..
+            } => Err("no comments".to_string()),
+            _ => Ok(()),
+        }));
+        assert!(matches!(
+            child.replace_with(&mut doc, Node::Comment("x".to_string())),
+            Err(Error::Rejected(_))
+        ));
+        assert_eq!(child.parent(&doc), Some(root));
+    }
+
+    #[test]
+    fn test_dissolve() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<p>Hello <b>bold <i>and</i> <!--c--> text</b>, world</p>"#;
+        let opts = ReadOptions {
+            trim_text: false,
+            ..ReadOptions::default()
+        };
+        let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+        let p = doc.root_element().unwrap();
+        let b = p.find(&doc, "b").unwrap();
+        let i = b.find(&doc, "i").unwrap();
+
+        let moved = b.dissolve(&mut doc).unwrap();
+        assert_eq!(moved.len(), 5);
+        assert_eq!(moved[1].as_element(), Some(i));
+        assert_eq!(b.parent(&doc), None);
+        assert!(!b.has_children(&doc));
+        assert_eq!(i.parent(&doc), Some(p));
+        assert_eq!(p.children(&doc).len(), 7);
+        assert_eq!(p.children(&doc)[2].as_element(), Some(i));
+        assert_eq!(
+            doc.write_str().unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<p>Hello bold <i>and</i> <!--c--> text, world</p>"#
+        );
+
+        // A root element, after the line break following the declaration
+        i.dissolve(&mut doc).unwrap();
+        assert_eq!(p.text_content(&doc), "Hello bold and  text, world");
+        assert!(matches!(p.dissolve(&mut doc), Ok(nodes) if nodes.len() == 7));
+        assert_eq!(doc.root_nodes().len(), 8);
+        assert_eq!(doc.root_nodes()[1], Node::Text("Hello ".into()));
+
+        assert!(matches!(p.dissolve(&mut doc), Err(Error::NoParent)));
+        assert!(matches!(
+            doc.container().dissolve(&mut doc),
+            Err(Error::ContainerCannotMove)
+        ));
+    }
+
+    #[test]
+    fn test_wrap() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<list><item id="1"/>text<item id="2"/><!--c--></list>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let list = doc.root_element().unwrap();
+        let items = list.child_elements(&doc);
+
+        // Element.wrap
+        let deprecated = items[0].wrap(&mut doc, "deprecated").unwrap();
+        assert_eq!(deprecated.parent(&doc), Some(list));
+        assert_eq!(items[0].parent(&doc), Some(deprecated));
+        assert_eq!(list.children(&doc)[0].as_element(), Some(deprecated));
+        assert_eq!(list.children(&doc).len(), 4);
+
+        // Element.wrap_children
+        let group = Element::build("group").attribute("n", "2").finish(&mut doc);
+        Element::build("first").push_to(&mut doc, group);
+        list.wrap_children(&mut doc, 1..3, group).unwrap();
+        assert_eq!(group.parent(&doc), Some(list));
+        assert_eq!(items[1].parent(&doc), Some(group));
+        assert_eq!(group.children(&doc).len(), 3);
+        assert_eq!(
+            doc.write_str_with_opts(WriteOptions {
+                pretty: false,
+                ..WriteOptions::default()
+            })
+            .unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><list><deprecated><item id="1"/></deprecated><group n="2"><first/>text<item id="2"/></group><!--c--></list>"#
+        );
+
+        // Empty range
+        let empty = Element::new(&mut doc, "empty");
+        list.wrap_children(&mut doc, 3..3, empty).unwrap();
+        assert_eq!(list.children(&doc)[3].as_element(), Some(empty));
+        assert!(!empty.has_children(&doc));
+
+        // Errors leave the tree unchanged
+        let wrapper = Element::new(&mut doc, "wrapper");
+        let container = doc.container();
+        assert!(matches!(
+            container.wrap(&mut doc, "x"),
+            Err(Error::ContainerCannotMove)
+        ));
+        assert!(matches!(
+            items[0].wrap_with(&mut doc, container),
+            Err(Error::ContainerCannotMove)
+        ));
+        assert!(matches!(wrapper.wrap(&mut doc, "x"), Err(Error::NoParent)));
+        assert!(matches!(
+            items[0].wrap_with(&mut doc, group),
+            Err(Error::HasAParent)
+        ));
+        assert!(matches!(
+            list.wrap_children(&mut doc, 1..5, wrapper),
+            Err(Error::IndexOutOfBounds { index: 5, len: 4 })
+        ));
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 2..1;
+        assert!(matches!(
+            list.wrap_children(&mut doc, reversed, wrapper),
+            Err(Error::IndexOutOfBounds { .. })
+        ));
+        assert_eq!(wrapper.parent(&doc), None);
+        assert_eq!(list.children(&doc).len(), 4);
+        assert_eq!(items[0].parent(&doc), Some(deprecated));
+    }
+
+    #[test]
+    fn test_prefix_for_namespace() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <a xmlns="def" xmlns:p="U" xmlns:q="U" xmlns:r="R">
+            <b xmlns:p="V" xmlns="inner">
+                <c />
+            </b>
+        </a>"#;
+        let doc = Document::parse_str(xml).unwrap();
+        let a = doc.root_element().unwrap();
+        let b = a.child_elements(&doc)[0];
+        let c = b.child_elements(&doc)[0];
+
+        // p is rebound to V in b, so only q can be used for U.
+        assert_eq!(a.prefix_for_namespace(&doc, "U"), Some("p"));
+        assert_eq!(c.prefix_for_namespace(&doc, "U"), Some("q"));
+        assert_eq!(c.prefix_for_namespace(&doc, "V"), Some("p"));
+        assert_eq!(c.prefix_for_namespace(&doc, "R"), Some("r"));
+        assert_eq!(c.prefix_for_namespace(&doc, "none"), None);
+        // Default namespace
+        assert_eq!(a.prefix_for_namespace(&doc, "def"), Some(""));
+        assert_eq!(c.prefix_for_namespace(&doc, "def"), None);
+        assert_eq!(c.prefix_for_namespace(&doc, "inner"), Some(""));
+        assert_eq!(
+            c.prefix_for_namespace(&doc, "http://www.w3.org/XML/1998/namespace"),
+            Some("xml")
+        );
+
+        let scope = c.in_scope_namespaces(&doc);
+        assert_eq!(scope.len(), 4);
+        assert_eq!(scope[""], "inner");
+        assert_eq!(scope["p"], "V");
+        assert_eq!(scope["q"], "U");
+        assert_eq!(scope["r"], "R");
+    }
+
+    #[test]
+    fn test_mutation_validator() {
+        let mut doc = Document::new();
+        let container = doc.container();
+        let root = Element::build("root")
+            .attribute("id", "main")
+            .text_content("text")
+            .push_to(&mut doc, container);
+        doc.set_mutation_validator(Box::new(|event| match event {
+            MutationEvent::SetAttribute { value: "", .. } => {
+                Err("empty attribute value".to_string())
+            }
+            MutationEvent::SetText { text: "", .. } => Err("empty text".to_string()),
+            MutationEvent::PushChild {
+                node: Node::Comment(_),
+                ..
+            } => Err("no comments".to_string()),
+            _ => Ok(()),
+        }));
+
+        let err = root.try_set_attribute(&mut doc, "id", "").unwrap_err();
+        assert!(matches!(err, Error::Rejected(reason) if reason == "empty attribute value"));
+        assert_eq!(root.attribute(&doc, "id"), Some("main"));
+        root.try_set_attribute(&mut doc, "id", "other").unwrap();
+        assert_eq!(root.attribute(&doc, "id"), Some("other"));
+
+        assert!(root.try_set_text_content(&mut doc, "").is_err());
+        assert_eq!(root.text_content(&doc), "text");
+        let comment = Node::Comment("c".to_string());
+        assert!(root.push_child(&mut doc, comment.clone()).is_err());
+        assert!(root.insert_child(&mut doc, 0, comment).is_err());
+        assert_eq!(root.children(&doc).len(), 1);
+
+        // Unchecked methods always apply.
+        root.set_attribute(&mut doc, "id", "");
+        assert_eq!(root.attribute(&doc, "id"), Some(""));
+
+        doc.clear_mutation_validator();
+        root.try_set_text_content(&mut doc, "").unwrap();
+        assert_eq!(root.text_content(&doc), "");
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut doc = Document::new();
+        let container = doc.container();
+        let text = |s: &str| Node::Text(s.into());
+        let root = Element::new(&mut doc, "root");
+        let inner = Element::new(&mut doc, "inner");
+        let empty = Element::new(&mut doc, "empty");
+        for node in [
+            text(""),
+            text("a"),
+            text(""),
+            text("b"),
+            Node::CData("c".into()),
+            text("d"),
+            text("e"),
+            inner.as_node(),
+            text(""),
+            Node::Comment("f".to_string()),
+            text("g"),
+            text(""),
+            empty.as_node(),
+        ] {
+            root.push_child(&mut doc, node).unwrap();
+        }
+        for node in [text("x"), text("y"), Node::CData("z".into()), text("")] {
+            inner.push_child(&mut doc, node).unwrap();
+        }
+        empty.push_child(&mut doc, text("")).unwrap();
+        doc.push_root_node(text("")).unwrap();
+        doc.push_root_node(root.as_node()).unwrap();
+
+        doc.normalize();
+        assert_eq!(container.children(&doc), &[root.as_node()]);
+        assert_eq!(
+            root.children(&doc),
+            &[
+                text("ab"),
+                Node::CData("c".into()),
+                text("de"),
+                inner.as_node(),
+                Node::Comment("f".to_string()),
+                text("g"),
+                empty.as_node(),
+            ]
+        );
+        assert_eq!(inner.children(&doc), &[text("xy"), Node::CData("z".into())]);
+        assert!(!empty.has_children(&doc));
+        assert_eq!(inner.parent(&doc), Some(root));
+        assert_eq!(empty.parent(&doc), Some(root));
+
+        // Normalized elements aren't modified.
+        let revision = doc.revision();
+        doc.normalize();
+        assert_eq!(doc.revision(), revision);
+
+        // A template instance is materialized only if its own children change.
+        let template_root = Element::new(&mut doc, "t");
+        let list = Element::new(&mut doc, "list");
+        template_root.push_child(&mut doc, list.as_node()).unwrap();
+        list.push_child(&mut doc, text("1")).unwrap();
+        let template = doc.create_template(template_root);
+        let clean = root.push_template_instance(&mut doc, template);
+        root.normalize(&mut doc);
+        assert!(clean.is_template_instance(&doc));
+
+        template_root.push_child(&mut doc, text("2")).unwrap();
+        list.push_child(&mut doc, text("3")).unwrap();
+        let template = doc.create_template(template_root);
+        let dirty = root.push_template_instance(&mut doc, template);
+        root.normalize(&mut doc);
+        assert!(dirty.is_template_instance(&doc));
+        let copy = dirty.child_elements(&doc)[0];
+        assert_eq!(copy.children(&doc), &[text("13")]);
+        // The template isn't changed.
+        let stamped = root.push_template_instance(&mut doc, template);
+        let stamped_list = stamped.child_elements(&doc)[0];
+        assert_eq!(stamped_list.children(&doc), &[text("1"), text("3")]);
+    }
+
+    #[test]
+    fn test_append_text() {
+        let xml = r#"<?xml version="1.0"?><p>Hello <b>world</b></p>"#;
+        let mut doc = Document::parse_str_with_opts(
+            xml,
+            ReadOptions {
+                trim_text: false,
+                ..ReadOptions::default()
+            },
+        )
+        .unwrap();
+        let p = doc.root_element().unwrap();
+        let b = p.child_elements(&doc)[0];
+        let text = |s: &str| Node::Text(s.into());
+
+        // After an element child
+        p.append_text(&mut doc, "!");
+        assert_eq!(p.children(&doc), &[text("Hello "), b.as_node(), text("!")]);
+        // After a text child
+        p.append_text(&mut doc, "!");
+        assert_eq!(p.children(&doc), &[text("Hello "), b.as_node(), text("!!")]);
+        p.append_text(&mut doc, "");
+        assert_eq!(p.children(&doc).len(), 3);
+        assert_eq!(b.parent(&doc), Some(p));
+
+        p.prepend_text(&mut doc, "Oh, ");
+        assert_eq!(p.children(&doc)[0], text("Oh, Hello "));
+        b.prepend_text(&mut doc, "big ");
+        assert_eq!(b.children(&doc), &[text("big world")]);
+
+        // Empty text is added as is.
+        let c = Element::new(&mut doc, "c");
+        p.push_child(&mut doc, c.as_node()).unwrap();
+        p.append_text(&mut doc, "");
+        assert_eq!(p.children(&doc).last(), Some(&text("")));
+        c.prepend_text(&mut doc, "");
+        assert_eq!(c.children(&doc), &[text("")]);
+        p.append_text(&mut doc, "end");
+        assert_eq!(p.children(&doc).last(), Some(&text("end")));
+        assert_eq!(p.text_content(&doc), "Oh, Hello big world!!end");
+
+        let built = Element::build("q")
+            .append_text("a")
+            .append_text("b")
+            .child_element("br", |br| br)
+            .append_text("c")
+            .text_content("d")
+            .finish(&mut doc);
+        let children = built.children(&doc);
+        assert_eq!(children.len(), 4);
+        assert_eq!(children[0], text("ab"));
+        assert_eq!(children[2], text("c"));
+        assert_eq!(children[3], text("d"));
+    }
 }
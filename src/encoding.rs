@@ -0,0 +1,296 @@
+use crate::document::UnmappablePolicy;
+use crate::error::{Error, Result};
+use encoding_rs::{Encoder, EncoderResult, Encoding, UTF_8};
+use std::borrow::Cow;
+use std::io::Write;
+
+/// Encoding of the written XML. See [`WriteOptions::encoding`](crate::WriteOptions::encoding).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputEncoding {
+    Utf8,
+    // encoding_rs treats these labels as windows-1252, which maps more characters
+    // than a reader expecting ASCII or ISO-8859-1 would accept.
+    Ascii,
+    Latin1,
+    Other(&'static Encoding),
+}
+
+impl OutputEncoding {
+    pub(crate) fn for_label(label: Option<&str>) -> Result<OutputEncoding> {
+        let label = match label {
+            Some(label) => label.trim().to_ascii_lowercase(),
+            None => return Ok(OutputEncoding::Utf8),
+        };
+        match label.as_str() {
+            "us-ascii" | "ascii" | "ansi_x3.4-1968" | "iso-ir-6" | "iso646-us" | "us"
+            | "ibm367" | "cp367" | "csascii" => return Ok(OutputEncoding::Ascii),
+            "iso-8859-1" | "iso8859-1" | "iso88591" | "iso_8859-1" | "iso_8859-1:1987"
+            | "latin1" | "l1" | "iso-ir-100" | "ibm819" | "cp819" | "csisolatin1" => {
+                return Ok(OutputEncoding::Latin1)
+            }
+            _ => (),
+        }
+        match Encoding::for_label(label.as_bytes()) {
+            Some(encoding) if encoding == UTF_8 => Ok(OutputEncoding::Utf8),
+            // UTF-16 and the replacement encoding can't be encoded to.
+            Some(encoding) if encoding.output_encoding() == encoding => {
+                Ok(OutputEncoding::Other(encoding))
+            }
+            _ => Err(Error::UnsupportedEncoding(label)),
+        }
+    }
+
+    /// Name written in the XML declaration.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            OutputEncoding::Utf8 => "UTF-8",
+            OutputEncoding::Ascii => "US-ASCII",
+            OutputEncoding::Latin1 => "ISO-8859-1",
+            OutputEncoding::Other(encoding) => encoding.name(),
+        }
+    }
+
+    fn can_encode(self, c: char) -> bool {
+        match self {
+            OutputEncoding::Utf8 => true,
+            OutputEncoding::Ascii => c.is_ascii(),
+            OutputEncoding::Latin1 => (c as u32) <= 0xFF,
+            OutputEncoding::Other(encoding) if c.is_ascii() && encoding.is_ascii_compatible() => {
+                true
+            }
+            OutputEncoding::Other(encoding) => {
+                let (_, _, unmappable) = encoding.encode(c.encode_utf8(&mut [0; 4]));
+                !unmappable
+            }
+        }
+    }
+
+    /// First character of `text` that can't be encoded.
+    pub(crate) fn find_unmappable(self, text: &str) -> Option<char> {
+        match self {
+            OutputEncoding::Utf8 => None,
+            _ => text.chars().find(|c| !self.can_encode(*c)),
+        }
+    }
+
+    /// Returns [`Error::Unmappable`] if `text` has a character that can't be encoded.
+    /// Its element path is left empty, to be filled in by the writer.
+    pub(crate) fn check(self, text: &str, context: &'static str) -> Result<()> {
+        match self.find_unmappable(text) {
+            Some(character) => Err(Error::Unmappable {
+                character,
+                encoding: self.name(),
+                context,
+                element_path: String::new(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Handle characters of escaped text or attribute value that can't be encoded, following `policy`.
+    pub(crate) fn encode_value<'a>(
+        self,
+        text: &'a str,
+        policy: UnmappablePolicy,
+        context: &'static str,
+    ) -> Result<Cow<'a, str>> {
+        match policy {
+            UnmappablePolicy::CharRef => Ok(self.char_refs(text)),
+            UnmappablePolicy::Error => {
+                self.check(text, context)?;
+                Ok(Cow::Borrowed(text))
+            }
+        }
+    }
+
+    /// Replace characters that can't be encoded with character references, e.g. `&#8364;`.
+    pub(crate) fn char_refs(self, text: &str) -> Cow<'_, str> {
+        if self.find_unmappable(text).is_none() {
+            return Cow::Borrowed(text);
+        }
+        let mut replaced = String::with_capacity(text.len() + 8);
+        for c in text.chars() {
+            if self.can_encode(c) {
+                replaced.push(c);
+            } else {
+                replaced.push_str(&format!("&#{};", c as u32));
+            }
+        }
+        Cow::Owned(replaced)
+    }
+}
+
+/// Encodes UTF-8 written by quick-xml into the output encoding.
+///
+/// Characters that can't be encoded are replaced or rejected before they are written,
+/// so finding one here is an error.
+pub(crate) struct EncodingWriter<W: Write> {
+    inner: W,
+    encoding: OutputEncoding,
+    encoder: Option<Encoder>,
+    pending: Vec<u8>, // incomplete UTF-8 sequence at the end of last write
+    encoded: Vec<u8>,
+}
+
+impl<W: Write> EncodingWriter<W> {
+    pub(crate) fn new(inner: W, encoding: OutputEncoding) -> Self {
+        let encoder = match encoding {
+            OutputEncoding::Other(encoding) => Some(encoding.new_encoder()),
+            _ => None,
+        };
+        EncodingWriter {
+            inner,
+            encoding,
+            encoder,
+            pending: Vec::new(),
+            encoded: Vec::new(),
+        }
+    }
+
+    fn encode(&mut self, text: &str, last: bool) -> std::io::Result<()> {
+        self.encoded.clear();
+        match (self.encoding, &mut self.encoder) {
+            (OutputEncoding::Other(_), Some(encoder)) => {
+                let mut text = text;
+                loop {
+                    let needed = encoder
+                        .max_buffer_length_from_utf8_without_replacement(text.len())
+                        .unwrap_or(text.len() * 4 + 16);
+                    let start = self.encoded.len();
+                    self.encoded.resize(start + needed, 0);
+                    let (result, read, written) = encoder.encode_from_utf8_without_replacement(
+                        text,
+                        &mut self.encoded[start..],
+                        last,
+                    );
+                    self.encoded.truncate(start + written);
+                    text = &text[read..];
+                    match result {
+                        EncoderResult::InputEmpty => break,
+                        EncoderResult::OutputFull => continue,
+                        EncoderResult::Unmappable(c) => return Err(unmappable(c)),
+                    }
+                }
+            }
+            (OutputEncoding::Utf8, _) | (OutputEncoding::Other(_), None) => {
+                return self.inner.write_all(text.as_bytes());
+            }
+            (encoding, _) => {
+                for c in text.chars() {
+                    if !encoding.can_encode(c) {
+                        return Err(unmappable(c));
+                    }
+                    self.encoded.push(c as u8);
+                }
+            }
+        }
+        self.inner.write_all(&self.encoded)
+    }
+
+    /// Flush the state of the encoder. Call after everything is written.
+    pub(crate) fn finish(&mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Incomplete UTF-8 sequence at the end of output",
+            ));
+        }
+        self.encode("", true)
+    }
+}
+
+fn unmappable(c: char) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Character U+{:04X} can't be encoded", c as u32),
+    )
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.encoding == OutputEncoding::Utf8 {
+            return self.inner.write(buf);
+        }
+        self.pending.extend_from_slice(buf);
+        let valid_len = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(err) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+        };
+        let pending = std::mem::take(&mut self.pending);
+        // Bytes up to valid_len are checked above.
+        #[allow(clippy::unwrap_used)]
+        let valid = std::str::from_utf8(&pending[..valid_len]).unwrap();
+        let result = self.encode(valid, false);
+        self.pending = pending;
+        self.pending.drain(..valid_len);
+        result?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_label() {
+        let utf8 = OutputEncoding::for_label(None).unwrap();
+        assert_eq!(utf8, OutputEncoding::Utf8);
+        assert_eq!(
+            OutputEncoding::for_label(Some("utf8")).unwrap(),
+            OutputEncoding::Utf8
+        );
+        assert_eq!(
+            OutputEncoding::for_label(Some(" Latin1 ")).unwrap().name(),
+            "ISO-8859-1"
+        );
+        assert_eq!(
+            OutputEncoding::for_label(Some("windows-1252"))
+                .unwrap()
+                .name(),
+            "windows-1252"
+        );
+        for label in ["utf-16", "utf-16be", "replacement", "no-such-encoding"] {
+            assert!(matches!(
+                OutputEncoding::for_label(Some(label)),
+                Err(Error::UnsupportedEncoding(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_char_refs() {
+        let latin1 = OutputEncoding::Latin1;
+        assert_eq!(latin1.find_unmappable("é€"), Some('€'));
+        assert_eq!(latin1.char_refs("5 € é"), "5 &#8364; é");
+        assert!(matches!(latin1.char_refs("é"), Cow::Borrowed(_)));
+        let cp1252 = OutputEncoding::for_label(Some("windows-1252")).unwrap();
+        assert_eq!(cp1252.char_refs("€ 日"), "€ &#26085;");
+        assert_eq!(OutputEncoding::Ascii.char_refs("é"), "&#233;");
+    }
+
+    #[test]
+    fn test_encoding_writer() {
+        let shift_jis = OutputEncoding::for_label(Some("shift_jis")).unwrap();
+        let mut out = Vec::new();
+        let mut writer = EncodingWriter::new(&mut out, shift_jis);
+        let text = "<a>日本語</a>".as_bytes();
+        // Split in the middle of a character.
+        writer.write_all(&text[..5]).unwrap();
+        writer.write_all(&text[5..]).unwrap();
+        writer.finish().unwrap();
+        let (expected, _, _) = encoding_rs::SHIFT_JIS.encode("<a>日本語</a>");
+        assert_eq!(out, expected.as_ref());
+
+        let mut out = Vec::new();
+        let mut writer = EncodingWriter::new(&mut out, OutputEncoding::Latin1);
+        writer.write_all("é".as_bytes()).unwrap();
+        assert!(writer.write_all("€".as_bytes()).is_err());
+        assert_eq!(out, [0xE9]);
+    }
+}
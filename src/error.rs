@@ -9,11 +9,36 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     /// [`std::io`] related error.
     Io(std::io::Error),
+    /// The writer returned an error while writing a document.
+    WriteInterrupted {
+        /// Bytes successfully written before the error.
+        bytes_written: usize,
+        /// Bytes written before the last successful flush.
+        /// See [`WriteOptions::flush_every_bytes`](crate::WriteOptions::flush_every_bytes).
+        bytes_flushed: usize,
+        /// [`Element::path()`](crate::Element::path) of the element being written,
+        /// or `"/"` if it failed outside of any element.
+        at: String,
+        source: std::io::Error,
+    },
+    /// [`std::fmt::Write`] target returned an error.
+    Fmt(std::fmt::Error),
     /// Decoding related error.
     /// Maybe the XML declaration has an encoding value that it doesn't recognize,
     /// or it doesn't match its actual encoding,
-    CannotDecode,
-    /// Assorted errors while parsing XML.
+    CannotDecode {
+        /// What was being decoded. e.g. `"text"`, `"attribute value"`, `"encoding label"`
+        context: &'static str,
+        /// Approximate byte position of the invalid byte, in the input decoded to UTF-8.
+        position: Option<usize>,
+        /// The invalid byte.
+        byte: Option<u8>,
+        /// Encoding label in the XML declaration.
+        /// `None` if the document doesn't declare one, or if the error didn't happen while parsing.
+        declared_encoding: Option<String>,
+    },
+    /// Assorted errors while parsing XML,
+    /// or when writing a document would result in malformed XML.
     MalformedXML(String),
     /// The container element cannot have a parent.
     /// Use `element.is_container()` to check if it is a container before
@@ -21,19 +46,295 @@ pub enum Error {
     ContainerCannotMove,
     /// You need to call `element.detatch()` before assigning another parent.
     HasAParent,
+    /// The element isn't a child of any element, so it can't be replaced.
+    NoParent,
+    /// Renaming a namespace prefix would change the namespace of a name,
+    /// because the new prefix (the `String`) is already bound to a different namespace in scope.
+    PrefixCollision(String),
+    /// Namespace prefix of an element or attribute name isn't declared.
+    UndeclaredPrefix {
+        prefix: String,
+        /// Path of the element. See [`Element::path()`](crate::Element::path).
+        element_path: String,
+    },
+    /// Character can't be written in the output encoding.
+    /// See [`WriteOptions::unmappable`](crate::WriteOptions::unmappable).
+    Unmappable {
+        character: char,
+        /// Name of the output encoding.
+        encoding: &'static str,
+        /// What the character is in, e.g. `"text"` or `"comment"`.
+        context: &'static str,
+        /// Path of the element the character is in. See [`Element::path()`](crate::Element::path).
+        element_path: String,
+    },
+    /// Output encoding isn't supported. See [`WriteOptions::encoding`](crate::WriteOptions::encoding).
+    UnsupportedEncoding(String),
+    /// Element (the `parent`) doesn't have a child element that was expected.
+    MissingChild {
+        parent: String,
+        /// Path of the parent element. See [`Element::path()`](crate::Element::path).
+        element_path: String,
+        /// Name of the expected child element. `None` if any element was expected.
+        wanted: Option<String>,
+    },
+    /// Element (the `parent`) has more child elements than expected.
+    ExtraChildren {
+        parent: String,
+        /// Path of the parent element. See [`Element::path()`](crate::Element::path).
+        element_path: String,
+        /// Name of the expected child element. `None` if any element was expected.
+        wanted: Option<String>,
+        /// Number of matching child elements it has.
+        found_count: usize,
+    },
+    /// Element doesn't have an attribute that was expected.
+    MissingAttribute {
+        /// Path of the element. See [`Element::path()`](crate::Element::path).
+        element_path: String,
+        /// Name of the expected attribute.
+        wanted: String,
+    },
+    /// Element was expected to only have text, but has other child nodes.
+    NotTextOnly {
+        /// Path of the element. See [`Element::path()`](crate::Element::path).
+        element_path: String,
+    },
+    /// Attribute value or text content couldn't be parsed into the requested type.
+    /// See [`Element::attribute_parse()`](crate::Element::attribute_parse).
+    InvalidValue {
+        /// Path of the element. See [`Element::path()`](crate::Element::path).
+        element_path: String,
+        /// Name of the attribute, or `None` if it is the text content of the element.
+        attribute: Option<String>,
+        /// The value that couldn't be parsed.
+        value: String,
+        /// Error message of the parse error.
+        reason: String,
+    },
+    /// Modification was rejected by the document's validator, with its reason.
+    /// See [`Document::set_mutation_validator()`](crate::Document::set_mutation_validator).
+    Rejected(String),
+    /// Name is not a valid XML [Name](https://www.w3.org/TR/xml/#NT-Name).
+    InvalidName {
+        name: String,
+        /// Approximate byte position in the input decoded to UTF-8, if found while parsing.
+        position: Option<usize>,
+    },
+    /// Index is out of bounds of the children of an element.
+    IndexOutOfBounds {
+        index: usize,
+        /// Number of children.
+        len: usize,
+    },
+    /// Document has more than one root element.
+    /// See [`Document::assert_single_root()`](crate::Document::assert_single_root).
+    MultipleRoots {
+        /// Number of root elements.
+        count: usize,
+    },
+    /// Root node would make the prolog invalid, with the reason.
+    /// See [`Document::set_prolog_policy()`](crate::Document::set_prolog_policy).
+    InvalidProlog(String),
+    /// Expression isn't valid in the subset of XPath supported by
+    /// [`Document::evaluate()`](crate::Document::evaluate), with the reason.
+    InvalidQuery(String),
+    /// A limit set in [`ReadOptions`](crate::ReadOptions) was exceeded while parsing.
+    LimitExceeded {
+        /// Name of the option. e.g. `"max_text_len"`
+        limit: &'static str,
+        /// Value of the option.
+        max: usize,
+        /// Approximate byte position in the input decoded to UTF-8.
+        position: usize,
+    },
+    /// The document was modified after a [`ChildCursor`](crate::ChildCursor) was created,
+    /// other than by the cursor.
+    StaleCursor,
+    /// The [`Element`](crate::Element) doesn't exist in the document.
+    /// It may be from another document, or from content replaced by
+    /// [`Parser::parse_into()`](crate::Parser::parse_into). See [`Element::is_valid()`](crate::Element::is_valid).
+    ///
+    /// Methods that return a [`Result`] return this instead of panicking
+    /// when an element they are given isn't valid.
+    ElementNotFound,
+    /// A namespace prefix is bound to different namespaces in different parts of the document,
+    /// so its declarations can't be hoisted to the root element.
+    /// See [`WriteOptions::hoist_namespace_decls`](crate::WriteOptions::hoist_namespace_decls).
+    PrefixConflict {
+        /// Empty for the default namespace.
+        prefix: String,
+        /// Namespace of the first declaration in document order.
+        first: String,
+        /// Namespace of the conflicting declaration.
+        second: String,
+        /// Path of the element with the conflicting declaration. See [`Element::path()`](crate::Element::path).
+        element_path: String,
+    },
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::Io(err) => write!(f, "IO Error: {}", err),
-            Error::CannotDecode => write!(f, "Cannot decode XML"),
+            Error::WriteInterrupted {
+                bytes_written,
+                at,
+                source,
+                ..
+            } => write!(
+                f,
+                "Writing was interrupted at '{}' after {} bytes: {}",
+                at, bytes_written, source
+            ),
+            Error::Fmt(err) => write!(f, "Format Error: {}", err),
+            Error::CannotDecode {
+                context,
+                position,
+                byte,
+                declared_encoding,
+            } => {
+                write!(f, "Cannot decode {}", context)?;
+                if let Some(position) = position {
+                    write!(f, " at byte {}", position)?;
+                }
+                if let Some(byte) = byte {
+                    write!(f, " (invalid byte 0x{:02X})", byte)?;
+                }
+                match declared_encoding {
+                    Some(label) => write!(
+                        f,
+                        ". The document declares encoding '{}', check that it is the actual encoding of the document.",
+                        label
+                    ),
+                    // Also used where the declaration isn't known, so don't guess what it was.
+                    None => Ok(()),
+                }
+            }
             Error::MalformedXML(err) => write!(f, "Malformed XML: {}", err),
             Error::ContainerCannotMove => write!(f, "Container element cannot move"),
             Error::HasAParent => write!(
                 f,
                 "Element already has a parent. Call detatch() before changing parent."
             ),
+            Error::NoParent => write!(f, "Element has no parent"),
+            Error::PrefixCollision(prefix) => {
+                write!(f, "Namespace prefix '{}' is already in use", prefix)
+            }
+            Error::UndeclaredPrefix {
+                prefix,
+                element_path,
+            } => write!(
+                f,
+                "Namespace prefix '{}' is not declared at {}",
+                prefix, element_path
+            ),
+            Error::Unmappable {
+                character,
+                encoding,
+                context,
+                element_path,
+            } => write!(
+                f,
+                "Character '{}' (U+{:04X}) in {} at {} can't be written in {}",
+                character, *character as u32, context, element_path, encoding
+            ),
+            Error::UnsupportedEncoding(label) => {
+                write!(f, "Cannot write in encoding '{}'", label)
+            }
+            Error::MissingChild {
+                element_path,
+                wanted,
+                ..
+            } => match wanted {
+                Some(wanted) => write!(
+                    f,
+                    "Element '{}' doesn't have child element '{}'",
+                    element_path, wanted
+                ),
+                None => write!(f, "Element '{}' doesn't have a child element", element_path),
+            },
+            Error::ExtraChildren {
+                element_path,
+                wanted,
+                found_count,
+                ..
+            } => match wanted {
+                Some(wanted) => write!(
+                    f,
+                    "Element '{}' has {} child elements '{}', expected one",
+                    element_path, found_count, wanted
+                ),
+                None => write!(
+                    f,
+                    "Element '{}' has {} child elements, expected one",
+                    element_path, found_count
+                ),
+            },
+            Error::MissingAttribute {
+                element_path,
+                wanted,
+            } => write!(
+                f,
+                "Element '{}' doesn't have attribute '{}'",
+                element_path, wanted
+            ),
+            Error::NotTextOnly { element_path } => write!(
+                f,
+                "Element '{}' has child nodes other than text",
+                element_path
+            ),
+            Error::InvalidValue {
+                element_path,
+                attribute,
+                value,
+                reason,
+            } => {
+                match attribute {
+                    Some(name) => write!(f, "Attribute '{}' of '{}'", name, element_path)?,
+                    None => write!(f, "Text of '{}'", element_path)?,
+                }
+                write!(f, " has invalid value '{}': {}", value, reason)
+            }
+            Error::Rejected(reason) => write!(f, "Modification rejected: {}", reason),
+            Error::InvalidName { name, position } => {
+                write!(f, "Invalid XML name '{}'", name)?;
+                if let Some(position) = position {
+                    write!(f, " at byte {}", position)?;
+                }
+                Ok(())
+            }
+            Error::IndexOutOfBounds { index, len } => {
+                write!(f, "Index {} is out of bounds of {} children", index, len)
+            }
+            Error::MultipleRoots { count } => {
+                write!(f, "Document has {} root elements, expected one", count)
+            }
+            Error::InvalidProlog(reason) => write!(f, "Invalid prolog: {}", reason),
+            Error::InvalidQuery(reason) => write!(f, "Invalid query: {}", reason),
+            Error::LimitExceeded {
+                limit,
+                max,
+                position,
+            } => write!(f, "Limit {} = {} exceeded at byte {}", limit, max, position),
+            Error::StaleCursor => write!(f, "Document was modified after the cursor was created"),
+            Error::ElementNotFound => write!(f, "Element doesn't exist in the document"),
+            Error::PrefixConflict {
+                prefix,
+                first,
+                second,
+                element_path,
+            } => {
+                match prefix.as_str() {
+                    "" => write!(f, "Default namespace")?,
+                    prefix => write!(f, "Namespace prefix '{}'", prefix)?,
+                }
+                write!(
+                    f,
+                    " is bound to both '{}' and '{}', at {}",
+                    first, second, element_path
+                )
+            }
         }
     }
 }
@@ -42,6 +343,8 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Error::Io(err) => Some(err),
+            Error::WriteInterrupted { source, .. } => Some(source),
+            Error::Fmt(err) => Some(err),
             _ => None,
         }
     }
@@ -55,20 +358,37 @@ impl From<XMLError> for Error {
                 expected, found,
             )),
             XMLError::Io(err) => Error::Io(err),
-            XMLError::Utf8(_) => Error::CannotDecode,
+            XMLError::Utf8(err) => Error::from(err),
             err => Error::MalformedXML(err.to_string()),
         }
     }
 }
 
 impl From<FromUtf8Error> for Error {
-    fn from(_: FromUtf8Error) -> Error {
-        Error::CannotDecode
+    fn from(err: FromUtf8Error) -> Error {
+        let offset = err.utf8_error().valid_up_to();
+        Error::CannotDecode {
+            context: "UTF-8",
+            position: Some(offset),
+            byte: err.as_bytes().get(offset).copied(),
+            declared_encoding: None,
+        }
     }
 }
 impl From<Utf8Error> for Error {
-    fn from(_: Utf8Error) -> Error {
-        Error::CannotDecode
+    fn from(err: Utf8Error) -> Error {
+        Error::CannotDecode {
+            context: "UTF-8",
+            position: Some(err.valid_up_to()),
+            byte: None,
+            declared_encoding: None,
+        }
+    }
+}
+
+impl From<std::fmt::Error> for Error {
+    fn from(err: std::fmt::Error) -> Error {
+        Error::Fmt(err)
     }
 }
 
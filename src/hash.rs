@@ -0,0 +1,341 @@
+use crate::document::{read_external_text, Document, Node};
+use crate::element::Element;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+
+// Markers written to the hasher before each part of the canonical content.
+const START: u8 = 1;
+const END: u8 = 2;
+const TEXT: u8 = 3;
+const PI: u8 = 4;
+const CHILD: u8 = 5;
+
+/// Start tag in canonical form: expanded names, without namespace declarations,
+/// and attributes sorted by namespace and then by name.
+#[derive(Debug, PartialEq, Eq)]
+struct CanonicalTag<'a> {
+    namespace: Option<&'a str>,
+    name: &'a str,
+    attributes: Vec<(Option<&'a str>, &'a str, &'a str)>,
+}
+
+/// Child of an element in canonical form.
+#[derive(Debug)]
+enum CanonicalItem<'a> {
+    // Adjacent text, CDATA and external text, merged. Never empty.
+    Text(Cow<'a, str>),
+    PI(&'a str),
+    Element(Element),
+}
+
+// Namespace and name of `full_name` used in `element`. A name whose prefix isn't declared
+// is kept as is. The default namespace only applies to element names.
+fn expanded_name<'a>(
+    doc: &'a Document,
+    element: Element,
+    full_name: &'a str,
+    is_attribute: bool,
+) -> (Option<&'a str>, &'a str) {
+    let (prefix, name) = Element::separate_prefix_name(full_name);
+    if prefix.is_empty() && is_attribute {
+        return (None, name);
+    }
+    match element.namespace_for_prefix(doc, prefix) {
+        Some("") | None if prefix.is_empty() => (None, name),
+        Some(namespace) => (Some(namespace), name),
+        None => (None, full_name),
+    }
+}
+
+fn canonical_tag<'a>(doc: &'a Document, element: Element) -> CanonicalTag<'a> {
+    let (namespace, name) = expanded_name(doc, element, element.full_name(doc), false);
+    let mut attributes: Vec<_> = element
+        .attributes(doc)
+        .iter()
+        .filter(|(key, _)| *key != "xmlns" && !key.starts_with("xmlns:"))
+        .map(|(key, value)| {
+            let (namespace, name) = expanded_name(doc, element, key, true);
+            (namespace, name, value.as_str())
+        })
+        .collect();
+    attributes.sort_unstable();
+    CanonicalTag {
+        namespace,
+        name,
+        attributes,
+    }
+}
+
+fn canonical_children<'a>(doc: &'a Document, element: Element) -> Vec<CanonicalItem<'a>> {
+    let mut items = Vec::new();
+    let mut text: Option<Cow<str>> = None;
+    for node in element.children(doc) {
+        let item = match node {
+            Node::Text(node_text) | Node::CData(node_text) => {
+                append_text(&mut text, Cow::Borrowed(node_text));
+                continue;
+            }
+            Node::ExternalText(node_text) => {
+                append_text(&mut text, Cow::Owned(read_external_text(node_text)));
+                continue;
+            }
+            Node::Element(elem) => CanonicalItem::Element(*elem),
+            Node::PI(pi) => CanonicalItem::PI(pi),
+            // Comments and DOCTYPE are not part of the content.
+            _ => continue,
+        };
+        push_text(&mut items, text.take());
+        items.push(item);
+    }
+    push_text(&mut items, text);
+    items
+}
+
+fn append_text<'a>(text: &mut Option<Cow<'a, str>>, more: Cow<'a, str>) {
+    *text = Some(match text.take() {
+        Some(text) => Cow::Owned(text.into_owned() + &more),
+        None => more,
+    });
+}
+
+fn push_text<'a>(items: &mut Vec<CanonicalItem<'a>>, text: Option<Cow<'a, str>>) {
+    if let Some(text) = text.filter(|text| !text.is_empty()) {
+        items.push(CanonicalItem::Text(text));
+    }
+}
+
+// Lengths are written as little-endian `u64`, so the hash is the same on every platform,
+// unlike with `Hash` for slices, which hashes a native-endian `usize`.
+fn hash_len<H: Hasher>(state: &mut H, len: usize) {
+    state.write(&(len as u64).to_le_bytes());
+}
+
+fn hash_str<H: Hasher>(state: &mut H, text: &str) {
+    hash_len(state, text.len());
+    state.write(text.as_bytes());
+}
+
+fn hash_option_str<H: Hasher>(state: &mut H, text: Option<&str>) {
+    match text {
+        Some(text) => {
+            state.write_u8(1);
+            hash_str(state, text);
+        }
+        None => state.write_u8(0),
+    }
+}
+
+fn hash_tag<H: Hasher>(state: &mut H, tag: &CanonicalTag) {
+    state.write_u8(START);
+    hash_option_str(state, tag.namespace);
+    hash_str(state, tag.name);
+    hash_len(state, tag.attributes.len());
+    for (namespace, name, value) in &tag.attributes {
+        hash_option_str(state, *namespace);
+        hash_str(state, name);
+        hash_str(state, value);
+    }
+}
+
+impl Element {
+    /// Hash of the canonical content of this element and its descendants.
+    ///
+    /// Elements with equal canonical content have the same hash, whatever prefixes they use.
+    /// The canonical content follows XML canonicalization (C14N, without comments):
+    /// - Element and attribute names are compared by namespace and name without prefix.
+    ///   A name whose prefix isn't declared is compared as is.
+    /// - Attributes are compared regardless of order. Namespace declarations are not compared.
+    /// - Adjacent text, CDATA and external text are compared as one text. Empty text is ignored.
+    /// - Comments and DOCTYPE are ignored. Processing instructions are compared.
+    ///
+    /// The hash is the same on every run and platform, but may change with the Rust version,
+    /// since it is computed with [`DefaultHasher`]. Use [`Element::content_hash_with()`]
+    /// to choose the hasher, for example to seed it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?>
+    /// <root xmlns:a="urn:addr" xmlns:b="urn:addr">
+    ///   <a:address a:id="1" zip="123"><a:city>Paris</a:city></a:address>
+    ///   <b:address zip="123" b:id="1"><b:city><![CDATA[Paris]]></b:city></b:address>
+    /// </root>"#).unwrap();
+    /// let addresses = doc.root_element().unwrap().child_elements(&doc);
+    /// assert_eq!(addresses[0].content_hash(&doc), addresses[1].content_hash(&doc));
+    /// ```
+    pub fn content_hash(&self, doc: &Document) -> u64 {
+        let mut state = DefaultHasher::new();
+        self.content_hash_with(doc, &mut state);
+        state.finish()
+    }
+
+    /// Feed the canonical content of this element and its descendants to `state`.
+    /// See [`Element::content_hash()`].
+    ///
+    /// The content is written in document order, so it can be combined
+    /// with other data written to `state` before or after.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    pub fn content_hash_with<H: Hasher>(&self, doc: &Document, state: &mut H) {
+        self.assert_valid(doc);
+        hash_tag(state, &canonical_tag(doc, *self));
+        let mut stack = vec![canonical_children(doc, *self).into_iter()];
+        while let Some(items) = stack.last_mut() {
+            match items.next() {
+                Some(CanonicalItem::Text(text)) => {
+                    state.write_u8(TEXT);
+                    hash_str(state, &text);
+                }
+                Some(CanonicalItem::PI(pi)) => {
+                    state.write_u8(PI);
+                    hash_str(state, pi);
+                }
+                Some(CanonicalItem::Element(elem)) => {
+                    hash_tag(state, &canonical_tag(doc, elem));
+                    stack.push(canonical_children(doc, elem).into_iter());
+                }
+                None => {
+                    state.write_u8(END);
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    // Returns `true` if both elements have the same canonical content. See `content_hash`.
+    fn canonical_eq(&self, doc: &Document, other: Element) -> bool {
+        let mut stack = vec![(*self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if canonical_tag(doc, a) != canonical_tag(doc, b) {
+                return false;
+            }
+            let (a_items, b_items) = (canonical_children(doc, a), canonical_children(doc, b));
+            if a_items.len() != b_items.len() {
+                return false;
+            }
+            for pair in a_items.into_iter().zip(b_items) {
+                match pair {
+                    (CanonicalItem::Text(a), CanonicalItem::Text(b)) if a == b => {}
+                    (CanonicalItem::PI(a), CanonicalItem::PI(b)) if a == b => {}
+                    (CanonicalItem::Element(a), CanonicalItem::Element(b)) => stack.push((a, b)),
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Document {
+    /// Find elements of the document with the same canonical content, as compared by
+    /// [`Element::content_hash()`]. Elements with the same hash are compared again,
+    /// so elements of a group are always equal.
+    ///
+    /// Only subtrees of at least `min_size` nodes are considered, counting the element,
+    /// its descendant elements, processing instructions and texts, where adjacent text nodes count as one.
+    /// Groups whose elements are all inside elements of other groups are not returned,
+    /// so the children of duplicate subtrees aren't reported again.
+    ///
+    /// Returns groups of at least two elements, in document order of their first element.
+    /// Elements in each group are in document order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file of a [`Node::ExternalText`] can't be read.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?>
+    /// <orders>
+    ///   <order><address><city>Paris</city><zip>75001</zip></address></order>
+    ///   <order><address><city>Lyon</city><zip>69001</zip></address></order>
+    ///   <order><address><zip>75001</zip><city>Paris</city></address></order>
+    ///   <order><address><city>Paris</city><zip>75001</zip></address></order>
+    /// </orders>"#).unwrap();
+    /// let orders = doc.root_element().unwrap().child_elements(&doc);
+    /// // Their `<address>` elements are equal too, but are inside the orders.
+    /// assert_eq!(doc.find_duplicate_subtrees(3), [vec![orders[0], orders[3]]]);
+    /// ```
+    pub fn find_duplicate_subtrees(&self, min_size: usize) -> Vec<Vec<Element>> {
+        let elements: Vec<Element> = self.container().descendants(self).collect();
+        // Hash and size of each subtree, computed from those of child elements.
+        // Descendants come after their ancestors in document order.
+        let mut subtrees: HashMap<Element, (u64, usize)> = HashMap::with_capacity(elements.len());
+        for &elem in elements.iter().rev() {
+            let mut state = DefaultHasher::new();
+            hash_tag(&mut state, &canonical_tag(self, elem));
+            let mut size = 1;
+            for item in canonical_children(self, elem) {
+                match item {
+                    CanonicalItem::Text(text) => {
+                        state.write_u8(TEXT);
+                        hash_str(&mut state, &text);
+                        size += 1;
+                    }
+                    CanonicalItem::PI(pi) => {
+                        state.write_u8(PI);
+                        hash_str(&mut state, pi);
+                        size += 1;
+                    }
+                    CanonicalItem::Element(child) => {
+                        let (hash, child_size) = subtrees.get(&child).copied().unwrap_or_default();
+                        state.write_u8(CHILD);
+                        state.write_u64(hash);
+                        size += child_size;
+                    }
+                }
+            }
+            state.write_u8(END);
+            subtrees.insert(elem, (state.finish(), size));
+        }
+
+        let mut by_hash: HashMap<u64, Vec<Element>> = HashMap::new();
+        for &elem in &elements {
+            if let Some(&(hash, size)) = subtrees.get(&elem) {
+                if size >= min_size {
+                    by_hash.entry(hash).or_default().push(elem);
+                }
+            }
+        }
+        let mut groups: Vec<Vec<Element>> = Vec::new();
+        for candidates in by_hash.into_values().filter(|c| c.len() > 1) {
+            // Split elements with the same hash into groups of equal elements.
+            let mut equal_groups: Vec<Vec<Element>> = Vec::new();
+            for elem in candidates {
+                match equal_groups
+                    .iter_mut()
+                    .find(|group| group[0].canonical_eq(self, elem))
+                {
+                    Some(group) => group.push(elem),
+                    None => equal_groups.push(vec![elem]),
+                }
+            }
+            groups.extend(equal_groups.into_iter().filter(|group| group.len() > 1));
+        }
+
+        let grouped: HashSet<Element> = groups.iter().flatten().copied().collect();
+        groups.retain(|group| {
+            group.iter().any(|elem| {
+                !elem
+                    .ancestors(self)
+                    .any(|ancestor| grouped.contains(&ancestor))
+            })
+        });
+        let order: HashMap<Element, usize> = elements
+            .iter()
+            .enumerate()
+            .map(|(index, elem)| (*elem, index))
+            .collect();
+        groups.sort_by_key(|group| order.get(&group[0]).copied());
+        groups
+    }
+}
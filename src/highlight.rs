@@ -0,0 +1,198 @@
+use crate::document::{escape_attribute_value, Document, Node};
+use crate::element::Element;
+use crate::error::Result;
+use quick_xml::escape::escape;
+use std::io::Write;
+
+/// Kinds of tokens that a [`Theme`] can highlight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    /// Element name in start and end tags.
+    ElementName,
+    /// Attribute name, including namespace declarations. (e.g. `xmlns:ns`)
+    AttrName,
+    /// Attribute value, including its quotes.
+    AttrValue,
+    /// Text and CDATA sections.
+    Text,
+    /// Comments, including `<!--` and `-->`.
+    Comment,
+}
+
+/// Strings written around each token by [`Element::write_highlighted()`].
+pub trait Theme {
+    /// Written before a token of `class`.
+    fn prefix(&self, class: TokenClass) -> &str;
+    /// Written after a token of `class`.
+    fn suffix(&self, class: TokenClass) -> &str;
+}
+
+/// Theme that doesn't highlight anything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullTheme;
+
+impl Theme for NullTheme {
+    fn prefix(&self, _class: TokenClass) -> &str {
+        ""
+    }
+    fn suffix(&self, _class: TokenClass) -> &str {
+        ""
+    }
+}
+
+/// Theme that colors tokens with ANSI escape codes, for terminals.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnsiTheme;
+
+impl Theme for AnsiTheme {
+    fn prefix(&self, class: TokenClass) -> &str {
+        match class {
+            TokenClass::ElementName => "\x1b[34m", // blue
+            TokenClass::AttrName => "\x1b[36m",    // cyan
+            TokenClass::AttrValue => "\x1b[32m",   // green
+            TokenClass::Text => "",
+            TokenClass::Comment => "\x1b[90m", // bright black
+        }
+    }
+    fn suffix(&self, class: TokenClass) -> &str {
+        match class {
+            TokenClass::Text => "",
+            _ => "\x1b[0m",
+        }
+    }
+}
+
+/// Writes an element like [`Document::write()`] does, surrounding tokens with theme strings.
+pub(crate) struct HighlightWriter<'a, W: Write> {
+    doc: &'a Document,
+    out: &'a mut W,
+    theme: &'a dyn Theme,
+    depth: usize,
+    // Follows quick-xml's indentation: no line break after text.
+    line_break: bool,
+}
+
+const INDENT: &str = "  ";
+
+impl<'a, W: Write> HighlightWriter<'a, W> {
+    pub(crate) fn new(doc: &'a Document, out: &'a mut W, theme: &'a dyn Theme) -> Self {
+        HighlightWriter {
+            doc,
+            out,
+            theme,
+            depth: 0,
+            line_break: false,
+        }
+    }
+
+    fn token(&mut self, class: TokenClass, content: &[u8]) -> Result<()> {
+        self.out.write_all(self.theme.prefix(class).as_bytes())?;
+        self.out.write_all(content)?;
+        self.out.write_all(self.theme.suffix(class).as_bytes())?;
+        Ok(())
+    }
+
+    fn indent(&mut self) -> Result<()> {
+        if self.line_break {
+            self.out.write_all(b"\n")?;
+            for _ in 0..self.depth {
+                self.out.write_all(INDENT.as_bytes())?;
+            }
+        }
+        self.line_break = true;
+        Ok(())
+    }
+
+    pub(crate) fn write_element(&mut self, element: Element) -> Result<()> {
+        let doc = self.doc;
+        self.indent()?;
+        self.out.write_all(b"<")?;
+        self.token(TokenClass::ElementName, element.full_name(doc).as_bytes())?;
+        // Same order as `Document::build_start_tag` with `NsPosition::First`.
+        for (prefix, val) in element.namespace_decls(doc) {
+            if prefix.is_empty() {
+                self.write_attribute("xmlns", val)?;
+            } else {
+                self.write_attribute(&format!("xmlns:{}", prefix), val)?;
+            }
+        }
+        for (key, val) in element.attributes(doc) {
+            self.write_attribute(key, val)?;
+        }
+        if !element.has_children(doc) {
+            self.out.write_all(b"/>")?;
+            return Ok(());
+        }
+        self.out.write_all(b">")?;
+        self.depth += 1;
+        for node in element.children(doc) {
+            self.write_node(node)?;
+        }
+        self.depth -= 1;
+        self.indent()?;
+        self.out.write_all(b"</")?;
+        self.token(TokenClass::ElementName, element.full_name(doc).as_bytes())?;
+        self.out.write_all(b">")?;
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, key: &str, val: &str) -> Result<()> {
+        self.out.write_all(b" ")?;
+        self.token(TokenClass::AttrName, key.as_bytes())?;
+        self.out.write_all(b"=")?;
+        let mut quoted = Vec::with_capacity(val.len() + 2);
+        quoted.push(b'"');
+        quoted.extend_from_slice(escape_attribute_value(val, b'"').as_bytes());
+        quoted.push(b'"');
+        self.token(TokenClass::AttrValue, &quoted)
+    }
+
+    fn write_node(&mut self, node: &Node) -> Result<()> {
+        match node {
+            Node::Element(elem) => self.write_element(*elem)?,
+            Node::Text(text) => {
+                self.token(TokenClass::Text, &escape(text.as_bytes()))?;
+                self.line_break = false;
+            }
+            Node::ExternalText(text) if text.is_cdata() => {
+                self.out
+                    .write_all(self.theme.prefix(TokenClass::Text).as_bytes())?;
+                self.out.write_all(b"<![CDATA[")?;
+                text.for_each_cdata_chunk(|chunk| Ok(self.out.write_all(chunk)?))?;
+                self.out.write_all(b"]]>")?;
+                self.out
+                    .write_all(self.theme.suffix(TokenClass::Text).as_bytes())?;
+                self.line_break = false;
+            }
+            Node::ExternalText(text) => {
+                self.out
+                    .write_all(self.theme.prefix(TokenClass::Text).as_bytes())?;
+                text.for_each_chunk(|chunk| Ok(self.out.write_all(&escape(chunk))?))?;
+                self.out
+                    .write_all(self.theme.suffix(TokenClass::Text).as_bytes())?;
+                self.line_break = false;
+            }
+            Node::CData(text) => {
+                let cdata = format!("<![CDATA[{}]]>", text);
+                self.token(TokenClass::Text, cdata.as_bytes())?;
+                self.line_break = false;
+            }
+            Node::Comment(text) => {
+                self.indent()?;
+                let comment = format!("<!--{}-->", text);
+                self.token(TokenClass::Comment, comment.as_bytes())?;
+            }
+            Node::PI(text) => {
+                self.indent()?;
+                write!(self.out, "<?{}?>", text)?;
+            }
+            Node::DocType(text) => {
+                self.indent()?;
+                self.out.write_all(b"<!DOCTYPE ")?;
+                self.out.write_all(&escape(text.as_bytes()))?;
+                self.out.write_all(b">")?;
+            }
+        }
+        Ok(())
+    }
+}
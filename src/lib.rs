@@ -36,6 +36,8 @@
 //!
 //! Below example goes through the root element's children and removes all nodes that isn't `<conf>...</conf>`
 //! ```no_run
+//! # #[cfg(feature = "fs")]
+//! # {
 //! use std::path::Path;
 //! use xml_doc::{Document, Node};
 //!
@@ -58,14 +60,96 @@
 //!     root.remove_child(&mut doc, *i);
 //! }
 //! doc.write_file(&xml_file);
+//! # }
 //! ```
 //!
+//! # Panics
+//!
+//! Methods return [`Result`] for everything that can go wrong with valid input.
+//! Those that can panic document it in a `# Panics` section. The complete list:
+//!
+//! - Any method given an [`Element`] that wasn't created in the same [`Document`], or whose document
+//!   content was replaced by [`Parser::parse_into()`] since. Elements are ids, so an element of another
+//!   document may also silently refer to a different element. See [`Element::is_valid()`].
+//! - [`Element::push_template_instance()`] with a [`TemplateId`] that wasn't created in the same [`Document`].
+//! - [`Element::remove_child()`] with an out of bounds index. Use [`Element::try_remove_child()`].
+//! - [`Element::ensure_child_at()`] with an out of bounds index. Use [`Element::try_ensure_child_at()`].
+//! - [`ElementBuilder::finish()`] when an element added with [`ElementBuilder::node()`] has a parent
+//!   or is the container. Use [`ElementBuilder::try_finish()`].
+//! - [`ElementBuilder::push_to()`] with a parent that isn't an element of the document,
+//!   or for the same reasons as [`ElementBuilder::finish()`]. Use [`ElementBuilder::try_push_to()`].
+//! - [`Document::import_element()`], [`Document::import_with_namespaces()`] and
+//!   [`Element::clone_subtree()`] with the container. Use [`Document::clone()`] to copy the whole document.
+//! - Methods reading the text of a [`Node::ExternalText`] when its file can't be read:
+//!   [`Element::text_content()`], [`Element::text_content_sep()`], [`Element::text_chunks()`],
+//!   [`Element::has_text_content()`], [`Element::child_text()`], [`Element::child_text_trimmed()`],
+//!   [`Element::child_text_ns()`], [`Element::strip_markup()`], [`Element::as_property_map()`],
+//!   [`Element::content_hash()`], [`Element::content_hash_with()`], [`Node::text_content()`],
+//!   [`Node::is_whitespace_only()`], [`Document::extract_text()`], [`Document::find_duplicate_subtrees()`]
+//!   and [`ops::map_text()`].
+//!   Use [`Element::try_text_content()`] or [`Node::try_text_content()`] to get an [`Error::Io`] instead.
+//!
+//! Methods prefixed with `try_` are the fallible versions of methods without the prefix.
+//! They either return an [`Error`] where the other panics, or check names or the
+//! [`MutationValidator`] where the other doesn't.
+//!
+#![cfg_attr(not(test), deny(clippy::unwrap_used))]
+#[cfg(feature = "capi")]
+pub mod capi;
+mod cursor;
 mod document;
+mod dtd;
+#[cfg(feature = "fs")]
+mod edit;
 mod element;
+mod encoding;
 mod error;
+mod hash;
+mod highlight;
+mod names;
+pub mod ops;
+#[cfg(feature = "rayon")]
+mod parallel;
 mod parser;
+mod patterns;
+mod replace;
+mod roundtrip;
+mod spill;
+#[cfg(feature = "tracing")]
+mod trace;
+mod value;
+mod vocabulary;
+#[cfg(feature = "js")]
+pub mod wasm;
+mod xpath;
 
-pub use crate::document::{Document, Node, WriteOptions};
-pub use crate::element::{Element, ElementBuilder};
+pub use crate::cursor::ChildCursor;
+pub use crate::document::{
+    DeclPolicy, Document, DocumentStats, ElementHook, ElementWriteStyle, MutationEvent,
+    MutationValidator, Node, NodeKind, NsPosition, PrologPolicy, QuoteStyle, SelfCloseEmpty,
+    TemplateId, UnmappablePolicy, WriteOptions, WriteStats,
+};
+pub use crate::dtd::parse_dtd_entities;
+#[cfg(feature = "fs")]
+pub use crate::edit::{edit_file, EditOptions, EditOutcome};
+pub use crate::element::{
+    Ancestors, AttrBatch, ChildElementList, ChildElements, DescendantNodes, Descendants, Element,
+    ElementBuilder, ElementShape, TextChunks, TextMatch,
+};
 pub use crate::error::{Error, Result};
-pub use crate::parser::{normalize_space, ReadOptions};
+pub use crate::highlight::{AnsiTheme, NullTheme, Theme, TokenClass};
+#[cfg(feature = "rayon")]
+pub use crate::parallel::parse_files_parallel;
+pub use crate::parser::{
+    normalize_space, Diagnostic, ElementAction, ParseMetrics, Parser, ReadOptions,
+    UndefinedEntityPolicy, WhitespaceMode,
+};
+pub use crate::patterns::ValueSource;
+pub use crate::replace::ReplaceFlags;
+pub use crate::roundtrip::{
+    roundtrip_check, roundtrip_check_with_max, Divergence, RoundtripReport, DEFAULT_MAX_DIVERGENCES,
+};
+pub use crate::spill::ExternalText;
+pub use crate::value::Value;
+pub use crate::vocabulary::{ElementVocabulary, Violation, Vocabulary};
+pub use indexmap::IndexMap;
@@ -0,0 +1,132 @@
+use std::convert::TryFrom;
+
+// Size of the first chunk of a `NameArena`. Each new chunk is twice as large, up to `MAX_CHUNK`.
+const FIRST_CHUNK: usize = 1 << 10;
+const MAX_CHUNK: usize = 1 << 16;
+
+/// Name of an element, either owned or stored in the document's [`NameArena`].
+#[derive(Debug, Clone)]
+pub(crate) enum Name {
+    Owned(String),
+    Arena { chunk: u32, start: u32, len: u32 },
+}
+
+impl From<String> for Name {
+    fn from(name: String) -> Name {
+        Name::Owned(name)
+    }
+}
+
+impl Name {
+    /// Heap bytes owned by the name itself. Names in the arena own none.
+    pub(crate) fn heap_size(&self) -> usize {
+        match self {
+            Name::Owned(name) => name.capacity(),
+            Name::Arena { .. } => 0,
+        }
+    }
+}
+
+/// Bump allocator for element names, used with [`ReadOptions::name_arena`](crate::ReadOptions::name_arena).
+///
+/// Names are appended to the last chunk, and a new chunk is started when it is full,
+/// so chunks never reallocate. Nothing is freed until the arena is cleared or dropped.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct NameArena {
+    chunks: Vec<String>,
+}
+
+impl NameArena {
+    pub(crate) fn alloc(&mut self, name: &str) -> Name {
+        let fits = match self.chunks.last() {
+            Some(chunk) => chunk.capacity() - chunk.len() >= name.len(),
+            None => false,
+        };
+        if !fits {
+            let size = match self.chunks.last() {
+                Some(chunk) => (chunk.capacity() * 2).min(MAX_CHUNK),
+                None => FIRST_CHUNK,
+            };
+            self.chunks
+                .push(String::with_capacity(size.max(name.len())));
+        }
+        let chunk_index = self.chunks.len() - 1;
+        let chunk = &mut self.chunks[chunk_index];
+        let start = chunk.len();
+        match (
+            u32::try_from(chunk_index),
+            u32::try_from(start),
+            u32::try_from(name.len()),
+        ) {
+            (Ok(chunk_index), Ok(start), Ok(len)) => {
+                chunk.push_str(name);
+                Name::Arena {
+                    chunk: chunk_index,
+                    start,
+                    len,
+                }
+            }
+            _ => Name::Owned(name.to_string()),
+        }
+    }
+
+    pub(crate) fn get<'a>(&'a self, name: &'a Name) -> &'a str {
+        match name {
+            Name::Owned(name) => name,
+            Name::Arena { chunk, start, len } => {
+                let start = *start as usize;
+                &self.chunks[*chunk as usize][start..start + *len as usize]
+            }
+        }
+    }
+
+    /// Remove all names, keeping the last chunk for reuse.
+    pub(crate) fn clear(&mut self) {
+        if let Some(mut chunk) = self.chunks.pop() {
+            chunk.clear();
+            self.chunks.clear();
+            self.chunks.push(chunk);
+        }
+    }
+
+    pub(crate) fn heap_size(&self) -> usize {
+        let chunks = self.chunks.capacity() * std::mem::size_of::<String>();
+        chunks + self.chunks.iter().map(String::capacity).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_arena() {
+        let mut arena = NameArena::default();
+        let a = arena.alloc("item");
+        let long = "x".repeat(FIRST_CHUNK + 1);
+        let b = arena.alloc(&long);
+        let c = arena.alloc("p:item");
+        assert!(matches!(a, Name::Arena { chunk: 0, .. }));
+        assert!(matches!(b, Name::Arena { chunk: 1, .. }));
+        assert!(matches!(c, Name::Arena { chunk: 1, start, .. } if start as usize == long.len()));
+        assert_eq!(arena.get(&a), "item");
+        assert_eq!(arena.get(&b), long);
+        assert_eq!(arena.get(&c), "p:item");
+        assert_eq!(a.heap_size(), 0);
+
+        let owned = Name::from("owned".to_string());
+        assert_eq!(arena.get(&owned), "owned");
+
+        arena.clear();
+        assert_eq!(arena.chunks.len(), 1);
+        let d = arena.alloc("d");
+        assert!(matches!(
+            d,
+            Name::Arena {
+                chunk: 0,
+                start: 0,
+                len: 1
+            }
+        ));
+    }
+}
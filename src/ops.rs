@@ -0,0 +1,190 @@
+//! Helpers for modifying elements found by reading the document.
+//!
+//! Reading methods borrow [`Document`] immutably, so their results can't be held
+//! while calling methods that take `&mut Document`:
+//!
+//! ```compile_fail
+//! use xml_doc::Document;
+//!
+//! let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/><b/></a>"#).unwrap();
+//! let root = doc.root_element().unwrap();
+//! for b in root.descendants(&doc) {
+//!     b.set_attribute(&mut doc, "seen", "true"); // `doc` is still borrowed by `descendants`
+//! }
+//! ```
+//!
+//! Since [`Element`] is a `Copy` id, the fix is to collect elements first, then modify them.
+//! The functions below do that for common tasks.
+use crate::document::Document;
+use crate::element::Element;
+
+/// Call `f` on every descendant element of `root` whose name (without prefix) is `name`,
+/// in document order.
+///
+/// Matching elements are collected before `f` is first called,
+/// so `f` is called on elements it has detached as well, and not on elements it adds.
+///
+/// ```
+/// use xml_doc::{ops, Document};
+///
+/// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b/><c><b/></c></a>"#).unwrap();
+/// let root = doc.root_element().unwrap();
+/// let mut count = 0;
+/// ops::for_each_matching(&mut doc, root, "b", |doc, b| {
+///     count += 1;
+///     b.set_attribute(doc, "n", count.to_string());
+/// });
+/// assert_eq!(doc.write_str().unwrap(), r#"<?xml version="1.0" encoding="UTF-8"?>
+/// <a>
+///   <b n="1"/>
+///   <c>
+///     <b n="2"/>
+///   </c>
+/// </a>"#);
+/// ```
+pub fn for_each_matching<F>(doc: &mut Document, root: Element, name: &str, mut f: F)
+where
+    F: FnMut(&mut Document, Element),
+{
+    collect_then(
+        doc,
+        |doc| -> Vec<Element> {
+            root.descendants(doc)
+                .filter(|elem| elem.name_is(doc, name))
+                .collect()
+        },
+        |doc, elem| f(doc, elem),
+    );
+}
+
+/// Replace the text content of every descendant element of `root` whose name (without prefix)
+/// is `name` with `f(text_content)`. Returns the number of matching elements.
+///
+/// As with [`Element::set_text_content()`], children of a matching element are replaced with a single text node.
+/// Elements inside a matching element are matched before it is replaced,
+/// so they are detached by the time they would be modified.
+///
+/// ```
+/// use xml_doc::{ops, Document};
+///
+/// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b>one</b><b>two</b></a>"#).unwrap();
+/// let root = doc.root_element().unwrap();
+/// assert_eq!(ops::map_text(&mut doc, root, "b", |text| text.to_uppercase()), 2);
+/// assert_eq!(root.text_content(&doc), "ONETWO");
+/// ```
+///
+/// # Panics
+///
+/// Panics if the file of a [`Node::ExternalText`](crate::Node::ExternalText) can't be read.
+pub fn map_text<F>(doc: &mut Document, root: Element, name: &str, f: F) -> usize
+where
+    F: Fn(&str) -> String,
+{
+    let mut count = 0;
+    for_each_matching(doc, root, name, |doc, elem| {
+        count += 1;
+        let text = f(&elem.text_content(doc));
+        elem.set_text_content(doc, text);
+    });
+    count
+}
+
+/// Call `collect` with the document, then `mutate` with each item it returned.
+///
+/// Items are iterated directly, so `collect` can return any collection or iterator
+/// that doesn't borrow the document, such as a `Vec<Element>` or an `Option<Element>`.
+///
+/// ```
+/// use xml_doc::{ops, Document, Element};
+///
+/// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b id="1"/><b/></a>"#).unwrap();
+/// let root = doc.root_element().unwrap();
+/// // Move `id` attributes to child elements.
+/// ops::collect_then(
+///     &mut doc,
+///     |doc| -> Vec<(Element, String)> {
+///         root.descendants(doc)
+///             .filter_map(|e| Some((e, e.attribute(doc, "id")?.to_string())))
+///             .collect()
+///     },
+///     |doc, (elem, id)| {
+///         elem.mut_attributes(doc).remove("id");
+///         Element::build("id").text_content(id).push_to(doc, elem);
+///     },
+/// );
+/// assert_eq!(root.find(&doc, "b").unwrap().child_text(&doc, "id"), Some("1".to_string()));
+/// ```
+pub fn collect_then<T, I, C, M>(doc: &mut Document, collect: C, mut mutate: M)
+where
+    I: IntoIterator<Item = T>,
+    C: FnOnce(&Document) -> I,
+    M: FnMut(&mut Document, T),
+{
+    for item in collect(doc) {
+        mutate(doc, item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Node;
+
+    #[test]
+    fn test_for_each_matching() {
+        let xml = r#"<?xml version="1.0"?><a><b><b/></b><x:b xmlns:x="urn:x"/><c/></a>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let mut visited = Vec::new();
+        for_each_matching(&mut doc, root, "b", |doc, b| {
+            visited.push(b);
+            // Detaching doesn't stop later elements from being visited.
+            b.detatch(doc).unwrap();
+            // Added elements aren't visited.
+            Element::build("b").push_to(doc, root);
+        });
+        assert_eq!(visited.len(), 3);
+        assert!(visited.iter().all(|b| b.parent(&doc).is_none()));
+        assert_eq!(root.child_elements(&doc).len(), 4);
+
+        // Root itself isn't matched.
+        let mut count = 0;
+        for_each_matching(&mut doc, root, "a", |_, _| count += 1);
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_map_text() {
+        let xml = r#"<?xml version="1.0"?><a><p>one <b>two</b></p><p/><q>three</q></a>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let count = map_text(&mut doc, root, "p", |text| format!("[{}]", text));
+        assert_eq!(count, 2);
+        let texts: Vec<_> = root
+            .child_elements_iter(&doc)
+            .map(|e| e.text_content(&doc))
+            .collect();
+        assert_eq!(texts, ["[onetwo]", "[]", "three"]);
+        assert!(matches!(
+            root.child_elements(&doc)[0].children(&doc).as_slice(),
+            [Node::Text(_)]
+        ));
+        assert_eq!(map_text(&mut doc, root, "none", |t| t.to_string()), 0);
+    }
+
+    #[test]
+    fn test_collect_then() {
+        let xml = r#"<?xml version="1.0"?><a><b/><c/></a>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let root = doc.root_element().unwrap();
+        collect_then(
+            &mut doc,
+            |doc| root.last_child_element(doc),
+            |doc, elem| elem.set_name(doc, "last"),
+        );
+        assert!(root.find(&doc, "last").is_some());
+        let mut calls = 0;
+        collect_then(&mut doc, |doc| root.find(doc, "none"), |_, _| calls += 1);
+        assert_eq!(calls, 0);
+    }
+}
@@ -0,0 +1,35 @@
+use crate::document::Document;
+use crate::error::Result;
+use crate::parser::ReadOptions;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Parse files in parallel with rayon's global thread pool. Enabled with the `rayon` feature.
+///
+/// Results are in the same order as `paths`.
+/// Failing to read or parse a file doesn't affect the other files.
+///
+/// ```no_run
+/// use xml_doc::{parse_files_parallel, ReadOptions};
+///
+/// let paths = ["a.xml", "b.xml"];
+/// for (path, result) in parse_files_parallel(&paths, &ReadOptions::default()) {
+///     match result {
+///         Ok(doc) => println!("{}: {} root nodes", path.display(), doc.root_nodes().len()),
+///         Err(err) => eprintln!("{}: {}", path.display(), err),
+///     }
+/// }
+/// ```
+pub fn parse_files_parallel<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    opts: &ReadOptions,
+) -> Vec<(PathBuf, Result<Document>)> {
+    paths
+        .par_iter()
+        .map(|path| {
+            let path = path.as_ref();
+            let doc = Document::parse_file_with_opts(path, opts.clone());
+            (path.to_path_buf(), doc)
+        })
+        .collect()
+}
@@ -1,13 +1,46 @@
 use crate::document::{Document, Node};
-use crate::element::Element;
+use crate::element::{namespace_decl_prefix, Element, ElementShape};
 use crate::error::{Error, Result};
-use encoding_rs::Decoder;
+use crate::spill::ExternalText;
+use crate::value::Value;
+use encoding_rs::{CoderResult, Decoder};
 use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use indexmap::map::Entry;
+use indexmap::IndexMap;
+use quick_xml::escape::{escape, unescape};
 use quick_xml::events::{BytesDecl, BytesStart, Event};
-use quick_xml::Reader;
+use quick_xml::{Error as XMLError, Reader};
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::{BufRead, Read};
+use std::path::PathBuf;
+
+const UNDECODED_SIZE: usize = 4096;
+const DECODED_SIZE: usize = 12288;
+
+/// Scratch buffers that a [`Parser`] keeps between parses.
+#[derive(Default)]
+pub(crate) struct ParseBuffers {
+    event: Vec<u8>,
+    element_stack: Vec<Element>,
+    undecoded: Box<[u8]>, // empty until first used
+    decoded: Box<[u8]>,
+    // Text of a byte slice decoded from another encoding, before and after switching to
+    // the encoding of the XML declaration.
+    decoded_text: String,
+    decoded_rest: String,
+    // Document parsed into by `Parser::parse_into`, swapped with the target on success.
+    document: Option<Document>,
+}
+
+impl ParseBuffers {
+    fn take(buffer: &mut Box<[u8]>, size: usize) -> Box<[u8]> {
+        match std::mem::take(buffer) {
+            buffer if buffer.len() == size => buffer,
+            _ => vec![0; size].into_boxed_slice(),
+        }
+    }
+}
 
 pub(crate) struct DecodeReader<R: Read> {
     decoder: Option<Decoder>,
@@ -24,26 +57,22 @@ pub(crate) struct DecodeReader<R: Read> {
 
 impl<R: Read> DecodeReader<R> {
     // If Decoder is not set, don't decode.
-    pub(crate) fn new(reader: R, decoder: Option<Decoder>) -> DecodeReader<R> {
+    // Takes buffers from `buffers`. Give them back with `ParseSource::return_buffers`.
+    fn with_buffers(reader: R, decoder: Option<Decoder>, buffers: &mut ParseBuffers) -> Self {
         DecodeReader {
             decoder,
             inner: reader,
-            undecoded: vec![0; 4096].into_boxed_slice(),
+            undecoded: ParseBuffers::take(&mut buffers.undecoded, UNDECODED_SIZE),
             undecoded_pos: 0,
             undecoded_cap: 0,
             remaining: [0; 32],
-            decoded: vec![0; 12288].into_boxed_slice(),
+            decoded: ParseBuffers::take(&mut buffers.decoded, DECODED_SIZE),
             decoded_pos: 0,
             decoded_cap: 0,
             done: false,
         }
     }
 
-    pub(crate) fn set_encoding(&mut self, encoding: Option<&'static Encoding>) {
-        self.decoder = encoding.map(|e| e.new_decoder_without_bom_handling());
-        self.done = false;
-    }
-
     // Call this only when decoder is Some
     fn fill_buf_decode(&mut self) -> std::io::Result<&[u8]> {
         if self.decoded_pos >= self.decoded_cap {
@@ -64,7 +93,8 @@ impl<R: Read> DecodeReader<R> {
                 self.undecoded_cap = remaining + read;
             }
 
-            // Fill decoded buffer
+            // Fill decoded buffer. `fill_buf` only calls this when decoder is Some.
+            #[allow(clippy::unwrap_used)]
             let (_res, read, written, _replaced) = self.decoder.as_mut().unwrap().decode_to_utf8(
                 &self.undecoded[self.undecoded_pos..self.undecoded_cap],
                 &mut self.decoded,
@@ -87,6 +117,279 @@ impl<R: Read> DecodeReader<R> {
     }
 }
 
+// Reads decoded UTF-8 from a `BufRead`, without copying when it isn't decoded.
+pub(crate) struct BufDecodeReader<B: BufRead> {
+    decoder: Option<Decoder>,
+    inner: B,
+    decoded: Box<[u8]>,
+    decoded_pos: usize,
+    decoded_cap: usize,
+    done: bool,
+}
+
+impl<B: BufRead> BufDecodeReader<B> {
+    // Like `DecodeReader::with_buffers`, but only the decoded buffer is used.
+    fn with_buffers(reader: B, decoder: Option<Decoder>, buffers: &mut ParseBuffers) -> Self {
+        BufDecodeReader {
+            decoder,
+            inner: reader,
+            decoded: ParseBuffers::take(&mut buffers.decoded, DECODED_SIZE),
+            decoded_pos: 0,
+            decoded_cap: 0,
+            done: false,
+        }
+    }
+
+    // Call this only when decoder is Some
+    fn fill_buf_decode(&mut self) -> std::io::Result<&[u8]> {
+        // The decoder may keep a partial character without writing anything, so read until
+        // something is written or the input ends.
+        while self.decoded_pos >= self.decoded_cap && !self.done {
+            let input = self.inner.fill_buf()?;
+            let last = input.is_empty();
+            // `fill_buf` only calls this when decoder is Some.
+            #[allow(clippy::unwrap_used)]
+            let (_res, read, written, _replaced) =
+                self.decoder
+                    .as_mut()
+                    .unwrap()
+                    .decode_to_utf8(input, &mut self.decoded, last);
+            self.inner.consume(read);
+            self.done = last;
+            self.decoded_cap = written;
+            self.decoded_pos = 0;
+        }
+        Ok(&self.decoded[self.decoded_pos..self.decoded_cap])
+    }
+}
+
+impl<B: BufRead> Read for BufDecodeReader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.fill_buf()?.read(buf)?;
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<B: BufRead> BufRead for BufDecodeReader<B> {
+    // Decoder may change from None to Some.
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        match &self.decoder {
+            Some(_) => self.fill_buf_decode(),
+            None => self.inner.fill_buf(),
+        }
+    }
+    fn consume(&mut self, amt: usize) {
+        match &self.decoder {
+            Some(_) => {
+                self.decoded_pos = std::cmp::min(self.decoded_pos + amt, self.decoded_cap);
+            }
+            None => self.inner.consume(amt),
+        }
+    }
+}
+
+// Returns an error from `fill_buf` when a text or CDATA section in the UTF-8 read from `inner`
+// gets longer than `limit`, so that quick-xml doesn't read all of it into the event buffer.
+// See `ReadOptions::max_text_len`.
+struct TextLimitReader<B: BufRead> {
+    inner: B,
+    limit: Option<usize>,
+    state: MarkupState,
+    len: usize, // length of the current text or CDATA section, with "\r\n" counted once
+    after_cr: bool, // previous byte was '\r'
+    scanned: usize, // bytes of `inner.fill_buf()` already scanned
+    exceeded: bool,
+}
+
+// Where the reader is in the markup. Only precise enough to find text and CDATA sections
+// in well-formed XML. Other input is rejected by quick-xml.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MarkupState {
+    Text,
+    Open,                                     // after '<'
+    Bang(usize),                              // after "<!" and `usize` bytes of "[CDATA["
+    BangDash,                                 // after "<!-"
+    Tag(Option<u8>),                          // in a tag, and in a quoted value if `Some(quote)`
+    Decl { quote: Option<u8>, depth: usize }, // in a DOCTYPE, `depth` brackets deep
+    Comment(usize),                           // in a comment, after `usize` '-'
+    PI(bool),                                 // in a processing instruction, after '?' if true
+    CData(usize),                             // in a CDATA section, after `usize` ']'
+}
+
+#[derive(Debug)]
+struct TextTooLong;
+
+impl std::fmt::Display for TextTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "text is longer than max_text_len")
+    }
+}
+
+impl std::error::Error for TextTooLong {}
+
+impl<B: BufRead> TextLimitReader<B> {
+    fn new(inner: B, limit: Option<usize>) -> Self {
+        TextLimitReader {
+            inner,
+            limit,
+            state: MarkupState::Text,
+            len: 0,
+            after_cr: false,
+            scanned: 0,
+            exceeded: false,
+        }
+    }
+
+    // Start scanning again from the next byte of `inner`, which must start text.
+    fn reset(&mut self) {
+        self.state = MarkupState::Text;
+        self.len = 0;
+        self.after_cr = false;
+        self.scanned = 0;
+    }
+
+    fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl MarkupState {
+    fn next(self, byte: u8) -> MarkupState {
+        use MarkupState::*;
+        match (self, byte) {
+            (Text, b'<') => Open,
+            (Text, _) => Text,
+            (Open, b'!') => Bang(0),
+            (Open, b'?') => PI(false),
+            (Open, _) => Tag(None).next(byte),
+            (Bang(0), b'-') => BangDash,
+            (Bang(6), b'[') => CData(0),
+            (Bang(n), _) if b"[CDATA["[n] == byte => Bang(n + 1),
+            (BangDash, b'-') => Comment(0),
+            (Bang(_) | BangDash, _) => Decl {
+                quote: None,
+                depth: 0,
+            }
+            .next(byte),
+            (Tag(None), b'>') => Text,
+            (Tag(None), b'"' | b'\'') => Tag(Some(byte)),
+            (Tag(Some(quote)), _) if quote == byte => Tag(None),
+            (Tag(_), _) => self,
+            (
+                Decl {
+                    quote: Some(quote),
+                    depth,
+                },
+                _,
+            ) if quote == byte => Decl { quote: None, depth },
+            (Decl { quote: Some(_), .. }, _) => self,
+            (Decl { depth: 0, .. }, b'>') => Text,
+            (Decl { depth, .. }, b'"' | b'\'') => Decl {
+                quote: Some(byte),
+                depth,
+            },
+            (Decl { depth, .. }, b'[') => Decl {
+                quote: None,
+                depth: depth + 1,
+            },
+            (Decl { depth, .. }, b']') => Decl {
+                quote: None,
+                depth: depth.saturating_sub(1),
+            },
+            (Decl { .. }, _) => self,
+            (Comment(n), b'>') if n >= 2 => Text,
+            (Comment(n), b'-') => Comment(n + 1),
+            (Comment(_), _) => Comment(0),
+            (PI(true), b'>') => Text,
+            (PI(_), _) => PI(byte == b'?'),
+            (CData(n), b'>') if n >= 2 => Text,
+            (CData(n), b']') => CData(n + 1),
+            (CData(_), _) => CData(0),
+        }
+    }
+}
+
+impl<B: BufRead> Read for TextLimitReader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.fill_buf()?.read(buf)?;
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<B: BufRead> BufRead for TextLimitReader<B> {
+    // Returns the bytes up to the one exceeding the limit, and then an error.
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        let available = self.inner.fill_buf()?;
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Ok(available),
+        };
+        if !self.exceeded {
+            for &byte in &available[self.scanned..] {
+                let state = self.state.next(byte);
+                let counted = matches!(
+                    (self.state, state),
+                    (MarkupState::Text, MarkupState::Text)
+                        | (MarkupState::CData(_), MarkupState::CData(_))
+                );
+                if !counted {
+                    self.len = 0;
+                } else if !(self.after_cr && byte == b'\n') {
+                    self.len += 1;
+                }
+                self.after_cr = byte == b'\r';
+                self.state = state;
+                // The "]]" ending a CDATA section are counted before the end is known.
+                if self.len > limit + 2 {
+                    self.exceeded = true;
+                    break;
+                }
+                self.scanned += 1;
+            }
+        }
+        match self.exceeded && self.scanned == 0 {
+            true => Err(std::io::Error::other(TextTooLong)),
+            false => Ok(&available[..self.scanned]),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.limit.is_some() {
+            self.scanned -= amt;
+        }
+        self.inner.consume(amt);
+    }
+}
+
+// A reader that `parse_start` can switch to the declared encoding.
+trait ParseSource: BufRead {
+    fn set_encoding(&mut self, encoding: Option<&'static Encoding>);
+    fn return_buffers(self, buffers: &mut ParseBuffers);
+}
+
+impl<R: Read> ParseSource for DecodeReader<R> {
+    fn set_encoding(&mut self, encoding: Option<&'static Encoding>) {
+        self.decoder = encoding.map(|e| e.new_decoder_without_bom_handling());
+        self.done = false;
+    }
+    fn return_buffers(self, buffers: &mut ParseBuffers) {
+        buffers.undecoded = self.undecoded;
+        buffers.decoded = self.decoded;
+    }
+}
+
+impl<B: BufRead> ParseSource for BufDecodeReader<B> {
+    fn set_encoding(&mut self, encoding: Option<&'static Encoding>) {
+        self.decoder = encoding.map(|e| e.new_decoder_without_bom_handling());
+        self.done = false;
+    }
+    fn return_buffers(self, buffers: &mut ParseBuffers) {
+        buffers.decoded = self.decoded;
+    }
+}
+
 impl<R: Read> Read for DecodeReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         (&self.decoded[..]).read(buf)
@@ -114,6 +417,10 @@ impl<R: Read> BufRead for DecodeReader<R> {
 }
 
 /// Options when parsing xml.
+///
+/// Line endings `\r\n` and `\r` in text, CDATA, comments, processing instructions and DOCTYPE
+/// are read as `\n`, so a document parses the same with either line ending whatever the options.
+/// A `\r` written as the character reference `&#13;` is kept.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ReadOptions {
     /// <tag></tag> will have a Node::Text("") as its children, while <tag /> won't.
@@ -126,6 +433,10 @@ pub struct ReadOptions {
     /// Only makes sense if `trim_text` is `false`. (If both are `true`, performance takes a hit for no gain)
     /// Default: `false`
     pub ignore_whitespace_only: bool,
+    /// How whitespace in text is handled. With [`WhitespaceMode::Smart`],
+    /// `trim_text` and `ignore_whitespace_only` are ignored.
+    /// Default: [`WhitespaceMode::Manual`]
+    pub whitespace: WhitespaceMode,
     /// Returns error if document doesn't start with XML declaration.
     /// If there is no XML declaration, the parser won't be able to decode encodings other than UTF-8, unless `encoding` below is set.
     /// Default: `true`
@@ -135,48 +446,682 @@ pub struct ReadOptions {
     /// See [`encoding_rs::Encoding::for_label`] for valid values.
     /// Default: `None`
     pub encoding: Option<String>,
+    /// Recover from some errors instead of failing to parse,
+    /// such as a `standalone` value that isn't `yes` or `no`.
+    /// Each recovered error is recorded in [`Document::diagnostics()`].
+    ///
+    /// Unknown pseudo-attributes in the XML declaration are always ignored,
+    /// but are only recorded in [`Document::diagnostics()`] if this is `true`.
+    /// Default: `false`
+    pub lenient: bool,
+    /// Keep the source of start tags, including whitespace between attributes and quote characters.
+    /// Elements whose name, attributes and namespace declarations weren't modified
+    /// are written with the original start tag.
+    /// Default: `false`
+    pub high_fidelity: bool,
+    /// Returns [`Error::InvalidName`] if an element name, attribute name or processing instruction target
+    /// isn't a valid XML [Name](https://www.w3.org/TR/xml/#NT-Name).
+    /// Default: `false`
+    pub validate_names: bool,
+    /// What to do with references to entities that are not predefined,
+    /// such as entities declared in an external DTD.
+    /// Each undefined entity reference is recorded in [`Document::diagnostics()`]
+    /// unless the policy is [`UndefinedEntityPolicy::Error`].
+    /// Default: [`UndefinedEntityPolicy::Error`]
+    pub undefined_entities: UndefinedEntityPolicy,
+    /// Replacement texts of entities, by entity name, to expand references in text and attribute values.
+    /// Consulted before `undefined_entities`. Predefined entities can't be overridden.
+    ///
+    /// Replacement texts are inserted as text: markup and references in them are not parsed.
+    /// Use [`parse_dtd_entities()`](crate::parse_dtd_entities) to read them from a DTD.
+    /// Default: `None`
+    pub entity_catalog: Option<HashMap<String, String>>,
+    /// Maximum length in bytes of a single text or CDATA section, measured in the input
+    /// decoded to UTF-8, before unescaping or trimming whitespace. Returns [`Error::LimitExceeded`]
+    /// if a longer one is found, unless `spill_large_text_to` is set.
+    ///
+    /// Reading stops as soon as the limit is exceeded, so a longer section is never
+    /// read into memory whole.
+    /// Default: `None`
+    pub max_text_len: Option<usize>,
+    /// If set along with `max_text_len`, longer text and CDATA sections are stored in files
+    /// in this directory as [`Node::ExternalText`], instead of returning an error.
+    /// The files are deleted when the document is dropped.
+    /// CDATA sections stored this way are written back as CDATA sections.
+    /// See [`ExternalText::is_cdata()`](crate::ExternalText::is_cdata).
+    ///
+    /// Each of those sections is read into memory once before it is written to its file.
+    /// Default: `None`
+    pub spill_large_text_to: Option<PathBuf>,
+    /// Record a diagnostic in [`Document::diagnostics()`] for each root element after the first.
+    /// Documents with multiple root elements are parsed either way.
+    /// Default: `false`
+    pub diagnose_multiple_roots: bool,
+    /// Measure the document while parsing, such as the longest attribute value
+    /// and the deepest nesting. See [`Document::parse_metrics()`].
+    /// Default: `false`
+    pub collect_metrics: bool,
+    /// Store element names in an arena owned by the document, instead of allocating
+    /// a `String` for each element. Dropping the document frees the arena at once.
+    ///
+    /// Names are read the same way. A name changed after parsing is stored as an owned `String`,
+    /// and the old one stays in the arena until the document is dropped or parsed into again.
+    /// Only element names are stored in the arena. Text and attribute values are [`Value`]s,
+    /// which [`ReadOptions::dedup_values`] can share instead.
+    /// Default: `false`
+    pub name_arena: bool,
+    /// Store each distinct text and attribute value once, shared by all nodes and attributes
+    /// with that value, instead of allocating a `String` for each of them.
+    /// This saves memory for documents where the same values repeat many times.
+    ///
+    /// Values are read the same way. Changing a shared value copies it first,
+    /// so other nodes and attributes sharing it are not affected. See [`Value`].
+    /// Default: `false`
+    pub dedup_values: bool,
 }
 
-impl ReadOptions {
+impl Default for ReadOptions {
     /// Create ReadOptions with default options.
-    pub fn default() -> ReadOptions {
+    fn default() -> ReadOptions {
         ReadOptions {
             empty_text_node: true,
             trim_text: true,
             ignore_whitespace_only: false,
+            whitespace: WhitespaceMode::Manual,
             require_decl: true,
             encoding: None,
+            lenient: false,
+            high_fidelity: false,
+            validate_names: false,
+            undefined_entities: UndefinedEntityPolicy::Error,
+            entity_catalog: None,
+            max_text_len: None,
+            spill_large_text_to: None,
+            diagnose_multiple_roots: false,
+            collect_metrics: false,
+            name_arena: false,
+            dedup_values: false,
         }
     }
 }
 
+/// How whitespace in text is handled. See [`ReadOptions::whitespace`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhitespaceMode {
+    /// Follow [`ReadOptions::trim_text`] and [`ReadOptions::ignore_whitespace_only`].
+    #[default]
+    Manual,
+    /// Text is kept untrimmed, except that whitespace-only text nodes are removed
+    /// from elements that have child elements but no other text, such as indentation.
+    /// Whitespace in mixed content is kept.
+    ///
+    /// Elements in scope of `xml:space="preserve"` keep all whitespace.
+    /// Whitespace-only text between root nodes is removed as well.
+    Smart,
+}
+
+/// How to handle references to undefined entities in text and attribute values.
+/// See [`ReadOptions::undefined_entities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UndefinedEntityPolicy {
+    /// Fail to parse the document.
+    #[default]
+    Error,
+    /// Keep the reference as literal text, e.g. `&chapter1;`
+    KeepReference,
+    /// Remove the reference.
+    Drop,
+}
+
+/// A problem the parser recovered from.
+/// See [`ReadOptions::lenient`] and [`ReadOptions::undefined_entities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Approximate byte position in the input decoded to UTF-8.
+    pub position: usize,
+    pub message: String,
+}
+
+/// Measurements of a parsed document. See [`ReadOptions::collect_metrics`].
+///
+/// Lengths are in bytes of the input decoded to UTF-8, after unescaping.
+/// Subtrees skipped by an element filter aren't measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseMetrics {
+    /// Length of the longest attribute value, including namespace declarations.
+    pub max_attribute_value_len: usize,
+    /// Most attributes on one element, including namespace declarations.
+    pub max_attributes: usize,
+    /// Deepest nesting of elements. A root element has depth 1.
+    pub max_depth: usize,
+    /// References to entities of [`ReadOptions::entity_catalog`] that were expanded.
+    pub entity_references: usize,
+    /// Length of those references, from `&` to `;`.
+    pub entity_reference_bytes: usize,
+    /// Length of the replacement texts of those references.
+    pub entity_expansion_bytes: usize,
+    pub elements: usize,
+    /// Text nodes. Ignored whitespace isn't counted.
+    /// Text and CDATA sections stored in files are counted here.
+    pub texts: usize,
+    pub cdatas: usize,
+    pub comments: usize,
+    pub pis: usize,
+    pub doctypes: usize,
+}
+
+impl ParseMetrics {
+    /// How many times longer the replacement texts of entity references are than the references,
+    /// or `None` if no entity was expanded.
+    pub fn entity_expansion_ratio(&self) -> Option<f64> {
+        if self.entity_reference_bytes == 0 {
+            return None;
+        }
+        Some(self.entity_expansion_bytes as f64 / self.entity_reference_bytes as f64)
+    }
+}
+
+/// A reusable parser, for parsing many documents with the same options.
+///
+/// The parser keeps its scratch buffers between parses,
+/// so parsing many small documents allocates less than [`Document::parse_str_with_opts()`].
+///
+/// ```
+/// use xml_doc::{Document, Parser, ReadOptions};
+///
+/// let mut parser = Parser::new(ReadOptions::default());
+/// let mut doc = Document::new();
+/// for id in 0..3 {
+///     let xml = format!(r#"<?xml version="1.0"?><message id="{}"/>"#, id);
+///     parser.parse_into(&mut doc, &xml).unwrap();
+///     let root = doc.root_element().unwrap();
+///     assert_eq!(root.attribute(&doc, "id"), Some(id.to_string().as_str()));
+/// }
+/// ```
+pub struct Parser {
+    opts: ReadOptions,
+    buffers: ParseBuffers,
+}
+
+impl Parser {
+    pub fn new(opts: ReadOptions) -> Parser {
+        Parser {
+            opts,
+            buffers: ParseBuffers::default(),
+        }
+    }
+
+    pub fn options(&self) -> &ReadOptions {
+        &self.opts
+    }
+
+    pub fn parse_str(&mut self, str: &str) -> Result<Document> {
+        self.parse_bytes(str.as_bytes())
+    }
+
+    /// See [`Document::parse_bytes()`].
+    pub fn parse_bytes(&mut self, bytes: &[u8]) -> Result<Document> {
+        let opts = self.opts.clone();
+        DocumentParser::parse_bytes_with(bytes, opts, Document::new(), &mut self.buffers)
+    }
+
+    pub fn parse_reader<R: Read>(&mut self, reader: R) -> Result<Document> {
+        let opts = self.opts.clone();
+        DocumentParser::parse_with(reader, opts, None, Document::new(), &mut self.buffers)
+    }
+
+    /// See [`Document::parse_bufread()`].
+    pub fn parse_bufread<B: BufRead>(&mut self, reader: B) -> Result<Document> {
+        let opts = self.opts.clone();
+        DocumentParser::parse_bufread_with(reader, opts, Document::new(), &mut self.buffers)
+    }
+
+    /// Parse `str` into `doc`, replacing its content.
+    ///
+    /// The storage of `doc` is kept by the parser and reused by the next call,
+    /// so this allocates less than creating a new document.
+    /// Everything in `doc` is replaced, including its diagnostics
+    /// and [mutation validator](Document::set_mutation_validator).
+    ///
+    /// # Errors
+    /// - Errors from parsing `str`, like [`Document::parse_str()`]. `doc` is left unchanged.
+    pub fn parse_into(&mut self, doc: &mut Document, str: &str) -> Result<()> {
+        let mut target = self.buffers.document.take().unwrap_or_default();
+        target.clear_after(doc);
+        let opts = self.opts.clone();
+        let parsed =
+            DocumentParser::parse_bytes_with(str.as_bytes(), opts, target, &mut self.buffers)?;
+        self.buffers.document = Some(std::mem::replace(doc, parsed));
+        Ok(())
+    }
+}
+
+/// What to do with an element while parsing. Returned by the filter of
+/// [`Document::parse_reader_with_filter()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementAction {
+    /// Add the element to the document.
+    Keep,
+    /// Don't add the element and its children to the document.
+    SkipSubtree,
+    /// Don't add the element, but add its children to its parent.
+    SkipElementKeepChildren,
+}
+
+type ElementFilter<'f> = &'f mut dyn FnMut(&str, &IndexMap<String, Value>) -> ElementAction;
+
 //TODO: don't unwrap element_stack.last() or pop(). Invalid XML file can crash the software.
-pub(crate) struct DocumentParser {
+pub(crate) struct DocumentParser<'f> {
     doc: Document,
     read_opts: ReadOptions,
     encoding: Option<&'static Encoding>,
+    decl_encoding: Option<String>, // encoding label in XML declaration
     element_stack: Vec<Element>,
+    element_filter: Option<ElementFilter<'f>>,
+    skip_depth: usize,             // depth inside a skipped subtree
+    spliced_depths: Vec<usize>, // element_stack lengths where a parent was pushed again for SkipElementKeepChildren
+    pos_base: usize,            // position where current quick-xml reader started reading
+    event_pos: usize,           // position where current event starts
+    metrics: Option<ParseMetrics>, // `Some` if `ReadOptions::collect_metrics`
+    depth: usize,               // depth of the current element, for `ParseMetrics::max_depth`
 }
 
-impl DocumentParser {
+impl<'f> DocumentParser<'f> {
     pub(crate) fn parse_reader<R: Read>(reader: R, opts: ReadOptions) -> Result<Document> {
-        let doc = Document::new();
-        let element_stack = vec![doc.container()];
-        let mut parser = DocumentParser {
+        DocumentParser::parse_reader_with_filter(reader, opts, None)
+    }
+
+    pub(crate) fn parse_reader_with_filter<R: Read>(
+        reader: R,
+        opts: ReadOptions,
+        element_filter: Option<ElementFilter<'f>>,
+    ) -> Result<Document> {
+        let buffers = &mut ParseBuffers::default();
+        DocumentParser::parse_with(reader, opts, element_filter, Document::new(), buffers)
+    }
+
+    // Parse into `doc`, which must be empty, using scratch buffers from `buffers`.
+    fn parse_with<R: Read>(
+        reader: R,
+        opts: ReadOptions,
+        element_filter: Option<ElementFilter<'f>>,
+        doc: Document,
+        buffers: &mut ParseBuffers,
+    ) -> Result<Document> {
+        let mut parser = DocumentParser::new(opts, element_filter, doc, buffers);
+        let reader = DecodeReader::with_buffers(reader, None, buffers);
+        parser.traced(|parser| parser.parse_start(reader, buffers))?;
+        Ok(parser.finish(buffers))
+    }
+
+    pub(crate) fn parse_bufread<B: BufRead>(reader: B, opts: ReadOptions) -> Result<Document> {
+        let buffers = &mut ParseBuffers::default();
+        DocumentParser::parse_bufread_with(reader, opts, Document::new(), buffers)
+    }
+
+    // Same as `parse_with`, but UTF-8 is read from the buffer of `reader` without copying.
+    fn parse_bufread_with<B: BufRead>(
+        reader: B,
+        opts: ReadOptions,
+        doc: Document,
+        buffers: &mut ParseBuffers,
+    ) -> Result<Document> {
+        let mut parser = DocumentParser::new(opts, None, doc, buffers);
+        let reader = BufDecodeReader::with_buffers(reader, None, buffers);
+        parser.traced(|parser| parser.parse_start(reader, buffers))?;
+        Ok(parser.finish(buffers))
+    }
+
+    pub(crate) fn parse_bytes(bytes: &[u8], opts: ReadOptions) -> Result<Document> {
+        let buffers = &mut ParseBuffers::default();
+        DocumentParser::parse_bytes_with(bytes, opts, Document::new(), buffers)
+    }
+
+    // Same as `parse_with`, but an in-memory document isn't copied through `DecodeReader`.
+    fn parse_bytes_with(
+        bytes: &[u8],
+        opts: ReadOptions,
+        doc: Document,
+        buffers: &mut ParseBuffers,
+    ) -> Result<Document> {
+        let mut parser = DocumentParser::new(opts, None, doc, buffers);
+        parser.traced(|parser| parser.parse_start_bytes(bytes, buffers))?;
+        Ok(parser.finish(buffers))
+    }
+
+    // Parse `xml` as a fragment, pushing its nodes to `parent` in `doc`.
+    // Nodes pushed before an error are left in `parent`.
+    pub(crate) fn parse_fragment(
+        doc: &mut Document,
+        parent: Element,
+        xml: &str,
+        opts: ReadOptions,
+    ) -> Result<()> {
+        let buffers = &mut ParseBuffers::default();
+        let mut parser = DocumentParser::new(opts, None, std::mem::take(doc), buffers);
+        parser.element_stack.clear();
+        parser.element_stack.push(parent);
+        let result = parser.parse_fragment_content(xml);
+        parser.doc.values.prune();
+        *doc = parser.doc;
+        result
+    }
+
+    fn parse_fragment_content(&mut self, xml: &str) -> Result<()> {
+        let mut reader =
+            Reader::from_reader(TextLimitReader::new(xml.as_bytes(), self.text_limit()));
+        reader.trim_text(self.trim_text());
+        let mut buf = Vec::new();
+        loop {
+            self.event_pos = reader.buffer_position();
+            buf.clear();
+            let event = match self.read_event(&mut reader, &mut buf)? {
+                Event::Decl(_) => {
+                    return Err(Error::MalformedXML(
+                        "XML declaration isn't allowed in a fragment".to_string(),
+                    ))
+                }
+                Event::DocType(_) => {
+                    return Err(Error::MalformedXML(
+                        "DOCTYPE isn't allowed in a fragment".to_string(),
+                    ))
+                }
+                event => event,
+            };
+            if self.handle_event(event)? {
+                if self.element_stack.len() == 1 {
+                    return Ok(());
+                } else {
+                    return Err(Error::MalformedXML("Closing tag not found.".to_string()));
+                }
+            }
+        }
+    }
+
+    fn new(
+        opts: ReadOptions,
+        element_filter: Option<ElementFilter<'f>>,
+        doc: Document,
+        buffers: &mut ParseBuffers,
+    ) -> DocumentParser<'f> {
+        let mut element_stack = std::mem::take(&mut buffers.element_stack);
+        element_stack.clear();
+        element_stack.push(doc.container());
+        let metrics = opts.collect_metrics.then(ParseMetrics::default);
+        DocumentParser {
             doc,
             read_opts: opts,
             encoding: None,
-            element_stack: element_stack,
+            decl_encoding: None,
+            element_stack,
+            element_filter,
+            skip_depth: 0,
+            spliced_depths: Vec::new(),
+            pos_base: 0,
+            event_pos: 0,
+            metrics,
+            depth: 0,
+        }
+    }
+
+    // Run `parse` in a span, if the `tracing` feature is enabled.
+    fn traced<F>(&mut self, parse: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        #[cfg(feature = "tracing")]
+        {
+            let span = crate::trace::parse_span();
+            let _entered = span.enter();
+            let result = parse(self);
+            crate::trace::parse_finished(
+                &span,
+                self.event_pos,
+                self.encoding,
+                self.doc.store.len() - 1,
+                &self.doc.diagnostics,
+                &result,
+            );
+            result
+        }
+        #[cfg(not(feature = "tracing"))]
+        parse(self)
+    }
+
+    fn finish(mut self, buffers: &mut ParseBuffers) -> Document {
+        buffers.element_stack = self.element_stack;
+        self.doc.read_opts = Some(self.read_opts);
+        self.doc.parse_metrics = self.metrics;
+        // Values of elements skipped by a filter are no longer used.
+        self.doc.values.prune();
+        self.doc
+    }
+
+    // Convert bytes of current event into String,
+    // reporting approximate position of invalid bytes on error.
+    fn decode_str<'b>(&self, bytes: &'b [u8], context: &'static str) -> Result<&'b str> {
+        std::str::from_utf8(bytes).map_err(|err| {
+            let offset = err.valid_up_to();
+            Error::CannotDecode {
+                context,
+                position: Some(self.event_pos + offset),
+                byte: bytes.get(offset).copied(),
+                declared_encoding: self.decl_encoding.clone(),
+            }
+        })
+    }
+
+    fn decode_string(&self, bytes: Vec<u8>, context: &'static str) -> Result<String> {
+        String::from_utf8(bytes).map_err(|err| {
+            let offset = err.utf8_error().valid_up_to();
+            Error::CannotDecode {
+                context,
+                position: Some(self.event_pos + offset),
+                byte: err.as_bytes().get(offset).copied(),
+                declared_encoding: self.decl_encoding.clone(),
+            }
+        })
+    }
+
+    // Text or attribute value, shared with equal values if `ReadOptions::dedup_values` is set.
+    fn value(&mut self, bytes: Cow<[u8]>, context: &'static str) -> Result<Value> {
+        if self.read_opts.dedup_values {
+            let value = self.decode_str(&bytes, context)?;
+            Ok(self.doc.values.intern(value))
+        } else {
+            Ok(self.decode_string(bytes.into_owned(), context)?.into())
+        }
+    }
+
+    /// Returns `true` if a text of raw length `len` should be stored in a file.
+    fn is_large_text(&self, len: usize) -> Result<bool> {
+        match self.read_opts.max_text_len {
+            Some(max) if len > max => match self.read_opts.spill_large_text_to {
+                Some(_) => Ok(true),
+                None => Err(self.text_too_long(max)),
+            },
+            _ => Ok(false),
+        }
+    }
+
+    fn text_too_long(&self, max: usize) -> Error {
+        Error::LimitExceeded {
+            limit: "max_text_len",
+            max,
+            position: self.event_pos,
+        }
+    }
+
+    // Limit for `TextLimitReader`. Longer text is read to be stored in a file if it is spilled.
+    fn text_limit(&self) -> Option<usize> {
+        match self.read_opts.spill_large_text_to {
+            Some(_) => None,
+            None => self.read_opts.max_text_len,
+        }
+    }
+
+    // Read the next event, returning `Error::LimitExceeded` if `TextLimitReader` stopped reading.
+    fn read_event<'b, B: BufRead>(
+        &self,
+        reader: &mut Reader<B>,
+        buf: &'b mut Vec<u8>,
+    ) -> Result<Event<'b>> {
+        reader.read_event(buf).map_err(|err| match err {
+            XMLError::Io(err) if err.get_ref().is_some_and(|err| err.is::<TextTooLong>()) => {
+                self.text_too_long(self.read_opts.max_text_len.unwrap_or_default())
+            }
+            err => self.with_declared_encoding(err.into()),
+        })
+    }
+
+    // Add the encoding declared by the document to a decoding error converted from another error.
+    fn with_declared_encoding(&self, err: Error) -> Error {
+        match err {
+            Error::CannotDecode {
+                context,
+                position,
+                byte,
+                declared_encoding: None,
+            } => Error::CannotDecode {
+                context,
+                position,
+                byte,
+                declared_encoding: self.decl_encoding.clone(),
+            },
+            err => err,
+        }
+    }
+
+    // Store a text or CDATA section in a file. `cdata` is `true` for a CDATA section.
+    fn spill_text(&self, bytes: Vec<u8>, cdata: bool) -> Result<Node> {
+        let content = self.decode_string(bytes, if cdata { "CDATA" } else { "text" })?;
+        match &self.read_opts.spill_large_text_to {
+            Some(dir) => Ok(Node::ExternalText(ExternalText::create(
+                dir,
+                content.as_bytes(),
+                cdata,
+            )?)),
+            None if cdata => Ok(Node::CData(content.into())),
+            None => Ok(Node::Text(content.into())),
+        }
+    }
+
+    fn validate_name(&self, name: &str) -> Result<()> {
+        if self.read_opts.validate_names && !is_name(name) {
+            return Err(Error::InvalidName {
+                name: name.to_string(),
+                position: Some(self.event_pos),
+            });
+        }
+        Ok(())
+    }
+
+    // Unescape text or attribute value, expanding entities of `ReadOptions::entity_catalog`
+    // and handling undefined entity references according to `ReadOptions::undefined_entities`.
+    fn unescape<'a>(&mut self, raw: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        let mut resolved: Option<Vec<u8>> = None;
+        let mut last = 0;
+        let mut start = 0;
+        while let Some(amp) = raw[start..].iter().position(|b| *b == b'&') {
+            let amp = start + amp;
+            let end = match raw[amp..].iter().position(|b| *b == b';') {
+                Some(end) => amp + end,
+                None => break, // quick-xml reports the error
+            };
+            start = end + 1;
+            let name = &raw[amp + 1..end];
+            let is_reference = std::str::from_utf8(name).is_ok_and(is_name);
+            if !is_reference || is_predefined_entity(name) {
+                continue;
+            }
+            let catalog = self.read_opts.entity_catalog.as_ref();
+            let replacement = std::str::from_utf8(name)
+                .ok()
+                .and_then(|name| catalog?.get(name));
+            if let Some(replacement) = replacement {
+                if let Some(metrics) = self.metrics.as_mut() {
+                    metrics.entity_references += 1;
+                    metrics.entity_reference_bytes += end + 1 - amp;
+                    metrics.entity_expansion_bytes += replacement.len();
+                }
+                let buf = resolved.get_or_insert_with(Vec::new);
+                buf.extend_from_slice(&raw[last..amp]);
+                buf.extend_from_slice(&escape(replacement.as_bytes()));
+                last = end + 1;
+                continue;
+            }
+            let message = format!("Undefined entity '&{};'", String::from_utf8_lossy(name));
+            if self.read_opts.undefined_entities == UndefinedEntityPolicy::Error {
+                return Err(Error::MalformedXML(message));
+            }
+            self.doc.diagnostics.push(Diagnostic {
+                position: self.event_pos,
+                message,
+            });
+            let buf = resolved.get_or_insert_with(Vec::new);
+            buf.extend_from_slice(&raw[last..amp]);
+            if self.read_opts.undefined_entities == UndefinedEntityPolicy::KeepReference {
+                buf.extend_from_slice(b"&amp;");
+                buf.extend_from_slice(&raw[amp + 1..=end]);
+            }
+            last = end + 1;
+        }
+        let unescaped = match resolved {
+            Some(mut buf) => {
+                buf.extend_from_slice(&raw[last..]);
+                Cow::Owned(unescape(&buf).map_err(XMLError::EscapeError)?.into_owned())
+            }
+            None => unescape(raw).map_err(XMLError::EscapeError)?,
         };
-        parser.parse_start(reader)?;
-        Ok(parser.doc)
+        Ok(unescaped)
+    }
+
+    // Record a diagnostic if lenient, or else return error.
+    fn recover(&mut self, message: String) -> Result<()> {
+        if !self.read_opts.lenient {
+            return Err(Error::MalformedXML(message));
+        }
+        self.doc.diagnostics.push(Diagnostic {
+            position: self.event_pos,
+            message,
+        });
+        Ok(())
     }
 
     fn handle_decl(&mut self, ev: &BytesDecl) -> Result<()> {
-        self.doc.version = String::from_utf8(ev.version()?.to_vec())?;
-        self.encoding = match ev.encoding() {
-            Some(res) => {
-                let encoding = Encoding::for_label(&res?).ok_or(Error::CannotDecode)?;
+        let mut raw_decl = String::with_capacity(ev.len() + 4);
+        raw_decl.push_str("<?");
+        raw_decl.push_str(&String::from_utf8_lossy(ev));
+        raw_decl.push_str("?>");
+        self.doc.raw_decl = Some(raw_decl);
+        let decl = BytesStart::borrowed(ev, 3);
+        // Same as `BytesDecl::version()` and `BytesDecl::encoding()`, which allocate for checks
+        // each time. Duplicates are checked by the loop below.
+        let mut version = None;
+        let mut label = None;
+        for (i, attr) in decl.attributes().with_checks(false).enumerate() {
+            let attr = attr?;
+            match attr.key {
+                b"version" if i == 0 => version = Some(attr.value),
+                key if i == 0 => {
+                    let found = String::from_utf8_lossy(key).into_owned();
+                    return Err(XMLError::XmlDeclWithoutVersion(Some(found)).into());
+                }
+                b"encoding" if label.is_none() => label = Some(attr.value),
+                _ => {}
+            }
+        }
+        let version = version.ok_or(XMLError::XmlDeclWithoutVersion(None))?;
+        self.doc.version = self.decode_string(version.into_owned(), "XML declaration")?;
+        self.encoding = match label {
+            Some(label) => {
+                self.decl_encoding = Some(String::from_utf8_lossy(&label).into_owned());
+                let encoding = Encoding::for_label(&label).ok_or_else(|| Error::CannotDecode {
+                    context: "encoding label",
+                    position: None,
+                    byte: None,
+                    declared_encoding: self.decl_encoding.clone(),
+                })?;
                 if encoding == UTF_8 {
                     None
                 } else {
@@ -185,71 +1130,193 @@ impl DocumentParser {
             }
             None => None,
         };
-        self.doc.standalone = match ev.standalone() {
-            Some(res) => {
-                let val = std::str::from_utf8(&res?)?.to_lowercase();
-                match val.as_str() {
-                    "yes" => true,
-                    "no" => false,
-                    _ => {
-                        return Err(Error::MalformedXML(
-                            "Standalone Document Declaration has non boolean value".to_string(),
-                        ))
-                    }
+        for attr in decl.attributes() {
+            let attr = attr?;
+            match attr.key {
+                b"version" | b"encoding" => {}
+                b"standalone" => {
+                    let val = self.decode_str(&attr.value, "standalone")?.to_lowercase();
+                    self.doc.standalone = match val.as_str() {
+                        "yes" => Some(true),
+                        "no" => Some(false),
+                        _ => {
+                            self.recover(
+                                "Standalone Document Declaration has non boolean value".to_string(),
+                            )?;
+                            None
+                        }
+                    };
                 }
+                // Unknown pseudo-attributes are ignored, and only reported when lenient.
+                key if self.read_opts.lenient => self.recover(format!(
+                    "Unknown pseudo-attribute '{}' in XML declaration",
+                    String::from_utf8_lossy(key)
+                ))?,
+                _ => {}
             }
-            None => false,
-        };
+        }
         Ok(())
     }
 
-    fn create_element(&mut self, parent: Element, ev: &BytesStart) -> Result<Element> {
-        let full_name = String::from_utf8(ev.name().to_vec())?;
-        let mut namespace_decls = HashMap::new();
-        let mut attributes = HashMap::new();
-        for attr in ev.attributes() {
+    // Returns `None` if the element filter skipped the element.
+    fn create_element(
+        &mut self,
+        parent: Element,
+        ev: &BytesStart,
+    ) -> Result<(Option<Element>, ElementAction)> {
+        let full_name = self.decode_str(ev.name(), "element name")?;
+        self.validate_name(full_name)?;
+        let mut namespace_decls = IndexMap::new();
+        let mut attributes = if ev.attributes_raw().is_empty() {
+            IndexMap::new()
+        } else {
+            self.doc.store.take_attributes()
+        };
+        let mut decls_after_attribute = false;
+        let mut attribute_count = 0;
+        // Duplicates are checked below to report them with names, or recover from them.
+        for attr in ev.attributes().with_checks(false) {
             let mut attr = attr?;
             attr.value = Cow::Owned(normalize_space(&attr.value));
-            let key = String::from_utf8(attr.key.to_vec())?;
-            let value = String::from_utf8(attr.unescaped_value()?.to_vec())?;
-            if key == "xmlns" {
-                namespace_decls.insert(String::new(), value);
-                continue;
-            } else if let Some(prefix) = key.strip_prefix("xmlns:") {
-                namespace_decls.insert(prefix.to_owned(), value);
-                continue;
+            let key = self.decode_string(attr.key.to_vec(), "attribute name")?;
+            self.validate_name(&key)?;
+            let value = self.unescape(&attr.value)?;
+            if let Some(metrics) = self.metrics.as_mut() {
+                attribute_count += 1;
+                metrics.max_attribute_value_len = metrics.max_attribute_value_len.max(value.len());
+            }
+            let decl_prefix = namespace_decl_prefix(&key);
+            // The last one is kept if lenient.
+            if let Some(prefix) = decl_prefix {
+                let value = self.decode_string(value.into_owned(), "attribute value")?;
+                decls_after_attribute |= !attributes.is_empty();
+                if namespace_decls.insert(prefix.to_owned(), value).is_some() {
+                    self.recover(format!(
+                        "Namespace prefix '{}' is declared twice on element '{}'",
+                        prefix, full_name
+                    ))?;
+                }
+            } else {
+                let value = self.value(value, "attribute value")?;
+                match attributes.entry(key) {
+                    Entry::Occupied(mut entry) => {
+                        self.recover(format!(
+                            "Duplicate attribute '{}' on element '{}'",
+                            entry.key(),
+                            full_name
+                        ))?;
+                        entry.insert(value);
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(value);
+                    }
+                }
+            }
+        }
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.elements += 1;
+            metrics.max_attributes = metrics.max_attributes.max(attribute_count);
+            metrics.max_depth = metrics.max_depth.max(self.depth + 1);
+        }
+        if let Some(filter) = self.element_filter.as_mut() {
+            let action = filter(full_name, &attributes);
+            if action != ElementAction::Keep {
+                return Ok((None, action));
             }
-            attributes.insert(key, value);
         }
+        let full_name = if self.read_opts.name_arena {
+            self.doc.store.alloc_name(full_name)
+        } else {
+            full_name.to_string().into()
+        };
         let elem = Element::with_data(&mut self.doc, full_name, attributes, namespace_decls);
-        parent
-            .push_child(&mut self.doc, Node::Element(elem))
-            .unwrap();
-        Ok(elem)
+        if self.read_opts.high_fidelity {
+            let raw = self.decode_string(ev.to_vec(), "start tag")?;
+            self.doc.raw_start_tags.insert(elem, raw);
+            if decls_after_attribute {
+                self.doc.namespace_decls_last.insert(elem);
+            }
+            let single_quoted = single_quoted_keys(ev, ev.name().len())
+                .into_iter()
+                .map(|key| Ok(self.decode_str(key, "attribute name")?.to_string()))
+                .collect::<Result<Vec<String>>>()?;
+            if !single_quoted.is_empty() {
+                self.doc.single_quoted.insert(elem, single_quoted);
+            }
+        }
+        if self.read_opts.diagnose_multiple_roots
+            && parent.is_container()
+            && parent.child_elements_iter(&self.doc).next().is_some()
+        {
+            self.doc.diagnostics.push(Diagnostic {
+                position: self.event_pos,
+                message: format!("Another root element '{}'", elem.full_name(&self.doc)),
+            });
+        }
+        parent.push_child(&mut self.doc, Node::Element(elem))?;
+        Ok((Some(elem), ElementAction::Keep))
     }
 
     // Returns true if document parsing is finished.
     fn handle_event(&mut self, event: Event) -> Result<bool> {
+        if self.skip_depth > 0 {
+            // Inside a subtree skipped by the element filter.
+            match event {
+                Event::Start(_) => self.skip_depth += 1,
+                Event::End(_) => self.skip_depth -= 1,
+                Event::Eof => {
+                    return Err(Error::MalformedXML("Closing tag not found.".to_string()))
+                }
+                _ => {}
+            }
+            return Ok(false);
+        }
         match event {
             Event::Start(ref ev) => {
                 let parent = *self
                     .element_stack
                     .last()
                     .ok_or_else(|| Error::MalformedXML("Malformed Element Tree".to_string()))?;
-                let element = self.create_element(parent, ev)?;
-                self.element_stack.push(element);
+                match self.create_element(parent, ev)? {
+                    (Some(element), _) => {
+                        self.depth += 1;
+                        self.element_stack.push(element);
+                    }
+                    (None, ElementAction::SkipSubtree) => self.skip_depth = 1,
+                    (None, _) => {
+                        self.depth += 1;
+                        // Children of the skipped element are added to `parent`.
+                        self.element_stack.push(parent);
+                        self.spliced_depths.push(self.element_stack.len());
+                    }
+                }
                 Ok(false)
             }
             Event::End(_) => {
+                self.depth = self.depth.saturating_sub(1);
+                if self.spliced_depths.last() == Some(&self.element_stack.len()) {
+                    self.spliced_depths.pop();
+                    self.element_stack.pop();
+                    return Ok(false);
+                }
                 let elem = self
                     .element_stack
                     .pop()
                     .ok_or_else(|| Error::MalformedXML("Malformed Element Tree".to_string()))?; // quick-xml checks if tag names match for us
+                if self.read_opts.whitespace == WhitespaceMode::Smart {
+                    self.remove_insignificant_whitespace(elem);
+                }
+                let shape = if elem.has_children(&self.doc) {
+                    ElementShape::WithContent
+                } else {
+                    ElementShape::EmptyPair
+                };
+                elem.set_authored_shape(&mut self.doc, shape);
                 if self.read_opts.empty_text_node {
                     // distinguish <tag></tag> and <tag />
                     if !elem.has_children(&self.doc) {
-                        elem.push_child(&mut self.doc, Node::Text(String::new()))
-                            .unwrap();
+                        elem.push_child(&mut self.doc, Node::Text(Value::new()))?;
+                        self.doc.synthesized_empty_text.insert(elem);
                     }
                 }
                 Ok(false)
@@ -259,165 +1326,358 @@ impl DocumentParser {
                     .element_stack
                     .last()
                     .ok_or_else(|| Error::MalformedXML("Malformed Element Tree".to_string()))?;
-                self.create_element(parent, ev)?;
+                if let (Some(elem), _) = self.create_element(parent, ev)? {
+                    elem.set_authored_shape(&mut self.doc, ElementShape::SelfClosing);
+                }
                 Ok(false)
             }
             // Comment, CData, and PI content should not be escaped,
             // but quick-xml assumes only CDATA is not escaped.
             Event::Text(ev) => {
-                if self.read_opts.ignore_whitespace_only && only_has_whitespace(&ev) {
+                if self.ignore_whitespace_only() && only_has_whitespace(&ev) {
                     return Ok(false);
                 }
                 // when trim_text, ignore_whitespace_only, empty_text_node are all false
                 if ev.is_empty() {
                     return Ok(false);
                 }
-                let content = String::from_utf8(ev.unescaped()?.to_vec())?;
-                let node = Node::Text(content);
+                let raw = normalize_line_endings(&ev);
+                let large = self.is_large_text(raw.len())?;
+                let content = self.unescape(&raw)?;
+                let node = if large {
+                    self.spill_text(content.into_owned(), false)?
+                } else {
+                    Node::Text(self.value(content, "text")?)
+                };
                 let parent = *self
                     .element_stack
                     .last()
                     .ok_or_else(|| Error::MalformedXML("Malformed Element Tree".to_string()))?;
-                parent.push_child(&mut self.doc, node).unwrap();
+                self.count_node(&node);
+                parent.push_child(&mut self.doc, node)?;
                 Ok(false)
             }
             Event::DocType(ev) => {
                 // Event::DocType comes with one leading whitespace. Strip the whitespace.
-                let raw = ev.unescaped()?;
+                let unescaped = ev.unescaped()?;
+                let raw = normalize_line_endings(&unescaped);
                 let content = if !raw.is_empty() && raw[0] == b' ' {
-                    String::from_utf8(raw[1..].to_vec())?
+                    self.decode_string(raw[1..].to_vec(), "DOCTYPE")?
                 } else {
-                    String::from_utf8(raw.to_vec())?
+                    self.decode_string(raw.to_vec(), "DOCTYPE")?
                 };
                 let node = Node::DocType(content);
                 let parent = *self
                     .element_stack
                     .last()
                     .ok_or_else(|| Error::MalformedXML("Malformed Element Tree".to_string()))?;
-                parent.push_child(&mut self.doc, node).unwrap();
+                self.count_node(&node);
+                parent.push_child(&mut self.doc, node)?;
                 Ok(false)
             }
             Event::Comment(ev) => {
-                let content = String::from_utf8(ev.escaped().to_vec())?;
+                let raw = normalize_line_endings(ev.escaped());
+                let content = self.decode_string(raw.into_owned(), "comment")?;
                 let node = Node::Comment(content);
                 let parent = *self
                     .element_stack
                     .last()
                     .ok_or_else(|| Error::MalformedXML("Malformed Element Tree".to_string()))?;
-                parent.push_child(&mut self.doc, node).unwrap();
+                self.count_node(&node);
+                parent.push_child(&mut self.doc, node)?;
                 Ok(false)
             }
             Event::CData(ev) => {
-                let content = String::from_utf8(ev.unescaped()?.to_vec())?;
-                let node = Node::CData(content);
+                let content = normalize_line_endings(&ev.unescaped()?).into_owned();
+                let node = if self.is_large_text(content.len())? {
+                    self.spill_text(content, true)?
+                } else {
+                    Node::CData(self.value(Cow::Owned(content), "CDATA")?)
+                };
                 let parent = *self
                     .element_stack
                     .last()
                     .ok_or_else(|| Error::MalformedXML("Malformed Element Tree".to_string()))?;
-                parent.push_child(&mut self.doc, node).unwrap();
+                self.count_node(&node);
+                parent.push_child(&mut self.doc, node)?;
                 Ok(false)
             }
             Event::PI(ev) => {
-                let content = String::from_utf8(ev.escaped().to_vec())?;
+                let raw = normalize_line_endings(ev.escaped());
+                let content = self.decode_string(raw.into_owned(), "processing instruction")?;
+                let target = content.split(is_whitespace_char).next().unwrap_or("");
+                self.validate_name(target)?;
                 let node = Node::PI(content);
                 let parent = *self
                     .element_stack
                     .last()
                     .ok_or_else(|| Error::MalformedXML("Malformed Element Tree".to_string()))?;
-                parent.push_child(&mut self.doc, node).unwrap();
+                self.count_node(&node);
+                parent.push_child(&mut self.doc, node)?;
                 Ok(false)
             }
             Event::Decl(_) => Err(Error::MalformedXML(
                 "XML declaration found in the middle of the document".to_string(),
             )),
-            Event::Eof => Ok(true),
+            Event::Eof => {
+                if self.read_opts.whitespace == WhitespaceMode::Smart {
+                    if let Some(&container) = self.element_stack.first() {
+                        if container.is_container() {
+                            self.remove_insignificant_whitespace(container);
+                        }
+                    }
+                }
+                Ok(true)
+            }
         }
     }
 
-    // Sniff encoding and consume BOM
-    fn sniff_encoding<R: Read>(
-        &mut self,
-        decodereader: &mut DecodeReader<R>,
+    fn count_node(&mut self, node: &Node) {
+        if let Some(metrics) = self.metrics.as_mut() {
+            match node {
+                Node::Text(_) | Node::ExternalText(_) => metrics.texts += 1,
+                Node::CData(_) => metrics.cdatas += 1,
+                Node::Comment(_) => metrics.comments += 1,
+                Node::PI(_) => metrics.pis += 1,
+                Node::DocType(_) => metrics.doctypes += 1,
+                Node::Element(_) => {} // counted in `create_element`
+            }
+        }
+    }
+
+    fn trim_text(&self) -> bool {
+        self.read_opts.trim_text && self.read_opts.whitespace == WhitespaceMode::Manual
+    }
+
+    fn ignore_whitespace_only(&self) -> bool {
+        self.read_opts.ignore_whitespace_only && self.read_opts.whitespace == WhitespaceMode::Manual
+    }
+
+    // Remove whitespace-only text children of `elem` if it has child elements but no other text,
+    // unless it is in scope of xml:space="preserve". See `WhitespaceMode::Smart`.
+    fn remove_insignificant_whitespace(&mut self, elem: Element) {
+        let mut has_element = false;
+        for node in elem.children(&self.doc) {
+            match node {
+                Node::Element(_) => has_element = true,
+                Node::Text(text) if text.chars().all(is_whitespace_char) => {}
+                Node::Text(_) | Node::CData(_) | Node::ExternalText(_) => return,
+                _ => {}
+            }
+        }
+        if !has_element || self.preserves_space(elem) {
+            return;
+        }
+        elem.retain_children(&mut self.doc, |node| match node {
+            Node::Text(text) => !text.chars().all(is_whitespace_char),
+            _ => true,
+        });
+    }
+
+    // Whether the nearest xml:space attribute of `elem` or its ancestors is "preserve".
+    fn preserves_space(&self, elem: Element) -> bool {
+        let mut current = Some(elem);
+        while let Some(elem) = current {
+            if elem.is_container() {
+                break;
+            }
+            if let Some(space) = elem.attribute(&self.doc, "xml:space") {
+                return space == "preserve";
+            }
+            current = elem.parent(&self.doc);
+        }
+        false
+    }
+
+    // Sniff encoding from the first bytes. Returns the encoding and the length of the BOM.
+    fn sniff_encoding(bytes: &[u8]) -> (Option<&'static Encoding>, usize) {
+        match bytes {
+            [0x3c, 0x3f, ..] => (None, 0),           // UTF-8 '<?'
+            [0xfe, 0xff, ..] => (Some(UTF_16BE), 2), // UTF-16 BE BOM
+            [0xff, 0xfe, ..] => (Some(UTF_16LE), 2), // UTF-16 LE BOM
+            [0xef, 0xbb, 0xbf, ..] => (None, 3),     // UTF-8 BOM
+            [0x00, 0x3c, 0x00, 0x3f, ..] => (Some(UTF_16BE), 0),
+            [0x3c, 0x00, 0x3f, 0x00, ..] => (Some(UTF_16LE), 0),
+            _ => (None, 0), // Try decoding it with UTF-8
+        }
+    }
+
+    // Encoding to start reading with, given the sniffed encoding.
+    fn init_encoding(
+        &self,
+        sniffed: Option<&'static Encoding>,
     ) -> Result<Option<&'static Encoding>> {
-        let bytes = decodereader.fill_buf()?;
-        let encoding = match bytes {
-            [0x3c, 0x3f, ..] => None, // UTF-8 '<?'
-            [0xfe, 0xff, ..] => {
-                // UTF-16 BE BOM
-                decodereader.consume(2);
-                Some(UTF_16BE)
-            }
-            [0xff, 0xfe, ..] => {
-                // UTF-16 LE BOM
-                decodereader.consume(2);
-                Some(UTF_16LE)
-            }
-            [0xef, 0xbb, 0xbf, ..] => {
-                // UTF-8 BOM
-                decodereader.consume(3);
-                None
-            }
-            [0x00, 0x3c, 0x00, 0x3f, ..] => Some(UTF_16BE),
-            [0x3c, 0x00, 0x3f, 0x00, ..] => Some(UTF_16LE),
-            _ => None, // Try decoding it with UTF-8
-        };
-        Ok(encoding)
+        match &self.read_opts.encoding {
+            Some(enc) => Ok(Some(Encoding::for_label(enc.as_bytes()).ok_or(
+                Error::CannotDecode {
+                    context: "encoding label",
+                    position: None,
+                    byte: None,
+                    declared_encoding: None,
+                },
+            )?)),
+            None => Ok(sniffed),
+        }
+    }
+
+    // Encoding::for_label("UTF-16") defaults to UTF-16 LE, even though it could be UTF-16 BE
+    fn declared_other_encoding(&self, init_encoding: Option<&'static Encoding>) -> bool {
+        self.encoding != init_encoding
+            && !(self.encoding == Some(UTF_16LE) && init_encoding == Some(UTF_16BE))
     }
 
     // Look at the document decl and figure out the document encoding
-    fn parse_start<R: Read>(&mut self, reader: R) -> Result<()> {
-        let mut decodereader = DecodeReader::new(reader, None);
-        let mut init_encoding = self.sniff_encoding(&mut decodereader)?;
-        if let Some(enc) = &self.read_opts.encoding {
-            init_encoding = Some(Encoding::for_label(enc.as_bytes()).ok_or(Error::CannotDecode)?)
-        }
+    fn parse_start<S: ParseSource>(
+        &mut self,
+        mut decodereader: S,
+        buffers: &mut ParseBuffers,
+    ) -> Result<()> {
+        let (sniffed, bom_len) = DocumentParser::sniff_encoding(decodereader.fill_buf()?);
+        decodereader.consume(bom_len);
+        let init_encoding = self.init_encoding(sniffed)?;
         decodereader.set_encoding(init_encoding);
-        let mut xmlreader = Reader::from_reader(decodereader);
-        xmlreader.trim_text(self.read_opts.trim_text);
+        let mut xmlreader =
+            Reader::from_reader(TextLimitReader::new(decodereader, self.text_limit()));
+        xmlreader.trim_text(self.trim_text());
 
-        let mut buf = Vec::with_capacity(200);
+        let mut buf = std::mem::take(&mut buffers.event);
+        buf.clear();
+        buf.reserve(200); // reduce time increasing capacity at start.
 
         // Skip first event if it only has whitespace
-        let event = match xmlreader.read_event(&mut buf)? {
+        let event = match self.read_event(&mut xmlreader, &mut buf)? {
             Event::Text(ev) => {
-                if ev.len() == 0 {
-                    xmlreader.read_event(&mut buf)?
-                } else if self.read_opts.ignore_whitespace_only && only_has_whitespace(&ev) {
-                    xmlreader.read_event(&mut buf)?
+                if ev.is_empty() || (self.ignore_whitespace_only() && only_has_whitespace(&ev)) {
+                    self.event_pos = xmlreader.buffer_position();
+                    self.read_event(&mut xmlreader, &mut buf)?
                 } else {
                     Event::Text(ev)
                 }
             }
             ev => ev,
         };
+        let result = match event {
+            Event::Decl(ev) => {
+                self.handle_decl(&ev)?;
+                if self.declared_other_encoding(init_encoding) {
+                    #[cfg(feature = "tracing")]
+                    crate::trace::encoding_switched(init_encoding, self.encoding);
+                    self.pos_base = xmlreader.buffer_position();
+                    let mut limit_reader = xmlreader.into_underlying_reader();
+                    limit_reader.inner.set_encoding(self.encoding);
+                    limit_reader.reset();
+                    xmlreader = Reader::from_reader(limit_reader);
+                    xmlreader.trim_text(self.trim_text());
+                }
+                self.parse_content(&mut xmlreader, &mut buf)
+            }
+            event => match self.start_without_decl(event) {
+                Ok(false) => self.parse_content(&mut xmlreader, &mut buf),
+                res => res.map(|_| ()),
+            },
+        };
+        xmlreader
+            .into_underlying_reader()
+            .into_inner()
+            .return_buffers(buffers);
+        buffers.event = buf;
+        result
+    }
+
+    // Same as `parse_start`, but reads from a slice.
+    //
+    // If the document is in UTF-8, events are read from `bytes` directly.
+    // Otherwise the rest of `bytes` is decoded at once into a `String` to read from.
+    fn parse_start_bytes(&mut self, bytes: &[u8], buffers: &mut ParseBuffers) -> Result<()> {
+        let (sniffed, bom_len) = DocumentParser::sniff_encoding(bytes);
+        let bytes = &bytes[bom_len..];
+        let init_encoding = match self.init_encoding(sniffed)? {
+            Some(encoding) if encoding == UTF_8 => None,
+            encoding => encoding,
+        };
+        let content = match init_encoding {
+            Some(encoding) => decode_with_buffer(encoding, bytes, &mut buffers.decoded_text),
+            None => bytes,
+        };
+        let mut xmlreader = Reader::from_reader(TextLimitReader::new(content, self.text_limit()));
+        xmlreader.trim_text(self.trim_text());
 
-        if let Event::Decl(ev) = event {
-            self.handle_decl(&ev)?;
-            // Encoding::for_label("UTF-16") defaults to UTF-16 LE, even though it could be UTF-16 BE
-            if self.encoding != init_encoding
-                && !(self.encoding == Some(UTF_16LE) && init_encoding == Some(UTF_16BE))
-            {
-                let mut decode_reader = xmlreader.into_underlying_reader();
-                decode_reader.set_encoding(self.encoding);
-                xmlreader = Reader::from_reader(decode_reader);
-                xmlreader.trim_text(self.read_opts.trim_text);
-            }
-        } else if self.read_opts.require_decl {
+        let mut buf = std::mem::take(&mut buffers.event);
+        buf.clear();
+        buf.reserve(200); // reduce time increasing capacity at start.
+
+        // Skip first event if it only has whitespace
+        let event = match self.read_event(&mut xmlreader, &mut buf)? {
+            Event::Text(ev) => {
+                if ev.is_empty() || (self.ignore_whitespace_only() && only_has_whitespace(&ev)) {
+                    self.event_pos = xmlreader.buffer_position();
+                    self.read_event(&mut xmlreader, &mut buf)?
+                } else {
+                    Event::Text(ev)
+                }
+            }
+            ev => ev,
+        };
+        let result = match event {
+            Event::Decl(ev) => {
+                self.handle_decl(&ev)?;
+                let pos = xmlreader.buffer_position();
+                // The declaration is in ASCII, which is one byte per character in encodings
+                // other than UTF-16. Keep the initial encoding if the position in `bytes` isn't known.
+                let raw_pos = match init_encoding {
+                    _ if !content[..pos].is_ascii() => None,
+                    Some(encoding) if encoding == UTF_16LE || encoding == UTF_16BE => Some(pos * 2),
+                    _ => Some(pos),
+                };
+                if let (true, Some(raw_pos)) =
+                    (self.declared_other_encoding(init_encoding), raw_pos)
+                {
+                    #[cfg(feature = "tracing")]
+                    crate::trace::encoding_switched(init_encoding, self.encoding);
+                    self.pos_base = pos;
+                    let remaining = &bytes[raw_pos..];
+                    let remaining = match self.encoding {
+                        Some(encoding) => {
+                            decode_with_buffer(encoding, remaining, &mut buffers.decoded_rest)
+                        }
+                        None => remaining,
+                    };
+                    xmlreader =
+                        Reader::from_reader(TextLimitReader::new(remaining, self.text_limit()));
+                    xmlreader.trim_text(self.trim_text());
+                }
+                self.parse_content(&mut xmlreader, &mut buf)
+            }
+            event => match self.start_without_decl(event) {
+                Ok(false) => self.parse_content(&mut xmlreader, &mut buf),
+                res => res.map(|_| ()),
+            },
+        };
+        buffers.event = buf;
+        result
+    }
+
+    // Handle the first event of a document without XML declaration.
+    // Returns true if document parsing is finished.
+    fn start_without_decl(&mut self, event: Event) -> Result<bool> {
+        if self.read_opts.require_decl {
             return Err(Error::MalformedXML(
                 "Didn't find XML Declaration at the start of file".to_string(),
             ));
-        } else if self.handle_event(event)? {
-            return Ok(());
         }
-        // Handle rest of the events
-        self.parse_content(xmlreader)
+        self.doc.fragment = true;
+        self.handle_event(event)
     }
 
-    fn parse_content<B: BufRead>(&mut self, mut reader: Reader<B>) -> Result<()> {
-        let mut buf = Vec::with_capacity(200); // reduce time increasing capacity at start.
-
+    fn parse_content<B: BufRead>(
+        &mut self,
+        reader: &mut Reader<B>,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
         loop {
-            let ev = reader.read_event(&mut buf)?;
+            self.event_pos = self.pos_base + reader.buffer_position();
+            buf.clear();
+            let ev = self.read_event(reader, buf)?;
 
             if self.handle_event(ev)? {
                 if self.element_stack.len() == 1 {
@@ -431,19 +1691,137 @@ impl DocumentParser {
     }
 }
 
+// Keys of the attributes quoted with `'` in a start tag, whose name is `name_len` bytes long.
+// The attributes were already checked by quick-xml.
+fn single_quoted_keys(tag: &[u8], name_len: usize) -> Vec<&[u8]> {
+    let mut keys = Vec::new();
+    let mut pos = name_len;
+    let skip_whitespace = |mut pos: usize| {
+        while tag.get(pos).copied().is_some_and(is_whitespace) {
+            pos += 1;
+        }
+        pos
+    };
+    loop {
+        let key_start = skip_whitespace(pos);
+        let key_end = match tag[key_start..]
+            .iter()
+            .position(|b| *b == b'=' || is_whitespace(*b))
+        {
+            Some(len) => key_start + len,
+            None => break,
+        };
+        let eq = skip_whitespace(key_end);
+        if tag.get(eq) != Some(&b'=') {
+            break;
+        }
+        let value_start = skip_whitespace(eq + 1);
+        let quote = match tag.get(value_start) {
+            Some(quote @ (b'"' | b'\'')) => *quote,
+            _ => break,
+        };
+        let value_len = match tag[value_start + 1..].iter().position(|b| *b == quote) {
+            Some(len) => len,
+            None => break,
+        };
+        if quote == b'\'' {
+            keys.push(&tag[key_start..key_end]);
+        }
+        pos = value_start + value_len + 2;
+    }
+    keys
+}
+
 /// Returns true if byte is an XML whitespace character
-fn is_whitespace(byte: u8) -> bool {
-    match byte {
-        b'\r' | b'\n' | b'\t' | b' ' => true,
+pub(crate) fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b'\r' | b'\n' | b'\t' | b' ')
+}
+
+/// Returns true if `name` is a predefined entity or a character reference.
+fn is_predefined_entity(name: &[u8]) -> bool {
+    matches!(name, b"lt" | b"gt" | b"amp" | b"apos" | b"quot") || name.starts_with(b"#")
+}
+
+pub(crate) fn is_whitespace_char(c: char) -> bool {
+    matches!(c, '\r' | '\n' | '\t' | ' ')
+}
+
+fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        ':' | 'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}' | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}' | '\u{10000}'..='\u{EFFFF}')
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c,
+            '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}')
+}
+
+/// Returns true if `name` matches the [Name](https://www.w3.org/TR/xml/#NT-Name) production.
+pub(crate) fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if is_name_start_char(c) => chars.all(is_name_char),
         _ => false,
     }
 }
 
+/// Decode `bytes` into `buf`, replacing malformed sequences.
+/// ASCII bytes are returned as is if `encoding` is ASCII compatible, like
+/// [`Encoding::decode_without_bom_handling()`] does.
+fn decode_with_buffer<'b>(
+    encoding: &'static Encoding,
+    bytes: &'b [u8],
+    buf: &'b mut String,
+) -> &'b [u8] {
+    if encoding.is_ascii_compatible() && bytes.is_ascii() {
+        return bytes;
+    }
+    buf.clear();
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut bytes = bytes;
+    loop {
+        let needed = decoder
+            .max_utf8_buffer_length(bytes.len())
+            .unwrap_or(bytes.len());
+        buf.reserve(needed);
+        let (result, read, _) = decoder.decode_to_string(bytes, buf, true);
+        bytes = &bytes[read..];
+        if result == CoderResult::InputEmpty {
+            return buf.as_bytes();
+        }
+    }
+}
+
 /// Returns true if bytes.len() == 0 or bytes only has a whitespace-like character.
 fn only_has_whitespace(bytes: &[u8]) -> bool {
     bytes.iter().all(|b| is_whitespace(*b))
 }
 
+/// Replace `\r\n` and `\r` with `\n`, as in [End-of-Line Handling](https://www.w3.org/TR/xml/#sec-line-ends).
+///
+/// Character references such as `&#13;` are expanded later, so they are kept.
+fn normalize_line_endings(bytes: &[u8]) -> Cow<'_, [u8]> {
+    if !bytes.contains(&b'\r') {
+        return Cow::Borrowed(bytes);
+    }
+    let mut normalized = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' {
+            bytes.next_if_eq(&&b'\n');
+            normalized.push(b'\n');
+        } else {
+            normalized.push(byte);
+        }
+    }
+    Cow::Owned(normalized)
+}
+
 /// #xD(\r), #xA(\n), #x9(\t) is normalized into #x20.
 /// Leading and trailing spaces(#x20) are discarded
 /// and sequence of spaces are replaced by a single space.
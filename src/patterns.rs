@@ -0,0 +1,267 @@
+use crate::document::{Document, Node};
+use crate::element::Element;
+use crate::error::Result;
+use std::collections::HashMap;
+
+/// Where the value of a property element is stored.
+/// See [`Element::as_property_map()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueSource<'a> {
+    /// In an attribute with this full name. e.g. `<property name="x" value="y"/>`
+    Attribute(&'a str),
+    /// In the text content of the element. e.g. `<entry key="x">y</entry>`
+    Text,
+}
+
+/// Below are methods for elements holding key/value properties as child elements,
+/// such as `<property name="x" value="y"/>` or `<entry key="x">y</entry>`.
+impl Element {
+    /// Get `(key, value)` of child elements with full name `child_name`, in document order.
+    ///
+    /// The key is the value of attribute `key_attr`. Child elements without it are skipped.
+    /// If the value attribute is missing, the value is an empty string.
+    ///
+    /// ```
+    /// use xml_doc::{Document, ValueSource};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?>
+    /// <properties>
+    ///   <property name="host" value="localhost"/>
+    ///   <property name="port" value="8080"/>
+    /// </properties>"#).unwrap();
+    /// let properties = doc.root_element().unwrap();
+    /// let map = properties.as_property_map(&doc, "property", "name", ValueSource::Attribute("value"));
+    /// assert_eq!(map, vec![
+    ///     ("host".to_string(), "localhost".to_string()),
+    ///     ("port".to_string(), "8080".to_string()),
+    /// ]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value_source` is [`ValueSource::Text`] and the file of a
+    /// [`Node::ExternalText`] can't be read.
+    pub fn as_property_map(
+        &self,
+        doc: &Document,
+        child_name: &str,
+        key_attr: &str,
+        value_source: ValueSource,
+    ) -> Vec<(String, String)> {
+        self.child_elements_iter(doc)
+            .filter(|child| child.full_name_is(doc, child_name))
+            .filter_map(|child| {
+                let key = child.attribute(doc, key_attr)?;
+                match property_value(doc, child, value_source) {
+                    Ok(value) => Some((key.to_string(), value)),
+                    Err(err) => panic!("{}", err),
+                }
+            })
+            .collect()
+    }
+
+    /// Make the property child elements match `entries`, without rebuilding them.
+    ///
+    /// - Existing properties whose key is in `entries` are updated in place, keeping their position.
+    ///   Elements whose value didn't change aren't modified.
+    /// - Properties whose key is not in `entries`, or that repeat an earlier key, are removed.
+    /// - New keys are appended in the order of `entries`, after the last property element.
+    ///
+    /// Other child nodes, such as comments and child elements without `key_attr`, are kept as is.
+    /// If a key appears multiple times in `entries`, the last value is used.
+    ///
+    /// # Errors
+    /// - [`Error::Rejected`](crate::Error::Rejected): The document's mutation validator
+    ///   rejected inserting a new property. Properties before it may already have been modified.
+    /// - [`Error::Io`](crate::Error::Io): `value_source` is [`ValueSource::Text`] and the file of a
+    ///   [`Node::ExternalText`] can't be read. Properties after it
+    ///   may already have been modified.
+    pub fn set_property_map<I, K, V>(
+        &self,
+        doc: &mut Document,
+        child_name: &str,
+        key_attr: &str,
+        value_source: ValueSource,
+        entries: I,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let mut keys: Vec<String> = Vec::new();
+        let mut values: HashMap<String, String> = HashMap::new();
+        for (key, value) in entries {
+            let key = key.into();
+            if values.insert(key.clone(), value.into()).is_none() {
+                keys.push(key);
+            }
+        }
+
+        let mut existing: Vec<(usize, Element, String)> = Vec::new();
+        for (index, node) in self.children(doc).iter().enumerate() {
+            if let Node::Element(child) = node {
+                if !child.full_name_is(doc, child_name) {
+                    continue;
+                }
+                if let Some(key) = child.attribute(doc, key_attr) {
+                    existing.push((index, *child, key.to_string()));
+                }
+            }
+        }
+        let mut seen: Vec<&str> = Vec::new();
+        let mut keep: Vec<bool> = Vec::with_capacity(existing.len());
+        for (_, _, key) in &existing {
+            let first = !seen.contains(&key.as_str());
+            seen.push(key);
+            keep.push(first && values.contains_key(key));
+        }
+        // Update or remove existing properties, from the back so indices stay valid.
+        let mut last_index = None;
+        for ((index, child, key), keep) in existing.iter().zip(keep).rev() {
+            if !keep {
+                self.remove_child(doc, *index);
+                if let Some(last_index) = last_index.as_mut() {
+                    *last_index -= 1;
+                }
+                continue;
+            }
+            // `keep` is only true for the first occurrence of a key in `values`.
+            #[allow(clippy::unwrap_used)]
+            let value = values.remove(key).unwrap();
+            if property_value(doc, *child, value_source)? != value {
+                match value_source {
+                    ValueSource::Attribute(value_attr) => {
+                        child.set_attribute(doc, value_attr, value)
+                    }
+                    ValueSource::Text => child.set_text_content(doc, value),
+                }
+            }
+            if last_index.is_none() {
+                last_index = Some(*index);
+            }
+        }
+
+        // Remaining values are new keys.
+        let mut index = match last_index {
+            Some(last_index) => last_index + 1,
+            None => self.children(doc).len(),
+        };
+        for key in keys {
+            let value = match values.remove(&key) {
+                Some(value) => value,
+                None => continue,
+            };
+            let builder = Element::build(child_name).attribute(key_attr, key);
+            let builder = match value_source {
+                ValueSource::Attribute(value_attr) => builder.attribute(value_attr, value),
+                ValueSource::Text => builder.text_content(value),
+            };
+            let child = builder.finish(doc);
+            self.insert_child(doc, index, child.as_node())?;
+            index += 1;
+        }
+        Ok(())
+    }
+}
+
+fn property_value(doc: &Document, elem: Element, value_source: ValueSource) -> Result<String> {
+    match value_source {
+        ValueSource::Attribute(value_attr) => {
+            Ok(elem.attribute(doc, value_attr).unwrap_or("").to_string())
+        }
+        ValueSource::Text => elem.try_text_content(doc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ValueSource;
+    use crate::document::{Document, Node};
+
+    #[test]
+    fn test_set_property_map() {
+        let xml = r#"<?xml version="1.0"?>
+<properties>
+  <!-- connection -->
+  <property name="host" value="localhost"/>
+  <property name="port" value="8080"/>
+  <other/>
+  <!-- logging -->
+  <property name="log" value="info"/>
+  <property name="host" value="duplicate"/>
+</properties>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let properties = doc.root_element().unwrap();
+        let source = ValueSource::Attribute("value");
+        let host = properties.child_elements(&doc)[0];
+        properties
+            .set_property_map(
+                &mut doc,
+                "property",
+                "name",
+                source,
+                [("log", "debug"), ("user", "admin"), ("host", "localhost")],
+            )
+            .unwrap();
+
+        assert_eq!(
+            properties.as_property_map(&doc, "property", "name", source),
+            vec![
+                ("host".to_string(), "localhost".to_string()),
+                ("log".to_string(), "debug".to_string()),
+                ("user".to_string(), "admin".to_string()),
+            ]
+        );
+        // Unchanged elements are kept as is.
+        assert_eq!(properties.child_elements(&doc)[0], host);
+        let children = properties.children(&doc);
+        assert!(matches!(&children[0], Node::Comment(c) if c == " connection "));
+        assert!(matches!(&children[3], Node::Comment(c) if c == " logging "));
+        assert_eq!(children[2].as_element().unwrap().name(&doc), "other");
+        assert_eq!(children.len(), 6);
+
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<properties>
+  <!-- connection -->
+  <property name="host" value="localhost"/>
+  <other/>
+  <!-- logging -->
+  <property name="log" value="debug"/>
+  <property name="user" value="admin"/>
+</properties>"#;
+        assert_eq!(doc.write_str().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_property_map_text() {
+        let xml = r#"<?xml version="1.0"?>
+<map><entry key="a">1</entry><!-- c --><entry>no key</entry></map>"#;
+        let mut doc = Document::parse_str(xml).unwrap();
+        let map = doc.root_element().unwrap();
+        map.set_property_map(
+            &mut doc,
+            "entry",
+            "key",
+            ValueSource::Text,
+            vec![("b", "2"), ("a", "3")],
+        )
+        .unwrap();
+        assert_eq!(
+            map.as_property_map(&doc, "entry", "key", ValueSource::Text),
+            vec![
+                ("a".to_string(), "3".to_string()),
+                ("b".to_string(), "2".to_string()),
+            ]
+        );
+        assert_eq!(map.children(&doc).len(), 4);
+        assert_eq!(map.child_elements(&doc)[2].text_content(&doc), "no key");
+
+        // Without existing properties, new ones are appended at the end.
+        map.set_property_map(&mut doc, "item", "id", ValueSource::Text, [("x", "y")])
+            .unwrap();
+        let last = map.child_elements(&doc)[3];
+        assert_eq!(last.name(&doc), "item");
+        assert_eq!(last.attribute(&doc, "id"), Some("x"));
+    }
+}
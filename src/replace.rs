@@ -0,0 +1,171 @@
+use crate::document::{Document, Node};
+use crate::element::Element;
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+/// Nodes searched by [`Document::replace_text_literal()`] besides text nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplaceFlags {
+    /// Replace in CDATA sections. (default: `false`)
+    pub cdata: bool,
+    /// Replace in comments. (default: `false`)
+    pub comments: bool,
+}
+
+/// Below are methods to find and replace in text and attribute values of the whole document.
+///
+/// Only nodes attached to the document are searched, in document order.
+/// Element names, attribute names and namespace declarations are never changed.
+/// Text stored in files, [`Node::ExternalText`], isn't searched.
+/// The methods return the number of replaced matches.
+///
+/// Replacements aren't checked: a CDATA section that gets `]]>`
+/// or a comment that gets `--` fails to be written.
+impl Document {
+    /// Replace every occurrence of `from` with `to` in text nodes,
+    /// and in CDATA sections and comments if `flags` says so.
+    ///
+    /// Nothing is replaced if `from` is empty.
+    ///
+    /// ```
+    /// use xml_doc::{Document, ReplaceFlags};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b>old.host</b><!--old.host--></a>"#).unwrap();
+    /// let count = doc.replace_text_literal("old.host", "new.host", ReplaceFlags::default());
+    /// assert_eq!(count, 1);
+    /// assert_eq!(doc.extract_text(""), "new.host");
+    /// ```
+    pub fn replace_text_literal(&mut self, from: &str, to: &str, flags: ReplaceFlags) -> usize {
+        self.replace_in_nodes(flags, |text| literal_replace(from, to, text))
+    }
+
+    /// Replace every occurrence of `from` with `to` in attribute values.
+    /// If `attr_name` is given, only values of attributes with that full name are changed.
+    ///
+    /// Nothing is replaced if `from` is empty.
+    pub fn replace_in_attributes_literal(
+        &mut self,
+        attr_name: Option<&str>,
+        from: &str,
+        to: &str,
+    ) -> usize {
+        self.replace_in_values(attr_name, |value| literal_replace(from, to, value))
+    }
+
+    /// Same as [`Document::replace_text_literal()`], but replaces matches of `pattern`.
+    ///
+    /// `$1` and `$name` in `replacement` are expanded as in [`Regex::replace_all()`].
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// use xml_doc::{Document, ReplaceFlags};
+    ///
+    /// let mut doc = Document::parse_str(r#"<?xml version="1.0"?><a><b>v1.2</b><c>v3.4</c></a>"#).unwrap();
+    /// let pattern = Regex::new(r"v(\d+)\.(\d+)").unwrap();
+    /// assert_eq!(doc.replace_text(&pattern, "$1-$2", ReplaceFlags::default()), 2);
+    /// assert_eq!(doc.extract_text(" "), "1-2 3-4");
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn replace_text(
+        &mut self,
+        pattern: &Regex,
+        replacement: &str,
+        flags: ReplaceFlags,
+    ) -> usize {
+        self.replace_in_nodes(flags, |text| regex_replace(pattern, replacement, text))
+    }
+
+    /// Same as [`Document::replace_in_attributes_literal()`], but replaces matches of `pattern`.
+    ///
+    /// `$1` and `$name` in `replacement` are expanded as in [`Regex::replace_all()`].
+    #[cfg(feature = "regex")]
+    pub fn replace_in_attributes(
+        &mut self,
+        attr_name: Option<&str>,
+        pattern: &Regex,
+        replacement: &str,
+    ) -> usize {
+        self.replace_in_values(attr_name, |value| {
+            regex_replace(pattern, replacement, value)
+        })
+    }
+
+    fn replace_in_nodes<F>(&mut self, flags: ReplaceFlags, mut replace: F) -> usize
+    where
+        F: FnMut(&str) -> Option<(String, usize)>,
+    {
+        let container = self.container();
+        let elements: Vec<Element> = std::iter::once(container)
+            .chain(container.descendants(self))
+            .collect();
+        let mut count = 0;
+        for elem in elements {
+            let mut replaced = Vec::new();
+            for (index, node) in elem.children(self).iter().enumerate() {
+                let new = match node {
+                    Node::Text(text) => replace(text).map(|(text, n)| (Node::Text(text.into()), n)),
+                    Node::CData(text) if flags.cdata => {
+                        replace(text).map(|(text, n)| (Node::CData(text.into()), n))
+                    }
+                    Node::Comment(text) if flags.comments => {
+                        replace(text).map(|(text, n)| (Node::Comment(text), n))
+                    }
+                    _ => None,
+                };
+                if let Some((node, n)) = new {
+                    count += n;
+                    replaced.push((index, node));
+                }
+            }
+            for (index, node) in replaced {
+                elem.set_text_child(self, index, node);
+            }
+        }
+        count
+    }
+
+    fn replace_in_values<F>(&mut self, attr_name: Option<&str>, mut replace: F) -> usize
+    where
+        F: FnMut(&str) -> Option<(String, usize)>,
+    {
+        let elements: Vec<Element> = self.container().descendants(self).collect();
+        let mut count = 0;
+        for elem in elements {
+            let mut replaced = Vec::new();
+            for (name, value) in elem.attributes(self) {
+                if attr_name.is_some_and(|attr_name| attr_name != name) {
+                    continue;
+                }
+                if let Some((value, n)) = replace(value) {
+                    count += n;
+                    replaced.push((name.clone(), value));
+                }
+            }
+            for (name, value) in replaced {
+                elem.set_attribute(self, name, value);
+            }
+        }
+        count
+    }
+}
+
+// Returns the replaced text and the number of matches, or `None` if there isn't any match.
+fn literal_replace(from: &str, to: &str, text: &str) -> Option<(String, usize)> {
+    if from.is_empty() {
+        return None;
+    }
+    let count = text.matches(from).count();
+    if count == 0 {
+        return None;
+    }
+    Some((text.replace(from, to), count))
+}
+
+#[cfg(feature = "regex")]
+fn regex_replace(pattern: &Regex, replacement: &str, text: &str) -> Option<(String, usize)> {
+    let count = pattern.find_iter(text).count();
+    if count == 0 {
+        return None;
+    }
+    Some((pattern.replace_all(text, replacement).into_owned(), count))
+}
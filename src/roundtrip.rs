@@ -0,0 +1,184 @@
+use crate::document::{Document, WriteOptions};
+use crate::error::Result;
+use crate::parser::ReadOptions;
+
+/// Maximum number of [`Divergence`]s collected by [`roundtrip_check`].
+pub const DEFAULT_MAX_DIVERGENCES: usize = 16;
+
+// Number of bytes that must match again after a divergence to consider both streams in sync.
+const SYNC_LEN: usize = 4;
+// How far ahead to look for a point where both streams are in sync again.
+const SYNC_WINDOW: usize = 256;
+// Number of matching bytes before a divergence kept as context.
+const CONTEXT_LEN: usize = 24;
+
+/// A span where the written bytes differ from the input bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Byte position of the divergence in the input.
+    pub input_pos: usize,
+    /// Byte position of the divergence in the written output.
+    pub output_pos: usize,
+    /// Matching bytes just before the divergence.
+    pub context: String,
+    /// Input bytes that were replaced. May be empty if bytes were only inserted.
+    pub input: String,
+    /// Output bytes that replaced them. May be empty if bytes were only removed.
+    pub output: String,
+}
+
+/// Result of [`roundtrip_check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundtripReport {
+    /// The re-parsed document has the same content as the parsed document.
+    /// (See [`Document::content_eq`])
+    pub content_eq: bool,
+    /// The written bytes are identical to the input.
+    pub bytes_eq: bool,
+    /// Spans where the output differs from the input, in order.
+    /// At most [`DEFAULT_MAX_DIVERGENCES`] are collected,
+    /// or the limit given to [`roundtrip_check_with_max`].
+    pub divergences: Vec<Divergence>,
+}
+
+impl RoundtripReport {
+    /// Returns `true` if parse → write keeps document content. Bytes may still differ.
+    pub fn is_lossless(&self) -> bool {
+        self.content_eq
+    }
+}
+
+/// Parse `input`, write it, and parse the written xml again
+/// to check if parse → write is lossless with the given options.
+///
+/// Note that the document is always written in UTF-8,
+/// so bytes are never identical for inputs in other encodings.
+///
+/// # Errors
+/// Returns error if either `input` or the written xml could not be parsed,
+/// or if the document could not be written.
+///
+/// # Examples
+/// ```
+/// use xml_doc::{roundtrip_check, ReadOptions, WriteOptions};
+///
+/// let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+/// <root a='1'/>"#;
+/// let report = roundtrip_check(xml.as_bytes(), ReadOptions::default(), WriteOptions::default()).unwrap();
+/// assert!(report.content_eq);
+/// assert!(!report.bytes_eq); // written as `<root a="1"/>`
+/// ```
+pub fn roundtrip_check(
+    input: &[u8],
+    read_opts: ReadOptions,
+    write_opts: WriteOptions,
+) -> Result<RoundtripReport> {
+    roundtrip_check_with_max(input, read_opts, write_opts, DEFAULT_MAX_DIVERGENCES)
+}
+
+/// Same as [`roundtrip_check`], but collects at most `max_divergences` [`Divergence`]s.
+///
+/// # Examples
+/// ```
+/// use xml_doc::{roundtrip_check_with_max, ReadOptions, WriteOptions};
+///
+/// let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+/// <root a='first' b='second' c='third'/>"#;
+/// let report =
+///     roundtrip_check_with_max(xml.as_bytes(), ReadOptions::default(), WriteOptions::default(), 2)
+///         .unwrap();
+/// assert_eq!(report.divergences.len(), 2);
+/// ```
+pub fn roundtrip_check_with_max(
+    input: &[u8],
+    read_opts: ReadOptions,
+    write_opts: WriteOptions,
+    max_divergences: usize,
+) -> Result<RoundtripReport> {
+    let doc = Document::parse_reader_with_opts(input, read_opts.clone())?;
+    let mut output: Vec<u8> = Vec::with_capacity(input.len());
+    doc.write_with_opts(&mut output, write_opts)?;
+    let new_doc = Document::parse_reader_with_opts(&output[..], read_opts)?;
+
+    Ok(RoundtripReport {
+        content_eq: doc.content_eq(&new_doc),
+        bytes_eq: input == &output[..],
+        divergences: divergences(input, &output, max_divergences),
+    })
+}
+
+/// Find spans where `output` differs from `input`, up to `max` spans.
+fn divergences(input: &[u8], output: &[u8], max: usize) -> Vec<Divergence> {
+    let mut found = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while found.len() < max {
+        while i < input.len() && j < output.len() && input[i] == output[j] {
+            i += 1;
+            j += 1;
+        }
+        if i == input.len() && j == output.len() {
+            break;
+        }
+        let (skip_i, skip_j) =
+            resync(&input[i..], &output[j..]).unwrap_or((input.len() - i, output.len() - j));
+        found.push(Divergence {
+            input_pos: i,
+            output_pos: j,
+            context: String::from_utf8_lossy(&input[i.saturating_sub(CONTEXT_LEN)..i]).to_string(),
+            input: String::from_utf8_lossy(&input[i..i + skip_i]).to_string(),
+            output: String::from_utf8_lossy(&output[j..j + skip_j]).to_string(),
+        });
+        i += skip_i;
+        j += skip_j;
+    }
+    found
+}
+
+/// Find the smallest combined skip `(a, b)` after which
+/// `input[a..]` and `output[b..]` start with [`SYNC_LEN`] matching bytes, or both end.
+fn resync(input: &[u8], output: &[u8]) -> Option<(usize, usize)> {
+    let in_sync = |a: usize, b: usize| {
+        let (rest_in, rest_out) = (&input[a..], &output[b..]);
+        if rest_in.len() < SYNC_LEN || rest_out.len() < SYNC_LEN {
+            rest_in == rest_out
+        } else {
+            rest_in[..SYNC_LEN] == rest_out[..SYNC_LEN]
+        }
+    };
+    for distance in 1..=SYNC_WINDOW {
+        for a in 0..=distance {
+            let b = distance - a;
+            if a <= input.len() && b <= output.len() && in_sync(a, b) {
+                return Some((a, b));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_divergences() {
+        let input = b"<a x=\"1\"/>";
+        let output = b"<a x=\"1\" />";
+        let diffs = divergences(input, output, DEFAULT_MAX_DIVERGENCES);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].input_pos, 8);
+        assert_eq!(diffs[0].output_pos, 8);
+        assert_eq!(diffs[0].input, "");
+        assert_eq!(diffs[0].output, " ");
+        assert_eq!(diffs[0].context, "<a x=\"1\"");
+
+        assert!(divergences(input, input, DEFAULT_MAX_DIVERGENCES).is_empty());
+        let diffs = divergences(b"abcdefgh", b"Xbcdefgh", DEFAULT_MAX_DIVERGENCES);
+        assert_eq!(
+            (diffs[0].input.as_str(), diffs[0].output.as_str()),
+            ("a", "X")
+        );
+        let diffs = divergences(b"abcd1234abcd", b"abcd5678abcd", 1);
+        assert_eq!(diffs.len(), 1);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::error::{Error, Result};
+use crate::parser::is_whitespace;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Text stored in a file instead of memory. Content of [`Node::ExternalText`](crate::Node::ExternalText).
+///
+/// Created when parsing with [`ReadOptions::spill_large_text_to`](crate::ReadOptions::spill_large_text_to).
+/// The file is deleted when the last clone of this handle is dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalText {
+    file: Arc<SpilledFile>,
+    cdata: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct SpilledFile {
+    path: PathBuf,
+    len: usize,
+}
+
+impl Drop for SpilledFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Makes file names unique within the process.
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Size of chunks read from the file when streaming.
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+impl ExternalText {
+    /// Write `content`, which must be valid UTF-8, to a new file in `dir`.
+    /// `cdata` is `true` if it was a CDATA section.
+    pub(crate) fn create(dir: &Path, content: &[u8], cdata: bool) -> Result<ExternalText> {
+        let count = SPILL_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("xml-doc-{}-{}.txt", std::process::id(), count));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let spilled = SpilledFile {
+            path,
+            len: content.len(),
+        };
+        // `spilled` removes the file if writing fails.
+        file.write_all(content)?;
+        Ok(ExternalText {
+            file: Arc::new(spilled),
+            cdata,
+        })
+    }
+
+    /// Length of the text in bytes.
+    pub fn len(&self) -> usize {
+        self.file.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file.len == 0
+    }
+
+    /// Returns `true` if the text was a CDATA section. It is written as one.
+    pub fn is_cdata(&self) -> bool {
+        self.cdata
+    }
+
+    /// Path of the file the text is stored in.
+    pub fn path(&self) -> &Path {
+        &self.file.path
+    }
+
+    /// Open the file to stream the text. The file contains the text in UTF-8.
+    pub fn open(&self) -> Result<File> {
+        Ok(File::open(&self.file.path)?)
+    }
+
+    /// Read the whole text into memory.
+    pub fn read_to_string(&self) -> Result<String> {
+        let mut text = String::with_capacity(self.file.len);
+        self.open()?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    /// Returns `true` if the text has only XML whitespace, reading the file until it doesn't.
+    pub(crate) fn is_whitespace_only(&self) -> Result<bool> {
+        let mut whitespace_only = true;
+        let result = self.for_each_chunk(|chunk| {
+            // Whitespace characters are ASCII, so no other byte is part of one.
+            whitespace_only = chunk.iter().copied().all(is_whitespace);
+            match whitespace_only {
+                true => Ok(()),
+                false => Err(Error::MalformedXML(String::new())), // stops reading
+            }
+        });
+        match result {
+            Err(_) if !whitespace_only => Ok(false),
+            result => result.map(|_| whitespace_only),
+        }
+    }
+
+    /// Same as `for_each_chunk`, but "]]>" is split into two CDATA sections
+    /// so that the chunks can be written inside one.
+    pub(crate) fn for_each_cdata_chunk<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        // Number of ']' right before the current byte, which may be in the previous chunk.
+        let mut brackets = 0;
+        self.for_each_chunk(|chunk| {
+            let mut start = 0;
+            for (i, &byte) in chunk.iter().enumerate() {
+                if byte == b'>' && brackets >= 2 {
+                    f(&chunk[start..i])?;
+                    f(b"]]><![CDATA[")?;
+                    start = i;
+                }
+                brackets = if byte == b']' { brackets + 1 } else { 0 };
+            }
+            f(&chunk[start..])
+        })
+    }
+
+    /// Call `f` with chunks of the text in order.
+    /// Chunks are split at arbitrary bytes, so they may not be valid UTF-8 by themselves.
+    pub(crate) fn for_each_chunk<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        let mut file = self.open()?;
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let read = file.read(&mut buf)?;
+            if read == 0 {
+                return Ok(());
+            }
+            f(&buf[..read])?;
+        }
+    }
+}
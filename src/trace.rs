@@ -0,0 +1,80 @@
+// Spans and events of the `tracing` feature.
+//
+// Fields are recorded once when parsing or writing finishes, so nothing is done per node.
+use crate::document::WriteStats;
+use crate::error::{Error, Result};
+use crate::parser::Diagnostic;
+use encoding_rs::Encoding;
+use tracing::field::Empty;
+use tracing::Span;
+
+// Name of `encoding`, where `None` is UTF-8.
+fn encoding_name(encoding: Option<&'static Encoding>) -> &'static str {
+    encoding.map_or("UTF-8", |encoding| encoding.name())
+}
+
+pub(crate) fn parse_span() -> Span {
+    tracing::debug_span!(
+        "parse",
+        bytes = Empty,
+        encoding = Empty,
+        elements = Empty,
+        diagnostics = Empty
+    )
+}
+
+pub(crate) fn encoding_switched(from: Option<&'static Encoding>, to: Option<&'static Encoding>) {
+    tracing::debug!(
+        from = encoding_name(from),
+        to = encoding_name(to),
+        "switched to declared encoding"
+    );
+}
+
+// `bytes` is the length of the input read, decoded to UTF-8.
+pub(crate) fn parse_finished(
+    span: &Span,
+    bytes: usize,
+    encoding: Option<&'static Encoding>,
+    elements: usize,
+    diagnostics: &[Diagnostic],
+    result: &Result<()>,
+) {
+    span.record("bytes", bytes);
+    span.record("encoding", encoding_name(encoding));
+    span.record("elements", elements);
+    span.record("diagnostics", diagnostics.len());
+    for diagnostic in diagnostics {
+        tracing::warn!(position = diagnostic.position, "{}", diagnostic.message);
+    }
+    if let Err(err) = result {
+        if let Error::LimitExceeded {
+            limit,
+            max,
+            position,
+        } = err
+        {
+            tracing::warn!(limit, max, position, "limit exceeded");
+        }
+        tracing::error!(error = %err, "parse failed");
+    }
+}
+
+pub(crate) fn write_span(encoding: &str) -> Span {
+    tracing::debug_span!(
+        "write",
+        encoding,
+        elements = Empty,
+        attributes = Empty,
+        bytes = Empty
+    )
+}
+
+pub(crate) fn write_finished<T>(span: &Span, stats: &WriteStats, result: &Result<T>) {
+    span.record("elements", stats.elements);
+    span.record("attributes", stats.attributes);
+    span.record("bytes", stats.bytes);
+    if let Err(err) = result {
+        tracing::error!(error = %err, "write failed");
+    }
+}
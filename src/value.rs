@@ -0,0 +1,295 @@
+use std::borrow::{Borrow, Cow};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Text or attribute value, either owned or shared with equal values of the same document.
+///
+/// Values are shared when parsed with [`ReadOptions::dedup_values`](crate::ReadOptions::dedup_values),
+/// and between a template and its instances. See [`Document::create_template()`](crate::Document::create_template).
+/// A `Value` derefs to `str`, and compares and hashes like one.
+/// [`Value::to_mut()`] copies a shared value before changing it,
+/// so other nodes and attributes sharing it are not affected.
+///
+/// ```
+/// use xml_doc::Value;
+///
+/// let mut value = Value::from("USD");
+/// assert_eq!(value, "USD");
+/// value.to_mut().push_str(" 100");
+/// assert_eq!(value.as_str(), "USD 100");
+/// ```
+#[derive(Clone)]
+pub struct Value(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Owned(String),
+    Shared(Arc<str>),
+}
+
+impl Value {
+    /// Create an empty owned value.
+    pub fn new() -> Value {
+        Value(Repr::Owned(String::new()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Repr::Owned(value) => value,
+            Repr::Shared(value) => value,
+        }
+    }
+
+    /// Returns `true` if the value may be shared with other nodes or attributes.
+    pub fn is_shared(&self) -> bool {
+        matches!(self.0, Repr::Shared(_))
+    }
+
+    /// Get a mutable reference to the owned value, copying it first if it is shared.
+    pub fn to_mut(&mut self) -> &mut String {
+        if let Repr::Shared(value) = &self.0 {
+            self.0 = Repr::Owned(value.to_string());
+        }
+        match &mut self.0 {
+            Repr::Owned(value) => value,
+            Repr::Shared(_) => unreachable!("shared value was just copied"),
+        }
+    }
+
+    /// Make the value shared, so its clones don't copy it.
+    pub(crate) fn share(&mut self) {
+        if let Repr::Owned(value) = &mut self.0 {
+            self.0 = Repr::Shared(Arc::from(std::mem::take(value)));
+        }
+    }
+
+    /// Convert into a `String`, copying the value if it is shared.
+    pub fn into_string(self) -> String {
+        match self.0 {
+            Repr::Owned(value) => value,
+            Repr::Shared(value) => value.to_string(),
+        }
+    }
+
+    /// Heap bytes used by the value. A shared value is only counted
+    /// the first time it is seen, and its address is added to `seen`.
+    pub(crate) fn heap_size(&self, seen: &mut HashSet<*const u8>) -> usize {
+        match &self.0 {
+            Repr::Owned(value) => value.capacity(),
+            // Strong and weak counts are stored with the value.
+            Repr::Shared(value) if seen.insert(value.as_ptr()) => {
+                value.len() + 2 * std::mem::size_of::<usize>()
+            }
+            Repr::Shared(_) => 0,
+        }
+    }
+}
+
+impl Default for Value {
+    fn default() -> Value {
+        Value::new()
+    }
+}
+
+impl Deref for Value {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Value {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for Value {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+macro_rules! impl_eq_str {
+    ($($ty:ty),*) => {
+        $(
+            impl PartialEq<$ty> for Value {
+                fn eq(&self, other: &$ty) -> bool {
+                    self.as_str() == AsRef::<str>::as_ref(other)
+                }
+            }
+
+            impl PartialEq<Value> for $ty {
+                fn eq(&self, other: &Value) -> bool {
+                    AsRef::<str>::as_ref(self) == other.as_str()
+                }
+            }
+        )*
+    };
+}
+
+impl_eq_str!(str, &str, String);
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value(Repr::Owned(value))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value(Repr::Owned(value.to_string()))
+    }
+}
+
+impl From<&String> for Value {
+    fn from(value: &String) -> Value {
+        Value(Repr::Owned(value.clone()))
+    }
+}
+
+impl<'a> From<Cow<'a, str>> for Value {
+    fn from(value: Cow<'a, str>) -> Value {
+        Value(Repr::Owned(value.into_owned()))
+    }
+}
+
+impl From<Value> for String {
+    fn from(value: Value) -> String {
+        value.into_string()
+    }
+}
+
+/// Distinct values shared by a document. See [`ReadOptions::dedup_values`](crate::ReadOptions::dedup_values).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ValuePool {
+    values: HashSet<Arc<str>>,
+    // Number of values at which unused ones are dropped before adding another. See `ValuePool::prune`.
+    prune_at: usize,
+}
+
+// Pools smaller than this are never pruned while interning.
+const MIN_PRUNE_AT: usize = 1024;
+
+impl ValuePool {
+    /// Get a shared value equal to `value`, adding it to the pool if there isn't one yet.
+    pub(crate) fn intern(&mut self, value: &str) -> Value {
+        if self.values.len() >= self.prune_at {
+            self.prune();
+        }
+        let shared = match self.values.get(value) {
+            Some(shared) => Arc::clone(shared),
+            None => {
+                let shared: Arc<str> = Arc::from(value);
+                self.values.insert(Arc::clone(&shared));
+                shared
+            }
+        };
+        Value(Repr::Shared(shared))
+    }
+
+    /// Drop values that are no longer used by any node or attribute,
+    /// because they were modified or removed.
+    /// Pruning is amortized: the pool is pruned again once it has doubled.
+    pub(crate) fn prune(&mut self) {
+        self.values.retain(|value| Arc::strong_count(value) > 1);
+        self.prune_at = (self.values.len() * 2).max(MIN_PRUNE_AT);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+        self.prune_at = 0;
+    }
+
+    /// Heap bytes of the pool's table. Values are counted where they are used.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.values.capacity() * std::mem::size_of::<Arc<str>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_pool() {
+        let mut pool = ValuePool::default();
+        let a = pool.intern("USD");
+        let mut b = pool.intern("USD");
+        assert!(a.is_shared() && b.is_shared());
+        assert_eq!(pool.values.len(), 1);
+        let mut seen = HashSet::new();
+        assert!(a.heap_size(&mut seen) >= 3);
+        assert_eq!(b.heap_size(&mut seen), 0);
+
+        b.to_mut().push_str(" 100");
+        assert!(!b.is_shared());
+        assert_eq!(a, "USD");
+        assert_eq!(b, "USD 100");
+        assert!(b.heap_size(&mut seen) >= 7);
+        assert_eq!(pool.intern("USD").heap_size(&mut seen), 0);
+        assert_eq!(pool.values.len(), 1);
+
+        assert_eq!(a, Value::from("USD"));
+        assert_eq!(format!("{} {:?}", a, a), "USD \"USD\"");
+
+        // Values only kept by the pool are dropped.
+        let c = pool.intern("EUR");
+        assert_eq!(pool.values.len(), 2);
+        drop(c);
+        pool.prune();
+        assert_eq!(pool.values.len(), 1);
+        drop(a);
+        pool.prune();
+        assert!(pool.values.is_empty());
+
+        // Interning prunes the pool once it has grown enough.
+        for i in 0..MIN_PRUNE_AT * 4 {
+            pool.intern(&i.to_string());
+        }
+        assert!(pool.values.len() <= MIN_PRUNE_AT);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::document::{Document, Node};
+use crate::element::Element;
+use crate::parser::is_whitespace_char;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Element and attribute names seen in documents, as an inferred schema.
+/// Returned by [`Document::vocabulary()`].
+///
+/// Names are full names, with their prefix.
+/// With the `serde` feature, it implements `Serialize` and `Deserialize`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vocabulary {
+    /// Names of root elements.
+    pub roots: BTreeSet<String>,
+    /// What was seen in elements, by element name.
+    pub elements: BTreeMap<String, ElementVocabulary>,
+}
+
+/// What was seen in elements with the same name. See [`Vocabulary`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ElementVocabulary {
+    /// Names of attributes. Namespace declarations are not included.
+    pub attributes: BTreeSet<String>,
+    /// Names of child elements.
+    pub children: BTreeSet<String>,
+    /// Whether an element had a text or CDATA child that isn't only whitespace.
+    pub has_text: bool,
+}
+
+/// A difference of a document from a [`Vocabulary`]. Returned by [`Document::conforms_to()`].
+///
+/// `path` is the path of the element, as returned by [`Element::path()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// An element whose name isn't in the vocabulary.
+    UnknownElement { path: String },
+    /// A known element that wasn't seen in its parent, or as a root element.
+    UnexpectedElement { path: String },
+    /// An attribute that wasn't seen in elements with the same name.
+    UnknownAttribute { path: String, name: String },
+    /// Text in an element that wasn't seen with text.
+    UnexpectedText { path: String },
+}
+
+impl Vocabulary {
+    /// Add names seen in `other`.
+    pub fn merge(&mut self, other: &Vocabulary) {
+        self.roots.extend(other.roots.iter().cloned());
+        for (name, other) in &other.elements {
+            let vocab = self.elements.entry(name.clone()).or_default();
+            vocab.attributes.extend(other.attributes.iter().cloned());
+            vocab.children.extend(other.children.iter().cloned());
+            vocab.has_text |= other.has_text;
+        }
+    }
+}
+
+/// Below are methods to infer a vocabulary from a document, and check documents against it.
+impl Document {
+    /// Collect names of elements, their attributes and child elements, and whether they have text.
+    ///
+    /// ```
+    /// use xml_doc::Document;
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><list><item id="1">a</item><item/></list>"#).unwrap();
+    /// let vocab = doc.vocabulary();
+    /// assert!(vocab.roots.contains("list"));
+    /// assert!(vocab.elements["list"].children.contains("item"));
+    /// assert!(vocab.elements["item"].attributes.contains("id"));
+    /// assert!(vocab.elements["item"].has_text);
+    /// ```
+    pub fn vocabulary(&self) -> Vocabulary {
+        let mut vocab = Vocabulary::default();
+        let container = self.container();
+        for root in container.child_elements_iter(self) {
+            vocab.roots.insert(root.full_name(self).to_string());
+        }
+        for elem in container.descendants(self) {
+            let entry = vocab
+                .elements
+                .entry(elem.full_name(self).to_string())
+                .or_default();
+            for name in elem.attributes(self).keys() {
+                if !entry.attributes.contains(name) {
+                    entry.attributes.insert(name.clone());
+                }
+            }
+            for child in elem.child_elements_iter(self) {
+                let name = child.full_name(self);
+                if !entry.children.contains(name) {
+                    entry.children.insert(name.to_string());
+                }
+            }
+            entry.has_text |= has_text(self, elem);
+        }
+        vocab
+    }
+
+    /// Check that elements, attributes and text are where `vocab` has seen them.
+    ///
+    /// Violations are in document order, with the element's own violations before its children's.
+    /// Elements in an unknown element aren't reported as unexpected.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Violation};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?><list><item id="1"/></list>"#).unwrap();
+    /// let vocab = doc.vocabulary();
+    /// let other = Document::parse_str(r#"<?xml version="1.0"?><list><item key="1"/></list>"#).unwrap();
+    /// assert_eq!(other.conforms_to(&vocab), [Violation::UnknownAttribute {
+    ///     path: "/list/item".to_string(),
+    ///     name: "key".to_string(),
+    /// }]);
+    /// ```
+    pub fn conforms_to(&self, vocab: &Vocabulary) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for elem in self.container().descendants(self) {
+            let name = elem.full_name(self);
+            let known = match vocab.elements.get(name) {
+                Some(known) => known,
+                None => {
+                    violations.push(Violation::UnknownElement {
+                        path: elem.path(self),
+                    });
+                    continue;
+                }
+            };
+            let expected = match elem.parent(self) {
+                Some(parent) if parent.is_container() => Some(vocab.roots.contains(name)),
+                Some(parent) => vocab
+                    .elements
+                    .get(parent.full_name(self))
+                    .map(|parent| parent.children.contains(name)),
+                None => Some(true),
+            };
+            if expected == Some(false) {
+                violations.push(Violation::UnexpectedElement {
+                    path: elem.path(self),
+                });
+            }
+            let mut unknown: Vec<&String> = elem
+                .attributes(self)
+                .keys()
+                .filter(|name| !known.attributes.contains(*name))
+                .collect();
+            unknown.sort_unstable();
+            for name in unknown {
+                violations.push(Violation::UnknownAttribute {
+                    path: elem.path(self),
+                    name: name.clone(),
+                });
+            }
+            if !known.has_text && has_text(self, elem) {
+                violations.push(Violation::UnexpectedText {
+                    path: elem.path(self),
+                });
+            }
+        }
+        violations
+    }
+}
+
+// Whether `elem` has a text or CDATA child that isn't only whitespace.
+// Large text is read until a character that isn't whitespace, and counts as text if it can't be read.
+fn has_text(doc: &Document, elem: Element) -> bool {
+    elem.children(doc).iter().any(|node| match node {
+        Node::Text(text) | Node::CData(text) => !text.chars().all(is_whitespace_char),
+        Node::ExternalText(text) => !text.is_whitespace_only().unwrap_or(false),
+        _ => false,
+    })
+}
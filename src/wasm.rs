@@ -0,0 +1,65 @@
+//! JavaScript bindings with `wasm-bindgen`, enabled with the `js` feature.
+//!
+//! Build with `wasm-pack build --no-default-features --features js`.
+//!
+//! Elements are referred to by their `u32` id, as returned by `rootElement()` and `find()`.
+use crate::document::Document;
+use crate::element::Element;
+use wasm_bindgen::prelude::*;
+
+/// A [`Document`] that can be used from JavaScript.
+#[wasm_bindgen]
+pub struct WasmDocument {
+    doc: Document,
+}
+
+fn to_js_error(err: crate::error::Error) -> JsValue {
+    JsValue::from_str(&format!("{:?}", err))
+}
+
+#[wasm_bindgen]
+impl WasmDocument {
+    /// Parses `xml` with default [`ReadOptions`](crate::ReadOptions). Throws on error.
+    pub fn parse(xml: &str) -> Result<WasmDocument, JsValue> {
+        let doc = Document::parse_str(xml).map_err(to_js_error)?;
+        Ok(WasmDocument { doc })
+    }
+
+    #[wasm_bindgen(js_name = writeStr)]
+    pub fn write_str(&self) -> Result<String, JsValue> {
+        self.doc.write_str().map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = rootElement)]
+    pub fn root_element(&self) -> Option<u32> {
+        self.doc.root_element().map(|elem| elem.id() as u32)
+    }
+
+    /// Find the first child element of `parent` with `name`.
+    pub fn find(&self, parent: u32, name: &str) -> Result<Option<u32>, JsValue> {
+        let parent = self.element(parent)?;
+        Ok(parent.find(&self.doc, name).map(|elem| elem.id() as u32))
+    }
+
+    #[wasm_bindgen(js_name = getAttribute)]
+    pub fn get_attribute(&self, elem: u32, name: &str) -> Result<Option<String>, JsValue> {
+        let elem = self.element(elem)?;
+        Ok(elem.attribute(&self.doc, name).map(String::from))
+    }
+
+    #[wasm_bindgen(js_name = setAttribute)]
+    pub fn set_attribute(&mut self, elem: u32, name: &str, value: &str) -> Result<(), JsValue> {
+        let elem = self.element(elem)?;
+        elem.set_attribute(&mut self.doc, name, value);
+        Ok(())
+    }
+}
+
+impl WasmDocument {
+    fn element(&self, id: u32) -> Result<Element, JsValue> {
+        match Element::from_id(&self.doc, id as usize) {
+            Some(elem) if !elem.is_container() => Ok(elem),
+            _ => Err(JsValue::from_str(&format!("No element with id {}", id))),
+        }
+    }
+}
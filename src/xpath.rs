@@ -0,0 +1,438 @@
+use crate::document::{Document, Node};
+use crate::element::{Element, TextMatch};
+use crate::error::{Error, Result};
+use crate::parser::is_name;
+use crate::value::Value;
+use std::collections::{HashMap, HashSet};
+
+/// Below are methods to query the document with a subset of [XPath](https://www.w3.org/TR/xpath-10/).
+///
+/// An expression is a list of steps separated by `/`, each selecting child elements:
+///
+/// - `name` selects child elements with name `name`. A name with a prefix such as `ns:tag`
+///   is compared with the full name, otherwise with the name without prefix.
+/// - `*` selects all child elements.
+/// - `//` before a step selects from descendants as well, e.g. `//title` or `book//title`.
+/// - `[n]` keeps the `n`th (1-based) matching child of each parent, e.g. `item[2]`.
+/// - `[@attr]` keeps elements with attribute `attr`, and `[@attr='value']` (or `"value"`)
+///   those where it equals `value`. Attribute names are compared with the full name.
+/// - `[child='value']` keeps elements with a child element `child` whose text content is `value`,
+///   and `[text()='value']` those whose own text content is `value`. `child` is compared like a step name.
+/// - `@attr` or `@*` as the last step selects attribute values, as [`Node::Text`].
+///
+/// Predicates apply in order, so `item[@id][2]` is the second `item` with an `id`.
+/// Text is compared after trimming leading and trailing whitespace, unless evaluated with [`TextMatch::Exact`].
+/// Results are in document order, without duplicates.
+impl Document {
+    /// Evaluate `expr` from the document. A leading `/` is allowed, so the first step
+    /// names the root element.
+    ///
+    /// ```
+    /// use xml_doc::{Document, Node};
+    ///
+    /// let doc = Document::parse_str(r#"<?xml version="1.0"?>
+    /// <root><item id="1"><title>a</title></item><item id="3"><title>b</title></item></root>"#).unwrap();
+    /// let items = doc.evaluate("/root/item[@id='3']").unwrap();
+    /// assert_eq!(items.len(), 1);
+    /// assert_eq!(doc.evaluate("//title").unwrap().len(), 2);
+    /// assert!(matches!(&doc.evaluate("root/item[1]/@id").unwrap()[..], [Node::Text(id)] if id == "1"));
+    /// ```
+    ///
+    /// # Errors
+    /// - [`Error::InvalidQuery`]: `expr` isn't a valid expression of the supported subset.
+    pub fn evaluate(&self, expr: &str) -> Result<Vec<Node>> {
+        self.container().evaluate(self, expr)
+    }
+
+    /// Same as [`Document::evaluate()`], comparing text in predicates as `text_match` says.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidQuery`]: `expr` isn't a valid expression of the supported subset.
+    pub fn evaluate_with(&self, expr: &str, text_match: TextMatch) -> Result<Vec<Node>> {
+        self.container().evaluate_with(self, expr, text_match)
+    }
+}
+
+impl Element {
+    /// Evaluate `expr` in the subtree of this element, as if this element was the document.
+    ///
+    /// Steps select from this element's children, and `//` from its descendants,
+    /// whether or not the expression starts with `/`.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidQuery`]: `expr` isn't a valid expression of the supported subset.
+    pub fn evaluate(&self, doc: &Document, expr: &str) -> Result<Vec<Node>> {
+        self.evaluate_with(doc, expr, TextMatch::Trimmed)
+    }
+
+    /// Same as [`Element::evaluate()`], comparing text in predicates as `text_match` says.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidQuery`]: `expr` isn't a valid expression of the supported subset.
+    pub fn evaluate_with(
+        &self,
+        doc: &Document,
+        expr: &str,
+        text_match: TextMatch,
+    ) -> Result<Vec<Node>> {
+        self.check_valid(doc)?;
+        Ok(Query::parse(expr)?.evaluate(doc, *self, text_match))
+    }
+}
+
+struct Query {
+    steps: Vec<Step>,
+    // `@attr` or `@*` at the end, and whether it follows `//`.
+    attribute: Option<(bool, NameTest)>,
+}
+
+struct Step {
+    // The step follows `//`, so it selects children of descendants as well.
+    descendant: bool,
+    name: NameTest,
+    predicates: Vec<Predicate>,
+}
+
+enum NameTest {
+    Any,
+    Name(String),
+}
+
+enum Predicate {
+    Position(usize),
+    HasAttribute(String),
+    AttributeEq(String, String),
+    ChildText(String, String),
+    TextEq(String),
+}
+
+impl NameTest {
+    fn matches_element(&self, doc: &Document, elem: Element) -> bool {
+        match self {
+            NameTest::Any => true,
+            NameTest::Name(name) if name.contains(':') => elem.full_name_is(doc, name),
+            NameTest::Name(name) => elem.name_is(doc, name),
+        }
+    }
+}
+
+impl Query {
+    fn parse(expr: &str) -> Result<Query> {
+        let mut parser = QueryParser { expr, pos: 0 };
+        let mut steps = Vec::new();
+        parser.skip_whitespace();
+        let mut descendant = parser.eat("//");
+        if !descendant {
+            parser.eat("/");
+        }
+        loop {
+            parser.skip_whitespace();
+            if parser.eat("@") {
+                let name = parser.name_test()?;
+                parser.skip_whitespace();
+                if !parser.at_end() {
+                    return Err(parser.error("Attribute step must be the last step"));
+                }
+                return Ok(Query {
+                    steps,
+                    attribute: Some((descendant, name)),
+                });
+            }
+            let name = parser.name_test()?;
+            let mut predicates = Vec::new();
+            parser.skip_whitespace();
+            while parser.eat("[") {
+                predicates.push(parser.predicate()?);
+                parser.skip_whitespace();
+            }
+            steps.push(Step {
+                descendant,
+                name,
+                predicates,
+            });
+            if parser.at_end() {
+                return Ok(Query {
+                    steps,
+                    attribute: None,
+                });
+            }
+            descendant = parser.eat("//");
+            if !descendant && !parser.eat("/") {
+                return Err(parser.error("Expected '/'"));
+            }
+        }
+    }
+
+    fn evaluate(&self, doc: &Document, context: Element, text_match: TextMatch) -> Vec<Node> {
+        let mut contexts = vec![context];
+        // Contexts may be nested after `//`, so their children need sorting into document order.
+        let mut nested = false;
+        let mut order: Option<HashMap<Element, usize>> = None;
+        for step in &self.steps {
+            if step.descendant {
+                contexts = descendants_or_self(doc, &contexts);
+                nested = true;
+            }
+            let mut selected = Vec::new();
+            for parent in contexts {
+                let mut matched: Vec<Element> = parent
+                    .child_elements_iter(doc)
+                    .filter(|elem| step.name.matches_element(doc, *elem))
+                    .collect();
+                for predicate in &step.predicates {
+                    matched = predicate.apply(doc, matched, text_match);
+                }
+                selected.extend(matched);
+            }
+            if nested && selected.len() > 1 {
+                let order = order.get_or_insert_with(|| {
+                    let container = doc.container();
+                    container.descendants(doc).zip(0..).collect()
+                });
+                selected.sort_by_key(|elem| order.get(elem));
+            }
+            contexts = selected;
+        }
+        match &self.attribute {
+            None => contexts.into_iter().map(Node::Element).collect(),
+            Some((descendant, name)) => {
+                if *descendant {
+                    contexts = descendants_or_self(doc, &contexts);
+                }
+                contexts
+                    .into_iter()
+                    .flat_map(|elem| attribute_values(doc, elem, name))
+                    .map(Node::Text)
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Predicate {
+    fn apply(&self, doc: &Document, elements: Vec<Element>, text_match: TextMatch) -> Vec<Element> {
+        match self {
+            Predicate::Position(position) => match position.checked_sub(1) {
+                Some(index) => elements.get(index).copied().into_iter().collect(),
+                None => Vec::new(),
+            },
+            Predicate::HasAttribute(name) => elements
+                .into_iter()
+                .filter(|elem| elem.attribute(doc, name).is_some())
+                .collect(),
+            Predicate::AttributeEq(name, value) => elements
+                .into_iter()
+                .filter(|elem| elem.attribute(doc, name) == Some(value.as_str()))
+                .collect(),
+            Predicate::ChildText(name, value) => elements
+                .into_iter()
+                .filter(|elem| elem.has_child_text(doc, name, value, text_match))
+                .collect(),
+            Predicate::TextEq(value) => elements
+                .into_iter()
+                .filter(|elem| elem.text_is(doc, value, text_match))
+                .collect(),
+        }
+    }
+}
+
+// `contexts` and their descendants in document order, given `contexts` in document order.
+fn descendants_or_self(doc: &Document, contexts: &[Element]) -> Vec<Element> {
+    let mut seen = HashSet::new();
+    let mut elements = Vec::new();
+    for context in contexts {
+        // A context inside an earlier one was already added with its descendants.
+        if !seen.insert(*context) {
+            continue;
+        }
+        elements.push(*context);
+        for elem in context.descendants(doc) {
+            seen.insert(elem);
+            elements.push(elem);
+        }
+    }
+    elements
+}
+
+// Values of attributes matching `name`, in stored order for `@*`.
+fn attribute_values(doc: &Document, elem: Element, name: &NameTest) -> Vec<Value> {
+    match name {
+        NameTest::Name(name) => elem
+            .attribute(doc, name)
+            .map(Value::from)
+            .into_iter()
+            .collect(),
+        NameTest::Any => elem.attributes(doc).values().cloned().collect(),
+    }
+}
+
+struct QueryParser<'a> {
+    expr: &'a str,
+    pos: usize,
+}
+
+impl<'a> QueryParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.expr[self.pos..]
+    }
+
+    fn at_end(&self) -> bool {
+        self.rest().is_empty()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::InvalidQuery(format!(
+            "{} at position {} in '{}'",
+            message, self.pos, self.expr
+        ))
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| "/[]@=*'\"".contains(c) || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let name = &rest[..len];
+        if !is_name(name) {
+            return Err(self.error("Expected a name"));
+        }
+        self.pos += len;
+        Ok(name.to_string())
+    }
+
+    fn name_test(&mut self) -> Result<NameTest> {
+        match self.eat("*") {
+            true => Ok(NameTest::Any),
+            false => Ok(NameTest::Name(self.name()?)),
+        }
+    }
+
+    // After `[`, until and including `]`.
+    fn predicate(&mut self) -> Result<Predicate> {
+        self.skip_whitespace();
+        let predicate = if self.eat("@") {
+            let name = self.name()?;
+            self.skip_whitespace();
+            if self.eat("=") {
+                self.skip_whitespace();
+                Predicate::AttributeEq(name, self.literal()?)
+            } else {
+                Predicate::HasAttribute(name)
+            }
+        } else if self.eat("text()") {
+            Predicate::TextEq(self.equals_literal()?)
+        } else if !self.rest().starts_with(|c: char| c.is_ascii_digit()) {
+            let name = self.name()?;
+            Predicate::ChildText(name, self.equals_literal()?)
+        } else {
+            let rest = self.rest();
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let position = rest[..len]
+                .parse()
+                .map_err(|_| self.error("Expected a position"))?;
+            self.pos += len;
+            Predicate::Position(position)
+        };
+        self.skip_whitespace();
+        if !self.eat("]") {
+            return Err(self.error("Expected ']'"));
+        }
+        Ok(predicate)
+    }
+
+    // `= 'value'`, with optional whitespace.
+    fn equals_literal(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        if !self.eat("=") {
+            return Err(self.error("Expected '='"));
+        }
+        self.skip_whitespace();
+        self.literal()
+    }
+
+    fn literal(&mut self) -> Result<String> {
+        let quote = match self.rest().chars().next() {
+            Some(quote @ ('\'' | '"')) => quote,
+            _ => return Err(self.error("Expected a quoted value")),
+        };
+        self.pos += 1;
+        match self.rest().find(quote) {
+            Some(len) => {
+                let value = self.rest()[..len].to_string();
+                self.pos += len + 1;
+                Ok(value)
+            }
+            None => Err(self.error("Unterminated value")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_errors() {
+        let doc = Document::parse_str(r#"<?xml version="1.0"?><a b="1"/>"#).unwrap();
+        for expr in [
+            "",
+            "/",
+            "a/",
+            "a//",
+            "a b",
+            "@b/a",
+            "a[",
+            "a[]",
+            "a[x]",
+            "a[@b=1]",
+            "a[@b='1]",
+            "a[1",
+            "1a",
+            "a]",
+            "a/@",
+            "a[b]",
+            "a[b=c]",
+            "a[text()]",
+            "a[text()='1'",
+        ] {
+            assert!(
+                matches!(doc.evaluate(expr), Err(Error::InvalidQuery(_))),
+                "{}",
+                expr
+            );
+        }
+        let err = doc.evaluate("a[@b=1]").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid query: Expected a quoted value at position 5 in 'a[@b=1]'"
+        );
+        for expr in [
+            " a ",
+            "/a",
+            "a[ @b = '1' ]",
+            "a [1]",
+            "//a",
+            "*",
+            "a/@*",
+            "a[b='1']",
+            "a[ text() = \"1\" ]",
+        ] {
+            assert!(doc.evaluate(expr).is_ok(), "{}", expr);
+        }
+    }
+}
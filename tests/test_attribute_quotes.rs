@@ -0,0 +1,122 @@
+use proptest::prelude::*;
+use xml_doc::{Document, Element, QuoteStyle, ReadOptions, WriteOptions};
+
+const NASTY: &[&str] = &["\"", "'", "<", ">", "&", "\n", "\t", "]]>", " ", "a"];
+
+fn nasty_value() -> impl Strategy<Value = String> {
+    prop::collection::vec(prop::sample::select(NASTY), 0..16).prop_map(|parts| parts.concat())
+}
+
+fn quote_style() -> impl Strategy<Value = QuoteStyle> {
+    prop_oneof![
+        Just(QuoteStyle::Double),
+        Just(QuoteStyle::Single),
+        Just(QuoteStyle::Preserve),
+    ]
+}
+
+fn write(doc: &Document, attribute_quotes: QuoteStyle, allow_quote_fallback: bool) -> String {
+    let opts = WriteOptions {
+        attribute_quotes,
+        allow_quote_fallback,
+        write_decl: false,
+        ..WriteOptions::default()
+    };
+    doc.write_str_with_opts(opts).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn prop_attribute_value_roundtrip(
+        value in nasty_value(),
+        style in quote_style(),
+        fallback in any::<bool>(),
+    ) {
+        let mut doc = Document::new();
+        let container = doc.container();
+        Element::build("a")
+            .attribute("v", value.as_str())
+            .namespace_decl("n", value.as_str())
+            .push_to(&mut doc, container);
+        let xml = write(&doc, style, fallback);
+        let parsed = Document::parse_str_with_opts(&xml, ReadOptions {
+            require_decl: false,
+            ..ReadOptions::default()
+        }).unwrap();
+        let a = parsed.root_element().unwrap();
+        prop_assert_eq!(a.attribute(&parsed, "v"), Some(value.as_str()));
+        prop_assert_eq!(a.namespace_decls(&parsed)["n"].as_str(), value.as_str());
+
+        // The quote is only switched if allowed.
+        let (quote, other) = match style {
+            QuoteStyle::Single => ('\'', '"'),
+            _ => ('"', '\''),
+        };
+        let quote = match fallback && value.contains(quote) && !value.contains(other) {
+            true => other,
+            false => quote,
+        };
+        let start = format!(" v={}", quote);
+        prop_assert!(xml.contains(&start));
+    }
+
+    #[test]
+    fn prop_preserved_quotes_roundtrip(value in nasty_value(), fallback in any::<bool>()) {
+        let read_opts = ReadOptions {
+            high_fidelity: true,
+            require_decl: false,
+            ..ReadOptions::default()
+        };
+        let xml = r#"<a s='1' xmlns:n='urn:n' d="2"/>"#;
+        let mut doc = Document::parse_str_with_opts(xml, read_opts.clone()).unwrap();
+        let a = doc.root_element().unwrap();
+        a.set_attribute(&mut doc, "s", value.as_str());
+        a.set_attribute(&mut doc, "d", value.as_str());
+        let xml = write(&doc, QuoteStyle::Preserve, fallback);
+        let parsed = Document::parse_str_with_opts(&xml, read_opts).unwrap();
+        let a = parsed.root_element().unwrap();
+        prop_assert_eq!(a.attribute(&parsed, "s"), Some(value.as_str()));
+        prop_assert_eq!(a.attribute(&parsed, "d"), Some(value.as_str()));
+        prop_assert!(xml.contains(" xmlns:n='urn:n'"));
+        if !fallback {
+            prop_assert!(xml.contains(" s='") && xml.contains(" d=\""));
+        }
+    }
+}
+
+#[test]
+fn test_attribute_quotes() {
+    let xml = r#"<?xml version="1.0"?><a b='x"y' c="x'y" d="both&quot;'" e="&#32;a &#32;b&#10;"/>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    let cases = [
+        (
+            QuoteStyle::Double,
+            false,
+            r#"<a b="x&quot;y" c="x'y" d="both&quot;'" e="&#32;a &#32;b&#10;"/>"#,
+        ),
+        (
+            QuoteStyle::Single,
+            false,
+            r#"<a b='x"y' c='x&apos;y' d='both"&apos;' e='&#32;a &#32;b&#10;'/>"#,
+        ),
+        (
+            QuoteStyle::Double,
+            true,
+            r#"<a b='x"y' c="x'y" d="both&quot;'" e="&#32;a &#32;b&#10;"/>"#,
+        ),
+        (
+            QuoteStyle::Single,
+            true,
+            r#"<a b='x"y' c="x'y" d='both"&apos;' e='&#32;a &#32;b&#10;'/>"#,
+        ),
+    ];
+    for (style, fallback, expected) in cases {
+        assert_eq!(write(&doc, style, fallback), expected);
+    }
+
+    // Without `high_fidelity`, quotes aren't recorded.
+    assert_eq!(
+        write(&doc, QuoteStyle::Preserve, false),
+        write(&doc, QuoteStyle::Double, false)
+    );
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "capi")]
+use std::ffi::CString;
+use std::os::raw::c_char;
+use xml_doc::capi::*;
+use xml_doc::Document;
+
+fn c_str(s: &str) -> CString {
+    CString::new(s).unwrap()
+}
+
+#[test]
+fn test_capi() {
+    unsafe {
+        let doc = xmldoc_parse_file(c_str("tests/documents/doc.xml").as_ptr());
+        assert!(!doc.is_null());
+        let root = xmldoc_root_element(doc);
+        assert_ne!(root, 0);
+
+        let mut buf = [0 as c_char; 64];
+        let len = xmldoc_elem_name(doc, root, buf.as_mut_ptr(), buf.len());
+        assert_eq!(len, "root".len());
+        let name = std::ffi::CStr::from_ptr(buf.as_ptr()).to_str().unwrap();
+        assert_eq!(name, "root");
+        // Buffer too small: only the length is returned.
+        let mut small = [1 as c_char; 4];
+        assert_eq!(xmldoc_elem_name(doc, root, small.as_mut_ptr(), 4), len);
+        assert_eq!(small, [1; 4]);
+
+        let code =
+            xmldoc_elem_set_attribute(doc, root, c_str("id").as_ptr(), c_str("new").as_ptr());
+        assert_eq!(code, XMLDOC_OK);
+        assert_eq!(
+            xmldoc_elem_set_attribute(doc, 9999, c_str("id").as_ptr(), c_str("x").as_ptr()),
+            XMLDOC_ERR_INVALID_ELEMENT
+        );
+        assert_eq!(
+            xmldoc_elem_set_attribute(doc, 0, c_str("id").as_ptr(), c_str("x").as_ptr()),
+            XMLDOC_ERR_INVALID_ELEMENT
+        );
+        assert_eq!(
+            xmldoc_elem_set_attribute(doc, root, std::ptr::null(), c_str("x").as_ptr()),
+            XMLDOC_ERR_NULL
+        );
+        let invalid_utf8 = [0xffu8 as c_char, 0];
+        assert_eq!(
+            xmldoc_elem_set_attribute(doc, root, invalid_utf8.as_ptr(), c_str("x").as_ptr()),
+            XMLDOC_ERR_UTF8
+        );
+
+        let out = std::env::temp_dir().join(format!("xml_doc_capi_{}.xml", std::process::id()));
+        let out_str = c_str(out.to_str().unwrap());
+        assert_eq!(xmldoc_write_file(doc, out_str.as_ptr()), XMLDOC_OK);
+        let written = Document::parse_file(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        let root_elem = written.root_element().unwrap();
+        assert_eq!(root_elem.attribute(&written, "id"), Some("new"));
+
+        assert_eq!(xmldoc_free(doc), XMLDOC_OK);
+    }
+}
+
+#[test]
+fn test_capi_errors() {
+    unsafe {
+        let doc = xmldoc_parse_file(c_str("tests/documents/missing.xml").as_ptr());
+        assert!(doc.is_null());
+        assert_eq!(xmldoc_last_error(), XMLDOC_ERR_IO);
+        let doc = xmldoc_parse_file(c_str("tests/documents/duplicate_prefix.xml").as_ptr());
+        assert!(doc.is_null());
+        assert_eq!(xmldoc_last_error(), XMLDOC_ERR_MALFORMED);
+        assert!(xmldoc_parse_file(std::ptr::null()).is_null());
+        assert_eq!(xmldoc_last_error(), XMLDOC_ERR_NULL);
+    }
+}
+
+#[test]
+fn test_capi_ownership() {
+    unsafe {
+        let doc = xmldoc_parse_file(c_str("tests/documents/nodes.xml").as_ptr());
+        assert!(!doc.is_null());
+        assert_eq!(xmldoc_free(doc), XMLDOC_OK);
+        // Double free
+        assert_eq!(xmldoc_free(doc), XMLDOC_ERR_INVALID_DOCUMENT);
+        // Use after free
+        assert_eq!(xmldoc_root_element(doc), 0);
+        assert_eq!(xmldoc_last_error(), XMLDOC_ERR_INVALID_DOCUMENT);
+        let mut buf = [0 as c_char; 16];
+        assert_eq!(xmldoc_elem_name(doc, 1, buf.as_mut_ptr(), buf.len()), 0);
+        assert_eq!(xmldoc_last_error(), XMLDOC_ERR_INVALID_DOCUMENT);
+
+        // Documents not created by xmldoc_parse_file are rejected.
+        let mut owned = Document::new();
+        assert_eq!(xmldoc_free(&mut owned), XMLDOC_ERR_INVALID_DOCUMENT);
+        assert_eq!(xmldoc_free(std::ptr::null_mut()), XMLDOC_ERR_NULL);
+    }
+}
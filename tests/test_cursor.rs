@@ -0,0 +1,165 @@
+use xml_doc::{Document, Element, Error, Node};
+
+fn names(doc: &Document, elem: Element) -> Vec<String> {
+    elem.children(doc)
+        .iter()
+        .map(|node| match node {
+            Node::Element(elem) => elem.full_name(doc).to_string(),
+            Node::Text(text) => format!("'{}'", text),
+            node => format!("{:?}", node.kind()),
+        })
+        .collect()
+}
+
+#[test]
+fn test_cursor_script() {
+    let xml = r#"<?xml version="1.0"?><list><a/><b/>text<c/><d/><e/></list>"#;
+    let mut doc = Document::parse_str(xml).unwrap();
+    let list = doc.root_element().unwrap();
+    let children = list.child_elements(&doc);
+    let (b, c, e) = (children[1], children[2], children[4]);
+
+    // (child index, operation), as generated against the original document.
+    enum Op {
+        Remove,
+        InsertAfter(&'static str),
+        InsertBefore(&'static str),
+    }
+    let script = [
+        (1, Op::Remove),
+        (3, Op::InsertAfter("x")),
+        (2, Op::Remove),
+        (0, Op::InsertBefore("y")),
+        (5, Op::InsertAfter("z")),
+        (4, Op::Remove),
+        (3, Op::InsertAfter("w")),
+        (6, Op::InsertBefore("end")),
+    ];
+    let mut cursor = list.cursor(&doc);
+    for (index, op) in script {
+        match op {
+            Op::Remove => cursor.remove(index).unwrap(),
+            Op::InsertAfter(name) => {
+                let elem = Element::new(&mut doc, name);
+                cursor.insert_after(index, elem.as_node()).unwrap();
+            }
+            Op::InsertBefore(name) => {
+                let elem = Element::new(&mut doc, name);
+                cursor.insert_before(index, elem.as_node()).unwrap();
+            }
+        }
+    }
+    // Creating elements doesn't modify the document.
+    assert_eq!(cursor.node(1), Some(&b.as_node()));
+    assert_eq!(cursor.index(0), Some(1));
+    assert_eq!(cursor.index(1), None);
+    assert_eq!(cursor.index(3), Some(2));
+    assert_eq!(cursor.index(5), Some(5));
+    assert_eq!(cursor.index(6), None);
+    cursor.commit(&mut doc).unwrap();
+
+    assert_eq!(
+        names(&doc, list),
+        ["y", "a", "c", "x", "w", "e", "z", "end"]
+    );
+    for child in list.child_elements(&doc) {
+        assert_eq!(child.parent(&doc), Some(list));
+    }
+    assert_eq!(b.parent(&doc), None);
+    assert_eq!(e.parent(&doc), Some(list));
+    assert_eq!(c.parent(&doc), Some(list));
+
+    // Naive indexes would have removed other children.
+    let mut doc = Document::parse_str(xml).unwrap();
+    let list = doc.root_element().unwrap();
+    list.remove_child(&mut doc, 1);
+    list.remove_child(&mut doc, 2);
+    assert_eq!(names(&doc, list), ["a", "'text'", "d", "e"]);
+}
+
+#[test]
+fn test_cursor_move() {
+    let xml = r#"<?xml version="1.0"?><list><a/><b/><c/></list>"#;
+    let mut doc = Document::parse_str(xml).unwrap();
+    let list = doc.root_element().unwrap();
+    let a = list.child_elements(&doc)[0];
+    let mut cursor = list.cursor(&doc);
+    cursor.remove(0).unwrap();
+    cursor.insert_after(2, a.as_node()).unwrap();
+    cursor
+        .insert_before(1, Node::Comment("moved a".to_string()))
+        .unwrap();
+    cursor.commit(&mut doc).unwrap();
+    assert_eq!(names(&doc, list), ["Comment", "b", "c", "a"]);
+    assert_eq!(a.parent(&doc), Some(list));
+
+    // An attached element can't be inserted unless the cursor removes it.
+    let b = list.child_elements(&doc)[0];
+    let mut cursor = list.cursor(&doc);
+    cursor.insert_before(0, b.as_node()).unwrap();
+    assert!(matches!(cursor.commit(&mut doc), Err(Error::HasAParent)));
+    assert_eq!(names(&doc, list), ["Comment", "b", "c", "a"]);
+
+    let mut cursor = list.cursor(&doc);
+    let x = Element::new(&mut doc, "x");
+    cursor.insert_before(0, x.as_node()).unwrap();
+    assert!(matches!(
+        cursor.insert_after(0, x.as_node()),
+        Err(Error::HasAParent)
+    ));
+    assert!(matches!(
+        cursor.insert_after(0, list.as_node()),
+        Err(Error::HasAParent)
+    ));
+    assert!(matches!(
+        cursor.insert_after(0, doc.container().as_node()),
+        Err(Error::ContainerCannotMove)
+    ));
+    assert!(matches!(
+        cursor.insert_after(4, x.as_node()),
+        Err(Error::IndexOutOfBounds { index: 4, len: 4 })
+    ));
+    assert!(matches!(
+        cursor.remove(4),
+        Err(Error::IndexOutOfBounds { index: 4, len: 4 })
+    ));
+}
+
+#[test]
+fn test_stale_cursor() {
+    let xml = r#"<?xml version="1.0"?><list><a/><b/></list>"#;
+    let mut doc = Document::parse_str(xml).unwrap();
+    let list = doc.root_element().unwrap();
+    let a = list.child_elements(&doc)[0];
+
+    let mut cursor = list.cursor(&doc);
+    cursor.remove(0).unwrap();
+    list.push_child(&mut doc, Node::Text("out of band".into()))
+        .unwrap();
+    assert!(matches!(cursor.commit(&mut doc), Err(Error::StaleCursor)));
+    assert_eq!(a.parent(&doc), Some(list));
+    assert_eq!(list.children(&doc).len(), 3);
+
+    // Modifying another element
+    let mut cursor = list.cursor(&doc);
+    cursor.remove(0).unwrap();
+    a.set_attribute(&mut doc, "id", "1");
+    assert!(matches!(cursor.commit(&mut doc), Err(Error::StaleCursor)));
+
+    // Committing a cursor makes other cursors stale.
+    let mut first = list.cursor(&doc);
+    let mut second = list.cursor(&doc);
+    first.remove(0).unwrap();
+    second.remove(1).unwrap();
+    first.commit(&mut doc).unwrap();
+    assert!(matches!(second.commit(&mut doc), Err(Error::StaleCursor)));
+    assert_eq!(names(&doc, list), ["b", "'out of band'"]);
+
+    // Reading doesn't
+    let revision = doc.revision();
+    let cursor = list.cursor(&doc);
+    let _ = doc.write_str().unwrap();
+    let _ = list.text_content(&doc);
+    assert_eq!(doc.revision(), revision);
+    cursor.commit(&mut doc).unwrap();
+}
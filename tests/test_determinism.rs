@@ -0,0 +1,47 @@
+#![cfg(feature = "fs")]
+use std::env;
+use std::fs;
+use std::process::Command;
+use xml_doc::Document;
+
+const FIXTURE: &str = "tests/documents/many_attributes.xml";
+// When set, `write_fixture_to_env_path` writes the serialized fixture to this path.
+const OUT_VAR: &str = "XML_DOC_DETERMINISM_OUT";
+
+fn serialize_fixture() -> String {
+    Document::parse_file(FIXTURE).unwrap().write_str().unwrap()
+}
+
+#[test]
+fn test_deterministic_in_process() {
+    let expected = serialize_fixture();
+    assert!(expected.contains(
+        r#"<root xmlns="urn:default" xmlns:a="urn:a" xmlns:b="urn:b" xmlns:c="urn:c" xmlns:d="urn:d" z="26""#
+    ));
+    for _ in 0..50 {
+        // Each parse creates new HashMaps with different random seeds.
+        assert_eq!(serialize_fixture(), expected);
+    }
+}
+
+#[test]
+fn test_deterministic_across_processes() {
+    let out = env::temp_dir().join(format!("xml_doc_determinism_{}.xml", std::process::id()));
+    // Run the test below in a new process, which gets a different HashMap seed.
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["write_fixture_to_env_path", "--exact"])
+        .env(OUT_VAR, &out)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let written = fs::read_to_string(&out).unwrap();
+    fs::remove_file(&out).unwrap();
+    assert_eq!(written, serialize_fixture());
+}
+
+#[test]
+fn write_fixture_to_env_path() {
+    if let Some(path) = env::var_os(OUT_VAR) {
+        fs::write(path, serialize_fixture()).unwrap();
+    }
+}
@@ -1,10 +1,12 @@
+#![cfg(feature = "fs")]
 use itertools::Itertools;
-use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Write;
+use std::fs::File;
+use std::io::BufReader;
 use std::path::Path;
 use std::str::FromStr;
-use xml_doc::{Document, Element, Node, ReadOptions};
+use xml_doc::{Document, Element, IndexMap, Node, ReadOptions};
 
 #[derive(Clone)]
 struct TStr(pub String);
@@ -15,7 +17,7 @@ impl PartialEq<Self> for TStr {
     }
 }
 
-impl<'a> fmt::Debug for TStr {
+impl fmt::Debug for TStr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "\n{}\n", self.0.trim())
     }
@@ -27,7 +29,7 @@ fn to_yaml(doc: &Document) -> String {
     write_line("Root:", depth, &mut buf);
     depth += 1;
     let container = doc.container();
-    render_nodes(doc, container.children(&doc), depth, &mut buf);
+    render_nodes(doc, container.children(doc), depth, &mut buf);
     buf
 }
 
@@ -59,6 +61,9 @@ fn render_nodes(doc: &Document, nodes: &Vec<Node>, depth: usize, buf: &mut Strin
                 depth,
                 buf,
             ),
+            Node::ExternalText(text) => {
+                write_line(&format!("- ExternalText: {} bytes", text.len()), depth, buf)
+            }
             Node::DocType(text) => write_line(
                 &format!(
                     "- DocType: \"{}\"",
@@ -87,32 +92,28 @@ fn render_element(doc: &Document, elem: Element, mut depth: usize, buf: &mut Str
     write_line(&format!("name: {}", name), depth, buf);
 
     let attrs = elem.attributes(doc);
-    if attrs.len() > 0 {
+    if !attrs.is_empty() {
         write_line("attributes:", depth, buf);
-        write_hashmap_alphabetical(attrs, depth, buf);
+        write_map(attrs, depth, buf);
     }
 
     let namespaces = elem.namespace_decls(doc);
-    if namespaces.len() > 0 {
+    if !namespaces.is_empty() {
         write_line("namespaces:", depth, buf);
-        write_hashmap_alphabetical(namespaces, depth, buf);
+        write_map(namespaces, depth, buf);
     }
     let children = elem.children(doc);
-    if children.len() > 0 {
+    if !children.is_empty() {
         write_line("children:", depth, buf);
         depth += 1;
         render_nodes(doc, children, depth, buf);
     }
 }
 
-fn write_hashmap_alphabetical(map: &HashMap<String, String>, depth: usize, buf: &mut String) {
-    let mut entries = Vec::new();
-    for (key, val) in map.iter() {
-        entries.push((key, val))
-    }
-    entries.sort_by_key(|x| x.0);
-    for entry in entries {
-        write_line(&format!("{}: \"{}\"", entry.0, entry.1), depth + 1, buf);
+// Entries are written in order, so the yaml also checks the order of attributes.
+fn write_map<V: std::fmt::Display>(map: &IndexMap<String, V>, depth: usize, buf: &mut String) {
+    for (key, val) in map {
+        write_line(&format!("{}: \"{}\"", key, val), depth + 1, buf);
     }
 }
 
@@ -141,7 +142,7 @@ fn get_expected(file_name: &str) -> TStr {
 // read(write(doc)) should be doc.
 // just a basic test for writing.
 fn test_write(doc: &Document) -> TStr {
-    let expected = TStr(to_yaml(&doc));
+    let expected = TStr(to_yaml(doc));
     let written_xml = doc.write_str().unwrap();
     println!("{:?}", &written_xml);
     let new_doc = Document::from_str(&written_xml).unwrap();
@@ -155,7 +156,42 @@ fn test_write(doc: &Document) -> TStr {
     expected
 }
 
+fn check_text_chunks(doc: &Document) {
+    for elem in doc.container().descendants(doc) {
+        let text = elem.text_content(doc);
+        assert_eq!(elem.text_chunks(doc).collect::<String>(), text);
+        assert!(elem.text_eq(doc, &text));
+    }
+}
+
+// Each fixture has a `_crlf` variant with CRLF line endings, which should parse the same.
 fn test<F, S>(xml_file: &str, expected: F)
+where
+    F: Fn(&ReadOptions) -> S,
+    S: Into<String>,
+{
+    let crlf_file = xml_file.replace(".xml", "_crlf.xml");
+    for xml_file in [xml_file, crlf_file.as_str()] {
+        test_file(xml_file, &expected);
+    }
+}
+
+fn to_result(parsed: xml_doc::Result<Document>) -> TStr {
+    match parsed {
+        Ok(doc) => {
+            check_text_chunks(&doc);
+            TStr(to_yaml(&doc))
+        }
+        Err(error) => {
+            println!("{:?}", error);
+            let debug_str = format!("{:?}", error);
+            let variant_name = debug_str.split('(').next().unwrap();
+            TStr(format!("error: {}", variant_name))
+        }
+    }
+}
+
+fn test_file<F, S>(xml_file: &str, expected: F)
 where
     F: Fn(&ReadOptions) -> S,
     S: Into<String>,
@@ -175,23 +211,36 @@ where
     ];
 
     for k in opts.iter().multi_cartesian_product() {
-        let mut read_options = ReadOptions::default();
-        read_options.empty_text_node = *k[0];
-        read_options.trim_text = *k[1];
-        read_options.ignore_whitespace_only = *k[2];
-        read_options.require_decl = *k[3];
+        let read_options = ReadOptions {
+            empty_text_node: *k[0],
+            trim_text: *k[1],
+            ignore_whitespace_only: *k[2],
+            require_decl: *k[3],
+            ..ReadOptions::default()
+        };
         let expected_name: String = expected(&read_options).into();
         let expected = get_expected(&expected_name);
 
-        let result = match Document::parse_file_with_opts(&xml_file, read_options.clone()) {
-            Ok(doc) => TStr(to_yaml(&doc)),
-            Err(error) => {
-                println!("{:?}", error);
-                let debug_str = format!("{:?}", error);
-                let variant_name = debug_str.splitn(2, "(").next().unwrap();
-                TStr(format!("error: {}", variant_name))
-            }
-        };
+        let result = to_result(Document::parse_file_with_opts(
+            &xml_file,
+            read_options.clone(),
+        ));
+        // A small buffer splits events and characters between reads.
+        for capacity in [7, 8192] {
+            let reader = BufReader::with_capacity(capacity, File::open(&xml_file).unwrap());
+            let bufread_result = to_result(Document::parse_bufread_with_opts(
+                reader,
+                read_options.clone(),
+            ));
+            assert!(
+                result == bufread_result,
+                "\noptions: {:?}, capacity: {}\n===parse_file==={:?}===parse_bufread==={:?}",
+                read_options,
+                capacity,
+                result,
+                bufread_result,
+            );
+        }
 
         assert!(
             expected == result,
@@ -250,3 +299,9 @@ fn encoding1() {
 fn encoding2() {
     test("encoding2.xml", expected_doc_yaml)
 }
+
+// Attributes and namespace declarations keep their source order through parse and write.
+#[test]
+fn attribute_order() {
+    test("attribute_order.xml", |_| "attribute_order_.yaml")
+}
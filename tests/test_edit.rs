@@ -0,0 +1,100 @@
+#![cfg(feature = "fs")]
+use std::fs;
+use std::path::PathBuf;
+use xml_doc::{edit_file, EditOptions, Error};
+
+const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+    <name>app</name>
+</config>
+"#;
+
+fn setup(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("xml-doc-edit-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.xml");
+    fs::write(&path, XML).unwrap();
+    path
+}
+
+#[test]
+fn test_edit_file_unchanged() {
+    let path = setup("unchanged");
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    let opts = EditOptions {
+        backup: true,
+        ..EditOptions::default()
+    };
+    let outcome = edit_file(&path, opts, |doc| {
+        let root = doc.root_element().unwrap();
+        assert_eq!(root.child_text(doc, "name").unwrap(), "app");
+        Ok(())
+    })
+    .unwrap();
+    assert!(!outcome.written);
+    assert_eq!(outcome.bytes_read, XML.len());
+    assert_eq!(outcome.bytes_written, 0);
+    assert_eq!(outcome.backup, None);
+    assert_eq!(fs::read_to_string(&path).unwrap(), XML);
+    assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+    assert!(!path.with_file_name("config.xml.bak").exists());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_edit_file_with_backup() {
+    let path = setup("backup");
+    let opts = EditOptions {
+        backup: true,
+        ..EditOptions::default()
+    };
+    let outcome = edit_file(&path, opts, |doc| {
+        let root = doc.root_element().unwrap();
+        root.set_child_text(doc, "name", "renamed");
+        Ok(())
+    })
+    .unwrap();
+    let backup = path.with_file_name("config.xml.bak");
+    assert!(outcome.written);
+    assert_eq!(outcome.backup.as_ref(), Some(&backup));
+    assert_eq!(fs::read_to_string(&backup).unwrap(), XML);
+    let written = fs::read_to_string(&path).unwrap();
+    assert_eq!(outcome.bytes_written, written.len());
+    assert!(written.contains("<name>renamed</name>"));
+    // Only the file and its backup are left.
+    assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 2);
+
+    // Changing only the declaration is a change too.
+    let outcome = edit_file(&path, EditOptions::default(), |doc| {
+        doc.set_standalone(Some(true));
+        Ok(())
+    })
+    .unwrap();
+    assert!(outcome.written);
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .contains(r#"standalone="yes""#));
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_edit_file_failure() {
+    let path = setup("failure");
+    let opts = EditOptions {
+        backup: true,
+        ..EditOptions::default()
+    };
+    let result = edit_file(&path, opts, |doc| {
+        let root = doc.root_element().unwrap();
+        root.set_child_text(doc, "name", "renamed");
+        Err(Error::MalformedXML("invalid edit".to_string()))
+    });
+    assert!(matches!(result, Err(Error::MalformedXML(_))));
+    assert_eq!(fs::read_to_string(&path).unwrap(), XML);
+    assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+    let missing = path.with_file_name("missing.xml");
+    let result = edit_file(&missing, EditOptions::default(), |_| Ok(()));
+    assert!(matches!(result, Err(Error::Io(_))));
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,272 @@
+use xml_doc::{Document, Element, Error, Node, Parser, ReadOptions};
+
+fn sample() -> (Document, Element) {
+    let doc = Document::parse_str(r#"<?xml version="1.0"?><root><a/><b/></root>"#).unwrap();
+    let root = doc.root_element().unwrap();
+    (doc, root)
+}
+
+#[test]
+fn test_index_out_of_bounds() {
+    let (mut doc, root) = sample();
+
+    let node = Node::Text("text".into());
+    assert!(matches!(
+        root.insert_child(&mut doc, 3, node),
+        Err(Error::IndexOutOfBounds { index: 3, len: 2 })
+    ));
+    let elem = Element::new(&mut doc, "c");
+    assert!(matches!(
+        root.insert_child(&mut doc, 5, elem.as_node()),
+        Err(Error::IndexOutOfBounds { index: 5, len: 2 })
+    ));
+    // Failed insertion doesn't attach the element.
+    assert_eq!(elem.parent(&doc), None);
+    assert!(root.insert_child(&mut doc, 2, elem.as_node()).is_ok());
+
+    assert!(matches!(
+        root.try_remove_child(&mut doc, 3),
+        Err(Error::IndexOutOfBounds { index: 3, len: 3 })
+    ));
+    assert_eq!(
+        root.try_remove_child(&mut doc, 2).unwrap().as_element(),
+        Some(elem)
+    );
+
+    assert!(matches!(
+        root.try_ensure_child_at(&mut doc, "d", 3),
+        Err(Error::IndexOutOfBounds { index: 3, len: 2 })
+    ));
+    assert_eq!(root.find(&doc, "d"), None);
+    // An existing child is returned regardless of index.
+    let a = root.find(&doc, "a").unwrap();
+    assert_eq!(root.try_ensure_child_at(&mut doc, "a", 10).unwrap(), a);
+    let d = root.try_ensure_child_at(&mut doc, "d", 0).unwrap();
+    assert_eq!(root.children(&doc)[0].as_element(), Some(d));
+
+    let comment = Node::Comment("c".to_string());
+    assert!(matches!(
+        doc.insert_root_node(2, comment),
+        Err(Error::IndexOutOfBounds { index: 2, len: 1 })
+    ));
+}
+
+#[test]
+fn test_invalid_names() {
+    let (mut doc, root) = sample();
+
+    assert!(matches!(
+        Element::try_new(&mut doc, "1a"),
+        Err(Error::InvalidName { .. })
+    ));
+    assert!(matches!(
+        Element::build("x").attribute("1", "v").try_finish(&mut doc),
+        Err(Error::InvalidName { .. })
+    ));
+    assert!(matches!(
+        Element::build("x")
+            .namespace_decl("-p", "ns")
+            .try_push_to(&mut doc, root),
+        Err(Error::InvalidName { .. })
+    ));
+    assert_eq!(root.children(&doc).len(), 2);
+}
+
+#[test]
+fn test_rejected() {
+    let (mut doc, root) = sample();
+    doc.set_mutation_validator(Box::new(|_| Err("read only".to_string())));
+
+    assert!(matches!(
+        root.try_set_attribute(&mut doc, "id", "1"),
+        Err(Error::Rejected(_))
+    ));
+    assert!(matches!(
+        root.try_set_text_content(&mut doc, "text"),
+        Err(Error::Rejected(_))
+    ));
+    assert!(matches!(
+        root.try_set_full_name(&mut doc, "other"),
+        Err(Error::Rejected(_))
+    ));
+    assert!(matches!(
+        Element::build("x").try_push_to(&mut doc, root),
+        Err(Error::Rejected(_))
+    ));
+    assert_eq!(root.full_name(&doc), "root");
+    assert_eq!(root.children(&doc).len(), 2);
+}
+
+#[test]
+fn test_try_text_content() {
+    let dir = std::env::temp_dir().join(format!("xml-doc-fallible-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let opts = ReadOptions {
+        max_text_len: Some(4),
+        spill_large_text_to: Some(dir.clone()),
+        ..ReadOptions::default()
+    };
+    let xml = r#"<?xml version="1.0"?><root>ab<large>long text</large></root>"#;
+    let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    let root = doc.root_element().unwrap();
+    let large = root.find(&doc, "large").unwrap();
+    assert_eq!(root.try_text_content(&doc).unwrap(), "ablong text");
+
+    let path = match &large.children(&doc)[0] {
+        Node::ExternalText(text) => text.path().to_path_buf(),
+        node => panic!("Expected ExternalText, got {:?}", node.kind()),
+    };
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(root.try_text_content(&doc), Err(Error::Io(_))));
+    assert!(matches!(
+        large.children(&doc)[0].try_text_content(&doc),
+        Err(Error::Io(_))
+    ));
+    assert!(matches!(
+        root.as_node().try_text_content(&doc),
+        Err(Error::Io(_))
+    ));
+    assert_eq!(root.children(&doc)[0].try_text_content(&doc).unwrap(), "ab");
+    assert!(matches!(large.flatten_to_text(&mut doc), Err(Error::Io(_))));
+    assert_eq!(large.parent(&doc), Some(root));
+    assert_eq!(root.children(&doc).len(), 2);
+
+    drop(doc);
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn test_element_not_found() {
+    let (mut doc, root) = sample();
+    let b = root.child_elements(&doc)[1];
+    let mut other = Document::parse_str(
+        r#"<?xml version="1.0"?><root><a/><b/><c><d/></c><e/><f>text</f></root>"#,
+    )
+    .unwrap();
+    let foreign = other.root_element().unwrap().child_elements(&other)[4];
+    assert!(b.is_valid(&doc));
+    assert!(!foreign.is_valid(&doc));
+    assert!(foreign.is_valid(&other));
+
+    let not_found = |result: Result<(), Error>| matches!(result, Err(Error::ElementNotFound));
+    assert!(not_found(foreign.try_name(&doc).map(|_| ())));
+    assert!(not_found(foreign.try_full_name(&doc).map(|_| ())));
+    assert!(not_found(foreign.try_attribute(&doc, "x").map(|_| ())));
+    assert!(not_found(foreign.try_parent(&doc).map(|_| ())));
+    assert!(not_found(foreign.try_children(&doc).map(|_| ())));
+    assert!(not_found(foreign.try_text_content(&doc).map(|_| ())));
+    assert!(not_found(
+        foreign.as_node().try_text_content(&doc).map(|_| ())
+    ));
+    assert!(not_found(foreign.expect_child(&doc, "a").map(|_| ())));
+    assert!(not_found(foreign.evaluate(&doc, "a").map(|_| ())));
+    assert!(not_found(foreign.try_set_attribute(&mut doc, "x", "1")));
+    assert!(not_found(foreign.detatch(&mut doc)));
+    assert!(not_found(foreign.wrap(&mut doc, "w").map(|_| ())));
+    assert!(not_found(root.push_child(&mut doc, foreign.as_node())));
+    assert!(not_found(root.insert_child(&mut doc, 0, foreign.as_node())));
+    assert!(not_found(
+        root.replace_child(&mut doc, 0, foreign.as_node())
+            .map(|_| ())
+    ));
+    assert!(not_found(b.replace_with(&mut doc, foreign.as_node())));
+    assert!(not_found(b.wrap_with(&mut doc, foreign)));
+    assert!(not_found(b.clone_to(&mut doc, foreign).map(|_| ())));
+    assert!(not_found(doc.push_root_node(foreign.as_node())));
+    assert!(not_found(
+        Element::build("x")
+            .try_push_to(&mut doc, foreign)
+            .map(|_| ())
+    ));
+    assert!(not_found(
+        Element::build("x")
+            .node(foreign.as_node())
+            .try_finish(&mut doc)
+            .map(|_| ())
+    ));
+    let mut cursor = root.cursor(&doc);
+    cursor.insert_before(0, foreign.as_node()).unwrap();
+    assert!(not_found(cursor.commit(&mut doc)));
+    assert_eq!(root.child_elements(&doc).len(), 2);
+
+    // Elements of replaced content
+    let f = foreign;
+    Parser::new(ReadOptions::default())
+        .parse_into(&mut other, r#"<?xml version="1.0"?><root/>"#)
+        .unwrap();
+    assert!(!f.is_valid(&other));
+    assert!(not_found(f.try_name(&other).map(|_| ())));
+}
+
+#[test]
+fn test_stale_element_after_parse_into() {
+    let mut parser = Parser::new(ReadOptions::default());
+    let xml = r#"<?xml version="1.0"?><root a="1"><b>text</b></root>"#;
+    let mut doc = parser.parse_str(xml).unwrap();
+    let old_root = doc.root_element().unwrap();
+    let old_b = old_root.child_elements(&doc)[0];
+    let old_container = doc.container();
+    // Parse twice, so the document is parsed into storage it had before.
+    let cursor = old_root.cursor(&doc);
+    parser.parse_into(&mut doc, xml).unwrap();
+    assert!(matches!(cursor.commit(&mut doc), Err(Error::StaleCursor)));
+    let cursor = doc.root_element().unwrap().cursor(&doc);
+
+    // Same shape, so the new elements have the same ids.
+    parser.parse_into(&mut doc, xml).unwrap();
+    assert!(matches!(cursor.commit(&mut doc), Err(Error::StaleCursor)));
+    let root = doc.root_element().unwrap();
+    assert_ne!(root, old_root);
+    assert!(root.is_valid(&doc));
+    assert!(doc.container().is_valid(&doc));
+    for stale in [old_root, old_b, old_container] {
+        assert!(!stale.is_valid(&doc));
+    }
+
+    let not_found = |result: Result<(), Error>| matches!(result, Err(Error::ElementNotFound));
+    assert!(not_found(old_root.try_name(&doc).map(|_| ())));
+    assert!(not_found(old_root.try_attribute(&doc, "a").map(|_| ())));
+    assert!(not_found(old_b.try_text_content(&doc).map(|_| ())));
+    assert!(not_found(old_b.expect_text(&doc).map(|_| ())));
+    assert!(not_found(old_root.expect_attribute(&doc, "a").map(|_| ())));
+    assert!(not_found(old_root.evaluate(&doc, "b").map(|_| ())));
+    assert!(not_found(old_b.detatch(&mut doc)));
+    assert!(not_found(root.push_child(&mut doc, old_b.as_node())));
+    let wrapper = Element::new(&mut doc, "w");
+    assert!(not_found(old_root.wrap_children(&mut doc, 0..1, wrapper)));
+    assert!(not_found(root.wrap_children(&mut doc, 0..1, old_b)));
+    assert_eq!(
+        doc.write_str().unwrap(),
+        parser.parse_str(xml).unwrap().write_str().unwrap()
+    );
+
+    // Methods without a `Result` panic instead of reading the new elements.
+    let panics = |f: &dyn Fn()| std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).is_err();
+    assert!(panics(&|| {
+        old_b.name(&doc);
+    }));
+    assert!(panics(&|| {
+        old_b.path(&doc);
+    }));
+    assert!(panics(&|| {
+        old_root.cursor(&doc);
+    }));
+    assert!(panics(&|| {
+        old_root.content_hash(&doc);
+    }));
+    assert!(panics(&|| {
+        Document::new().import_element(&doc, old_b);
+    }));
+}
+
+#[test]
+fn test_document_auto_traits() {
+    fn assert_traits<T: Send + Sync + std::panic::UnwindSafe + std::panic::RefUnwindSafe>() {}
+    assert_traits::<Document>();
+    assert_traits::<Element>();
+
+    let mut doc = Document::parse_str(r#"<?xml version="1.0"?><root/>"#).unwrap();
+    doc.set_mutation_validator(Box::new(|_| Ok(())));
+    let result = std::panic::catch_unwind(|| doc.root_element().unwrap().name(&doc).len());
+    assert_eq!(result.unwrap(), 4);
+}
@@ -0,0 +1,123 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use xml_doc::{Document, Element, Node};
+
+fn children(doc: &Document) -> Vec<Element> {
+    doc.root_element().unwrap().child_elements(doc)
+}
+
+#[test]
+fn test_content_hash_prefixes() {
+    let xml = r#"<?xml version="1.0"?>
+<root xmlns:p="urn:addr" xmlns:x="urn:extra">
+  <p:address p:id="1" kind="home" x:checked="yes"><p:city>Paris</p:city><!-- note --><?pi data?></p:address>
+  <address xmlns="urn:addr" xmlns:q="urn:addr" xmlns:y="urn:extra" y:checked="yes" kind="home" q:id="1"><city>Par<![CDATA[is]]></city><?pi data?></address>
+  <p:address id="1" kind="home" x:checked="yes"><p:city>Paris</p:city><?pi data?></p:address>
+  <p:address p:id="1" kind="home" x:checked="yes"><p:city>Paris</p:city></p:address>
+  <p:address p:id="1" kind="home" x:checked="yes"><p:town>Paris</p:town><?pi data?></p:address>
+</root>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    let elems = children(&doc);
+    let hashes: Vec<u64> = elems.iter().map(|e| e.content_hash(&doc)).collect();
+    assert_eq!(hashes[0], hashes[1]);
+    // `id` without prefix has no namespace.
+    assert_ne!(hashes[0], hashes[2]);
+    // Processing instructions are compared.
+    assert_ne!(hashes[0], hashes[3]);
+    assert_ne!(hashes[0], hashes[4]);
+
+    // With a seeded hasher.
+    let seed = RandomState::new();
+    let hash_with = |elem: Element| {
+        let mut state = seed.build_hasher();
+        elem.content_hash_with(&doc, &mut state);
+        state.finish()
+    };
+    assert_eq!(hash_with(elems[0]), hash_with(elems[1]));
+    assert_ne!(hash_with(elems[0]), hash_with(elems[2]));
+}
+
+#[test]
+fn test_content_hash_changes() {
+    let xml = r#"<?xml version="1.0"?>
+<root><a x="1" y="2"><b>text</b></a><a y="2" x="1"><b>text</b></a></root>"#;
+    let mut doc = Document::parse_str(xml).unwrap();
+    let (a, b) = (children(&doc)[0], children(&doc)[1]);
+    let hash = a.content_hash(&doc);
+    assert_eq!(hash, b.content_hash(&doc));
+
+    b.set_attribute(&mut doc, "y", "3");
+    assert_ne!(hash, b.content_hash(&doc));
+    b.set_attribute(&mut doc, "y", "2");
+    assert_eq!(hash, b.content_hash(&doc));
+    b.set_attribute(&mut doc, "z", "");
+    assert_ne!(hash, b.content_hash(&doc));
+    b.remove_attribute(&mut doc, "z");
+
+    // Text split in several nodes, and empty text
+    let inner = b.child_elements(&doc)[0];
+    inner.append_text(&mut doc, "");
+    assert_eq!(hash, b.content_hash(&doc));
+    inner.set_text_content(&mut doc, "te");
+    inner
+        .push_child(&mut doc, Node::CData("xt".into()))
+        .unwrap();
+    assert_eq!(hash, b.content_hash(&doc));
+    inner.append_text(&mut doc, " ");
+    assert_ne!(hash, b.content_hash(&doc));
+}
+
+#[test]
+fn test_find_duplicate_subtrees() {
+    // Addresses are distinct, except for planted copies.
+    let address = |i: usize, prefix: &str| {
+        format!(
+            r#"<{p}address id="{i}"><{p}street>{i} Main St</{p}street><{p}city>City {c}</{p}city><{p}zip>{z:05}</{p}zip></{p}address>"#,
+            p = prefix,
+            i = i,
+            c = i % 7,
+            z = i * 37 % 100_000,
+        )
+    };
+    let mut records = Vec::new();
+    let mut planted = Vec::new();
+    for i in 0..1000 {
+        records.push(address(i, ""));
+        // Some are copied later with another prefix, or twice.
+        if i % 97 == 13 {
+            planted.push((i, records.len() - 1));
+        }
+    }
+    let mut expected_positions = Vec::new();
+    for (n, (i, pos)) in planted.iter().enumerate() {
+        let mut positions = vec![*pos];
+        let copies = if n % 2 == 0 { 1 } else { 2 };
+        for c in 0..copies {
+            let prefix = if c == 0 { "a:" } else { "" };
+            records.push(address(*i, prefix));
+            positions.push(records.len() - 1);
+        }
+        expected_positions.push(positions);
+    }
+    let xml = format!(
+        r#"<?xml version="1.0"?><book xmlns="urn:addr" xmlns:a="urn:addr">{}</book>"#,
+        records.concat()
+    );
+    let doc = Document::parse_str(&xml).unwrap();
+    let elems = children(&doc);
+    let expected: Vec<Vec<Element>> = expected_positions
+        .iter()
+        .map(|positions| positions.iter().map(|pos| elems[*pos]).collect())
+        .collect();
+    assert_eq!(doc.find_duplicate_subtrees(7), expected);
+
+    // Cities are repeated, but are smaller than `min_size`.
+    let groups = doc.find_duplicate_subtrees(3);
+    assert_eq!(groups.len(), expected.len());
+    let groups = doc.find_duplicate_subtrees(2);
+    assert!(groups.len() > expected.len());
+    assert!(groups
+        .iter()
+        .all(|group| group[0].name(&doc) == "city" || group[0].name(&doc) == "address"));
+    assert!(doc.find_duplicate_subtrees(8).is_empty());
+}
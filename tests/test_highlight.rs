@@ -0,0 +1,68 @@
+use xml_doc::{Document, Theme, TokenClass};
+
+struct MarkerTheme;
+
+impl Theme for MarkerTheme {
+    fn prefix(&self, class: TokenClass) -> &str {
+        match class {
+            TokenClass::ElementName => "[EN]",
+            TokenClass::AttrName => "[AN]",
+            TokenClass::AttrValue => "[AV]",
+            TokenClass::Text => "[T]",
+            TokenClass::Comment => "[C]",
+        }
+    }
+    fn suffix(&self, class: TokenClass) -> &str {
+        match class {
+            TokenClass::ElementName => "[/EN]",
+            TokenClass::AttrName => "[/AN]",
+            TokenClass::AttrValue => "[/AV]",
+            TokenClass::Text => "[/T]",
+            TokenClass::Comment => "[/C]",
+        }
+    }
+}
+
+#[test]
+fn test_marker_theme() {
+    let xml = r#"<?xml version="1.0"?>
+<a:item xmlns:a="urn:a" id="1"><!--note--><name>x &amp; y</name><empty/></a:item>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    let mut out = Vec::new();
+    doc.root_element()
+        .unwrap()
+        .write_highlighted(&doc, &mut out, &MarkerTheme)
+        .unwrap();
+    let expected = r#"<[EN]a:item[/EN] [AN]xmlns:a[/AN]=[AV]"urn:a"[/AV] [AN]id[/AN]=[AV]"1"[/AV]>
+  [C]<!--note-->[/C]
+  <[EN]name[/EN]>[T]x &amp; y[/T]</[EN]name[/EN]>
+  <[EN]empty[/EN]/>
+</[EN]a:item[/EN]>"#;
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_null_theme() {
+    use xml_doc::{NullTheme, WriteOptions};
+
+    for file in ["doc.xml", "nodes.xml", "many_attributes.xml"] {
+        let doc = Document::parse_file(format!("tests/documents/{}", file)).unwrap();
+        let root = doc.root_element().unwrap();
+        let mut out = Vec::new();
+        root.write_highlighted(&doc, &mut out, &NullTheme).unwrap();
+
+        // Write a document with only the root element
+        let mut root_doc = Document::parse_file(format!("tests/documents/{}", file)).unwrap();
+        let container = root_doc.container();
+        let nodes = container.clear_children(&mut root_doc);
+        let root = nodes.iter().find_map(|n| n.as_element()).unwrap();
+        root_doc.push_root_node(root.as_node()).unwrap();
+        let opts = WriteOptions {
+            write_decl: false,
+            ..WriteOptions::default()
+        };
+        let expected = root_doc.write_str_with_opts(opts).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), expected, "{}", file);
+    }
+}
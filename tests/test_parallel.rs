@@ -0,0 +1,43 @@
+#![cfg(feature = "rayon")]
+use std::path::PathBuf;
+use xml_doc::{parse_files_parallel, Document, Error, ReadOptions};
+
+#[test]
+fn test_parse_files_parallel() {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir("tests/documents")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("xml".as_ref()))
+        .collect();
+    paths.sort();
+    paths.push(PathBuf::from("tests/documents/missing.xml"));
+    let opts = ReadOptions::default();
+
+    let results = parse_files_parallel(&paths, &opts);
+    assert_eq!(results.len(), paths.len());
+    let mut failed = Vec::new();
+    for ((path, result), expected_path) in results.iter().zip(&paths) {
+        assert_eq!(path, expected_path);
+        let sequential = Document::parse_file_with_opts(path, opts.clone());
+        match (result, sequential) {
+            (Ok(doc), Ok(expected)) => assert!(doc.content_eq(&expected), "{:?}", path),
+            (Err(err), Err(expected)) => {
+                assert_eq!(err.to_string(), expected.to_string());
+                failed.push(path.file_name().unwrap().to_str().unwrap());
+            }
+            (result, sequential) => panic!("{:?}: {:?} != {:?}", path, result, sequential),
+        }
+    }
+    // Malformed files don't affect the files after them.
+    assert_eq!(
+        failed,
+        vec![
+            "duplicate_prefix.xml",
+            "fragment.xml",
+            "misdeclared.xml",
+            "undefined_entity.xml",
+            "missing.xml"
+        ]
+    );
+    assert!(matches!(results.last().unwrap().1, Err(Error::Io(_))));
+}
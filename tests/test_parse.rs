@@ -1,4 +1,8 @@
-use xml_doc::{Document, Error, Node, ReadOptions};
+use std::io::BufReader;
+use xml_doc::{
+    parse_dtd_entities, Document, Element, ElementAction, ElementWriteStyle, Error, MutationEvent,
+    Node, NodeKind, Parser, ReadOptions, UndefinedEntityPolicy, WhitespaceMode, WriteOptions,
+};
 
 #[test]
 fn test_normalize_attr() {
@@ -19,8 +23,10 @@ fn test_normalize_attr() {
 fn test_closing_tag_mismatch_err() {
     // no closing tag
     let xml = "<img>";
-    let mut opts = ReadOptions::default();
-    opts.require_decl = false;
+    let opts = ReadOptions {
+        require_decl: false,
+        ..ReadOptions::default()
+    };
     let doc = Document::parse_str_with_opts(xml, opts.clone());
     assert!(matches!(doc.unwrap_err(), Error::MalformedXML(_)));
 
@@ -42,8 +48,10 @@ fn test_unescape() {
     <!-- <&amp; cmt -->
     <!DOCTYPE &amp;>
     <?<&amp;?>"#;
-    let mut opts = ReadOptions::default();
-    opts.require_decl = false;
+    let opts = ReadOptions {
+        require_decl: false,
+        ..ReadOptions::default()
+    };
     let doc = Document::parse_str_with_opts(xml, opts).unwrap();
 
     let abc = doc.root_element().unwrap();
@@ -60,17 +68,1248 @@ fn test_unescape() {
     if let Node::Comment(cmt) = comment {
         assert_eq!(cmt, " <&amp; cmt ");
     } else {
-        assert!(false);
+        unreachable!();
     }
 
     let doctype = &doc.root_nodes()[3];
     if let Node::DocType(doc) = doctype {
         assert_eq!(doc, "&");
     } else {
-        assert!(false);
+        unreachable!();
     }
 
     let pi = &doc.root_nodes()[4];
     assert!(matches!(pi, Node::PI(_)));
     assert_eq!(pi.text_content(&doc), "<&amp;");
 }
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_misdeclared_encoding_err() {
+    // Declares UTF-8, but 'é' is encoded in ISO-8859-1.
+    let err = Document::parse_file("tests/documents/misdeclared.xml").unwrap_err();
+    match &err {
+        Error::CannotDecode {
+            context,
+            position,
+            byte,
+            declared_encoding,
+        } => {
+            assert_eq!(*context, "text");
+            assert_eq!(*position, Some(57));
+            assert_eq!(*byte, Some(0xe9));
+            assert_eq!(declared_encoding.as_deref(), Some("UTF-8"));
+        }
+        _ => panic!("unexpected error: {:?}", err),
+    }
+    let msg = err.to_string();
+    assert!(msg.contains("at byte 57"), "{}", msg);
+    assert!(msg.contains("'UTF-8'"), "{}", msg);
+}
+
+#[test]
+fn test_undeclared_encoding_err() {
+    let err = Document::parse_bytes(b"<?xml version=\"1.0\"?><a>caf\xe9</a>").unwrap_err();
+    assert!(matches!(
+        err,
+        Error::CannotDecode {
+            declared_encoding: None,
+            ..
+        }
+    ));
+    assert!(!err.to_string().contains("declares"), "{}", err);
+    // Errors that aren't from parsing don't claim anything about the declaration.
+    let opts = ReadOptions {
+        encoding: Some("no-such-encoding".to_string()),
+        ..ReadOptions::default()
+    };
+    let err = Document::parse_str_with_opts("<a/>", opts).unwrap_err();
+    assert_eq!(err.to_string(), "Cannot decode encoding label");
+}
+
+#[test]
+fn test_decl_standalone() {
+    let doc = Document::parse_str(r#"<?xml version="1.0"?><a/>"#).unwrap();
+    assert_eq!(doc.standalone(), None);
+    assert_eq!(
+        doc.write_str().unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a/>"
+    );
+
+    let doc = Document::parse_str(r#"<?xml version="1.0" standalone="no"?><a/>"#).unwrap();
+    assert_eq!(doc.standalone(), Some(false));
+    assert_eq!(
+        doc.write_str().unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n<a/>"
+    );
+
+    let doc = Document::parse_str(r#"<?xml version="1.0" standalone="yes"?><a/>"#).unwrap();
+    assert_eq!(doc.standalone(), Some(true));
+}
+
+#[test]
+fn test_decl_unknown_pseudo_attr() {
+    let xml = r#"<?xml version="1.0" generator="tool" standalone="yes"?><a/>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    assert_eq!(doc.standalone(), Some(true));
+    assert!(doc.diagnostics().is_empty());
+    assert_eq!(doc.root_element().unwrap().name(&doc), "a");
+
+    let opts = ReadOptions {
+        lenient: true,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    assert_eq!(doc.standalone(), Some(true));
+    assert_eq!(doc.diagnostics().len(), 1);
+    assert!(doc.diagnostics()[0].message.contains("generator"));
+    assert_eq!(doc.root_element().unwrap().name(&doc), "a");
+}
+
+#[test]
+fn test_validate_names() {
+    let mut opts = ReadOptions {
+        require_decl: false,
+        ..ReadOptions::default()
+    };
+    // no-break space in element name, attribute name starting with a digit, PI target starting with a digit
+    let invalid = ["<a\u{a0}b/>", "<a 1b=\"x\"/>", "<?1pi x?><a/>"];
+    for xml in invalid.iter() {
+        assert!(Document::parse_str_with_opts(xml, opts.clone()).is_ok());
+    }
+    opts.validate_names = true;
+    let err = Document::parse_str_with_opts(invalid[0], opts.clone()).unwrap_err();
+    match err {
+        Error::InvalidName { name, position } => {
+            assert_eq!(name, "a\u{a0}b");
+            assert_eq!(position, Some(0));
+        }
+        err => panic!("unexpected error: {:?}", err),
+    }
+    for xml in invalid[1..].iter() {
+        let err = Document::parse_str_with_opts(xml, opts.clone()).unwrap_err();
+        assert!(matches!(err, Error::InvalidName { .. }));
+    }
+    let doc = Document::parse_str_with_opts("<ns:a-1 xml:lang='en'><?pi x?></ns:a-1>", opts);
+    assert!(doc.is_ok());
+
+    let mut doc = Document::new();
+    assert!(Element::try_new(&mut doc, "1a").is_err());
+    assert!(Element::try_new(&mut doc, "a b").is_err());
+    assert!(Element::try_new(&mut doc, "ns:item").is_ok());
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_undefined_entities() {
+    let err = Document::parse_file("tests/documents/undefined_entity.xml").unwrap_err();
+    assert!(matches!(err, Error::MalformedXML(msg) if msg.contains("&series;")));
+
+    let opts = ReadOptions {
+        undefined_entities: UndefinedEntityPolicy::KeepReference,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_file_with_opts("tests/documents/undefined_entity.xml", opts).unwrap();
+    let book = doc.root_element().unwrap();
+    assert_eq!(book.attribute(&doc, "title"), Some("&series; vol. 1"));
+    let intro = book.find(&doc, "intro").unwrap();
+    assert_eq!(intro.text_content(&doc), "Tom & Jerry");
+    let body = book.find(&doc, "body").unwrap();
+    assert_eq!(body.text_content(&doc), "Start &chapter1; end");
+    let diagnostics = doc.diagnostics();
+    assert_eq!(diagnostics.len(), 2);
+    assert!(diagnostics[0].message.contains("&series;"));
+    assert!(diagnostics[1].message.contains("&chapter1;"));
+    assert!(diagnostics[0].position < diagnostics[1].position);
+    // Kept reference is escaped when written
+    let written = doc.write_str().unwrap();
+    assert!(written.contains("Start &amp;chapter1; end"));
+
+    let opts = ReadOptions {
+        undefined_entities: UndefinedEntityPolicy::Drop,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_file_with_opts("tests/documents/undefined_entity.xml", opts).unwrap();
+    let book = doc.root_element().unwrap();
+    assert_eq!(book.attribute(&doc, "title"), Some(" vol. 1"));
+    let body = book.find(&doc, "body").unwrap();
+    assert_eq!(body.text_content(&doc), "Start  end");
+    assert_eq!(doc.diagnostics().len(), 2);
+}
+
+#[test]
+fn test_element_filter() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<files>
+  <file><name>a.png</name><binaryBlob encoding="base64">iVBO<chunk>Rw0K</chunk>Ggo=</binaryBlob></file>
+  <wrapper><file><name>b.png</name><binaryBlob/></file><!--wrapped--></wrapper>
+  <file><name>c.txt</name><binaryBlob encoding="plain">text</binaryBlob></file>
+</files>"#;
+    let mut seen = Vec::new();
+    let doc = Document::parse_reader_with_filter(
+        xml.as_bytes(),
+        ReadOptions::default(),
+        |name, attrs| {
+            seen.push(name.to_string());
+            match name {
+                "binaryBlob" if attrs.get("encoding").map(|e| e.as_str()) != Some("plain") => {
+                    ElementAction::SkipSubtree
+                }
+                "wrapper" => ElementAction::SkipElementKeepChildren,
+                _ => ElementAction::Keep,
+            }
+        },
+    )
+    .unwrap();
+    // Elements inside skipped subtrees are not passed to the filter.
+    assert!(!seen.iter().any(|name| name == "chunk"));
+
+    let files = doc.root_element().unwrap();
+    let children: Vec<Element> = files.child_elements(&doc);
+    assert_eq!(children.len(), 3);
+    assert_eq!(files.children(&doc).len(), 4); // comment in wrapper
+    assert!(matches!(&files.children(&doc)[2], Node::Comment(c) if c == "wrapped"));
+    for file in children.iter() {
+        assert_eq!(file.name(&doc), "file");
+        assert_eq!(file.parent(&doc), Some(files));
+    }
+    assert_eq!(children[0].child_element_count(&doc), 1);
+    assert_eq!(
+        children[0]
+            .first_child_element(&doc)
+            .unwrap()
+            .text_content(&doc),
+        "a.png"
+    );
+    assert_eq!(children[1].child_element_count(&doc), 1);
+    assert_eq!(children[2].child_element_count(&doc), 2);
+    assert_eq!(files.descendants(&doc).count(), 7);
+
+    // Skipped root element
+    let doc = Document::parse_reader_with_filter(xml.as_bytes(), ReadOptions::default(), |_, _| {
+        ElementAction::SkipSubtree
+    })
+    .unwrap();
+    assert!(doc.root_element().is_none());
+
+    let unclosed = r#"<?xml version="1.0"?><root><skip><a></a>"#;
+    let res = Document::parse_reader_with_filter(
+        unclosed.as_bytes(),
+        ReadOptions::default(),
+        |name, _| match name {
+            "skip" => ElementAction::SkipSubtree,
+            _ => ElementAction::Keep,
+        },
+    );
+    assert!(res.is_err());
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_duplicate_namespace_decl() {
+    let err = Document::parse_file("tests/documents/duplicate_prefix.xml").unwrap_err();
+    assert!(
+        matches!(err, Error::MalformedXML(msg) if msg == "Namespace prefix 'p' is declared twice on element 'e'")
+    );
+
+    let opts = ReadOptions {
+        lenient: true,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_file_with_opts("tests/documents/duplicate_prefix.xml", opts.clone())
+        .unwrap();
+    let e = doc.root_element().unwrap().find(&doc, "e").unwrap();
+    assert_eq!(e.namespace_decls(&doc).len(), 1);
+    assert_eq!(e.namespace_for_prefix(&doc, "p"), Some("urn:b"));
+    assert_eq!(doc.diagnostics().len(), 1);
+    assert!(doc.diagnostics()[0].message.contains("'p'"));
+
+    let xml = r#"<?xml version="1.0"?><e a="1" xmlns="x" a="2" xmlns="y"/>"#;
+    assert!(Document::parse_str(xml).is_err());
+    let doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    let e = doc.root_element().unwrap();
+    assert_eq!(e.attribute(&doc, "a"), Some("2"));
+    assert_eq!(e.namespace_for_prefix(&doc, ""), Some("y"));
+    assert_eq!(doc.diagnostics().len(), 2);
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_children_of_kind() {
+    let doc = Document::parse_file("tests/documents/nodes.xml").unwrap();
+    let container = doc.container();
+    for (kind, count) in [
+        (NodeKind::Element, 1),
+        (NodeKind::Text, 1),
+        (NodeKind::Comment, 1),
+        (NodeKind::CData, 1),
+        (NodeKind::PI, 1),
+        (NodeKind::DocType, 1),
+        (NodeKind::ExternalText, 0),
+    ] {
+        let nodes: Vec<&Node> = container.children_of_kind(&doc, kind).collect();
+        assert_eq!(nodes.len(), count, "{:?}", kind);
+        assert!(nodes.iter().all(|node| node.kind() == kind));
+    }
+    assert_eq!(container.child_comments(&doc), vec![" Comment "]);
+    assert_eq!(container.first_comment(&doc), Some(" Comment "));
+    assert_eq!(
+        container.child_cdata_sections(&doc),
+        vec!["<tag>text</tag>"]
+    );
+    assert_eq!(container.child_text_nodes(&doc), vec!["Text"]);
+
+    let el = doc.root_element().unwrap();
+    assert_eq!(el.children_of_kind(&doc, NodeKind::Text).count(), 0);
+    assert!(el.child_comments(&doc).is_empty());
+    assert_eq!(el.first_comment(&doc), None);
+}
+
+#[test]
+fn test_max_text_len() {
+    let text = "a&amp;b".repeat(100);
+    let xml = format!("<?xml version=\"1.0\"?><root>{}</root>", text);
+    let opts = ReadOptions {
+        max_text_len: Some(100),
+        ..ReadOptions::default()
+    };
+    let err = Document::parse_str_with_opts(&xml, opts).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::LimitExceeded {
+            limit: "max_text_len",
+            max: 100,
+            position: 27,
+        }
+    ));
+    let opts = ReadOptions {
+        max_text_len: Some(700),
+        ..ReadOptions::default()
+    };
+    assert!(Document::parse_str_with_opts(&xml, opts).is_ok());
+
+    // Only text and CDATA sections are limited, and "\r\n" counts as one byte.
+    let long = "x>]]".repeat(50);
+    let xml = format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE root [<!ENTITY e \"{3}\">]><root a=\"{0}\">\
+         <!--{0}--><?pi {3}?><![CDATA[{1}]]>{2}</root>",
+        long,
+        "c".repeat(100),
+        "t\r\n".repeat(49) + "tt",
+        "y".repeat(200)
+    );
+    let opts = ReadOptions {
+        max_text_len: Some(100),
+        ..ReadOptions::default()
+    };
+    assert_same_as_reader(xml.as_bytes(), opts.clone());
+    let doc = Document::parse_str_with_opts(&xml, opts.clone()).unwrap();
+    assert_eq!(doc.root_element().unwrap().text_content(&doc).len(), 200);
+    let xml = xml.replace("c]]>", "cc]]>");
+    assert!(matches!(
+        Document::parse_str_with_opts(&xml, opts),
+        Err(Error::LimitExceeded { max: 100, .. })
+    ));
+}
+
+// Text that never ends, to check that parsing stops at `max_text_len`.
+struct EndlessText {
+    start: &'static [u8],
+    read: usize,
+}
+
+impl std::io::Read for EndlessText {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        for byte in buf.iter_mut() {
+            *byte = *self.start.get(self.read).unwrap_or(&b'a');
+            self.read += 1;
+        }
+        Ok(buf.len())
+    }
+}
+
+#[test]
+fn test_max_text_len_stops_reading() {
+    let opts = ReadOptions {
+        max_text_len: Some(1000),
+        ..ReadOptions::default()
+    };
+    for start in [
+        &b"<?xml version=\"1.0\"?><root>"[..],
+        b"<?xml version=\"1.0\"?><root><![CDATA[",
+        b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><root>",
+    ] {
+        let mut reader = EndlessText { start, read: 0 };
+        let err = Document::parse_reader_with_opts(&mut reader, opts.clone()).unwrap_err();
+        assert!(
+            matches!(err, Error::LimitExceeded { max: 1000, .. }),
+            "{:?}",
+            err
+        );
+        assert!(reader.read < 100_000, "read {} bytes", reader.read);
+    }
+}
+
+#[test]
+fn test_import_element() {
+    let dir = std::env::temp_dir().join(format!("xml-doc-import-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let opts = ReadOptions {
+        max_text_len: Some(16),
+        spill_large_text_to: Some(dir.clone()),
+        ..ReadOptions::default()
+    };
+    let xml = r#"<?xml version="1.0"?>
+<root xmlns:a="urn:a" xmlns="urn:d">
+  <a:item a:id="1" xmlns:b="urn:b">text<b:child/><!-- comment --><![CDATA[<cdata>]]><?pi data?><large>a rather long text</large><empty></empty></a:item>
+</root>"#;
+    let mut source = Document::parse_str_with_opts(xml, opts).unwrap();
+    let item = source.root_element().unwrap().child_elements(&source)[0];
+    item.push_child(&mut source, Node::DocType("doctype".to_string()))
+        .unwrap();
+
+    // Into an empty document
+    let mut doc = Document::new();
+    let copy = doc.import_element(&source, item);
+    assert_eq!(copy.parent(&doc), None);
+    assert_eq!(copy.full_name(&doc), "a:item");
+    assert_eq!(copy.attributes(&doc), item.attributes(&source));
+    assert_eq!(copy.namespace_decls(&doc), item.namespace_decls(&source));
+    assert_eq!(copy.namespace(&doc), None);
+    let kinds: Vec<NodeKind> = copy.children(&doc).iter().map(Node::kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            NodeKind::Text,
+            NodeKind::Element,
+            NodeKind::Comment,
+            NodeKind::CData,
+            NodeKind::PI,
+            NodeKind::Element,
+            NodeKind::Element,
+            NodeKind::DocType,
+        ]
+    );
+    let large = copy.find(&doc, "large").unwrap();
+    assert_eq!(large.parent(&doc), Some(copy));
+    assert!(matches!(large.children(&doc)[0], Node::ExternalText(_)));
+    assert_eq!(large.text_content(&doc), "a rather long text");
+    for (child, original) in copy
+        .child_elements(&doc)
+        .into_iter()
+        .zip(item.child_elements(&source))
+    {
+        assert_eq!(child.parent(&doc), Some(copy));
+        assert_eq!(child.full_name(&doc), original.full_name(&source));
+        assert_eq!(child.authored_shape(&doc), original.authored_shape(&source));
+    }
+    let container = doc.container();
+    container.push_child(&mut doc, copy.as_node()).unwrap();
+    assert_eq!(doc.root_element(), Some(copy));
+    assert_eq!(
+        copy.find(&doc, "child").unwrap().namespace(&doc),
+        Some("urn:b")
+    );
+
+    // The source is unchanged
+    copy.set_attribute(&mut doc, "a:id", "2");
+    large
+        .push_child(&mut doc, Node::Text("more".into()))
+        .unwrap();
+    assert_eq!(item.attribute(&source, "a:id"), Some("1"));
+    assert_eq!(
+        item.find(&source, "large").unwrap().children(&source).len(),
+        1
+    );
+
+    // With namespaces in scope
+    let mut doc = Document::new();
+    let copy = doc.import_with_namespaces(&source, item);
+    let decls: Vec<(&str, &str)> = copy
+        .namespace_decls(&doc)
+        .iter()
+        .map(|(prefix, ns)| (prefix.as_str(), ns.as_str()))
+        .collect();
+    assert_eq!(decls, [("b", "urn:b"), ("a", "urn:a"), ("", "urn:d")]);
+
+    assert_eq!(copy.namespace(&doc), Some("urn:a"));
+    assert_eq!(
+        copy.find(&doc, "empty").unwrap().namespace(&doc),
+        Some("urn:d")
+    );
+
+    drop(source);
+    drop(doc);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_parse_metrics() {
+    use std::collections::HashMap;
+    use xml_doc::ParseMetrics;
+
+    let opts = ReadOptions {
+        collect_metrics: true,
+        ..ReadOptions::default()
+    };
+    let metrics = |path: &str| {
+        let doc = Document::parse_file_with_opts(path, opts.clone()).unwrap();
+        *doc.parse_metrics().unwrap()
+    };
+
+    let long = metrics("tests/documents/long_attribute.xml");
+    assert_eq!(long.max_attribute_value_len, 10_000);
+    assert_eq!(long.max_attributes, 2);
+    assert_eq!(long.max_depth, 2);
+    assert_eq!(long.elements, 2);
+    assert_eq!(long.texts, 0);
+
+    let wide = metrics("tests/documents/wide_element.xml");
+    assert_eq!(wide.max_attributes, 500);
+    assert_eq!(wide.max_attribute_value_len, 3);
+    assert_eq!(wide.max_depth, 2);
+
+    let deep = metrics("tests/documents/deep_nesting.xml");
+    assert_eq!(
+        deep,
+        ParseMetrics {
+            max_attribute_value_len: 2,
+            max_attributes: 1,
+            max_depth: 50,
+            elements: 50,
+            texts: 1,
+            cdatas: 1,
+            comments: 1,
+            pis: 1,
+            ..ParseMetrics::default()
+        }
+    );
+    assert_eq!(deep.entity_expansion_ratio(), None);
+
+    // Entity expansion
+    let mut catalog = HashMap::new();
+    catalog.insert("lol".to_string(), "lol".repeat(10));
+    let opts = ReadOptions {
+        collect_metrics: true,
+        entity_catalog: Some(catalog),
+        ..ReadOptions::default()
+    };
+    let xml = r#"<?xml version="1.0"?><a b="&lol;&amp;">&lol; &lt; &#65;</a>"#;
+    let doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    let metrics = doc.parse_metrics().unwrap();
+    assert_eq!(metrics.entity_references, 2);
+    assert_eq!(metrics.entity_reference_bytes, 10);
+    assert_eq!(metrics.entity_expansion_bytes, 60);
+    assert_eq!(metrics.entity_expansion_ratio(), Some(6.0));
+    assert_eq!(metrics.max_attribute_value_len, 31);
+
+    let doc = Document::parse_file("tests/documents/deep_nesting.xml").unwrap();
+    assert_eq!(doc.parse_metrics(), None);
+}
+
+#[test]
+fn test_spill_large_text() {
+    let dir = std::env::temp_dir().join(format!("xml-doc-spill-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let opts = ReadOptions {
+        max_text_len: Some(1 << 20),
+        spill_large_text_to: Some(dir.clone()),
+        ..ReadOptions::default()
+    };
+    let text = "<large> &\nünicode".repeat(200_000);
+    let mut doc = Document::new();
+    let container = doc.container();
+    let root = Element::build("root")
+        .text_content(text.as_str())
+        .push_to(&mut doc, container);
+    Element::build("small")
+        .text_content("x")
+        .push_to(&mut doc, root);
+    let xml = doc.write_str().unwrap();
+
+    let parsed = Document::parse_str_with_opts(&xml, opts.clone()).unwrap();
+    let root = parsed.root_element().unwrap();
+    let path = match &root.children(&parsed)[0] {
+        Node::ExternalText(external) => {
+            assert_eq!(external.len(), text.len());
+            assert_eq!(external.read_to_string().unwrap(), text);
+            external.path().to_path_buf()
+        }
+        node => panic!("Expected ExternalText, got {:?}", node.kind()),
+    };
+    assert!(path.starts_with(&dir));
+    assert!(matches!(root.children(&parsed)[1], Node::Element(_)));
+    assert!(root.text_eq(&parsed, &format!("{}x", text)));
+    assert!(!root.text_eq(&parsed, &text));
+    assert!(parsed.content_eq(&doc));
+
+    // Written back as escaped text.
+    assert_eq!(parsed.write_str().unwrap(), xml);
+    let reparsed = Document::parse_str_with_opts(&xml, opts).unwrap();
+    assert!(reparsed.content_eq(&parsed));
+
+    // CDATA sections are written back as CDATA sections.
+    let cdata = "<a>]]".repeat(1000);
+    let body = format!("<root><![CDATA[{0}]]><![CDATA[{0}]]></root>", cdata);
+    let xml = format!("<?xml version=\"1.0\"?>{}", body);
+    let opts = ReadOptions {
+        max_text_len: Some(1000),
+        spill_large_text_to: Some(dir.clone()),
+        ..ReadOptions::default()
+    };
+    let spilled = Document::parse_str_with_opts(&xml, opts.clone()).unwrap();
+    let root = spilled.root_element().unwrap();
+    assert!(root
+        .children(&spilled)
+        .iter()
+        .all(|node| matches!(node, Node::ExternalText(text) if text.is_cdata())));
+    assert!(spilled.write_str().unwrap().ends_with(&body));
+    let latin1 = spilled
+        .write_str_with_opts(WriteOptions {
+            encoding: Some("ISO-8859-1".to_string()),
+            ..WriteOptions::default()
+        })
+        .unwrap();
+    assert!(latin1.ends_with(&body));
+    let reparsed_cdata = Document::parse_str_with_opts(&xml, opts.clone()).unwrap();
+    assert!(reparsed_cdata.content_eq(&spilled));
+    assert!(Document::parse_str(&xml).unwrap().content_eq(&spilled));
+    assert!(
+        !Document::parse_str(&xml.replace("<![CDATA[", "<![CDATA[x"))
+            .unwrap()
+            .content_eq(&spilled)
+    );
+
+    // Spilled text written as CDATA has "]]>" split between two sections.
+    let text = "a]]>b".repeat(1000);
+    let xml = format!(
+        "<?xml version=\"1.0\"?><root>{}</root>",
+        text.replace('>', "&gt;")
+    );
+    let spilled_text = Document::parse_str_with_opts(&xml, opts).unwrap();
+    let written = spilled_text
+        .write_str_with_opts(WriteOptions {
+            element_hook: Some(Box::new(|_, _| ElementWriteStyle {
+                cdata_text: true,
+                ..ElementWriteStyle::default()
+            })),
+            ..WriteOptions::default()
+        })
+        .unwrap();
+    assert!(written.contains("<![CDATA[a]]]]><![CDATA[>b"));
+    let reparsed_text = Document::parse_str(&written).unwrap();
+    assert_eq!(
+        reparsed_text
+            .root_element()
+            .unwrap()
+            .text_content(&reparsed_text),
+        text
+    );
+
+    drop(parsed);
+    assert!(!path.exists());
+    drop(reparsed);
+    drop((spilled, reparsed_cdata, spilled_text));
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+// Parsing from a slice or a buffered reader must give the same result as parsing from a reader.
+fn assert_same_as_reader(bytes: &[u8], opts: ReadOptions) {
+    let from_reader = Document::parse_reader_with_opts(bytes, opts.clone());
+    let from_bytes = Document::parse_bytes_with_opts(bytes, opts.clone());
+    assert_same_result(bytes, &from_bytes, &from_reader);
+    // A small buffer splits characters between reads.
+    let bufreader = BufReader::with_capacity(5, bytes);
+    let from_bufread = Document::parse_bufread_with_opts(bufreader, opts);
+    assert_same_result(bytes, &from_bufread, &from_reader);
+}
+
+fn assert_same_result(bytes: &[u8], a: &xml_doc::Result<Document>, b: &xml_doc::Result<Document>) {
+    match (a, b) {
+        (Ok(a), Ok(b)) => {
+            assert!(a.content_eq(b), "{:?}", String::from_utf8_lossy(bytes));
+            assert_eq!(a.raw_decl(), b.raw_decl());
+            assert_eq!(a.is_fragment(), b.is_fragment());
+        }
+        (Err(a), Err(b)) => assert_eq!(a.to_string(), b.to_string()),
+        (a, b) => panic!(
+            "{:?} != {:?}",
+            a.as_ref().map(|_| ()),
+            b.as_ref().map(|_| ())
+        ),
+    }
+}
+
+fn encode_utf16(text: &str, big_endian: bool) -> Vec<u8> {
+    text.encode_utf16()
+        .flat_map(|unit| match big_endian {
+            true => unit.to_be_bytes(),
+            false => unit.to_le_bytes(),
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_bytes() {
+    for entry in std::fs::read_dir("tests/documents").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() == Some("xml".as_ref()) {
+            let bytes = std::fs::read(&path).unwrap();
+            assert_same_as_reader(&bytes, ReadOptions::default());
+        }
+    }
+
+    // Non-ASCII text right after the declaration is decoded with the declared encoding.
+    let xml = "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a>Café</a>";
+    let (latin1, _, _) = encoding_rs::WINDOWS_1252.encode(xml);
+    let doc = Document::parse_bytes(&latin1).unwrap();
+    assert_eq!(doc.root_element().unwrap().text_content(&doc), "Café");
+    assert_same_as_reader(&latin1, ReadOptions::default());
+
+    let xml = "<?xml version=\"1.0\" encoding=\"UTF-16\"?>\n<a b=\"ü\">日本語</a>";
+    for big_endian in [true, false] {
+        let mut bytes = encode_utf16(xml, big_endian);
+        assert_same_as_reader(&bytes, ReadOptions::default());
+        let doc = Document::parse_bytes(&bytes).unwrap();
+        assert_eq!(doc.root_element().unwrap().text_content(&doc), "日本語");
+        // With BOM
+        let bom: &[u8] = if big_endian {
+            &[0xfe, 0xff]
+        } else {
+            &[0xff, 0xfe]
+        };
+        bytes.splice(0..0, bom.iter().copied());
+        assert_same_as_reader(&bytes, ReadOptions::default());
+    }
+
+    // UTF-8 BOM, leading whitespace and documents without declaration.
+    assert_same_as_reader(
+        "\u{feff}<?xml version='1.0'?><a>é</a>".as_bytes(),
+        ReadOptions::default(),
+    );
+    let fragment = ReadOptions {
+        require_decl: false,
+        ..ReadOptions::default()
+    };
+    assert_same_as_reader(b"  <a>x</a><b/>", fragment.clone());
+    assert_same_as_reader(b"text", fragment.clone());
+    assert_same_as_reader(b"", fragment);
+    assert_same_as_reader(b"<a/>", ReadOptions::default());
+
+    // The declaration overrides the encoding in options.
+    let opts = ReadOptions {
+        encoding: Some("ISO-8859-1".to_string()),
+        ..ReadOptions::default()
+    };
+    let (sjis, _, _) =
+        encoding_rs::SHIFT_JIS.encode("<?xml version=\"1.0\" encoding=\"Shift_JIS\"?><a>日本</a>");
+    let doc = Document::parse_bytes_with_opts(&sjis, opts).unwrap();
+    assert_eq!(doc.root_element().unwrap().text_content(&doc), "日本");
+}
+
+#[test]
+fn test_entity_catalog() {
+    let dtd = r#"<!ENTITY company "Acme &amp; Co.">
+<!ENTITY legalnotice "Copyright &#169; 2024 &company; All rights reserved.">"#;
+    let catalog = parse_dtd_entities(dtd).unwrap();
+    let xml = r#"<?xml version="1.0"?>
+<!DOCTYPE book SYSTEM "book.dtd">
+<book notice="&legalnotice;"><p>&lt;&legalnotice;&gt; &unknown;</p></book>"#;
+    let notice = "Copyright © 2024 Acme & Co. All rights reserved.";
+
+    let opts = ReadOptions {
+        entity_catalog: Some(catalog.clone()),
+        ..ReadOptions::default()
+    };
+    // Entities not in the catalog are still undefined.
+    let err = Document::parse_str_with_opts(xml, opts).unwrap_err();
+    assert!(matches!(err, Error::MalformedXML(msg) if msg.contains("&unknown;")));
+
+    let opts = ReadOptions {
+        entity_catalog: Some(catalog),
+        undefined_entities: UndefinedEntityPolicy::KeepReference,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    let book = doc.root_element().unwrap();
+    assert_eq!(book.attribute(&doc, "notice"), Some(notice));
+    let p = book.find(&doc, "p").unwrap();
+    assert_eq!(p.text_content(&doc), format!("<{}> &unknown;", notice));
+    assert_eq!(doc.diagnostics().len(), 1);
+    // Expanded text is escaped when written.
+    let written = doc.write_str().unwrap();
+    assert!(written.contains("&lt;Copyright © 2024 Acme &amp; Co. All rights reserved.&gt;"));
+}
+
+#[test]
+fn test_multiple_roots() {
+    let xml = r#"<?xml version="1.0"?>
+<log day="1"><entry id="a"/><entry id="b"/></log>
+<!-- second producer -->
+<log day="2"><entry id="c"/></log>"#;
+    let opts = ReadOptions {
+        diagnose_multiple_roots: true,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_str_with_opts(xml, opts.clone()).unwrap();
+    let roots = doc.root_elements();
+    assert_eq!(roots.len(), 2);
+    assert_eq!(roots[1].attribute(&doc, "day"), Some("2"));
+    assert_eq!(doc.diagnostics().len(), 1);
+    assert!(doc.diagnostics()[0].message.contains("'log'"));
+
+    // Queries search every root.
+    let ids: Vec<&str> = doc
+        .find_all_descendants("entry")
+        .iter()
+        .map(|e| e.attribute(&doc, "id").unwrap())
+        .collect();
+    assert_eq!(ids, ["a", "b", "c"]);
+    assert_eq!(doc.find_descendant("log"), Some(roots[0]));
+    assert_eq!(doc.find_descendant("none"), None);
+
+    assert!(matches!(
+        doc.assert_single_root(),
+        Err(Error::MultipleRoots { count: 2 })
+    ));
+    // Not recorded unless asked for.
+    let doc = Document::parse_str(xml).unwrap();
+    assert!(doc.diagnostics().is_empty());
+
+    let doc = Document::parse_str_with_opts(r#"<?xml version="1.0"?><log/>"#, opts).unwrap();
+    assert!(doc.diagnostics().is_empty());
+    assert_eq!(
+        doc.assert_single_root().unwrap(),
+        doc.root_element().unwrap()
+    );
+    assert!(matches!(
+        Document::new().assert_single_root(),
+        Err(Error::MissingChild { wanted: None, .. })
+    ));
+}
+
+#[test]
+fn test_line_endings() {
+    let xml = "<?xml version=\"1.0\"?>\r\n<a>one\r\ntwo\rthree&#13;\r\n<![CDATA[x\r\ny]]><!--c\r\n--><?pi a\r\nb?></a>";
+    let opts = ReadOptions {
+        trim_text: false,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_str_with_opts(xml, opts.clone()).unwrap();
+    let a = doc.root_element().unwrap();
+    let children = a.children(&doc);
+    // The character reference is kept as `\r`.
+    assert!(matches!(&children[0], Node::Text(text) if text == "one\ntwo\nthree\r\n"));
+    assert!(matches!(&children[1], Node::CData(text) if text == "x\ny"));
+    assert!(matches!(&children[2], Node::Comment(text) if text == "c\n"));
+    assert!(matches!(&children[3], Node::PI(text) if text == "pi a\nb"));
+
+    let lf = Document::parse_str_with_opts(&xml.replace("\r\n", "\n"), opts).unwrap();
+    assert_eq!(
+        a.text_content(&doc),
+        lf.root_element().unwrap().text_content(&lf)
+    );
+}
+
+#[test]
+fn test_push_parsed_fragment() {
+    let xml = r#"<?xml version="1.0"?>
+<root xmlns="urn:d" xmlns:x="urn:x"><list><old/></list></root>"#;
+    let mut doc = Document::parse_str(xml).unwrap();
+    let list = doc.root_element().unwrap().find(&doc, "list").unwrap();
+
+    let nodes = list
+        .push_parsed_fragment(
+            &mut doc,
+            "text <x:item id='1'><sub/></x:item><!--c--><![CDATA[<d>]]><?pi data?> <item/>",
+        )
+        .unwrap();
+    let kinds: Vec<NodeKind> = nodes.iter().map(|node| node.kind()).collect();
+    assert_eq!(
+        kinds,
+        [
+            NodeKind::Text,
+            NodeKind::Element,
+            NodeKind::Comment,
+            NodeKind::CData,
+            NodeKind::PI,
+            NodeKind::Element,
+        ]
+    );
+    let children = list.child_elements(&doc);
+    assert_eq!(children.len(), 3);
+    assert_eq!(children[1].expanded_name(&doc), (Some("urn:x"), "item"));
+    assert_eq!(children[2].expanded_name(&doc), (Some("urn:d"), "item"));
+    assert_eq!(children[1].attribute(&doc, "id"), Some("1"));
+    assert_eq!(children[1].child_elements(&doc)[0].name(&doc), "sub");
+    assert_eq!(nodes[1].as_element(), Some(children[1]));
+
+    // Nothing is attached on error.
+    let before = doc.write_str().unwrap();
+    let nested = children[1];
+    for fragment in [
+        "<a>ok</a><b>",
+        "<a>ok</a></list>",
+        r#"<?xml version="1.0"?><a/>"#,
+        "<!DOCTYPE a><a/>",
+        "<a/>&undefined;",
+    ] {
+        assert!(
+            nested.push_parsed_fragment(&mut doc, fragment).is_err(),
+            "{}",
+            fragment
+        );
+        assert_eq!(doc.write_str().unwrap(), before, "{}", fragment);
+    }
+    assert_eq!(nested.children(&doc).len(), 1);
+
+    doc.set_mutation_validator(Box::new(|event| match event {
+        MutationEvent::PushChild {
+            node: Node::Comment(_),
+            ..
+        } => Err("no comments".to_string()),
+        _ => Ok(()),
+    }));
+    assert!(matches!(
+        nested.push_parsed_fragment(&mut doc, "<a/><!--c-->"),
+        Err(Error::Rejected(_))
+    ));
+    assert_eq!(doc.write_str().unwrap(), before);
+
+    // The document's options are used unless given.
+    let mut doc = Document::parse_str_with_opts(
+        r#"<?xml version="1.0"?><a/>"#,
+        ReadOptions {
+            trim_text: false,
+            ..ReadOptions::default()
+        },
+    )
+    .unwrap();
+    let a = doc.root_element().unwrap();
+    a.push_parsed_fragment(&mut doc, "<b> x </b>").unwrap();
+    assert_eq!(a.child_text(&doc, "b").unwrap(), " x ");
+    a.push_parsed_fragment_with(&mut doc, "<c> y </c>", ReadOptions::default())
+        .unwrap();
+    assert_eq!(a.child_text(&doc, "c").unwrap(), "y");
+}
+
+// Children of `elem` as `<name>` for elements, and their content for other nodes.
+fn child_summary(doc: &Document, elem: Element) -> Vec<String> {
+    elem.children(doc)
+        .iter()
+        .map(|node| match node {
+            Node::Element(elem) => format!("<{}>", elem.full_name(doc)),
+            Node::Text(text) => format!("text {:?}", text),
+            Node::CData(text) => format!("cdata {:?}", text),
+            Node::Comment(text) => format!("comment {:?}", text),
+            node => format!("{:?}", node.kind()),
+        })
+        .collect()
+}
+
+#[test]
+fn test_smart_whitespace() {
+    let opts = ReadOptions {
+        whitespace: WhitespaceMode::Smart,
+        ..ReadOptions::default()
+    };
+    let bytes = std::fs::read("tests/documents/mixed_content.xml").unwrap();
+    assert_same_as_reader(&bytes, opts.clone());
+    let doc = Document::parse_bytes_with_opts(&bytes, opts).unwrap();
+    let summary = |path: &str| {
+        let elem = match path {
+            "" => doc.container(),
+            path => doc.root_element().unwrap().find_path(&doc, path).unwrap(),
+        };
+        child_summary(&doc, elem)
+    };
+
+    assert_eq!(summary(""), [r#"comment " data and prose ""#, "<book>"]);
+    let book = doc.root_element().unwrap();
+    assert_eq!(child_summary(&doc, book), ["<meta>", "<chapter>", "<code>"]);
+
+    // Data: indentation is removed, text is kept untrimmed.
+    assert_eq!(summary("meta"), ["<title>", "<year>", "<empty>"]);
+    assert_eq!(summary("meta/title"), [r#"text "Whitespace""#]);
+    assert_eq!(summary("meta/year"), [r#"text " 2021 ""#]);
+    assert_eq!(summary("meta/empty"), [r#"text "   ""#]);
+
+    // Prose: whitespace in mixed content is kept.
+    assert_eq!(summary("chapter"), ["<p>", "<p>", "<p>"]);
+    let p: Vec<Element> = book.find(&doc, "chapter").unwrap().find_all(&doc, "p");
+    assert_eq!(
+        child_summary(&doc, p[0]),
+        [
+            r#"text "Some ""#,
+            "<b>",
+            r#"text " ""#,
+            "<i>",
+            r#"text " text.""#
+        ]
+    );
+    // Without other text, it looks the same as indentation.
+    assert_eq!(child_summary(&doc, p[1]), ["<b>", "<i>"]);
+    assert_eq!(
+        child_summary(&doc, p[2]),
+        [
+            r#"text " ""#,
+            r#"cdata "raw""#,
+            r#"text " ""#,
+            "<b>",
+            r#"text " ""#
+        ]
+    );
+
+    // xml:space="preserve" wins, until it is reset.
+    assert_eq!(
+        summary("code"),
+        [
+            r#"text "\n    ""#,
+            "<line>",
+            r#"text "\n    ""#,
+            "<inner>",
+            r#"text "\n  ""#
+        ]
+    );
+    assert_eq!(summary("code/inner"), ["<line>"]);
+
+    // Other whitespace options are ignored.
+    let opts = ReadOptions {
+        whitespace: WhitespaceMode::Smart,
+        trim_text: false,
+        ignore_whitespace_only: true,
+        ..ReadOptions::default()
+    };
+    let other = Document::parse_bytes_with_opts(&bytes, opts).unwrap();
+    assert!(other.content_eq(&doc));
+}
+
+#[test]
+fn test_name_arena() {
+    let opts = ReadOptions {
+        name_arena: true,
+        ..ReadOptions::default()
+    };
+    let xml = std::fs::read_to_string("benches/medium.xml").unwrap();
+    let doc = Document::parse_str_with_opts(&xml, opts.clone()).unwrap();
+    let owned = Document::parse_str(&xml).unwrap();
+    assert_eq!(doc.write_str().unwrap(), owned.write_str().unwrap());
+
+    let xml = r#"<?xml version="1.0"?><p:list xmlns:p="urn:p"><item/><item/><p:item/></p:list>"#;
+    let mut doc = Document::parse_str_with_opts(xml, opts.clone()).unwrap();
+    let list = doc.root_element().unwrap();
+    let items = list.child_elements(&doc);
+
+    // Modified names are owned, and other names are still read from the arena.
+    items[0].set_name(&mut doc, "first");
+    items[2].set_prefix(&mut doc, "");
+    list.set_name(&mut doc, "items");
+    assert_eq!(items[0].full_name(&doc), "first");
+    assert_eq!(items[1].full_name(&doc), "item");
+    assert_eq!(items[2].full_name(&doc), "item");
+    assert_eq!(list.full_name(&doc), "p:items");
+    let copy = items[1].clone_subtree(&mut doc);
+    assert_eq!(copy.full_name(&doc), "item");
+
+    // Names are copied out of the arena when imported into another document.
+    let mut other = Document::new();
+    let imported = other.import_element(&doc, list);
+    let cloned = doc.clone();
+    drop(doc);
+    assert_eq!(imported.full_name(&other), "p:items");
+    let names: Vec<&str> = imported
+        .child_elements(&other)
+        .iter()
+        .map(|e| e.full_name(&other))
+        .collect();
+    assert_eq!(names, ["first", "item", "item"]);
+    assert_eq!(items[1].full_name(&cloned), "item");
+
+    // Parsing into a document again reuses the arena.
+    let mut parser = Parser::new(opts);
+    let mut doc = Document::new();
+    parser.parse_into(&mut doc, xml).unwrap();
+    parser
+        .parse_into(&mut doc, r#"<?xml version="1.0"?><other><x/></other>"#)
+        .unwrap();
+    let root = doc.root_element().unwrap();
+    assert_eq!(root.full_name(&doc), "other");
+    assert_eq!(root.child_elements(&doc)[0].full_name(&doc), "x");
+}
+
+#[test]
+fn test_dedup_values() {
+    let opts = ReadOptions {
+        dedup_values: true,
+        ..ReadOptions::default()
+    };
+    let mut xml = String::from(r#"<?xml version="1.0"?><list>"#);
+    for _ in 0..2000 {
+        xml.push_str(r#"<item currency="USD" status="active">pending</item>"#);
+    }
+    xml.push_str("</list>");
+    let doc = Document::parse_str_with_opts(&xml, opts.clone()).unwrap();
+    let owned = Document::parse_str(&xml).unwrap();
+    assert!(doc.content_eq(&owned));
+    assert_eq!(doc.write_str().unwrap(), owned.write_str().unwrap());
+    // Each distinct value is stored once instead of 2000 times.
+    assert!(doc.estimate_memory_usage() + 30_000 <= owned.estimate_memory_usage());
+
+    let xml =
+        r#"<?xml version="1.0"?><list><item id="USD">USD</item><item id="USD">USD</item></list>"#;
+    let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    let items = doc.root_element().unwrap().child_elements(&doc);
+    let values = |doc: &Document| -> Vec<String> {
+        items
+            .iter()
+            .flat_map(|item| {
+                vec![
+                    item.attribute(doc, "id").unwrap().to_string(),
+                    item.text_content(doc),
+                ]
+            })
+            .collect()
+    };
+    assert!(matches!(&items[0].children(&doc)[0], Node::Text(text) if text.is_shared()));
+
+    // Changing a shared value doesn't change the other nodes and attributes sharing it.
+    items[0].append_text(&mut doc, " 100");
+    items[0].mut_attributes(&mut doc)["id"].to_mut().push('D');
+    assert_eq!(values(&doc), ["USDD", "USD 100", "USD", "USD"]);
+    items[1].set_attribute(&mut doc, "id", "EUR");
+    items[1].set_text_content(&mut doc, "EUR");
+    assert_eq!(values(&doc), ["USDD", "USD 100", "EUR", "EUR"]);
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_children_by_namespace() {
+    const ATOM: &str = "http://www.w3.org/2005/Atom";
+    const ITUNES: &str = "http://www.itunes.com/dtds/podcast-1.0.dtd";
+    const PODCAST: &str = "https://podcastindex.org/namespace/1.0";
+    let mut doc = Document::parse_file("tests/documents/feed.xml").unwrap();
+    let feed = doc.root_element().unwrap();
+    let names = |elems: &[Element], doc: &Document| -> Vec<String> {
+        elems.iter().map(|e| e.full_name(doc).to_string()).collect()
+    };
+
+    let atom = feed.children_in_namespace(&doc, ATOM);
+    assert_eq!(
+        names(&atom, &doc),
+        ["title", "id", "author", "a:link", "entry"]
+    );
+    let itunes = feed.children_in_namespace(&doc, ITUNES);
+    assert_eq!(names(&itunes, &doc), ["itunes:author", "itunes:explicit"]);
+    // `podcast:guid` redeclares its prefix.
+    let podcast = feed.children_in_namespace(&doc, PODCAST);
+    assert_eq!(names(&podcast, &doc), ["podcast:locked", "podcast:funding"]);
+    assert!(feed.children_in_namespace(&doc, "").is_empty());
+
+    let groups = feed.children_grouped_by_namespace(&doc);
+    assert_eq!(groups.len(), 5);
+    assert_eq!(groups[&Some(ATOM.to_string())], atom);
+    assert_eq!(groups[&Some(ITUNES.to_string())], itunes);
+    assert_eq!(groups[&Some(PODCAST.to_string())], podcast);
+    let legacy = &groups[&Some("urn:legacy-podcast".to_string())];
+    assert_eq!(names(legacy, &doc), ["podcast:guid"]);
+    assert_eq!(names(&groups[&None], &doc), ["tracking", "stats"]);
+    let total: usize = groups.values().map(Vec::len).sum();
+    assert_eq!(total, feed.child_element_count(&doc));
+
+    // Grandchildren are resolved in their own scope.
+    let stats = groups[&None][1];
+    let downloads = stats.children_grouped_by_namespace(&doc);
+    assert_eq!(names(&downloads[&None], &doc), ["downloads"]);
+    let entry = feed.find(&doc, "entry").unwrap();
+    assert_eq!(entry.children_grouped_by_namespace(&doc).len(), 2);
+
+    // An undeclared prefix isn't treated as no namespace.
+    let tracking = groups[&None][0];
+    tracking.set_prefix(&mut doc, "stats");
+    let groups = feed.children_grouped_by_namespace(&doc);
+    assert_eq!(names(&groups[&None], &doc), ["stats"]);
+    let total: usize = groups.values().map(Vec::len).sum();
+    assert_eq!(total, feed.child_element_count(&doc) - 1);
+}
+
+#[test]
+fn test_has_text_content() {
+    // Deep tree whose only text is whitespace
+    let mut doc = Document::new();
+    let mut parent = doc.container();
+    for _ in 0..2000 {
+        parent = Element::build("level")
+            .text_content(" \n\t")
+            .push_to(&mut doc, parent);
+    }
+    let root = doc.root_element().unwrap();
+    assert!(!root.has_text_content(&doc, true));
+    assert!(root.has_text_content(&doc, false));
+    assert!(root.as_node().is_whitespace_only(&doc));
+    assert_eq!(root.text_content_len(&doc), 3 * 2000);
+    let empty = Element::new(&mut doc, "empty");
+    assert!(!empty.has_text_content(&doc, false));
+    assert_eq!(empty.text_content_len(&doc), 0);
+    parent.push_child(&mut doc, Node::Text("x".into())).unwrap();
+    assert!(root.has_text_content(&doc, true));
+    assert!(!root.as_node().is_whitespace_only(&doc));
+
+    // CDATA counts as text, comments and processing instructions don't
+    let opts = ReadOptions {
+        trim_text: false,
+        ..ReadOptions::default()
+    };
+    let xml = "<?xml version=\"1.0\"?><a> <!--x--><?x y?><b><![CDATA[\u{a0}]]></b></a>";
+    let doc = Document::parse_str_with_opts(xml, opts.clone()).unwrap();
+    let a = doc.root_element().unwrap();
+    assert!(a.has_text_content(&doc, true));
+    assert_eq!(a.text_content_len(&doc), a.text_content(&doc).len());
+    let children = a.children(&doc);
+    assert!(children[0].is_whitespace_only(&doc));
+    assert!(!children[1].is_whitespace_only(&doc));
+    assert!(!children[2].is_whitespace_only(&doc));
+    assert!(!children[3].is_whitespace_only(&doc));
+    let xml = r#"<?xml version="1.0"?><a><![CDATA[  ]]><b/></a>"#;
+    let doc = Document::parse_str_with_opts(xml, opts.clone()).unwrap();
+    let a = doc.root_element().unwrap();
+    assert!(!a.has_text_content(&doc, true));
+    assert!(a.has_text_content(&doc, false));
+
+    // Stops at the first text that isn't whitespace: removed spill files
+    // and the large subtree after it are never read.
+    let dir = std::env::temp_dir().join(format!("xml-doc-has-text-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let opts = ReadOptions {
+        max_text_len: Some(1000),
+        spill_large_text_to: Some(dir.clone()),
+        ..opts
+    };
+    let spaces = " ".repeat(5000);
+    let xml = format!(
+        r#"<?xml version="1.0"?><a><w>{0}</w><t>{0}x</t><big>{1}</big><end>{0}x</end></a>"#,
+        spaces,
+        "<item><x> </x></item>".repeat(100_000),
+    );
+    let doc = Document::parse_str_with_opts(&xml, opts).unwrap();
+    let a = doc.root_element().unwrap();
+    let elems = a.child_elements(&doc);
+    let (w, t, end) = (elems[0], elems[1], elems[3]);
+    assert!(matches!(w.children(&doc)[0], Node::ExternalText(_)));
+    assert!(w.children(&doc)[0].is_whitespace_only(&doc));
+    assert!(!w.has_text_content(&doc, true));
+    assert!(t.has_text_content(&doc, true));
+    assert_eq!(a.text_content_len(&doc), a.text_content(&doc).len());
+
+    let remove_spill_file = |elem: Element| {
+        if let Node::ExternalText(text) = &elem.children(&doc)[0] {
+            std::fs::remove_file(text.path()).unwrap();
+        }
+    };
+    remove_spill_file(end);
+    assert!(a.has_text_content(&doc, true));
+    remove_spill_file(w);
+    assert!(w.has_text_content(&doc, false));
+    assert_eq!(end.text_content_len(&doc), 5001);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,138 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use xml_doc::{Document, Parser, ReadOptions};
+
+// Counts allocations and allocated bytes of the current thread,
+// so tests running in parallel don't interfere.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| {
+            let (allocs, bytes) = count.get();
+            count.set((allocs + 1, bytes + layout.size()));
+        });
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations() -> (usize, usize) {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+fn allocations_since(start: (usize, usize)) -> (usize, usize) {
+    let end = allocations();
+    (end.0 - start.0, end.1 - start.1)
+}
+
+const TINY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<note priority="high">
+  <to>Tove</to>
+  <from>Jani</from>
+  <body>Don't forget me this weekend!</body>
+</note>"#;
+
+#[test]
+fn test_parser_reuse_allocations() {
+    let count = 1000;
+
+    let start = allocations();
+    for _ in 0..count {
+        let doc = Document::parse_str(TINY).unwrap();
+        assert!(doc.root_element().is_some());
+    }
+    let parse_str = allocations_since(start);
+
+    let mut parser = Parser::new(ReadOptions::default());
+    let mut doc = Document::new();
+    let start = allocations();
+    for _ in 0..count {
+        parser.parse_into(&mut doc, TINY).unwrap();
+        assert!(doc.root_element().is_some());
+    }
+    let parse_into = allocations_since(start);
+
+    // Allocations for names and values remain, but scratch buffers are reused.
+    let message = format!("parse_into: {:?}, parse_str: {:?}", parse_into, parse_str);
+    assert!(parse_into.0 + count * 5 < parse_str.0, "{}", message);
+    assert!(parse_into.1 * 10 < parse_str.1, "{}", message);
+}
+
+#[test]
+fn test_parser_no_state_leak() {
+    let mut parser = Parser::new(ReadOptions {
+        lenient: true,
+        high_fidelity: true,
+        ..ReadOptions::default()
+    });
+    let mut doc = Document::new();
+    let first = r#"<?xml version="1.1" standalone="yes" bogus="1"?>
+<first xmlns:p="urn:p" a="1"><p:child b="2">text</p:child><!--c--></first>"#;
+    parser.parse_into(&mut doc, first).unwrap();
+    assert_eq!(doc.diagnostics().len(), 1);
+    assert_eq!(doc.standalone(), Some(true));
+
+    // A failed parse leaves the document unchanged.
+    let second = r#"<second/>"#;
+    let root = doc.root_element().unwrap();
+    assert!(parser.parse_into(&mut doc, second).is_err());
+    assert!(root.is_valid(&doc));
+    assert_eq!(root.name(&doc), "first");
+    assert_eq!(doc.diagnostics().len(), 1);
+
+    let third = r#"<?xml version="1.0"?><third c="3"/>"#;
+    parser.parse_into(&mut doc, third).unwrap();
+    let fresh = Document::parse_str(third).unwrap();
+    assert!(doc.content_eq(&fresh));
+    assert!(doc.diagnostics().is_empty());
+    assert_eq!(doc.standalone(), None);
+    assert_eq!(doc.raw_decl(), Some(r#"<?xml version="1.0"?>"#));
+    let root = doc.root_element().unwrap();
+    assert_eq!(root.attributes(&doc).len(), 1);
+    assert!(root.namespace_decls(&doc).is_empty());
+    assert_eq!(root.children(&doc).len(), 0);
+    let written = doc.write_str().unwrap();
+    for leaked in ["first", "child", "urn:p", "text", "1.1", "a="] {
+        assert!(!written.contains(leaked), "{}", written);
+    }
+
+    let doc = parser.parse_str(first).unwrap();
+    assert_eq!(doc.root_element().unwrap().name(&doc), "first");
+    assert_eq!(
+        doc.write_str().unwrap(),
+        Document::parse_str_with_opts(first, parser.options().clone())
+            .unwrap()
+            .write_str()
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_parser_reuse_decoded_text() {
+    let mut parser = Parser::new(ReadOptions::default());
+    let latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a>caf\xe9</a>";
+    let mut utf16 = vec![0xff, 0xfe];
+    for unit in
+        "<?xml version=\"1.0\" encoding=\"UTF-16\"?><b>\u{c5}ngstr\u{f6}m</b>".encode_utf16()
+    {
+        utf16.extend_from_slice(&unit.to_le_bytes());
+    }
+    for _ in 0..2 {
+        let doc = parser.parse_bytes(latin1).unwrap();
+        let root = doc.root_element().unwrap();
+        assert_eq!(root.text_content(&doc), "café");
+        let doc = parser.parse_bytes(&utf16).unwrap();
+        let root = doc.root_element().unwrap();
+        assert_eq!(root.text_content(&doc), "\u{c5}ngstr\u{f6}m");
+    }
+}
@@ -0,0 +1,158 @@
+use xml_doc::{Document, Node, ReplaceFlags};
+
+const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- served by old.example.com -->
+<config url="http://old.example.com/" backup="old.example.com">
+  <server old.example.com="old.example.com">old.example.com and old.example.com</server>
+  <mirror>
+    <host>old.example.com</host>
+    <![CDATA[old.example.com]]>
+    <!-- old.example.com -->
+  </mirror>
+  <old.example.com/>
+</config>
+"#;
+
+fn texts(doc: &Document, kind: fn(&Node) -> Option<&str>) -> Vec<String> {
+    doc.container()
+        .descendant_nodes(doc)
+        .filter_map(kind)
+        .map(String::from)
+        .collect()
+}
+
+fn text(node: &Node) -> Option<&str> {
+    match node {
+        Node::Text(text) => Some(text),
+        _ => None,
+    }
+}
+
+fn cdata(node: &Node) -> Option<&str> {
+    match node {
+        Node::CData(text) => Some(text),
+        _ => None,
+    }
+}
+
+fn comment(node: &Node) -> Option<&str> {
+    match node {
+        Node::Comment(text) => Some(text),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_replace_text_literal() {
+    let mut doc = Document::parse_str(XML).unwrap();
+    let count = doc.replace_text_literal(
+        "old.example.com",
+        "new.example.org",
+        ReplaceFlags::default(),
+    );
+    assert_eq!(count, 3);
+    assert_eq!(
+        texts(&doc, text),
+        ["new.example.org and new.example.org", "new.example.org"]
+    );
+    assert_eq!(texts(&doc, cdata), ["old.example.com"]);
+
+    // Names and attributes are untouched
+    let root = doc.root_element().unwrap();
+    assert!(root.find(&doc, "old.example.com").is_some());
+    let server = root.find(&doc, "server").unwrap();
+    assert_eq!(
+        server.attribute(&doc, "old.example.com"),
+        Some("old.example.com")
+    );
+
+    // CDATA and comments, including the comment before the root
+    let flags = ReplaceFlags {
+        cdata: true,
+        comments: true,
+    };
+    assert_eq!(
+        doc.replace_text_literal("old.example.com", "new.example.org", flags),
+        3
+    );
+    assert_eq!(texts(&doc, cdata), ["new.example.org"]);
+    assert_eq!(
+        texts(&doc, comment),
+        [" served by new.example.org ", " new.example.org "]
+    );
+
+    let flags = ReplaceFlags {
+        cdata: true,
+        ..ReplaceFlags::default()
+    };
+    assert_eq!(doc.replace_text_literal("new", "newer", flags), 4);
+    assert_eq!(doc.replace_text_literal("", "x", flags), 0);
+}
+
+#[test]
+fn test_replace_in_attributes_literal() {
+    let mut doc = Document::parse_str(XML).unwrap();
+    let root = doc.root_element().unwrap();
+    let server = root.find(&doc, "server").unwrap();
+
+    let count =
+        doc.replace_in_attributes_literal(Some("url"), "old.example.com", "new.example.org");
+    assert_eq!(count, 1);
+    assert_eq!(root.attribute(&doc, "url"), Some("http://new.example.org/"));
+    assert_eq!(root.attribute(&doc, "backup"), Some("old.example.com"));
+
+    let count = doc.replace_in_attributes_literal(None, "old.example.com", "new.example.org");
+    assert_eq!(count, 2);
+    assert_eq!(root.attribute(&doc, "backup"), Some("new.example.org"));
+    assert_eq!(
+        server.attribute(&doc, "old.example.com"),
+        Some("new.example.org")
+    );
+    assert_eq!(
+        server.text_content(&doc),
+        "old.example.com and old.example.com"
+    );
+    assert_eq!(
+        doc.replace_in_attributes_literal(Some("missing"), "new", "x"),
+        0
+    );
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_replace_regex() {
+    use regex::Regex;
+
+    let mut doc = Document::parse_str(XML).unwrap();
+    let pattern = Regex::new(r"old\.(\w+)\.com").unwrap();
+    assert_eq!(
+        doc.replace_text(&pattern, "new.$1.org", ReplaceFlags::default()),
+        3
+    );
+    let root = doc.root_element().unwrap();
+    let mirror = root.find(&doc, "mirror").unwrap();
+    assert_eq!(
+        mirror.find(&doc, "host").unwrap().text_content(&doc),
+        "new.example.org"
+    );
+    assert_eq!(
+        mirror.children(&doc)[1],
+        Node::CData("old.example.com".into())
+    );
+    let flags = ReplaceFlags {
+        cdata: true,
+        ..ReplaceFlags::default()
+    };
+    assert_eq!(doc.replace_text(&pattern, "new.$1.org", flags), 1);
+    assert_eq!(
+        mirror.children(&doc)[1],
+        Node::CData("new.example.org".into())
+    );
+
+    let count = doc.replace_in_attributes(Some("backup"), &pattern, "$1");
+    assert_eq!(count, 1);
+    assert_eq!(root.attribute(&doc, "backup"), Some("example"));
+    assert_eq!(doc.replace_in_attributes(None, &pattern, "$1"), 2);
+    assert_eq!(root.attribute(&doc, "url"), Some("http://example/"));
+    assert!(root.find(&doc, "old.example.com").is_some());
+}
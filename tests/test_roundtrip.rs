@@ -0,0 +1,124 @@
+use std::path::Path;
+use xml_doc::{
+    roundtrip_check, roundtrip_check_with_max, Document, ReadOptions, RoundtripReport,
+    WriteOptions, DEFAULT_MAX_DIVERGENCES,
+};
+
+fn check(file_name: &str, read_opts: ReadOptions) -> RoundtripReport {
+    let input = std::fs::read(Path::new("tests/documents").join(file_name)).unwrap();
+    roundtrip_check(&input, read_opts, WriteOptions::default()).unwrap()
+}
+
+#[test]
+fn roundtrip_fixtures() {
+    // Lossless, but quotes, whitespace and encoding are normalized.
+    for file_name in ["doc.xml", "nodes.xml", "encoding1.xml", "encoding2.xml"] {
+        let report = check(file_name, ReadOptions::default());
+        assert!(report.is_lossless(), "{}", file_name);
+        assert!(!report.bytes_eq, "{}", file_name);
+        assert!(!report.divergences.is_empty(), "{}", file_name);
+    }
+
+    let report = check("nodes.xml", ReadOptions::default());
+    let first = &report.divergences[0];
+    assert_eq!((first.input_pos, first.output_pos), (14, 14));
+    assert_eq!(first.context, "<?xml version=");
+    assert_eq!(first.input, "'1.0'");
+    assert_eq!(first.output, "\"1.0\"");
+}
+
+#[test]
+fn roundtrip_lossy() {
+    // Space character reference in text is written as a raw space,
+    // which is trimmed when parsed again.
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<root>&#32;a</root>"#;
+    let report = roundtrip_check(
+        xml.as_bytes(),
+        ReadOptions::default(),
+        WriteOptions::default(),
+    )
+    .unwrap();
+    assert!(!report.is_lossless());
+    assert_eq!(report.divergences[0].input, "&#32;");
+    assert_eq!(report.divergences[0].output, " ");
+}
+
+#[test]
+fn roundtrip_max_divergences() {
+    // Every single-quoted attribute is written with double quotes.
+    let attributes: String = (0..40).map(|i| format!(" a{}='{}'", i, i)).collect();
+    let xml = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<root{}/>"#,
+        attributes
+    );
+    let check = |max| {
+        roundtrip_check_with_max(
+            xml.as_bytes(),
+            ReadOptions::default(),
+            WriteOptions::default(),
+            max,
+        )
+        .unwrap()
+    };
+    let report = roundtrip_check(
+        xml.as_bytes(),
+        ReadOptions::default(),
+        WriteOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(report.divergences.len(), DEFAULT_MAX_DIVERGENCES);
+    assert_eq!(check(DEFAULT_MAX_DIVERGENCES), report);
+    assert_eq!(check(3).divergences[..], report.divergences[..3]);
+    let report = check(0);
+    assert!(report.content_eq && !report.bytes_eq);
+    assert!(report.divergences.is_empty());
+    assert!(check(usize::MAX).divergences.len() > DEFAULT_MAX_DIVERGENCES);
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn roundtrip_written() {
+    let doc = Document::parse_file("tests/documents/nodes.xml").unwrap();
+    let written = doc.write_str().unwrap();
+    let report = roundtrip_check(
+        written.as_bytes(),
+        ReadOptions::default(),
+        WriteOptions::default(),
+    )
+    .unwrap();
+    assert!(report.content_eq);
+    assert!(report.bytes_eq);
+    assert!(report.divergences.is_empty());
+}
+
+#[test]
+fn roundtrip_fragment() {
+    let input = std::fs::read("tests/documents/fragment.xml").unwrap();
+    let read_opts = ReadOptions {
+        require_decl: false,
+        trim_text: false,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_reader_with_opts(&input[..], read_opts.clone()).unwrap();
+    assert!(doc.is_fragment());
+    let roots: Vec<_> = doc
+        .root_nodes()
+        .iter()
+        .filter_map(|n| n.as_element())
+        .collect();
+    assert_eq!(roots.len(), 2);
+
+    let write_opts = WriteOptions {
+        pretty: false,
+        ..WriteOptions::default()
+    };
+    let report = roundtrip_check(&input, read_opts, write_opts).unwrap();
+    assert!(report.bytes_eq, "{:?}", report.divergences);
+
+    let mut doc = doc;
+    doc.set_fragment_mode(false);
+    let written = doc.write_str().unwrap();
+    assert!(written.starts_with("<?xml"));
+}
@@ -0,0 +1,196 @@
+use xml_doc::{Document, Element, ReadOptions, TemplateId};
+
+const ROW: &str = r#"<row kind="data" xmlns:x="urn:x"><x:id>1</x:id><!--note--><cell>a &amp; b</cell><cell/></row>"#;
+
+// Document with an empty <root>, and a template of ROW.
+fn template_doc() -> (Document, Element, TemplateId) {
+    let xml = format!(r#"<?xml version="1.0"?><root>{}</root>"#, ROW);
+    let mut doc = Document::parse_str(&xml).unwrap();
+    let root = doc.root_element().unwrap();
+    let row = root.find(&doc, "row").unwrap();
+    let template = doc.create_template(row);
+    row.detatch(&mut doc).unwrap();
+    (doc, root, template)
+}
+
+fn eager_doc(count: usize) -> Document {
+    let xml = format!(r#"<?xml version="1.0"?><root>{}</root>"#, ROW.repeat(count));
+    Document::parse_str(&xml).unwrap()
+}
+
+#[test]
+fn test_template_write() {
+    let (mut doc, root, template) = template_doc();
+    for _ in 0..3 {
+        let row = root.push_template_instance(&mut doc, template);
+        assert!(row.is_template_instance(&doc));
+        assert_eq!(row.parent(&doc), Some(root));
+    }
+    assert_eq!(doc.write_str().unwrap(), eager_doc(3).write_str().unwrap());
+    assert!(doc.content_eq(&eager_doc(3)));
+
+    let row = root.child_elements(&doc)[1];
+    assert_eq!(row.full_name(&doc), "row");
+    assert_eq!(row.attribute(&doc, "kind"), Some("data"));
+    assert_eq!(row.namespace_for_prefix(&doc, "x"), Some("urn:x"));
+    assert_eq!(row.text_content(&doc), "1a & b");
+
+    // Moving an instance doesn't materialize it.
+    row.detatch(&mut doc).unwrap();
+    root.push_child(&mut doc, row.as_node()).unwrap();
+    assert!(row.is_template_instance(&doc));
+}
+
+#[test]
+fn test_template_materialize() {
+    let (mut doc, root, template) = template_doc();
+    let rows: Vec<Element> = (0..3)
+        .map(|_| root.push_template_instance(&mut doc, template))
+        .collect();
+
+    rows[0].set_attribute(&mut doc, "kind", "header");
+    assert!(!rows[0].is_template_instance(&doc));
+    assert!(rows[1].is_template_instance(&doc));
+    assert_eq!(rows[0].attribute(&doc, "kind"), Some("header"));
+    assert_eq!(rows[1].attribute(&doc, "kind"), Some("data"));
+
+    // Descendants of a materialized instance are its own.
+    let cell = rows[0].find(&doc, "cell").unwrap();
+    assert_eq!(cell.parent(&doc), Some(rows[0]));
+    cell.set_text_content(&mut doc, "changed");
+    assert_eq!(
+        rows[0].find(&doc, "cell").unwrap().text_content(&doc),
+        "changed"
+    );
+    assert_eq!(
+        rows[1].find(&doc, "cell").unwrap().text_content(&doc),
+        "a & b"
+    );
+
+    // Modifying children materializes too.
+    Element::build("extra").push_to(&mut doc, rows[2]);
+    assert!(!rows[2].is_template_instance(&doc));
+    assert_eq!(rows[2].child_elements(&doc).len(), 4);
+    assert_eq!(rows[1].child_elements(&doc).len(), 3);
+
+    let mut expected = eager_doc(3);
+    let expected_rows = expected.root_element().unwrap().child_elements(&expected);
+    expected_rows[0].set_attribute(&mut expected, "kind", "header");
+    let cell = expected_rows[0].find(&expected, "cell").unwrap();
+    cell.set_text_content(&mut expected, "changed");
+    Element::build("extra").push_to(&mut expected, expected_rows[2]);
+    assert_eq!(doc.write_str().unwrap(), expected.write_str().unwrap());
+
+    // Materializing twice does nothing.
+    rows[0].materialize(&mut doc);
+    assert_eq!(rows[0].children(&doc).len(), 4);
+}
+
+#[test]
+fn test_template_instance_descendants() {
+    let (mut doc, root, template) = template_doc();
+    let first = root.push_template_instance(&mut doc, template);
+    let second = root.push_template_instance(&mut doc, template);
+    // Descendants of an instance are its own, even before it is materialized.
+    let cell = first.find(&doc, "cell").unwrap();
+    assert_ne!(second.find(&doc, "cell"), Some(cell));
+    assert_eq!(cell.parent(&doc), Some(first));
+    assert!(cell.ancestors(&doc).any(|e| e == root));
+    let id = first.find(&doc, "id").unwrap();
+    assert_eq!(id.namespace(&doc), Some("urn:x"));
+    assert_eq!(root.find_path(&doc, "row/cell"), Some(cell));
+
+    cell.set_attribute(&mut doc, "changed", "yes");
+    cell.set_text_content(&mut doc, "changed");
+    assert!(first.is_template_instance(&doc));
+    let other = second.find(&doc, "cell").unwrap();
+    assert_eq!(other.attribute(&doc, "changed"), None);
+    assert_eq!(other.text_content(&doc), "a & b");
+    let third = root.push_template_instance(&mut doc, template);
+    assert_eq!(
+        third.find(&doc, "cell").unwrap().text_content(&doc),
+        "a & b"
+    );
+
+    // Templates copy the element, which stays editable.
+    let (mut doc, root, template) = template_doc();
+    let row = Element::build("row").finish(&mut doc);
+    let empty = doc.create_template(row);
+    row.set_attribute(&mut doc, "n", "1");
+    let instance = root.push_template_instance(&mut doc, empty);
+    assert_eq!(instance.attribute(&doc, "n"), None);
+    // A template of an instance copies the template content.
+    let instance = root.push_template_instance(&mut doc, template);
+    let copied = doc.create_template(instance);
+    let copy = root.push_template_instance(&mut doc, copied);
+    assert_eq!(copy.text_content(&doc), "1a & b");
+}
+
+#[test]
+fn test_template_memory() {
+    let count = 100;
+    let (mut doc, root, template) = template_doc();
+    for _ in 0..count {
+        root.push_template_instance(&mut doc, template);
+    }
+    let eager = eager_doc(count);
+    assert_eq!(doc.write_str().unwrap(), eager.write_str().unwrap());
+    // Elements are copied, but text and attribute values are shared with the template.
+    let instanced = doc.estimate_memory_usage();
+    let cloned = eager.estimate_memory_usage();
+    assert!(
+        instanced < cloned,
+        "instanced: {}, cloned: {}",
+        instanced,
+        cloned
+    );
+    // So instances of a template with long text and attribute values grow the document
+    // exactly as much as instances of the same elements with short ones.
+    let long = "x".repeat(10_000);
+    let grow = |value: &str| {
+        let row = ROW.replace("a &amp; b", value).replace("data", value);
+        let xml = format!(r#"<?xml version="1.0"?><root>{}</root>"#, row);
+        let mut doc = Document::parse_str(&xml).unwrap();
+        let root = doc.root_element().unwrap();
+        let template = doc.create_template(root.find(&doc, "row").unwrap());
+        let before = doc.estimate_memory_usage();
+        for _ in 0..count {
+            root.push_template_instance(&mut doc, template);
+        }
+        doc.estimate_memory_usage() - before
+    };
+    let grown = grow(&long);
+    assert_eq!(grown, grow("y"));
+    assert!(grown < count * long.len(), "grown: {}", grown);
+
+    // Materializing every instance uses about as much as cloning.
+    for row in root.child_elements(&doc) {
+        row.materialize(&mut doc);
+    }
+    assert_eq!(doc.write_str().unwrap(), eager.write_str().unwrap());
+    assert!(doc.estimate_memory_usage() * 2 > cloned);
+}
+
+#[test]
+fn test_template_high_fidelity() {
+    let xml = r#"<?xml version="1.0"?><root><row  kind='data' xmlns:x="urn:x"><x:id   n='1'/></row></root>"#;
+    let opts = ReadOptions {
+        high_fidelity: true,
+        ..ReadOptions::default()
+    };
+    let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    let root = doc.root_element().unwrap();
+    let row = root.find(&doc, "row").unwrap();
+    let template = doc.create_template(row);
+    root.push_template_instance(&mut doc, template);
+    // Only the element itself keeps its source start tags.
+    let written = doc.write_str().unwrap();
+    for tag in [
+        r#"<row  kind='data' xmlns:x="urn:x">"#,
+        r#"<x:id   n='1'/>"#,
+        r#"<row xmlns:x="urn:x" kind="data">"#,
+        r#"<x:id n="1"/>"#,
+    ] {
+        assert_eq!(written.matches(tag).count(), 1, "{}", written);
+    }
+}
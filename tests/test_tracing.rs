@@ -0,0 +1,159 @@
+#![cfg(feature = "tracing")]
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+#[cfg(feature = "fs")]
+use xml_doc::WriteOptions;
+use xml_doc::{Document, ReadOptions};
+
+type Fields = HashMap<&'static str, String>;
+
+#[derive(Debug, Default)]
+struct Recorded {
+    // Name and fields of each span. The id of a span is its index + 1.
+    spans: Vec<(&'static str, Fields)>,
+    // Name of the span the event is in, and fields of the event.
+    events: Vec<(Option<&'static str>, Fields)>,
+    entered: Vec<usize>,
+}
+
+impl Recorded {
+    fn span(&self, name: &str) -> &Fields {
+        let mut spans = self.spans.iter().filter(|span| span.0 == name);
+        let span = spans.next().unwrap();
+        assert!(spans.next().is_none());
+        &span.1
+    }
+
+    fn event(&self, message: &str) -> &(Option<&'static str>, Fields) {
+        self.events
+            .iter()
+            .find(|event| event.1["message"] == message)
+            .unwrap()
+    }
+}
+
+struct Visitor<'a>(&'a mut Fields);
+
+impl<'a> Visit for Visitor<'a> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+}
+
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Recorded>>);
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+    fn new_span(&self, attrs: &Attributes) -> Id {
+        let mut recorded = self.0.lock().unwrap();
+        let mut fields = Fields::new();
+        attrs.record(&mut Visitor(&mut fields));
+        recorded.spans.push((attrs.metadata().name(), fields));
+        Id::from_u64(recorded.spans.len() as u64)
+    }
+    fn record(&self, span: &Id, values: &Record) {
+        let mut recorded = self.0.lock().unwrap();
+        let fields = &mut recorded.spans[span.into_u64() as usize - 1].1;
+        values.record(&mut Visitor(fields));
+    }
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+    fn event(&self, event: &Event) {
+        let mut recorded = self.0.lock().unwrap();
+        let mut fields = Fields::new();
+        event.record(&mut Visitor(&mut fields));
+        let span = recorded.entered.last().map(|id| recorded.spans[id - 1].0);
+        recorded.events.push((span, fields));
+    }
+    fn enter(&self, span: &Id) {
+        let mut recorded = self.0.lock().unwrap();
+        recorded.entered.push(span.into_u64() as usize);
+    }
+    fn exit(&self, _: &Id) {
+        self.0.lock().unwrap().entered.pop();
+    }
+}
+
+fn record<T>(f: impl FnOnce() -> T) -> (T, Recorded) {
+    let recorder = Recorder::default();
+    let result = tracing::subscriber::with_default(recorder.clone(), f);
+    let recorded = std::mem::take(&mut *recorder.0.lock().unwrap());
+    (result, recorded)
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_trace_parse_and_write() {
+    let path = "benches/medium.xml";
+    let len = std::fs::metadata(path).unwrap().len();
+    let (doc, recorded) = record(|| Document::parse_file(path).unwrap());
+    let span = recorded.span("parse");
+    assert_eq!(span["bytes"], len.to_string());
+    assert_eq!(span["encoding"], "UTF-8");
+    assert_eq!(span["elements"], doc.stats().elements.to_string());
+    assert_eq!(span["diagnostics"], "0");
+    assert!(recorded.events.is_empty());
+
+    let mut out = Vec::new();
+    let (stats, recorded) = record(|| {
+        doc.write_with_stats(&mut out, WriteOptions::default())
+            .unwrap()
+    });
+    let span = recorded.span("write");
+    assert_eq!(span["encoding"], "UTF-8");
+    assert_eq!(span["elements"], stats.elements.to_string());
+    assert_eq!(span["attributes"], stats.attributes.to_string());
+    assert_eq!(span["bytes"], out.len().to_string());
+    assert!(recorded.events.is_empty());
+}
+
+#[test]
+fn test_trace_failed_parse() {
+    let opts = ReadOptions {
+        max_text_len: Some(4),
+        ..ReadOptions::default()
+    };
+    let xml = r#"<?xml version="1.0"?><a><b>long text</b></a>"#;
+    let (result, recorded) = record(|| Document::parse_str_with_opts(xml, opts));
+    let err = result.unwrap_err();
+    assert_eq!(recorded.span("parse")["elements"], "2");
+    let (span, limit) = recorded.event("limit exceeded");
+    assert_eq!(*span, Some("parse"));
+    assert_eq!(limit["limit"], "max_text_len");
+    assert_eq!(limit["max"], "4");
+    let (span, failed) = recorded.event("parse failed");
+    assert_eq!(*span, Some("parse"));
+    assert_eq!(failed["error"], err.to_string());
+}
+
+#[test]
+fn test_trace_encoding_and_diagnostics() {
+    let opts = ReadOptions {
+        lenient: true,
+        ..ReadOptions::default()
+    };
+    let xml = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\" foo=\"1\"?><a>\xe9</a>";
+    let (doc, recorded) = record(|| Document::parse_bytes_with_opts(xml, opts).unwrap());
+    assert_eq!(doc.root_element().unwrap().text_content(&doc), "é");
+    let span = recorded.span("parse");
+    assert_eq!(span["encoding"], "windows-1252");
+    assert_eq!(span["diagnostics"], "1");
+    let (_, switched) = recorded.event("switched to declared encoding");
+    assert_eq!(switched["from"], "UTF-8");
+    assert_eq!(switched["to"], "windows-1252");
+    let (span, diagnostic) = recorded.event(&doc.diagnostics()[0].message);
+    assert_eq!(*span, Some("parse"));
+    assert_eq!(
+        diagnostic["position"],
+        doc.diagnostics()[0].position.to_string()
+    );
+}
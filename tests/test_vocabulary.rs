@@ -0,0 +1,112 @@
+use xml_doc::{Document, Node, ReadOptions};
+#[cfg(feature = "fs")]
+use xml_doc::{Violation, Vocabulary};
+
+#[cfg(feature = "fs")]
+fn parse(name: &str) -> Document {
+    Document::parse_file(format!("tests/documents/{}", name)).unwrap()
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_vocabulary() {
+    let vocab = parse("records.xml").vocabulary();
+    assert_eq!(vocab.roots.iter().collect::<Vec<_>>(), ["records"]);
+    assert_eq!(
+        vocab.elements.keys().collect::<Vec<_>>(),
+        ["name", "note", "record", "records", "status"]
+    );
+    let record = &vocab.elements["record"];
+    assert_eq!(record.attributes.iter().collect::<Vec<_>>(), ["id"]);
+    assert_eq!(
+        record.children.iter().collect::<Vec<_>>(),
+        ["name", "note", "status"]
+    );
+    assert!(!record.has_text);
+    assert!(vocab.elements["status"].has_text);
+    assert!(vocab.elements["status"].children.is_empty());
+
+    assert_eq!(parse("records_compatible.xml").conforms_to(&vocab), []);
+    assert_eq!(parse("records.xml").conforms_to(&vocab), []);
+
+    let path = |path: &str| path.to_string();
+    assert_eq!(
+        parse("records_modified.xml").conforms_to(&vocab),
+        [
+            Violation::UnknownAttribute {
+                path: path("/records/record[1]"),
+                name: path("owner"),
+            },
+            Violation::UnknownElement {
+                path: path("/records/record[1]/email"),
+            },
+            Violation::UnexpectedElement {
+                path: path("/records/record[2]/records"),
+            },
+            Violation::UnexpectedText {
+                path: path("/records/record[3]"),
+            },
+            Violation::UnknownAttribute {
+                path: path("/records/record[3]/name"),
+                name: path("lang"),
+            },
+        ]
+    );
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_vocabulary_merge() {
+    let mut vocab = parse("records.xml").vocabulary();
+    let modified = parse("records_modified.xml");
+    vocab.merge(&modified.vocabulary());
+    assert_eq!(modified.conforms_to(&vocab), []);
+    assert!(vocab.elements["record"].attributes.contains("owner"));
+    assert!(vocab.elements["record"].has_text);
+
+    // Merging is the same as collecting from all documents.
+    let mut merged = Vocabulary::default();
+    merged.merge(&parse("records_modified.xml").vocabulary());
+    merged.merge(&parse("records.xml").vocabulary());
+    assert_eq!(merged, vocab);
+
+    let doc = Document::parse_str(r#"<?xml version="1.0"?><other/>"#).unwrap();
+    assert_eq!(
+        doc.conforms_to(&vocab),
+        [Violation::UnknownElement {
+            path: "/other".to_string()
+        }]
+    );
+    let doc = Document::parse_str(r#"<?xml version="1.0"?><record/>"#).unwrap();
+    assert_eq!(
+        doc.conforms_to(&vocab),
+        [Violation::UnexpectedElement {
+            path: "/record".to_string()
+        }]
+    );
+}
+
+#[test]
+fn test_vocabulary_whitespace_text() {
+    let dir = std::env::temp_dir().join(format!("xml-doc-vocabulary-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let opts = ReadOptions {
+        trim_text: false,
+        ignore_whitespace_only: false,
+        max_text_len: Some(4),
+        spill_large_text_to: Some(dir.clone()),
+        ..ReadOptions::default()
+    };
+    let xml = r#"<?xml version="1.0"?><a><b><![CDATA[  ]]></b><c>          </c><d><![CDATA[ x ]]></d><e>    long    </e></a>"#;
+    let doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    let c = doc.root_element().unwrap().find(&doc, "c").unwrap();
+    assert!(matches!(c.children(&doc)[0], Node::ExternalText(_)));
+    let vocab = doc.vocabulary();
+    assert!(!vocab.elements["b"].has_text);
+    assert!(!vocab.elements["c"].has_text);
+    assert!(vocab.elements["d"].has_text);
+    assert!(vocab.elements["e"].has_text);
+
+    drop(doc);
+    std::fs::remove_dir(&dir).unwrap();
+}
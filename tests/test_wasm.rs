@@ -0,0 +1,28 @@
+//! Run with `wasm-pack test --headless --firefox -- --no-default-features --features js`.
+#![cfg(all(feature = "js", target_arch = "wasm32"))]
+
+use wasm_bindgen_test::*;
+use xml_doc::wasm::WasmDocument;
+
+#[wasm_bindgen_test]
+fn test_wasm_modify_attribute() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+  <server name="prod"/>
+</config>"#;
+    let mut doc = WasmDocument::parse(xml).unwrap();
+    let root = doc.root_element().unwrap();
+    let server = doc.find(root, "server").unwrap().unwrap();
+    assert_eq!(doc.find(root, "client").unwrap(), None);
+    doc.set_attribute(server, "name", "dev").unwrap();
+    assert_eq!(
+        doc.get_attribute(server, "name").unwrap().as_deref(),
+        Some("dev")
+    );
+
+    let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+  <server name="dev"/>
+</config>"#;
+    assert_eq!(doc.write_str().unwrap(), expected);
+}
@@ -1,10 +1,13 @@
-use xml_doc::{Document, Element, Node};
+use xml_doc::{
+    DeclPolicy, Document, Element, ElementShape, ElementWriteStyle, Error, Node, NsPosition,
+    PrologPolicy, ReadOptions, SelfCloseEmpty, UnmappablePolicy, WriteOptions,
+};
 
 #[test]
 fn test_escape() {
     let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
-<root attr="&gt;&lt;&amp;&quot;&apos;attrval">
-  <inner xmlns:ns="&gt;&lt;&amp;&quot;&apos;nsval">&gt;&lt;&amp;&quot;&apos;text</inner>
+<root attr=">&lt;&amp;&quot;'attrval">
+  <inner xmlns:ns=">&lt;&amp;&quot;'nsval">&gt;&lt;&amp;&quot;&apos;text</inner>
 </root>
 <!--<&amp;--><![CDATA[<&amp;]]><!DOCTYPE &lt;&amp;amp;>
 <?<&amp;?>"#;
@@ -19,8 +22,7 @@ fn test_escape() {
         .push_to(&mut doc, root);
     doc.push_root_node(Node::Comment("<&amp;".to_string()))
         .unwrap();
-    doc.push_root_node(Node::CData("<&amp;".to_string()))
-        .unwrap();
+    doc.push_root_node(Node::CData("<&amp;".into())).unwrap();
     doc.push_root_node(Node::DocType("<&amp;".to_string()))
         .unwrap();
     doc.push_root_node(Node::PI("<&amp;".to_string())).unwrap();
@@ -28,3 +30,1312 @@ fn test_escape() {
 
     assert_eq!(xml, expected);
 }
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_high_fidelity_start_tag() {
+    let opts = ReadOptions {
+        high_fidelity: true,
+        ..ReadOptions::default()
+    };
+    let mut doc = Document::parse_file_with_opts("tests/documents/aligned.xml", opts).unwrap();
+    let config = doc.root_element().unwrap();
+    let prod = config.child_elements(&doc)[0];
+    let dev = config.child_elements(&doc)[1];
+    dev.set_attribute(&mut doc, "name", "local");
+
+    let xml = doc.write_str().unwrap();
+    assert!(xml.contains(
+        "<server name=\"prod\"\n          host='example.com'\n          port=\"443\"  />"
+    ));
+    assert!(xml.contains("<server name=\"local\">"));
+
+    // Modified elements are written normally.
+    prod.set_attribute(&mut doc, "port", "80");
+    let xml = doc.write_str().unwrap();
+    assert!(!xml.contains("host='example.com'"));
+    assert!(xml.contains("host=\"example.com\""));
+}
+
+#[test]
+fn test_attribute_order() {
+    let xml = r#"<?xml version="1.0"?><a z="1" xmlns:y="urn:y" m="2" xmlns:b="urn:b" y:a="3"/>"#;
+    let mut doc = Document::parse_str(xml).unwrap();
+    let a = doc.root_element().unwrap();
+    let write = |doc: &Document| {
+        let opts = WriteOptions {
+            write_decl: false,
+            ..WriteOptions::default()
+        };
+        doc.write_str_with_opts(opts).unwrap()
+    };
+    assert_eq!(
+        write(&doc),
+        r#"<a xmlns:y="urn:y" xmlns:b="urn:b" z="1" m="2" y:a="3"/>"#
+    );
+
+    // Changed attributes keep their position, and new ones are added at the end.
+    a.set_attribute(&mut doc, "m", "4");
+    a.set_attribute(&mut doc, "b", "5");
+    a.remove_attribute(&mut doc, "z");
+    a.set_namespace_decl(&mut doc, "c", "urn:c");
+    a.remove_namespace_decl(&mut doc, "b");
+    assert_eq!(
+        write(&doc),
+        r#"<a xmlns:y="urn:y" xmlns:c="urn:c" m="4" y:a="3" b="5"/>"#
+    );
+    doc.rename_prefix("y", "x").unwrap();
+    assert_eq!(
+        write(&doc),
+        r#"<a xmlns:x="urn:y" xmlns:c="urn:c" m="4" x:a="3" b="5"/>"#
+    );
+}
+
+#[test]
+fn test_element_hook() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html>
+  <head>
+    <script>if (a &lt; b &amp;&amp; c) { f("]]>"); }<b>x</b></script>
+    <meta/>
+    <style/>
+  </head>
+  <body><p>text</p></body>
+</html>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    let opts = WriteOptions {
+        element_hook: Some(Box::new(|doc, elem| match elem.name(doc) {
+            "script" => ElementWriteStyle {
+                inline: true,
+                cdata_text: true,
+                ..ElementWriteStyle::default()
+            },
+            "style" => ElementWriteStyle {
+                expand_empty: true,
+                ..ElementWriteStyle::default()
+            },
+            "body" => ElementWriteStyle {
+                skip: true,
+                ..ElementWriteStyle::default()
+            },
+            _ => ElementWriteStyle::default(),
+        })),
+        ..WriteOptions::default()
+    };
+    let written = doc.write_str_with_opts(opts).unwrap();
+    let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html>
+  <head>
+    <script><![CDATA[if (a < b && c) { f("]]]]><![CDATA[>"); }]]><b>x</b></script>
+    <meta/>
+    <style></style>
+  </head>
+</html>"#;
+    assert_eq!(written, expected);
+
+    let new_doc = Document::parse_str(&written).unwrap();
+    let script = new_doc
+        .root_element()
+        .unwrap()
+        .find(&new_doc, "head")
+        .unwrap()
+        .find(&new_doc, "script")
+        .unwrap();
+    assert_eq!(
+        script.text_content(&new_doc),
+        "if (a < b && c) { f(\"]]>\"); }x"
+    );
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_write_fmt() {
+    let mut count = 0;
+    for entry in std::fs::read_dir("tests/documents").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|e| e.to_str()) != Some("xml") {
+            continue;
+        }
+        let doc = match Document::parse_file(&path) {
+            Ok(doc) => doc,
+            Err(_) => continue, // fixtures for parse errors
+        };
+        let mut out = String::new();
+        doc.write_fmt_with_opts(&mut out, WriteOptions::default())
+            .unwrap();
+        assert_eq!(out, doc.write_str().unwrap(), "{:?}", path);
+        count += 1;
+    }
+    assert!(count > 3);
+}
+
+// Fails after receiving `limit` bytes.
+#[cfg(feature = "fs")]
+struct LimitedSink {
+    buf: String,
+    limit: usize,
+}
+
+#[cfg(feature = "fs")]
+impl std::fmt::Write for LimitedSink {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        if self.buf.len() + s.len() > self.limit {
+            return Err(std::fmt::Error);
+        }
+        self.buf.push_str(s);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_write_fmt_error() {
+    let doc = Document::parse_file("tests/documents/nodes.xml").unwrap();
+    let mut sink = LimitedSink {
+        buf: String::new(),
+        limit: 60,
+    };
+    let err = doc
+        .write_fmt_with_opts(&mut sink, WriteOptions::default())
+        .unwrap_err();
+    assert!(matches!(err, Error::Fmt(_)));
+    assert!(doc.write_str().unwrap().starts_with(&sink.buf));
+}
+
+#[test]
+fn test_write_duplicate_namespace_decl() {
+    let mut doc = Document::new();
+    let container = doc.container();
+    let root = Element::build("p:root")
+        .namespace_decl("p", "urn:a")
+        .push_to(&mut doc, container);
+    root.set_attribute(&mut doc, "xmlns:p", "urn:b");
+    let err = doc.write_str().unwrap_err();
+    assert!(matches!(err, Error::MalformedXML(msg) if msg.contains("'p'")));
+
+    root.mut_attributes(&mut doc).shift_remove("xmlns:p");
+    root.set_attribute(&mut doc, "xmlns:q", "urn:b");
+    assert!(doc.write_str().is_ok());
+}
+
+// Fails after accepting `limit` bytes.
+struct FailingWriter {
+    written: usize,
+    limit: usize,
+}
+
+impl std::io::Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.written + buf.len() > self.limit {
+            return Err(std::io::Error::other("disk full"));
+        }
+        self.written += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_write_interrupted() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+  <server>
+    <name>prod</name>
+  </server>
+  <server>
+    <name>dev</name>
+  </server>
+</config>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    let full = doc.write_str().unwrap();
+    assert_eq!(full, xml);
+
+    let limit = full.find("dev").unwrap();
+    let mut sink = FailingWriter { written: 0, limit };
+    match doc.write(&mut sink).unwrap_err() {
+        Error::WriteInterrupted {
+            bytes_written,
+            bytes_flushed,
+            at,
+            source,
+        } => {
+            assert_eq!(bytes_written, sink.written);
+            assert_eq!(bytes_flushed, 0);
+            assert!(bytes_written <= limit);
+            assert_eq!(at, "/config/server[2]/name");
+            assert_eq!(source.to_string(), "disk full");
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+
+    // Fails while writing the declaration.
+    let mut sink = FailingWriter {
+        written: 0,
+        limit: 0,
+    };
+    match doc.write(&mut sink).unwrap_err() {
+        Error::WriteInterrupted {
+            bytes_written, at, ..
+        } => {
+            assert_eq!(bytes_written, 0);
+            assert_eq!(at, "/");
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+}
+
+// Records written bytes and their length at each flush. Fails after accepting `limit` bytes.
+#[derive(Default)]
+struct FlushRecorder {
+    output: Vec<u8>,
+    flushes: Vec<usize>,
+    limit: Option<usize>,
+}
+
+impl std::io::Write for FlushRecorder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(limit) = self.limit {
+            if self.output.len() + buf.len() > limit {
+                return Err(std::io::Error::other("connection reset"));
+            }
+        }
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flushes.push(self.output.len());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_flush_every_bytes() {
+    let mut xml = String::from(r#"<?xml version="1.0"?><rows>"#);
+    for i in 0..200 {
+        xml.push_str(&format!(
+            r#"<row id="{}"><name>row &amp; {}</name><!-- c --></row>"#,
+            i, i
+        ));
+    }
+    xml.push_str("</rows>");
+    let doc = Document::parse_str(&xml).unwrap();
+    let expected = doc.write_str().unwrap();
+
+    let every = 1000;
+    let opts = || WriteOptions {
+        flush_every_bytes: Some(every),
+        ..WriteOptions::default()
+    };
+    let mut sink = FlushRecorder::default();
+    let stats = doc.write_with_stats(&mut sink, opts()).unwrap();
+    assert_eq!(String::from_utf8(sink.output).unwrap(), expected);
+    assert_eq!(stats.flushes, sink.flushes.len());
+    assert_eq!(sink.flushes.last(), Some(&expected.len()));
+    let mut previous = 0;
+    for (i, &flushed) in sink.flushes.iter().enumerate() {
+        let interval = flushed - previous;
+        if i < sink.flushes.len() - 1 {
+            // Flushed between nodes, soon after `every` bytes.
+            assert!(interval >= every && interval < every + 100, "{}", interval);
+            let written = &expected[..flushed];
+            assert!(written.ends_with('>') || written.ends_with(char::is_numeric));
+            let escape = written.rfind('&').unwrap();
+            assert!(written[escape..].contains(';'));
+        }
+        previous = flushed;
+    }
+    assert_eq!(sink.flushes.len(), expected.len() / every + 1);
+
+    // No flushes by default.
+    let mut sink = FlushRecorder::default();
+    let stats = doc
+        .write_with_stats(&mut sink, WriteOptions::default())
+        .unwrap();
+    assert!(sink.flushes.is_empty());
+    assert_eq!(stats.flushes, 0);
+
+    // Failure tells how much was flushed.
+    let mut sink = FlushRecorder {
+        limit: Some(3500),
+        ..FlushRecorder::default()
+    };
+    match doc.write_with_opts(&mut sink, opts()).unwrap_err() {
+        Error::WriteInterrupted {
+            bytes_written,
+            bytes_flushed,
+            ..
+        } => {
+            assert_eq!(bytes_written, sink.output.len());
+            assert_eq!(Some(&bytes_flushed), sink.flushes.last());
+            assert!(bytes_flushed >= 3 * every && bytes_flushed <= bytes_written);
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_decl_policy() {
+    let verbatim = || WriteOptions {
+        decl: DeclPolicy::Verbatim,
+        ..WriteOptions::default()
+    };
+    for decl in [
+        "<?xml version='1.0'?>",
+        "<?xml  version = \"1.0\"   encoding='utf-8' standalone=\"yes\" ?>",
+    ] {
+        let xml = format!("{}\n<root>\n  <a/>\n</root>", decl);
+        let doc = Document::parse_str(&xml).unwrap();
+        assert_eq!(doc.raw_decl(), Some(decl));
+        assert_eq!(doc.write_str_with_opts(verbatim()).unwrap(), xml);
+    }
+
+    // Normalized is the default.
+    let xml = "<?xml version='1.0' standalone='no'?><root/>";
+    let mut doc = Document::parse_str(xml).unwrap();
+    let normalized = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n<root/>";
+    assert_eq!(doc.write_str().unwrap(), normalized);
+    let opts = WriteOptions {
+        decl: DeclPolicy::Omit,
+        ..WriteOptions::default()
+    };
+    assert_eq!(doc.write_str_with_opts(opts).unwrap(), "<root/>");
+
+    // Falls back to Normalized when the declaration was changed or not UTF-8.
+    doc.set_standalone(Some(true));
+    assert_eq!(doc.raw_decl(), None);
+    assert!(doc
+        .write_str_with_opts(verbatim())
+        .unwrap()
+        .starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>"));
+    let doc = Document::parse_file("tests/documents/encoding1.xml").unwrap();
+    assert!(doc
+        .write_str_with_opts(verbatim())
+        .unwrap()
+        .starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+    let mut doc = Document::new();
+    let container = doc.container();
+    Element::build("root").push_to(&mut doc, container);
+    assert_eq!(
+        doc.write_str_with_opts(verbatim()).unwrap(),
+        doc.write_str().unwrap()
+    );
+}
+
+#[test]
+fn test_preserve_shape() {
+    let xml = "<root><a/><b></b><c>  </c><d>x</d><e><!--c--></e></root>";
+    let preserve = || WriteOptions {
+        write_decl: false,
+        pretty: false,
+        self_close_empty: SelfCloseEmpty::Preserve,
+        ..WriteOptions::default()
+    };
+    for empty_text_node in [true, false] {
+        let opts = ReadOptions {
+            empty_text_node,
+            require_decl: false,
+            ..ReadOptions::default()
+        };
+        let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+        let root = doc.root_element().unwrap();
+        let shapes: Vec<Option<ElementShape>> = root
+            .child_elements(&doc)
+            .iter()
+            .map(|elem| elem.authored_shape(&doc))
+            .collect();
+        assert_eq!(
+            shapes,
+            [
+                Some(ElementShape::SelfClosing),
+                Some(ElementShape::EmptyPair),
+                // Its whitespace is trimmed.
+                Some(ElementShape::EmptyPair),
+                Some(ElementShape::WithContent),
+                Some(ElementShape::WithContent),
+            ]
+        );
+        assert_eq!(root.authored_shape(&doc), Some(ElementShape::WithContent));
+        assert_eq!(
+            doc.write_str_with_opts(preserve()).unwrap(),
+            "<root><a/><b></b><c></c><d>x</d><e><!--c--></e></root>"
+        );
+
+        // Content is written whatever the shape was.
+        let a = root.find(&doc, "a").unwrap();
+        a.set_text_content(&mut doc, "y");
+        assert_eq!(a.authored_shape(&doc), Some(ElementShape::SelfClosing));
+        let d = root.find(&doc, "d").unwrap();
+        d.clear_children(&mut doc);
+        let e = root.find(&doc, "e").unwrap();
+        e.clear_children(&mut doc);
+        let f = Element::build("f").push_to(&mut doc, root);
+        assert_eq!(f.authored_shape(&doc), None);
+        assert_eq!(
+            doc.write_str_with_opts(preserve()).unwrap(),
+            "<root><a>y</a><b></b><c></c><d></d><e></e><f/></root>"
+        );
+    }
+
+    // Pretty printing keeps the shapes.
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<root>
+  <a/>
+  <b></b>
+  <c>x</c>
+</root>"#;
+    let opts = ReadOptions {
+        empty_text_node: false,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    let opts = WriteOptions {
+        self_close_empty: SelfCloseEmpty::Preserve,
+        ..WriteOptions::default()
+    };
+    assert_eq!(doc.write_str_with_opts(opts).unwrap(), xml);
+    assert!(doc.write_str().unwrap().contains("<b/>"));
+}
+
+#[test]
+fn test_self_close_empty() {
+    let xml = "<root><a/><b></b><c>  </c><d>x</d></root>";
+    let opts = ReadOptions {
+        empty_text_node: true,
+        require_decl: false,
+        ..ReadOptions::default()
+    };
+    let mut doc = Document::parse_str_with_opts(xml, opts.clone()).unwrap();
+    assert_eq!(doc.read_options(), Some(&opts));
+    let compact = || WriteOptions {
+        write_decl: false,
+        pretty: false,
+        ..WriteOptions::default()
+    };
+    assert_eq!(
+        doc.write_str_with_opts(compact()).unwrap(),
+        "<root><a/><b></b><c></c><d>x</d></root>"
+    );
+    let self_close = || WriteOptions {
+        self_close_empty: SelfCloseEmpty::ParsedEmpty,
+        ..compact()
+    };
+    assert_eq!(
+        doc.write_str_with_opts(self_close()).unwrap(),
+        "<root><a/><b/><c/><d>x</d></root>"
+    );
+
+    // Empty text set by the user is kept.
+    let root = doc.root_element().unwrap();
+    let d = root.find(&doc, "d").unwrap();
+    d.set_text_content(&mut doc, "");
+    let c = root.find(&doc, "c").unwrap();
+    c.set_text_content(&mut doc, "y");
+    assert_eq!(
+        doc.write_str_with_opts(self_close()).unwrap(),
+        "<root><a/><b/><c>y</c><d></d></root>"
+    );
+
+    // `expand_empty` takes precedence.
+    let opts = WriteOptions {
+        element_hook: Some(Box::new(|doc, elem| ElementWriteStyle {
+            expand_empty: elem.name(doc) == "b",
+            ..ElementWriteStyle::default()
+        })),
+        ..self_close()
+    };
+    assert_eq!(
+        doc.write_str_with_opts(opts).unwrap(),
+        "<root><a/><b></b><c>y</c><d></d></root>"
+    );
+}
+
+#[test]
+fn test_prolog_policy() {
+    let build = |policy: PrologPolicy| {
+        let mut doc = Document::new();
+        doc.set_prolog_policy(policy);
+        doc.push_root_node(Node::Comment(" head ".to_string()))
+            .unwrap();
+        let root = Element::new(&mut doc, "root");
+        doc.push_root_node(root.as_node()).unwrap();
+        doc.push_root_node(Node::PI("pi".to_string())).unwrap();
+        doc.push_root_node(Node::DocType("root".to_string()))
+            .unwrap();
+        doc.push_root_node(Node::Comment(" tail ".to_string()))
+            .unwrap();
+        doc
+    };
+    let mut doc = build(PrologPolicy::Strict);
+    assert_eq!(
+        doc.write_str().unwrap(),
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- head -->
+<!DOCTYPE root>
+<root/>
+<?pi?>
+<!-- tail -->"#
+    );
+    let err = doc
+        .push_root_node(Node::DocType("root".to_string()))
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidProlog(_)));
+    doc.insert_root_node(0, Node::PI("first".to_string()))
+        .unwrap();
+    let other = Element::new(&mut doc, "other");
+    let err = doc.insert_root_node(1, other.as_node()).unwrap_err();
+    assert!(matches!(err, Error::InvalidProlog(_)));
+    assert_eq!(doc.root_nodes().len(), 6);
+    assert!(matches!(doc.root_nodes()[0], Node::PI(_)));
+
+    let mut doc = Document::new();
+    doc.set_prolog_policy(PrologPolicy::Strict);
+    let root = Element::new(&mut doc, "root");
+    doc.push_root_node(root.as_node()).unwrap();
+    let err = doc
+        .insert_root_node(1, Node::DocType("root".to_string()))
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidProlog(_)));
+
+    // Permissive and fragment mode keep the pushed order.
+    let expected = r#"<!-- head -->
+<root/>
+<?pi?>
+<!DOCTYPE root>
+<!-- tail -->"#;
+    let opts = || WriteOptions {
+        write_decl: false,
+        ..WriteOptions::default()
+    };
+    let doc = build(PrologPolicy::Permissive);
+    assert_eq!(doc.write_str_with_opts(opts()).unwrap(), expected);
+    let mut doc = Document::new();
+    doc.set_fragment_mode(true);
+    doc.set_prolog_policy(PrologPolicy::Strict);
+    doc.push_root_node(Node::DocType("a".to_string())).unwrap();
+    doc.push_root_node(Node::DocType("b".to_string())).unwrap();
+    assert_eq!(doc.root_nodes().len(), 2);
+}
+
+#[test]
+fn test_namespace_position() {
+    let xml = r#"<?xml version="1.0"?>
+<root b="2" xmlns="urn:d" a="1"><x:item xmlns:x="urn:x" x:k="v"/><x:other xmlns:x="urn:x"/></root>"#;
+    let opts = ReadOptions {
+        high_fidelity: true,
+        ..ReadOptions::default()
+    };
+    let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    // Modify the start tags so they aren't written as is.
+    for elem in doc
+        .root_element()
+        .unwrap()
+        .descendants(&doc)
+        .collect::<Vec<_>>()
+    {
+        elem.set_attribute(&mut doc, "n", "0");
+    }
+    let root = doc.root_element().unwrap();
+    root.set_attribute(&mut doc, "n", "0");
+    let write = |position: NsPosition| {
+        let opts = WriteOptions {
+            write_decl: false,
+            pretty: false,
+            namespace_position: position,
+            ..WriteOptions::default()
+        };
+        doc.write_str_with_opts(opts).unwrap()
+    };
+    let first = r#"<root xmlns="urn:d" b="2" a="1" n="0"><x:item xmlns:x="urn:x" x:k="v" n="0"/><x:other xmlns:x="urn:x" n="0"/></root>"#;
+    let last = r#"<root b="2" a="1" n="0" xmlns="urn:d"><x:item x:k="v" n="0" xmlns:x="urn:x"/><x:other n="0" xmlns:x="urn:x"/></root>"#;
+    let as_parsed = r#"<root b="2" a="1" n="0" xmlns="urn:d"><x:item xmlns:x="urn:x" x:k="v" n="0"/><x:other xmlns:x="urn:x" n="0"/></root>"#;
+    assert_eq!(write(NsPosition::default()), first);
+    assert_eq!(write(NsPosition::First), first);
+    assert_eq!(write(NsPosition::Last), last);
+    assert_eq!(write(NsPosition::AsParsed), as_parsed);
+    for xml in [first, last, as_parsed] {
+        let opts = ReadOptions {
+            require_decl: false,
+            ..ReadOptions::default()
+        };
+        let reparsed = Document::parse_str_with_opts(xml, opts).unwrap();
+        assert!(reparsed.content_eq(&doc));
+    }
+
+    // Without high fidelity, the source order isn't known.
+    let doc = Document::parse_str(xml).unwrap();
+    let opts = WriteOptions {
+        namespace_position: NsPosition::AsParsed,
+        ..WriteOptions::default()
+    };
+    assert!(doc
+        .write_str_with_opts(opts)
+        .unwrap()
+        .contains(r#"<root xmlns="urn:d" b="2" a="1">"#));
+}
+
+#[test]
+fn test_declare_missing_prefixes() {
+    let xml = r#"<?xml version="1.0"?>
+<root xmlns:x="urn:x"><a x:k="v"><b/></a><c/></root>"#;
+    let opts = ReadOptions {
+        high_fidelity: true,
+        ..ReadOptions::default()
+    };
+    let mut doc = Document::parse_str_with_opts(xml, opts).unwrap();
+    let root = doc.root_element().unwrap();
+    let a = root.find(&doc, "a").unwrap();
+    let b = a.find(&doc, "b").unwrap();
+    let c = root.find(&doc, "c").unwrap();
+    assert!(doc.check_prefixes().is_ok());
+    let write = |doc: &Document| {
+        let opts = WriteOptions {
+            write_decl: false,
+            pretty: false,
+            declare_missing_prefixes: true,
+            ..WriteOptions::default()
+        };
+        doc.write_str_with_opts(opts)
+    };
+    // Already declared prefixes are untouched.
+    assert_eq!(
+        write(&doc).unwrap(),
+        r#"<root xmlns:x="urn:x"><a x:k="v"><b/></a><c/></root>"#
+    );
+
+    // Undeclared attribute prefix is caught.
+    b.set_attribute(&mut doc, "foo:bar", "1");
+    assert_eq!(b.undeclared_prefixes(&doc), vec!["foo"]);
+    match doc.check_prefixes() {
+        Err(Error::UndeclaredPrefix {
+            prefix,
+            element_path,
+        }) => {
+            assert_eq!(prefix, "foo");
+            assert_eq!(element_path, "/root/a/b");
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!(matches!(
+        write(&doc),
+        Err(Error::UndeclaredPrefix { prefix, .. }) if prefix == "foo"
+    ));
+    // Without the option, it is written as is.
+    let plain = WriteOptions {
+        write_decl: false,
+        pretty: false,
+        ..WriteOptions::default()
+    };
+    assert!(doc
+        .write_str_with_opts(plain)
+        .unwrap()
+        .contains(r#"<b foo:bar="1"/>"#));
+
+    // Registered prefix is declared on the outermost element using it.
+    doc.register_namespace("foo", "urn:foo");
+    assert_eq!(doc.registered_namespace("foo"), Some("urn:foo"));
+    a.set_attribute(&mut doc, "foo:baz", "2");
+    c.set_full_name(&mut doc, "foo:c");
+    let written = write(&doc).unwrap();
+    assert_eq!(
+        written,
+        r#"<root xmlns:x="urn:x"><a xmlns:foo="urn:foo" x:k="v" foo:baz="2"><b foo:bar="1"/></a><foo:c xmlns:foo="urn:foo"/></root>"#
+    );
+    // Registering doesn't declare it in the document.
+    assert!(doc.check_prefixes().is_err());
+    let reparsed = Document::parse_str_with_opts(
+        &written,
+        ReadOptions {
+            require_decl: false,
+            ..ReadOptions::default()
+        },
+    )
+    .unwrap();
+    assert!(reparsed.check_prefixes().is_ok());
+}
+
+#[test]
+fn test_trailing_newline() {
+    let cases = [
+        (
+            "<root><a/></root>",
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<root>\n  <a/>\n</root>",
+        ),
+        (
+            "<root/><!--end-->",
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<root/>\n<!--end-->",
+        ),
+        (
+            "<root/><?pi end?>",
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<root/>\n<?pi end?>",
+        ),
+    ];
+    for (body, expected) in cases {
+        let xml = format!("<?xml version=\"1.0\"?>{}", body);
+        let doc = Document::parse_str(&xml).unwrap();
+        let write = |trailing_newline: bool| {
+            let opts = WriteOptions {
+                trailing_newline,
+                ..WriteOptions::default()
+            };
+            doc.write_str_with_opts(opts).unwrap()
+        };
+        assert_eq!(write(false).as_bytes(), expected.as_bytes());
+        let with_newline = write(true);
+        assert_eq!(
+            with_newline.as_bytes(),
+            format!("{}\n", expected).as_bytes()
+        );
+
+        // Rewriting a parsed output ends with exactly one newline again.
+        let reparsed = Document::parse_str(&with_newline).unwrap();
+        let opts = WriteOptions {
+            trailing_newline: true,
+            ..WriteOptions::default()
+        };
+        assert_eq!(reparsed.write_str_with_opts(opts).unwrap(), with_newline);
+    }
+
+    // Without indentation
+    let doc = Document::parse_str("<?xml version=\"1.0\"?><root/><!--end-->").unwrap();
+    let opts = WriteOptions {
+        pretty: false,
+        write_decl: false,
+        trailing_newline: true,
+        ..WriteOptions::default()
+    };
+    assert_eq!(
+        doc.write_str_with_opts(opts).unwrap(),
+        "<root/><!--end-->\n"
+    );
+
+    // Nothing is written for an empty fragment.
+    let mut doc = Document::new();
+    doc.set_fragment_mode(true);
+    let opts = WriteOptions {
+        trailing_newline: true,
+        ..WriteOptions::default()
+    };
+    assert_eq!(doc.write_str_with_opts(opts).unwrap(), "");
+}
+
+#[test]
+fn test_unmappable() {
+    let write = |xml: &str, unmappable: UnmappablePolicy| {
+        let doc = Document::parse_str(xml).unwrap();
+        let opts = WriteOptions {
+            encoding: Some("ISO-8859-1".to_string()),
+            unmappable,
+            ..WriteOptions::default()
+        };
+        let mut out = Vec::new();
+        doc.write_with_opts(&mut out, opts).map(|_| out)
+    };
+    let assert_unmappable = |res: Result<Vec<u8>, Error>, context: &str, path: &str| match res {
+        Err(Error::Unmappable {
+            character,
+            encoding,
+            context: err_context,
+            element_path,
+        }) => {
+            assert_eq!(character, '€');
+            assert_eq!(encoding, "ISO-8859-1");
+            assert_eq!(err_context, context);
+            assert_eq!(element_path, path);
+        }
+        res => panic!("unexpected result: {:?}", res),
+    };
+
+    let xml = r#"<?xml version="1.0"?><root attr="5 €"><p>10 € é</p></root>"#;
+    let mut expected = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n<root attr=\"5 &#8364;\">\n  <p>10 &#8364; "
+        .to_vec();
+    expected.push(0xE9); // é
+    expected.extend_from_slice(b"</p>\n</root>");
+    assert_eq!(write(xml, UnmappablePolicy::CharRef).unwrap(), expected);
+    assert_unmappable(
+        write(xml, UnmappablePolicy::Error),
+        "attribute value",
+        "/root",
+    );
+    let xml = r#"<?xml version="1.0"?><root><p/><p>10 €</p></root>"#;
+    assert_unmappable(write(xml, UnmappablePolicy::Error), "text", "/root/p[2]");
+
+    // Character references aren't recognized in comments, so it's always an error.
+    let xml = r#"<?xml version="1.0"?><root><!--5 €--></root>"#;
+    for policy in [UnmappablePolicy::CharRef, UnmappablePolicy::Error] {
+        assert_unmappable(write(xml, policy), "comment", "/root");
+    }
+    let xml = r#"<?xml version="1.0"?><root/><?pi 5 €?>"#;
+    assert_unmappable(
+        write(xml, UnmappablePolicy::CharRef),
+        "processing instruction",
+        "/",
+    );
+    let xml = r#"<?xml version="1.0"?><root><€/></root>"#;
+    assert_unmappable(
+        write(xml, UnmappablePolicy::CharRef),
+        "element name",
+        "/root/€",
+    );
+
+    // Output in ASCII is also valid UTF-8.
+    let doc = Document::parse_str(r#"<?xml version="1.0"?><a b="é">€</a>"#).unwrap();
+    let opts = WriteOptions {
+        encoding: Some("ascii".to_string()),
+        ..WriteOptions::default()
+    };
+    assert_eq!(
+        doc.write_str_with_opts(opts).unwrap(),
+        "<?xml version=\"1.0\" encoding=\"US-ASCII\"?>\n<a b=\"&#233;\">&#8364;</a>"
+    );
+    let opts = WriteOptions {
+        encoding: Some("utf-16".to_string()),
+        ..WriteOptions::default()
+    };
+    assert!(matches!(
+        doc.write_str_with_opts(opts),
+        Err(Error::UnsupportedEncoding(_))
+    ));
+}
+
+#[test]
+fn test_write_encoding() {
+    let (bytes, _, _) = encoding_rs::SHIFT_JIS
+        .encode("<?xml version='1.0' encoding='shift_jis'?>\n<a b=\"日本\">語 €</a>");
+    let doc = Document::parse_bytes(&bytes).unwrap();
+    // Verbatim declaration is kept when it declares the output encoding.
+    let opts = WriteOptions {
+        encoding: Some("Shift_JIS".to_string()),
+        decl: DeclPolicy::Verbatim,
+        ..WriteOptions::default()
+    };
+    let mut out = Vec::new();
+    doc.write_with_opts(&mut out, opts).unwrap();
+    let (expected, _, _) = encoding_rs::SHIFT_JIS
+        .encode("<?xml version='1.0' encoding='shift_jis'?>\n<a b=\"日本\">語 &#8364;</a>");
+    assert_eq!(out, expected.as_ref());
+    assert!(Document::parse_bytes(&out)
+        .unwrap()
+        .content_eq(&Document::parse_str("<?xml version='1.0'?><a b=\"日本\">語 €</a>").unwrap()));
+    // Otherwise the declaration is normalized.
+    let opts = WriteOptions {
+        encoding: Some("euc-jp".to_string()),
+        decl: DeclPolicy::Verbatim,
+        ..WriteOptions::default()
+    };
+    let mut out = Vec::new();
+    doc.write_with_opts(&mut out, opts).unwrap();
+    assert!(out.starts_with(b"<?xml version=\"1.0\" encoding=\"EUC-JP\"?>"));
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_align_comments() {
+    let read_opts = ReadOptions {
+        trim_text: false,
+        ..ReadOptions::default()
+    };
+    let path = "tests/documents/commented_config.xml";
+    let doc = Document::parse_file_with_opts(path, read_opts.clone()).unwrap();
+    let write = |align_comments: bool| {
+        let opts = WriteOptions {
+            align_comments,
+            ..WriteOptions::default()
+        };
+        doc.write_str_with_opts(opts).unwrap()
+    };
+    let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+  <!-- Address to listen on -->
+  <host>0.0.0.0</host>
+
+  <!-- Port to listen on.
+       Use 443 with TLS. -->
+  <!-- Defaults to 80. -->
+  <port>80</port>
+  <tls enabled="false"/>
+  <!-- Certificates are read from the key store -->
+  <keystore>
+    <!-- Path relative to the working directory -->
+    <path>keys.p12</path>
+  </keystore>
+  <!-- Mixed content is written as is -->
+  <note>See <!-- docs --> <b>docs</b>.</note>
+  <!-- Nothing follows this comment -->
+</config>
+"#;
+    assert_eq!(write(true), expected);
+    let not_aligned = write(false);
+    assert!(not_aligned.contains("<!-- Defaults to 80. --> <port>"));
+    assert!(not_aligned.contains("<!-- Address to listen on -->\n\n  <host>"));
+
+    // Aligned output is written the same when parsed again.
+    let doc = Document::parse_str_with_opts(expected, read_opts).unwrap();
+    let opts = WriteOptions {
+        align_comments: true,
+        ..WriteOptions::default()
+    };
+    assert_eq!(doc.write_str_with_opts(opts).unwrap(), expected);
+
+    // Trimmed documents are already aligned.
+    let doc = Document::parse_file(path).unwrap();
+    let opts = WriteOptions {
+        align_comments: true,
+        ..WriteOptions::default()
+    };
+    assert_eq!(
+        doc.write_str_with_opts(opts).unwrap(),
+        doc.write_str().unwrap()
+    );
+}
+
+#[test]
+fn test_write_stats() {
+    let xml = r#"<?xml version="1.0"?>
+<root xmlns:x="urn:x" lang="en">
+  <x:item id="1" name="a &amp; b">one</x:item>
+  <item id="2"><![CDATA[<two>]]></item>
+  <!-- comment -->
+  <skip note="not written"><inner/></skip>
+</root>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    let doc_stats = doc.stats();
+    assert_eq!(doc_stats.elements, 5);
+    assert_eq!(doc_stats.attributes, 5);
+    assert_eq!(doc_stats.namespace_decls, 1);
+    assert_eq!(doc_stats.texts, 2);
+    assert_eq!(doc_stats.comments, 1);
+
+    for pretty in [true, false] {
+        let opts = WriteOptions {
+            pretty,
+            ..WriteOptions::default()
+        };
+        let mut out = Vec::new();
+        let stats = doc.write_with_stats(&mut out, opts).unwrap();
+        assert_eq!(stats.elements, doc_stats.elements);
+        assert_eq!(stats.attributes, doc_stats.attributes);
+        assert_eq!(stats.bytes, out.len());
+        // Attribute values, the namespace declaration and the text. CDATA isn't escaped.
+        assert_eq!(stats.escape_calls, 5 + 1 + 1);
+        let expected = doc
+            .write_str_with_opts(WriteOptions {
+                pretty,
+                ..WriteOptions::default()
+            })
+            .unwrap();
+        assert_eq!(out, expected.as_bytes());
+    }
+
+    // Skipped elements aren't counted.
+    let opts = WriteOptions {
+        element_hook: Some(Box::new(|doc, elem| ElementWriteStyle {
+            skip: elem.name(doc) == "skip",
+            ..ElementWriteStyle::default()
+        })),
+        ..WriteOptions::default()
+    };
+    let stats = doc.write_with_stats(&mut Vec::new(), opts).unwrap();
+    assert_eq!(stats.elements, doc_stats.elements - 2);
+    assert_eq!(stats.attributes, doc_stats.attributes - 1);
+
+    // Start tags kept as parsed aren't escaped again.
+    let read_opts = ReadOptions {
+        high_fidelity: true,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_str_with_opts(xml, read_opts).unwrap();
+    let stats = doc
+        .write_with_stats(&mut Vec::new(), WriteOptions::default())
+        .unwrap();
+    assert_eq!(stats.attributes, doc_stats.attributes);
+    assert_eq!(stats.escape_calls, 1);
+}
+
+// Expanded names of elements and attributes, with attribute values.
+type ExpandedAttrs = Vec<(Option<String>, String, String)>;
+fn expanded_names(doc: &Document) -> Vec<(Option<String>, String, ExpandedAttrs)> {
+    doc.container()
+        .descendants(doc)
+        .map(|elem| {
+            let (ns, name) = elem.expanded_name(doc);
+            let mut attrs: ExpandedAttrs = elem
+                .attributes(doc)
+                .iter()
+                .map(|(key, value)| {
+                    let (prefix, name) = Element::separate_prefix_name(key);
+                    let ns = match prefix {
+                        "" => None,
+                        _ => elem.namespace_for_prefix(doc, prefix),
+                    };
+                    (ns.map(String::from), name.to_string(), value.to_string())
+                })
+                .collect();
+            attrs.sort();
+            (ns.map(String::from), name.to_string(), attrs)
+        })
+        .collect()
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_unify_namespace_prefixes() {
+    use std::collections::HashMap;
+
+    let soap = "http://schemas.xmlsoap.org/soap/envelope/";
+    let mut doc = Document::parse_file("tests/documents/merged_soap.xml").unwrap();
+    let before = expanded_names(&doc);
+    let preferred = HashMap::from([(soap.to_string(), "soap".to_string())]);
+    // Envelope, Header, Body and mustUnderstand
+    assert_eq!(doc.unify_namespace_prefixes(&preferred).unwrap(), 4);
+    assert_eq!(expanded_names(&doc), before);
+
+    let xml = doc.write_str().unwrap();
+    let written = Document::parse_str(&xml).unwrap();
+    assert_eq!(expanded_names(&written), before);
+    assert!(!xml.contains("ns1"));
+    assert!(!xml.contains("ns0:Envelope"));
+    assert!(!xml.contains(&format!(r#"xmlns:ns0="{}""#, soap)));
+    assert_eq!(xml.matches("xmlns:soap=").count(), 1);
+    // The shadowing prefix keeps its namespace, and unprefixed attributes have none.
+    let detail = doc.find_descendant("detail").unwrap();
+    assert_eq!(detail.full_name(&doc), "ns0:detail");
+    assert_eq!(detail.attribute(&doc, "soap:role"), Some("next"));
+    assert_eq!(detail.attribute(&doc, "code"), Some("42"));
+    let token = doc.find_descendant("token").unwrap();
+    assert_eq!(token.expanded_name(&doc), (Some("urn:auth"), "token"));
+    assert_eq!(token.attribute(&doc, "soap:mustUnderstand"), Some("1"));
+    assert_eq!(token.attribute(&doc, "id"), Some("t1"));
+
+    // Prefixes that are taken get a generated one.
+    let mut doc = Document::parse_file("tests/documents/merged_soap.xml").unwrap();
+    let preferred = HashMap::from([
+        (soap.to_string(), "ns0".to_string()),
+        ("urn:auth".to_string(), "ns1".to_string()),
+    ]);
+    doc.unify_namespace_prefixes(&preferred).unwrap();
+    assert_eq!(expanded_names(&doc), before);
+    assert_eq!(
+        doc.find_descendant("detail").unwrap().full_name(&doc),
+        "ns2:detail"
+    );
+
+    let mut doc = Document::parse_file("tests/documents/merged_soap.xml").unwrap();
+    for prefix in ["", "xml", "a:b"] {
+        let preferred = HashMap::from([(soap.to_string(), prefix.to_string())]);
+        assert!(matches!(
+            doc.unify_namespace_prefixes(&preferred),
+            Err(Error::PrefixCollision(_))
+        ));
+    }
+    let preferred = HashMap::from([
+        (soap.to_string(), "s".to_string()),
+        ("urn:auth".to_string(), "s".to_string()),
+    ]);
+    assert!(matches!(
+        doc.unify_namespace_prefixes(&preferred),
+        Err(Error::PrefixCollision(_))
+    ));
+    assert_eq!(doc.root_element().unwrap().full_name(&doc), "ns0:Envelope");
+}
+
+#[test]
+fn test_escape_long_values() {
+    let clean = "QUJD".repeat(1 << 18);
+    let dirty = format!("{}<&\"'>{}", clean, clean);
+    let mut doc = Document::new();
+    let container = doc.container();
+    Element::build("data")
+        .attribute("clean", clean.as_str())
+        .attribute("dirty", dirty.as_str())
+        .namespace_decl("x", "urn:x?a=1&b=2")
+        .text_content(dirty.as_str())
+        .push_to(&mut doc, container);
+    let xml = doc.write_str().unwrap();
+    let escaped = format!("{}&lt;&amp;&quot;&apos;&gt;{}", clean, clean);
+    let escaped_attr = format!("{}&lt;&amp;&quot;'>{}", clean, clean);
+    let expected = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<data xmlns:x="urn:x?a=1&amp;b=2" clean="{}" dirty="{}">{}</data>"#,
+        clean, escaped_attr, escaped
+    );
+    assert_eq!(xml, expected);
+    let parsed = Document::parse_str(&xml).unwrap();
+    let data = parsed.root_element().unwrap();
+    assert_eq!(data.attribute(&parsed, "dirty"), Some(dirty.as_str()));
+    assert_eq!(data.text_content(&parsed), dirty);
+}
+
+#[test]
+fn test_sort_children() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project>
+  <dependency name="serde" version="1"/>
+  <!-- pinned -->
+  <dependency name="indexmap" version="2"/>
+  <description>Example</description>
+  <dependency name="anyhow" version="1"/>
+</project>"#;
+    let mut doc = Document::parse_str(xml).unwrap();
+    let project = doc.root_element().unwrap();
+    project.sort_child_elements_by_key(&mut doc, |doc, elem| {
+        elem.attribute(doc, "name").map(str::to_string)
+    });
+    let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project>
+  <description>Example</description>
+  <!-- pinned -->
+  <dependency name="anyhow" version="1"/>
+  <dependency name="indexmap" version="2"/>
+  <dependency name="serde" version="1"/>
+</project>"#;
+    assert_eq!(doc.write_str().unwrap(), expected);
+    for elem in project.child_elements(&doc) {
+        assert_eq!(elem.parent(&doc), Some(project));
+    }
+
+    // Sorting all nodes, elements by version then name, and other nodes last.
+    project.sort_children_by(&mut doc, |doc, a, b| {
+        let key = |node: &Node| {
+            node.as_element().map(|elem| {
+                let version = elem.attribute(doc, "version").unwrap_or_default();
+                (version.to_string(), elem.name(doc).to_string())
+            })
+        };
+        match (key(a), key(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        }
+    });
+    let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project>
+  <description>Example</description>
+  <dependency name="anyhow" version="1"/>
+  <dependency name="serde" version="1"/>
+  <dependency name="indexmap" version="2"/>
+  <!-- pinned -->
+</project>"#;
+    assert_eq!(doc.write_str().unwrap(), expected);
+    for elem in project.child_elements(&doc) {
+        assert_eq!(elem.parent(&doc), Some(project));
+    }
+}
+
+#[test]
+fn test_hoist_namespace_decls() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="urn:atom" xmlns:a="urn:atom">
+  <entry xmlns:media="urn:media">
+    <media:thumbnail url="a.png" media:width="10"/>
+    <dc:creator xmlns:dc="urn:dc">Someone</dc:creator>
+  </entry>
+  <entry>
+    <media:content xmlns:media="urn:media" xmlns:dc="urn:dc" dc:rights="cc"/>
+    <a:title>Title</a:title>
+  </entry>
+</feed>"#;
+    let read_opts = ReadOptions {
+        high_fidelity: true,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_str_with_opts(xml, read_opts).unwrap();
+    let opts = || WriteOptions {
+        hoist_namespace_decls: true,
+        ..WriteOptions::default()
+    };
+    let mut out = Vec::new();
+    let stats = doc.write_with_stats(&mut out, opts()).unwrap();
+    let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="urn:atom" xmlns:a="urn:atom" xmlns:media="urn:media" xmlns:dc="urn:dc">
+  <entry>
+    <media:thumbnail url="a.png" media:width="10"/>
+    <dc:creator>Someone</dc:creator>
+  </entry>
+  <entry>
+    <media:content dc:rights="cc"/>
+    <a:title>Title</a:title>
+  </entry>
+</feed>"#;
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+    assert_eq!(stats.namespace_decls, 4);
+    let mut out = Vec::new();
+    let stats = doc
+        .write_with_stats(&mut out, WriteOptions::default())
+        .unwrap();
+    assert_eq!(stats.namespace_decls, 6);
+
+    let written = Document::parse_str(expected).unwrap();
+    assert_eq!(expanded_names(&written), expanded_names(&doc));
+    // The document isn't modified.
+    let entry = doc.root_element().unwrap().child_elements(&doc)[0];
+    assert_eq!(entry.namespace_decls(&doc).len(), 1);
+
+    // Only prefixes declared on the root element
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?><x:a xmlns:x="urn:x"><x:b/></x:a>"#;
+    let doc = Document::parse_str_with_opts(xml, ReadOptions::default()).unwrap();
+    let pretty = WriteOptions {
+        pretty: false,
+        ..opts()
+    };
+    assert_eq!(doc.write_str_with_opts(pretty).unwrap(), xml);
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_hoist_namespace_decls_conflict() {
+    let opts = || WriteOptions {
+        hoist_namespace_decls: true,
+        ..WriteOptions::default()
+    };
+    let doc = Document::parse_file("tests/documents/merged_soap.xml").unwrap();
+    let mut out = Vec::new();
+    let err = doc.write_with_opts(&mut out, opts()).unwrap_err();
+    assert!(out.is_empty());
+    match &err {
+        Error::PrefixConflict {
+            prefix,
+            first,
+            second,
+            element_path,
+        } => {
+            assert_eq!(prefix, "ns0");
+            assert_eq!(first, "http://schemas.xmlsoap.org/soap/envelope/");
+            assert_eq!(second, "urn:detail");
+            assert_eq!(element_path, "/ns0:Envelope/ns1:Body/soap:Fault");
+        }
+        err => panic!("{:?}", err),
+    }
+    let message = err.to_string();
+    assert!(message.contains("'http://schemas.xmlsoap.org/soap/envelope/'"));
+    assert!(message.contains("'urn:detail'"));
+
+    // A default namespace that doesn't apply to the whole document
+    let xml = r#"<?xml version="1.0"?><a><b xmlns="urn:b"/></a>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    let err = doc.write_str_with_opts(opts()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Default namespace is bound to both '' and 'urn:b', at /a/b"
+    );
+    let xml = r#"<?xml version="1.0"?><p:a xmlns:p="urn:p"><b xmlns="urn:b"/><c/></p:a>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    assert!(matches!(
+        doc.write_str_with_opts(opts()),
+        Err(Error::PrefixConflict { first, second, .. }) if first == "urn:b" && second.is_empty()
+    ));
+    // Undeclaring it where it isn't declared is fine.
+    let xml = r#"<?xml version="1.0"?><a><b xmlns=""/></a>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    let written = doc.write_str_with_opts(opts()).unwrap();
+    assert!(written.ends_with("<a>\n  <b/>\n</a>"));
+}
+
+#[test]
+fn test_write_options_auto_traits() {
+    fn assert_traits<T: Send + Sync>() {}
+    assert_traits::<WriteOptions>();
+}
@@ -0,0 +1,214 @@
+#![cfg(feature = "fs")]
+use xml_doc::{Document, Element, Error, Node, ReadOptions, TextMatch};
+
+fn elements(nodes: Vec<Node>) -> Vec<Element> {
+    nodes
+        .into_iter()
+        .map(|node| node.as_element().expect("element"))
+        .collect()
+}
+
+fn texts(nodes: Vec<Node>) -> Vec<String> {
+    nodes
+        .into_iter()
+        .map(|node| match node {
+            Node::Text(text) => text.into_string(),
+            node => panic!("expected text, found {:?}", node),
+        })
+        .collect()
+}
+
+#[test]
+fn test_evaluate_doc() {
+    let doc = Document::parse_file("tests/documents/doc.xml").unwrap();
+    let root = doc.root_element().unwrap();
+    let items = root.find(&doc, "items").unwrap();
+
+    assert_eq!(elements(doc.evaluate("/root").unwrap()), [root]);
+    assert_eq!(elements(doc.evaluate("root/items").unwrap()), [items]);
+    assert_eq!(
+        elements(doc.evaluate("/root/items/item").unwrap()),
+        items.child_elements(&doc)
+    );
+    let names: Vec<_> = elements(doc.evaluate("/root/*").unwrap())
+        .iter()
+        .map(|e| e.full_name(&doc).to_string())
+        .collect();
+    assert_eq!(names, ["self-closing", "b", "items"]);
+    assert_eq!(doc.evaluate("//*").unwrap().len(), 7);
+    assert!(doc.evaluate("/items").unwrap().is_empty());
+    assert!(doc.evaluate("//missing").unwrap().is_empty());
+
+    // Prefixed names match the full name.
+    assert_eq!(doc.evaluate("//ns:item").unwrap().len(), 2);
+    assert!(doc.evaluate("//other:item").unwrap().is_empty());
+
+    // Positions count matching children of each parent.
+    let second = elements(doc.evaluate("//item[2]").unwrap());
+    assert_eq!(second.len(), 1);
+    assert_eq!(second[0].text_content(&doc), "日本語");
+    assert_eq!(elements(doc.evaluate("//ns:item[2]").unwrap()), second);
+    assert!(doc.evaluate("//item[4]").unwrap().is_empty());
+    assert!(doc.evaluate("//item[0]").unwrap().is_empty());
+
+    // Attribute names are compared with the full name.
+    assert_eq!(
+        elements(doc.evaluate("//item[@ns:attr='val']").unwrap()),
+        second
+    );
+    assert_eq!(elements(doc.evaluate("//item[@ns:attr]").unwrap()), second);
+    assert!(doc.evaluate("//item[@attr]").unwrap().is_empty());
+    assert_eq!(
+        elements(doc.evaluate(r#"/root/items[@id="items"]"#).unwrap()),
+        [items]
+    );
+    assert!(doc.evaluate("/root/items[@id='other']").unwrap().is_empty());
+
+    assert_eq!(texts(doc.evaluate("/root/@attr2").unwrap()), ["hey"]);
+    assert_eq!(texts(doc.evaluate("/root/@*").unwrap()), ["value", "hey"]);
+    assert_eq!(texts(doc.evaluate("//@ns:attr").unwrap()), ["val"]);
+    assert!(doc.evaluate("/root/@missing").unwrap().is_empty());
+}
+
+#[test]
+fn test_evaluate_config() {
+    let doc = Document::parse_file("tests/documents/aligned.xml").unwrap();
+    let alias = elements(doc.evaluate("/config/server[@name='dev']/alias").unwrap());
+    assert_eq!(alias.len(), 1);
+    assert_eq!(alias[0].text_content(&doc), "local");
+    assert_eq!(texts(doc.evaluate("//server/@port").unwrap()), ["443"]);
+    assert_eq!(texts(doc.evaluate("//@name").unwrap()), ["prod", "dev"]);
+    assert_eq!(
+        texts(doc.evaluate("/config/server[@name][2]/@name").unwrap()),
+        ["dev"]
+    );
+
+    let doc = Document::parse_file("tests/documents/many_attributes.xml").unwrap();
+    assert_eq!(
+        texts(doc.evaluate("/root/@*").unwrap()),
+        ["26", "25", "24", "23"]
+    );
+    let values = texts(doc.evaluate("/root/item[1]/@*").unwrap());
+    assert_eq!(values.len(), 8);
+    assert_eq!(values[..2], ["1", "first"]);
+    assert_eq!(doc.evaluate("//empty/@*").unwrap().len(), 9);
+}
+
+#[test]
+fn test_evaluate_element() {
+    let doc = Document::parse_file("tests/documents/commented_config.xml").unwrap();
+    let config = doc.root_element().unwrap();
+    let keystore = config.find(&doc, "keystore").unwrap();
+    let path = keystore.find(&doc, "path").unwrap();
+
+    assert_eq!(elements(keystore.evaluate(&doc, "path").unwrap()), [path]);
+    // The element takes the place of the document.
+    assert_eq!(elements(keystore.evaluate(&doc, "/path").unwrap()), [path]);
+    assert!(keystore.evaluate(&doc, "/config").unwrap().is_empty());
+    assert_eq!(elements(config.evaluate(&doc, "//path").unwrap()), [path]);
+    assert!(keystore.evaluate(&doc, "//b").unwrap().is_empty());
+    assert_eq!(config.evaluate(&doc, "//b").unwrap().len(), 1);
+    assert_eq!(
+        texts(config.evaluate(&doc, "tls/@enabled").unwrap()),
+        ["false"]
+    );
+
+    assert!(matches!(
+        config.evaluate(&doc, "tls/@enabled/x"),
+        Err(Error::InvalidQuery(_))
+    ));
+}
+
+#[test]
+fn test_evaluate_order() {
+    // Children of nested elements are returned in document order, without duplicates.
+    let xml = r#"<?xml version="1.0"?><a><a><b>1</b><a><b>2</b></a></a><b>3</b></a>"#;
+    let doc = Document::parse_str(xml).unwrap();
+    let text: Vec<String> = elements(doc.evaluate("//a/b").unwrap())
+        .iter()
+        .map(|e| e.text_content(&doc))
+        .collect();
+    assert_eq!(text, ["1", "2", "3"]);
+    assert_eq!(doc.evaluate("//a//b").unwrap().len(), 3);
+    assert_eq!(doc.evaluate("//a//a").unwrap().len(), 2);
+    let first: Vec<String> = elements(doc.evaluate("//a/*[1]").unwrap())
+        .iter()
+        .map(|e| e.name(&doc).to_string())
+        .collect();
+    assert_eq!(first, ["a", "b", "b"]);
+}
+
+#[test]
+fn test_child_text_predicates() {
+    let opts = ReadOptions {
+        trim_text: false,
+        ignore_whitespace_only: true,
+        ..ReadOptions::default()
+    };
+    let doc = Document::parse_file_with_opts("tests/documents/records.xml", opts).unwrap();
+    let records = doc.root_element().unwrap();
+    let record = |id: &str| {
+        records
+            .find_by_attribute(&doc, "record", "id", id)
+            .expect("record")
+    };
+
+    let cases = [
+        ("status", "active", vec!["1", "3", "4"]),
+        ("status", "inactive", vec!["2"]),
+        ("name", "Grace", vec!["2"]),
+        ("note", "active", vec!["3"]),
+        ("status", "missing", vec![]),
+        ("missing", "active", vec![]),
+    ];
+    for (child, value, ids) in cases {
+        let expected: Vec<Element> = ids.into_iter().map(record).collect();
+        let query = format!("/records/record[{}='{}']", child, value);
+        assert_eq!(
+            elements(doc.evaluate(&query).unwrap()),
+            expected,
+            "{}",
+            query
+        );
+        assert_eq!(
+            records.find_where_child_text(&doc, child, value),
+            expected,
+            "{}",
+            query
+        );
+    }
+
+    // Exact comparison keeps surrounding whitespace.
+    let query = "records/record[status='inactive']";
+    assert!(doc
+        .evaluate_with(query, TextMatch::Exact)
+        .unwrap()
+        .is_empty());
+    assert!(records
+        .find_where_child_text_with(&doc, "status", "inactive", TextMatch::Exact)
+        .is_empty());
+    let spaced = "\n      inactive\n    ";
+    assert_eq!(
+        records.find_where_child_text_with(&doc, "status", spaced, TextMatch::Exact),
+        [record("2")]
+    );
+    assert_eq!(
+        elements(
+            records
+                .evaluate_with(&doc, "record[status='active']", TextMatch::Exact)
+                .unwrap()
+        ),
+        [record("1"), record("3"), record("4")]
+    );
+
+    // `text()` compares the text of the element itself.
+    assert_eq!(
+        elements(doc.evaluate("//status[text()='inactive']").unwrap()),
+        [record("2").find(&doc, "status").unwrap()]
+    );
+    assert_eq!(
+        elements(doc.evaluate("//record[status='active'][2]/name").unwrap()),
+        [record("3").find(&doc, "name").unwrap()]
+    );
+    assert!(doc.evaluate("//*[text()='Ken ']").unwrap().is_empty());
+}